@@ -0,0 +1,70 @@
+//! Compares a burst of 1000 triggers of tiny macros run on a thread each with the same burst on
+//! a worker pool, in time and in allocations. Runs on the simulated backend, so it sends no
+//! input.
+//!
+//! Run with `cargo bench --bench trigger_burst`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use input_macro_runner::bench::TriggerBurst;
+
+const TRIGGERS: usize = 1000;
+const WORKER_THREADS: usize = 4;
+const BURSTS: u32 = 10;
+
+/// The system allocator, counting every allocation.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn main() {
+    println!("{} triggers, mean of {} bursts", TRIGGERS, BURSTS);
+
+    for (name, worker_threads) in [
+        ("thread per trigger".to_string(), None),
+        (
+            format!("pool of {} workers", WORKER_THREADS),
+            Some(WORKER_THREADS),
+        ),
+    ] {
+        let mut elapsed = Duration::ZERO;
+        let mut allocations = 0;
+
+        for _ in 0..BURSTS {
+            // Starting the pool's threads is paid once, not per burst
+            let mut burst = TriggerBurst::new(TRIGGERS, worker_threads);
+            let allocated_before = ALLOCATIONS.load(Ordering::Relaxed);
+            let started = Instant::now();
+
+            assert_eq!(burst.run(), TRIGGERS);
+
+            elapsed += started.elapsed();
+            allocations += ALLOCATIONS.load(Ordering::Relaxed) - allocated_before;
+        }
+
+        println!(
+            "{:<20} {:>10.2?} a burst, {:>8} allocations",
+            name,
+            elapsed / BURSTS,
+            allocations / BURSTS as usize
+        );
+    }
+}
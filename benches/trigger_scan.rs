@@ -0,0 +1,48 @@
+//! Compares the listener's per-tick trigger scan, one read of the scan set into a snapshot, with
+//! the read of every key of every hotkey it replaced, over a config of 200 macros. Reads the real
+//! keyboard, so nothing should be held while it runs.
+//!
+//! Run with `cargo bench --bench trigger_scan`.
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use input_macro_runner::bench::TriggerScan;
+
+const MACROS: usize = 200;
+const TICKS: u32 = 2_000;
+
+fn main() -> Result<(), anyhow::Error> {
+    let mut scan = TriggerScan::new(MACROS)?;
+    let (per_macro_reads, snapshot_reads) = scan.key_reads();
+
+    let per_macro = time(|| black_box(scan.per_macro_tick()));
+    let snapshot = time(|| black_box(scan.snapshot_tick()));
+
+    println!("{} macros, mean of {} ticks", MACROS, TICKS);
+    println!(
+        "per macro: {:>10.2?} a tick, {:>4} key reads",
+        per_macro, per_macro_reads
+    );
+    println!(
+        "snapshot:  {:>10.2?} a tick, {:>4} key reads",
+        snapshot, snapshot_reads
+    );
+
+    Ok(())
+}
+
+/// The mean time of `TICKS` runs of `tick`, after a tenth as many to warm up.
+fn time(mut tick: impl FnMut() -> usize) -> Duration {
+    for _ in 0..TICKS / 10 {
+        tick();
+    }
+
+    let started = Instant::now();
+    for _ in 0..TICKS {
+        tick();
+    }
+    started.elapsed() / TICKS
+}
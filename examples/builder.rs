@@ -0,0 +1,27 @@
+//! Builds a macro in code and runs it until Shift+F6 is held or Enter is pressed in the console.
+//!
+//! Ctrl+Alt+L then tabs to the next field, types a user name, waits for the form to catch up and
+//! submits it with Ctrl+Enter.
+
+use input_macro_runner::{BuildCommands, Key, Macro, MacroConfig, MacroRunner};
+
+fn main() -> Result<(), anyhow::Error> {
+    let login = Macro::builder("login")
+        .hotkey([Key::LeftControl, Key::LeftMenu, Key::L])
+        .press(Key::Tab)
+        .text("me@example.com")
+        .wait_ms(250)
+        .key_combo([Key::LeftControl, Key::Return])
+        .build()?;
+
+    let macro_config = MacroConfig::builder()
+        .program_hotkey([Key::LeftShift, Key::F6])
+        .add_macro(login)
+        .build()?;
+
+    let runner = MacroRunner::run(macro_config)?;
+    println!("Press Ctrl+Alt+L to log in, Enter here to stop");
+    std::io::stdin().read_line(&mut String::new())?;
+
+    runner.stop()
+}
@@ -0,0 +1,533 @@
+use std::{
+    str::FromStr,
+    sync::{Arc, OnceLock},
+};
+#[cfg(test)]
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+#[cfg(test)]
+use super::clock::Clock;
+#[cfg(windows)]
+use super::gamepad::Gamepads;
+use super::{
+    screen,
+    screen::{Color, ScreenRect},
+    window::{self, WindowInfo},
+    Key, MouseButton,
+};
+
+/// The backend `--backend` chose, see `input_backend`.
+static CHOSEN: OnceLock<BackendKind> = OnceLock::new();
+
+/// A piece of input sent through an `InputBackend`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InputEvent {
+    KeyDown(Key),
+    KeyUp(Key),
+    ButtonDown(MouseButton),
+    ButtonUp(MouseButton),
+    /// Puts the cursor at this point on the virtual desktop.
+    MoveTo(i32, i32),
+    /// Turns the wheel by this many lines, away from the user when positive.
+    Wheel(i32),
+}
+
+/// Where macros read the state of the keyboard from and send their input to. Commands go through
+/// this rather than calling the Windows API directly, so that the source of key states and the
+/// destination of input can be swapped out.
+pub trait InputBackend: Send + Sync {
+    fn is_key_held(&self, key: Key) -> bool;
+    /// Sends `events` in order, stopping at the first that fails.
+    fn send(&self, events: &[InputEvent]) -> Result<(), anyhow::Error>;
+}
+
+/// Which `InputBackend` the runner injects input through.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BackendKind {
+    /// `SendInput` and the real keyboard state.
+    Windows,
+    /// Virtual devices made through `/dev/uinput`, for Linux without a display server.
+    Uinput,
+}
+
+impl BackendKind {
+    /// `uinput` on Linux when there is no `DISPLAY` to send input to, `windows` otherwise.
+    fn detect() -> Self {
+        if cfg!(target_os = "linux") && std::env::var_os("DISPLAY").is_none() {
+            BackendKind::Uinput
+        } else {
+            BackendKind::Windows
+        }
+    }
+}
+
+impl FromStr for BackendKind {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "windows" => Ok(BackendKind::Windows),
+            "uinput" => Ok(BackendKind::Uinput),
+            _ => Err(anyhow::anyhow!(
+                "Unknown backend {:?}, expected windows or uinput",
+                text
+            )),
+        }
+    }
+}
+
+/// Makes `input_backend` use `kind` rather than detecting one. Only the first call counts.
+pub fn choose(kind: BackendKind) {
+    let _ = CHOSEN.set(kind);
+}
+
+/// The backend the runner sends input through: the one `choose` was given, or else the one
+/// suited to this machine.
+pub fn input_backend() -> Result<Arc<dyn InputBackend>, anyhow::Error> {
+    match CHOSEN.get().copied().unwrap_or_else(BackendKind::detect) {
+        #[cfg(windows)]
+        BackendKind::Windows => Ok(Arc::new(WindowsBackend::default())),
+        #[cfg(target_os = "linux")]
+        BackendKind::Uinput => Ok(Arc::new(super::uinput::UinputBackend::open()?)),
+        #[allow(unreachable_patterns)]
+        kind => Err(anyhow::anyhow!(
+            "The {:?} backend is not available on this platform",
+            kind
+        )),
+    }
+}
+
+/// Reads the real keyboard state, and gamepad buttons from XInput.
+#[cfg(windows)]
+#[derive(Debug, Default)]
+pub struct WindowsBackend {
+    gamepads: Gamepads,
+}
+
+#[cfg(windows)]
+impl InputBackend for WindowsBackend {
+    fn is_key_held(&self, key: Key) -> bool {
+        if key.is_gamepad() {
+            return self.gamepads.is_held(key);
+        }
+
+        super::key_held(key.virtual_key())
+    }
+
+    fn send(&self, events: &[InputEvent]) -> Result<(), anyhow::Error> {
+        super::send_input_events(events)
+    }
+}
+
+/// Where macros read the layout of the screen, and which window is in front, from. The
+/// counterpart of `InputBackend` for the display.
+pub trait ScreenBackend: Send + Sync {
+    /// Size of the primary monitor.
+    fn primary_size(&self) -> (i32, i32);
+    /// The bounding box of every monitor.
+    fn virtual_bounds(&self) -> ScreenRect;
+    /// Every monitor, the primary one first and the rest from left to right.
+    fn monitors(&self) -> Vec<ScreenRect>;
+    /// The window that has the focus, `None` if there is none.
+    fn foreground_window(&self) -> Result<Option<WindowInfo>, anyhow::Error>;
+    /// Whether the window that has the focus is a fullscreen app.
+    fn foreground_fullscreen(&self) -> bool;
+    /// The color of the pixel at `(x, y)` on the virtual desktop.
+    fn pixel(&self, x: i32, y: i32) -> Result<Color, anyhow::Error>;
+}
+
+/// Reads the real monitor layout.
+#[derive(Debug, Default)]
+pub struct GdiScreen;
+
+impl ScreenBackend for GdiScreen {
+    fn primary_size(&self) -> (i32, i32) {
+        screen::primary_size()
+    }
+
+    fn virtual_bounds(&self) -> ScreenRect {
+        screen::virtual_desktop()
+    }
+
+    fn monitors(&self) -> Vec<ScreenRect> {
+        screen::monitors()
+    }
+
+    fn foreground_window(&self) -> Result<Option<WindowInfo>, anyhow::Error> {
+        window::foreground_window()
+    }
+
+    fn foreground_fullscreen(&self) -> bool {
+        window::foreground_fullscreen()
+    }
+
+    fn pixel(&self, x: i32, y: i32) -> Result<Color, anyhow::Error> {
+        screen::pixel(x, y)
+    }
+}
+
+/// A keyboard on which nothing is ever held, for `diff-run`, so that macros take the same branches
+/// on every run.
+#[derive(Debug, Default)]
+pub struct SimulatedInput;
+
+impl InputBackend for SimulatedInput {
+    fn is_key_held(&self, _key: Key) -> bool {
+        false
+    }
+
+    // Under `diff-run` the input functions record the events instead of sending them
+    fn send(&self, events: &[InputEvent]) -> Result<(), anyhow::Error> {
+        super::send_input_events(events)
+    }
+}
+
+/// A keyboard whose keys go down and come up on a script, for tests: each step puts a key down
+/// or lets it go at a time after the backend was made, as read off `clock`. Keys no step has
+/// put down yet are up.
+#[cfg(test)]
+pub struct ScriptedInput {
+    clock: Arc<dyn Clock>,
+    start: Instant,
+    /// When each key goes down (`true`) or comes up, in the order the steps were added.
+    timeline: Mutex<Vec<(Duration, Key, bool)>>,
+}
+
+#[cfg(test)]
+impl ScriptedInput {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        ScriptedInput {
+            start: clock.now(),
+            clock,
+            timeline: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Puts `key` down `at` after the backend was made.
+    pub fn press_at(&self, key: Key, at: Duration) -> &Self {
+        self.step(key, at, true)
+    }
+
+    /// Lets `key` go `at` after the backend was made.
+    pub fn release_at(&self, key: Key, at: Duration) -> &Self {
+        self.step(key, at, false)
+    }
+
+    /// Holds `key` down from `from` until `until`.
+    pub fn hold(&self, key: Key, from: Duration, until: Duration) -> &Self {
+        self.press_at(key, from).release_at(key, until)
+    }
+
+    fn step(&self, key: Key, at: Duration, held: bool) -> &Self {
+        self.timeline
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((at, key, held));
+        self
+    }
+}
+
+#[cfg(test)]
+impl InputBackend for ScriptedInput {
+    fn is_key_held(&self, key: Key) -> bool {
+        let now = self.clock.elapsed(self.start);
+
+        // The latest step for the key that has come about, the last added of those at once
+        self.timeline
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .enumerate()
+            .filter(|(_, (at, step_key, _))| *step_key == key && *at <= now)
+            .max_by_key(|(index, (at, _, _))| (*at, *index))
+            .is_some_and(|(_, (_, _, held))| *held)
+    }
+
+    fn send(&self, events: &[InputEvent]) -> Result<(), anyhow::Error> {
+        super::send_input_events(events)
+    }
+}
+
+/// A single 1920x1080 black monitor with no window in front, for `diff-run`, so that
+/// screen-relative coordinates resolve the same on every machine.
+#[derive(Debug, Default)]
+pub struct SimulatedScreen;
+
+impl SimulatedScreen {
+    const WIDTH: i32 = 1920;
+    const HEIGHT: i32 = 1080;
+}
+
+impl ScreenBackend for SimulatedScreen {
+    fn primary_size(&self) -> (i32, i32) {
+        (Self::WIDTH, Self::HEIGHT)
+    }
+
+    fn virtual_bounds(&self) -> ScreenRect {
+        ScreenRect {
+            left: 0,
+            top: 0,
+            width: Self::WIDTH,
+            height: Self::HEIGHT,
+        }
+    }
+
+    fn monitors(&self) -> Vec<ScreenRect> {
+        vec![self.virtual_bounds()]
+    }
+
+    fn foreground_window(&self) -> Result<Option<WindowInfo>, anyhow::Error> {
+        Ok(None)
+    }
+
+    fn foreground_fullscreen(&self) -> bool {
+        false
+    }
+
+    fn pixel(&self, _x: i32, _y: i32) -> Result<Color, anyhow::Error> {
+        Ok(Color::BLACK)
+    }
+}
+
+/// The simulated screen with the window in front changing on a script, for tests: each step
+/// brings a window to the front, or leaves none there, at a time after the screen was made, as
+/// read off `clock`. Until the first step no window is in front.
+#[cfg(test)]
+pub struct ScriptedScreen {
+    clock: Arc<dyn Clock>,
+    start: Instant,
+    /// Which window is in front from when, in the order the steps were added.
+    foreground: Mutex<Vec<(Duration, Option<WindowInfo>)>>,
+    foreground_reads: AtomicUsize,
+}
+
+#[cfg(test)]
+impl ScriptedScreen {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        ScriptedScreen {
+            start: clock.now(),
+            clock,
+            foreground: Mutex::new(Vec::new()),
+            foreground_reads: AtomicUsize::new(0),
+        }
+    }
+
+    /// Brings `window` to the front `at` after the screen was made, or leaves no window there.
+    pub fn focus_at(&self, at: Duration, window: Option<WindowInfo>) -> &Self {
+        self.foreground
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((at, window));
+        self
+    }
+
+    /// How many times the window in front has been asked for.
+    pub fn foreground_reads(&self) -> usize {
+        self.foreground_reads.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+impl ScreenBackend for ScriptedScreen {
+    fn primary_size(&self) -> (i32, i32) {
+        SimulatedScreen.primary_size()
+    }
+
+    fn virtual_bounds(&self) -> ScreenRect {
+        SimulatedScreen.virtual_bounds()
+    }
+
+    fn monitors(&self) -> Vec<ScreenRect> {
+        SimulatedScreen.monitors()
+    }
+
+    fn foreground_window(&self) -> Result<Option<WindowInfo>, anyhow::Error> {
+        self.foreground_reads.fetch_add(1, Ordering::SeqCst);
+        let now = self.clock.elapsed(self.start);
+
+        // The latest step that has come about, the last added of those at once
+        Ok(self
+            .foreground
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .enumerate()
+            .filter(|(_, (at, _))| *at <= now)
+            .max_by_key(|(index, (at, _))| (*at, *index))
+            .and_then(|(_, (_, window))| window.clone()))
+    }
+
+    fn foreground_fullscreen(&self) -> bool {
+        false
+    }
+
+    fn pixel(&self, x: i32, y: i32) -> Result<Color, anyhow::Error> {
+        SimulatedScreen.pixel(x, y)
+    }
+}
+
+/// A screen for tests with whatever monitors they set up, on which pixels start out black and
+/// are painted on a script: each step colors a pixel at a time after the screen was made, as read
+/// off `clock`. No window is ever in front.
+#[cfg(test)]
+pub struct MemoryScreen {
+    clock: Arc<dyn Clock>,
+    start: Instant,
+    /// The primary monitor first.
+    monitors: Vec<ScreenRect>,
+    fullscreen: bool,
+    /// Which color each pixel turns from when, in the order the steps were added.
+    paints: Mutex<Vec<(Duration, i32, i32, Color)>>,
+}
+
+#[cfg(test)]
+impl MemoryScreen {
+    /// A screen made of `monitors`, the primary one first.
+    pub fn new(clock: Arc<dyn Clock>, monitors: Vec<ScreenRect>) -> Self {
+        MemoryScreen {
+            start: clock.now(),
+            clock,
+            monitors,
+            fullscreen: false,
+            paints: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// A screen of a single `width` by `height` monitor.
+    pub fn sized(clock: Arc<dyn Clock>, width: i32, height: i32) -> Self {
+        let monitor = ScreenRect {
+            left: 0,
+            top: 0,
+            width,
+            height,
+        };
+
+        MemoryScreen::new(clock, vec![monitor])
+    }
+
+    /// Has a fullscreen app in front.
+    pub fn fullscreen(mut self) -> Self {
+        self.fullscreen = true;
+        self
+    }
+
+    /// Colors the pixel at `(x, y)` `at` after the screen was made.
+    pub fn paint_at(&self, at: Duration, x: i32, y: i32, color: Color) -> &Self {
+        self.paints
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((at, x, y, color));
+        self
+    }
+}
+
+#[cfg(test)]
+impl ScreenBackend for MemoryScreen {
+    fn primary_size(&self) -> (i32, i32) {
+        self.monitors
+            .first()
+            .map_or((0, 0), |primary| (primary.width, primary.height))
+    }
+
+    fn virtual_bounds(&self) -> ScreenRect {
+        let left = self.monitors.iter().map(|m| m.left).min().unwrap_or(0);
+        let top = self.monitors.iter().map(|m| m.top).min().unwrap_or(0);
+        let right = self.monitors.iter().map(|m| m.left + m.width).max();
+        let bottom = self.monitors.iter().map(|m| m.top + m.height).max();
+
+        ScreenRect {
+            left,
+            top,
+            width: right.unwrap_or(0) - left,
+            height: bottom.unwrap_or(0) - top,
+        }
+    }
+
+    fn monitors(&self) -> Vec<ScreenRect> {
+        self.monitors.clone()
+    }
+
+    fn foreground_window(&self) -> Result<Option<WindowInfo>, anyhow::Error> {
+        Ok(None)
+    }
+
+    fn foreground_fullscreen(&self) -> bool {
+        self.fullscreen
+    }
+
+    fn pixel(&self, x: i32, y: i32) -> Result<Color, anyhow::Error> {
+        let now = self.clock.elapsed(self.start);
+
+        // The latest step for the pixel that has come about, the last added of those at once
+        Ok(self
+            .paints
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .enumerate()
+            .filter(|(_, (at, paint_x, paint_y, _))| (*paint_x, *paint_y) == (x, y) && *at <= now)
+            .max_by_key(|(index, (at, ..))| (*at, *index))
+            .map_or(Color::BLACK, |(_, (.., color))| *color))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::VirtualClock, context::CancellationToken};
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn scripted_keys_follow_the_timeline() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60)));
+        let cancellation = CancellationToken::default();
+        let backend = ScriptedInput::new(clock.clone());
+        backend
+            .hold(Key::Shift, ms(100), ms(300))
+            .press_at(Key::A, ms(200));
+
+        assert!(!backend.is_key_held(Key::Shift));
+        assert!(!backend.is_key_held(Key::A));
+
+        clock.sleep(ms(100), &cancellation);
+        assert!(backend.is_key_held(Key::Shift));
+        assert!(!backend.is_key_held(Key::A));
+
+        clock.sleep(ms(150), &cancellation);
+        assert!(backend.is_key_held(Key::Shift));
+        assert!(backend.is_key_held(Key::A));
+
+        clock.sleep(ms(50), &cancellation);
+        assert!(!backend.is_key_held(Key::Shift));
+        assert!(backend.is_key_held(Key::A));
+        assert!(!backend.is_key_held(Key::B));
+    }
+
+    #[test]
+    fn steps_can_be_added_out_of_order() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60)));
+        let cancellation = CancellationToken::default();
+        let backend = ScriptedInput::new(clock.clone());
+        backend
+            .release_at(Key::Control, ms(500))
+            .press_at(Key::Control, ms(100));
+
+        clock.sleep(ms(200), &cancellation);
+        assert!(backend.is_key_held(Key::Control));
+        clock.sleep(ms(300), &cancellation);
+        assert!(!backend.is_key_held(Key::Control));
+
+        // At the same time, the step added last wins
+        backend.press_at(Key::Control, ms(500));
+        assert!(backend.is_key_held(Key::Control));
+    }
+}
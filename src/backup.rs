@@ -0,0 +1,231 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+use windows::Win32::{Foundation::SYSTEMTIME, System::SystemInformation::GetLocalTime};
+
+/// Where copies of a config are kept before it is overwritten, e.g. by `calibrate` or
+/// `restore-backup`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupConfig {
+    /// Directory for the backups, relative to the config file. Defaults to the config's own
+    /// directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir: Option<PathBuf>,
+    /// How many backups of each file to keep, the oldest are deleted beyond that.
+    #[serde(default = "default_keep")]
+    pub keep: usize,
+}
+
+fn default_keep() -> usize {
+    10
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        BackupConfig {
+            dir: None,
+            keep: default_keep(),
+        }
+    }
+}
+
+impl BackupConfig {
+    /// The `backup` block of a parsed config file, or the defaults. Read straight from the file,
+    /// rather than from a loaded config, so that a config too broken to load can still be
+    /// restored.
+    pub fn from_config_value(config_value: &Value) -> Self {
+        match config_value.get("backup") {
+            Some(backup) => serde_yaml::from_value(backup.clone()).unwrap_or_else(|e| {
+                log::warn!("Ignoring unreadable backup settings: {}", e);
+                BackupConfig::default()
+            }),
+            None => BackupConfig::default(),
+        }
+    }
+
+    /// The backup settings of the config file at `path`, see `from_config_value`.
+    pub fn read(path: &Path) -> Self {
+        match std::fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| serde_yaml::from_str(&contents).ok())
+        {
+            Some(config_value) => BackupConfig::from_config_value(&config_value),
+            None => BackupConfig::default(),
+        }
+    }
+
+    fn dir_for(&self, path: &Path) -> PathBuf {
+        let config_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        match &self.dir {
+            Some(dir) => config_dir.join(dir),
+            None => config_dir.to_path_buf(),
+        }
+    }
+}
+
+fn file_name(path: &Path) -> Result<String, anyhow::Error> {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| anyhow::anyhow!("{} is not a file", path.display()))
+}
+
+/// The local time as `YYYYMMDD-HHMMSS`, which sorts by age.
+fn timestamp() -> String {
+    let mut now = SYSTEMTIME::default();
+    unsafe { GetLocalTime(&mut now) };
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        now.wYear, now.wMonth, now.wDay, now.wHour, now.wMinute, now.wSecond
+    )
+}
+
+/// The backups of the file at `path`, oldest first.
+pub fn list_backups(path: &Path, config: &BackupConfig) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let dir = config.dir_for(path);
+    let prefix = format!("{}.bak-", file_name(path)?);
+
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(anyhow::anyhow!(
+                "Failed to read backups in {}: {}",
+                dir.display(),
+                e
+            ))
+        }
+    };
+
+    let mut backups: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|backup| {
+            backup
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with(&prefix))
+        })
+        .collect();
+    backups.sort();
+
+    Ok(backups)
+}
+
+/// Copies the file at `path`, if there is one, to a timestamped backup and deletes the backups
+/// beyond `keep`.
+fn back_up(path: &Path, config: &BackupConfig) -> Result<(), anyhow::Error> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let dir = config.dir_for(path);
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| anyhow::anyhow!("Failed to create {}: {}", dir.display(), e))?;
+
+    let backup = dir.join(format!("{}.bak-{}", file_name(path)?, timestamp()));
+    // A second write within the same second keeps the backup of the first, the older contents
+    if !backup.exists() {
+        std::fs::copy(path, &backup).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to back up {} to {}: {}",
+                path.display(),
+                backup.display(),
+                e
+            )
+        })?;
+        log::info!("Backed up {} to {}", path.display(), backup.display());
+    }
+
+    let backups = list_backups(path, config)?;
+    let surplus = backups.len().saturating_sub(config.keep.max(1));
+    for old_backup in backups.iter().take(surplus) {
+        if let Err(e) = std::fs::remove_file(old_backup) {
+            log::warn!(
+                "Failed to delete old backup {}: {}",
+                old_backup.display(),
+                e
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes `contents` to a temporary file next to `path`, flushes it to disk and moves it over
+/// `path`, so that a crash leaves either the old file or the new one, never a truncated one.
+fn write_atomically(path: &Path, contents: &str) -> Result<(), anyhow::Error> {
+    let temporary = path.with_file_name(format!(".{}.tmp", file_name(path)?));
+
+    let result = File::create(&temporary)
+        .and_then(|mut file| {
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|_| std::fs::rename(&temporary, path));
+
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&temporary);
+        return Err(anyhow::anyhow!("Failed to write {}: {}", path.display(), e));
+    }
+
+    Ok(())
+}
+
+/// Backs up the file at `path`, if it exists, then replaces it with `contents`. Every write of a
+/// config file goes through here.
+pub fn write_config(
+    path: &Path,
+    contents: &str,
+    config: &BackupConfig,
+) -> Result<(), anyhow::Error> {
+    back_up(path, config)?;
+    write_atomically(path, contents)
+}
+
+/// Prints the backups of the config at `path`, newest first.
+pub fn print_backups(path: &Path) -> Result<(), anyhow::Error> {
+    let backups = list_backups(path, &BackupConfig::read(path))?;
+
+    if backups.is_empty() {
+        println!("No backups of {}", path.display());
+    }
+
+    for backup in backups.iter().rev() {
+        println!("{}", backup.display());
+    }
+
+    Ok(())
+}
+
+/// Puts the backup named `name`, or else the newest one, back in place of the config at `path`.
+/// The config being replaced is itself backed up first, so a restore can be undone.
+pub fn restore_backup(path: &Path, name: Option<&str>) -> Result<(), anyhow::Error> {
+    let config = BackupConfig::read(path);
+    let backups = list_backups(path, &config)?;
+
+    let backup = match name {
+        Some(name) => backups
+            .iter()
+            .find(|backup| {
+                backup
+                    .file_name()
+                    .is_some_and(|file_name| file_name.to_string_lossy() == name)
+            })
+            .ok_or_else(|| anyhow::anyhow!("No backup of {} named {}", path.display(), name))?,
+        None => backups
+            .last()
+            .ok_or_else(|| anyhow::anyhow!("No backups of {}", path.display()))?,
+    };
+
+    let contents = std::fs::read_to_string(backup)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", backup.display(), e))?;
+    write_config(path, &contents, &config)?;
+    println!("Restored {} from {}", path.display(), backup.display());
+
+    Ok(())
+}
@@ -0,0 +1,144 @@
+//! What the benchmarks under `benches/` run, since they can only reach the public API. Not part
+//! of that API.
+
+use std::{collections::HashSet, sync::Arc, thread, time::Duration};
+
+use super::{
+    backend::{self, InputBackend, SimulatedInput, SimulatedScreen},
+    builder::BuildCommands,
+    clock::SystemClock,
+    events::TriggerSource,
+    executor::Executor,
+    listener::KeyStateTracker,
+    Key, Macro,
+};
+
+/// `count` macros, each with a hotkey of a modifier and two of the letter and function keys, so
+/// that many of them share keys as in a large config.
+fn synthetic_macros(count: usize) -> Vec<Macro> {
+    let modifiers = [Key::LeftControl, Key::LeftMenu, Key::LeftShift];
+    let keys: Vec<Key> = (0x41..=0x5A)
+        .chain(0x70..=0x7B)
+        .map(|code| Key::from_raw(code).expect("letter and function keys are known"))
+        .collect();
+
+    (0..count)
+        .map(|index| {
+            let step = index / modifiers.len();
+            Macro::builder(&format!("synthetic {}", index))
+                .hotkey([
+                    modifiers[index % modifiers.len()],
+                    keys[step % keys.len()],
+                    keys[(step + 1 + step / keys.len()) % keys.len()],
+                ])
+                .wait_ms(1)
+                .build()
+                .expect("synthetic macros are valid")
+        })
+        .collect()
+}
+
+/// The listener's per-tick trigger scan over a large synthetic config, against the real
+/// keyboard, both the way it reads the keys now and the way it did before.
+pub struct TriggerScan {
+    macros: Vec<Macro>,
+    backend: Arc<dyn InputBackend>,
+    tracker: KeyStateTracker,
+    /// Whether each macro's hotkey was all down at the previous `per_macro_tick`.
+    previously_active: Vec<bool>,
+}
+
+impl TriggerScan {
+    pub fn new(macro_count: usize) -> Result<Self, anyhow::Error> {
+        let macros = synthetic_macros(macro_count);
+
+        Ok(TriggerScan {
+            tracker: KeyStateTracker::new(&macros, false),
+            previously_active: vec![false; macros.len()],
+            macros,
+            backend: backend::input_backend()?,
+        })
+    }
+
+    /// Reads every key of every hotkey, a key shared by several once for each, and matches each
+    /// macro against its own reads, as the listener did before it took a snapshot. Returns how
+    /// many macros fired.
+    pub fn per_macro_tick(&mut self) -> usize {
+        let mut fired = 0;
+
+        for (current_macro, was_active) in self.macros.iter().zip(&mut self.previously_active) {
+            let hotkey = &current_macro.macro_hotkey;
+            let held = hotkey
+                .iter()
+                .filter(|key| self.backend.is_key_held(**key))
+                .count();
+            let active = held == hotkey.len();
+
+            if active && !*was_active {
+                fired += 1;
+            }
+            *was_active = active;
+        }
+
+        fired
+    }
+
+    /// Reads the scan set into a snapshot once and matches every macro against it, as the
+    /// listener does now. Returns how many macros fired.
+    pub fn snapshot_tick(&mut self) -> usize {
+        self.tracker.poll(self.backend.as_ref());
+
+        self.macros
+            .iter()
+            .filter(|current_macro| self.tracker.triggered(current_macro))
+            .count()
+    }
+
+    /// How many keys a `per_macro_tick` and a `snapshot_tick` read.
+    pub fn key_reads(&self) -> (usize, usize) {
+        let hotkeys = self
+            .macros
+            .iter()
+            .map(|current_macro| &current_macro.macro_hotkey);
+        let per_macro = hotkeys.clone().map(|hotkey| hotkey.len()).sum();
+        let snapshot = hotkeys.flatten().collect::<HashSet<_>>().len();
+
+        (per_macro, snapshot)
+    }
+}
+
+/// A burst of triggers, one for each of as many tiny macros, started at once on an executor that
+/// runs each on a thread of its own or on a worker pool, on the simulated backend.
+pub struct TriggerBurst {
+    executor: Executor,
+}
+
+impl TriggerBurst {
+    pub fn new(triggers: usize, worker_threads: Option<usize>) -> Self {
+        TriggerBurst {
+            executor: Executor::new(
+                synthetic_macros(triggers),
+                triggers,
+                worker_threads,
+                Arc::default(),
+                Arc::new(SimulatedInput),
+                Arc::new(SimulatedScreen),
+                Arc::new(SystemClock),
+            ),
+        }
+    }
+
+    /// Triggers every macro once and waits for all of them to finish. Returns how many started.
+    pub fn run(&mut self) -> usize {
+        let started = (0..self.executor.macros().len())
+            .filter(|index| self.executor.start(*index, 0, TriggerSource::Cli).is_some())
+            .count();
+
+        while self.executor.running_count() > 0 || self.executor.queued_count() > 0 {
+            self.executor.reap();
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        started
+    }
+}
@@ -0,0 +1,798 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use super::{
+    default_max_combo_keys, duration::DurationMs, error::ErrorKind, expr::Coordinate, Command,
+    KeyState, LockKey, LockState, Macro, MacroConfig, MediaAction, MouseButton, ProcessState,
+    ReplaceMethod,
+};
+use crate::{repeat::KeyRepeat, screen::Color, Key};
+
+/// The window a window command acts on, by exact title, class and/or process, as in a config.
+#[derive(Debug, Clone, Default)]
+pub struct TargetWindow {
+    title: Option<String>,
+    class: Option<String>,
+    process: Option<String>,
+    current_desktop_only: bool,
+}
+
+impl TargetWindow {
+    pub fn title(title: impl Into<String>) -> Self {
+        TargetWindow::default().and_title(title)
+    }
+
+    pub fn class(class: impl Into<String>) -> Self {
+        TargetWindow::default().and_class(class)
+    }
+
+    pub fn process(process: impl Into<String>) -> Self {
+        TargetWindow::default().and_process(process)
+    }
+
+    pub fn and_title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    pub fn and_class(mut self, class: impl Into<String>) -> Self {
+        self.class = Some(class.into());
+        self
+    }
+
+    pub fn and_process(mut self, process: impl Into<String>) -> Self {
+        self.process = Some(process.into());
+        self
+    }
+
+    /// Only finds the window on the virtual desktop being shown. `switch_to_window_desktop`
+    /// ignores this, it is there to find windows on other desktops.
+    pub fn current_desktop_only(mut self) -> Self {
+        self.current_desktop_only = true;
+        self
+    }
+}
+
+/// The commands of a block nested in another command, such as the body of a loop.
+#[derive(Debug, Default)]
+pub struct CommandsBuilder {
+    commands: Vec<Command>,
+}
+
+impl CommandsBuilder {
+    pub fn build(self) -> Vec<Command> {
+        self.commands
+    }
+}
+
+/// Runs `body` on an empty block and returns the commands it added.
+fn block(body: impl FnOnce(CommandsBuilder) -> CommandsBuilder) -> Vec<Command> {
+    body(CommandsBuilder::default()).build()
+}
+
+/// Adds commands, one method per kind of command, to a macro or to a block nested in another
+/// command. Each method takes what the command takes in a config, durations in milliseconds;
+/// `command` adds any `Command` as is.
+pub trait BuildCommands: Sized {
+    fn command(self, command: Command) -> Self;
+
+    fn get_mouse_pos(self) -> Self {
+        self.command(Command::GetMousePos)
+    }
+
+    fn set_mouse_pos(self, x: impl Into<Coordinate>, y: impl Into<Coordinate>) -> Self {
+        self.command(Command::SetMousePos(x.into(), y.into()))
+    }
+
+    fn left_click(self) -> Self {
+        self.command(Command::LeftClick)
+    }
+
+    fn middle_click(self) -> Self {
+        self.command(Command::MiddleClick)
+    }
+
+    fn right_click(self) -> Self {
+        self.command(Command::RightClick)
+    }
+
+    fn scroll_lines(self, lines: i32) -> Self {
+        self.command(Command::ScrollLines(lines))
+    }
+
+    fn press(self, key: Key) -> Self {
+        self.command(Command::PressKey(key))
+    }
+
+    fn key_combo(self, keys: impl IntoIterator<Item = Key>) -> Self {
+        self.command(Command::PressKeyCombo(keys.into_iter().collect()))
+    }
+
+    fn text(self, text: impl Into<String>) -> Self {
+        self.command(Command::TextInput(text.into()))
+    }
+
+    fn wait_ms(self, ms: u64) -> Self {
+        self.command(Command::Wait(DurationMs(ms)))
+    }
+
+    /// Runs `body` `iterations` times, or forever for 0.
+    fn repeat(
+        self,
+        iterations: u32,
+        body: impl FnOnce(CommandsBuilder) -> CommandsBuilder,
+    ) -> Self {
+        self.command(Command::Loop(iterations, block(body)))
+    }
+
+    fn named_loop(
+        self,
+        name: impl Into<String>,
+        iterations: u32,
+        body: impl FnOnce(CommandsBuilder) -> CommandsBuilder,
+    ) -> Self {
+        self.command(Command::NamedLoop {
+            name: name.into(),
+            iterations,
+            commands: block(body),
+        })
+    }
+
+    /// Leaves the innermost loop, or the enclosing loop named `name`.
+    fn break_loop(self, name: Option<&str>) -> Self {
+        self.command(Command::Break(name.map(str::to_string)))
+    }
+
+    /// Skips to the next iteration of the innermost loop, or the enclosing loop named `name`.
+    fn continue_loop(self, name: Option<&str>) -> Self {
+        self.command(Command::Continue(name.map(str::to_string)))
+    }
+
+    fn send_key_to_window(self, window: TargetWindow, key: Key) -> Self {
+        self.command(Command::SendKeyToWindow {
+            title: window.title,
+            class: window.class,
+            process: window.process,
+            current_desktop_only: window.current_desktop_only,
+            key,
+        })
+    }
+
+    fn send_text_to_window(self, window: TargetWindow, text: impl Into<String>) -> Self {
+        self.command(Command::SendTextToWindow {
+            title: window.title,
+            class: window.class,
+            process: window.process,
+            current_desktop_only: window.current_desktop_only,
+            text: text.into(),
+        })
+    }
+
+    fn hold_key(self, key: Key, duration_ms: u64, repeat: Option<KeyRepeat>) -> Self {
+        self.command(Command::HoldKey {
+            key,
+            duration_ms: DurationMs(duration_ms),
+            repeat,
+        })
+    }
+
+    fn key_down(self, key: Key, repeat: Option<KeyRepeat>) -> Self {
+        self.command(Command::KeyDown { key, repeat })
+    }
+
+    fn key_up(self, key: Key) -> Self {
+        self.command(Command::KeyUp(key))
+    }
+
+    fn with_keys_held(
+        self,
+        keys: impl IntoIterator<Item = Key>,
+        body: impl FnOnce(CommandsBuilder) -> CommandsBuilder,
+    ) -> Self {
+        self.command(Command::WithKeysHeld {
+            keys: keys.into_iter().collect(),
+            commands: block(body),
+        })
+    }
+
+    /// Runs `body`, starting it over on failure up to `attempts` runs in total. An empty
+    /// `retry_on` retries every kind of failure.
+    fn retry(
+        self,
+        attempts: u32,
+        backoff_ms: u64,
+        multiplier: f64,
+        retry_on: impl IntoIterator<Item = ErrorKind>,
+        body: impl FnOnce(CommandsBuilder) -> CommandsBuilder,
+    ) -> Self {
+        self.command(Command::RetryBlock {
+            attempts,
+            backoff_ms,
+            multiplier,
+            retry_on: retry_on.into_iter().collect(),
+            commands: block(body),
+        })
+    }
+
+    fn call_macro<K: Into<String>, V: Into<String>>(
+        self,
+        name: impl Into<String>,
+        args: impl IntoIterator<Item = (K, V)>,
+    ) -> Self {
+        self.command(Command::CallMacro {
+            name: name.into(),
+            args: args
+                .into_iter()
+                .map(|(name, value)| (name.into(), value.into()))
+                .collect(),
+        })
+    }
+
+    fn if_key_held(
+        self,
+        key: Key,
+        then: impl FnOnce(CommandsBuilder) -> CommandsBuilder,
+        otherwise: impl FnOnce(CommandsBuilder) -> CommandsBuilder,
+    ) -> Self {
+        self.command(Command::IfKeyHeld {
+            key,
+            then: block(then),
+            r#else: block(otherwise),
+        })
+    }
+
+    fn replace_text(self, text: impl Into<String>, method: ReplaceMethod, settle_ms: u64) -> Self {
+        self.command(Command::ReplaceText {
+            text: text.into(),
+            method,
+            settle_ms: DurationMs(settle_ms),
+        })
+    }
+
+    fn get_window_title(self, into: impl Into<String>) -> Self {
+        self.command(Command::GetWindowTitle { into: into.into() })
+    }
+
+    fn if_var_matches(
+        self,
+        var: impl Into<String>,
+        regex: impl Into<String>,
+        then: impl FnOnce(CommandsBuilder) -> CommandsBuilder,
+        otherwise: impl FnOnce(CommandsBuilder) -> CommandsBuilder,
+    ) -> Self {
+        self.command(Command::IfVarMatches {
+            var: var.into(),
+            regex: regex.into(),
+            then: block(then),
+            r#else: block(otherwise),
+        })
+    }
+
+    /// Moves the mouse through `points`, each `(x, y, ms)` with `ms` the time since the start.
+    fn mouse_path(
+        self,
+        points: impl IntoIterator<Item = (i32, i32, u64)>,
+        button: Option<MouseButton>,
+    ) -> Self {
+        self.command(Command::MousePath {
+            points: points.into_iter().collect(),
+            button,
+        })
+    }
+
+    fn media(self, action: MediaAction) -> Self {
+        self.command(Command::Media(action))
+    }
+
+    fn wait_for_process(
+        self,
+        name: impl Into<String>,
+        state: ProcessState,
+        timeout_ms: u64,
+    ) -> Self {
+        self.command(Command::WaitForProcess {
+            name: name.into(),
+            state,
+            timeout_ms: DurationMs(timeout_ms),
+        })
+    }
+
+    fn wait_for_process_idle(
+        self,
+        process: impl Into<String>,
+        cpu_below_percent: f32,
+        sustained_ms: u64,
+        timeout_ms: u64,
+    ) -> Self {
+        self.command(Command::WaitForProcessIdle {
+            process: process.into(),
+            cpu_below_percent,
+            sustained_ms: DurationMs(sustained_ms),
+            timeout_ms: DurationMs(timeout_ms),
+        })
+    }
+
+    fn if_file_exists(
+        self,
+        path: impl Into<String>,
+        then: impl FnOnce(CommandsBuilder) -> CommandsBuilder,
+        otherwise: impl FnOnce(CommandsBuilder) -> CommandsBuilder,
+    ) -> Self {
+        self.command(Command::IfFileExists {
+            path: path.into(),
+            then: block(then),
+            r#else: block(otherwise),
+        })
+    }
+
+    fn wait_for_file(self, path: impl Into<String>, timeout_ms: u64) -> Self {
+        self.command(Command::WaitForFile {
+            path: path.into(),
+            timeout_ms: DurationMs(timeout_ms),
+        })
+    }
+
+    fn wait_for_pixel(
+        self,
+        x: impl Into<Coordinate>,
+        y: impl Into<Coordinate>,
+        color: Color,
+        tolerance: u8,
+        timeout_ms: u64,
+    ) -> Self {
+        self.command(Command::WaitForPixel {
+            x: x.into(),
+            y: y.into(),
+            color,
+            tolerance,
+            timeout_ms: DurationMs(timeout_ms),
+        })
+    }
+
+    fn wait_for_clipboard_change(self, timeout_ms: u64, into: Option<&str>) -> Self {
+        self.command(Command::WaitForClipboardChange {
+            timeout_ms: DurationMs(timeout_ms),
+            into: into.map(str::to_string),
+        })
+    }
+
+    fn pause(self, message: Option<&str>, resume_key: Option<Key>) -> Self {
+        self.command(Command::Pause {
+            message: message.map(str::to_string),
+            resume_key,
+        })
+    }
+
+    /// Launches `program` from the current directory with the runner's environment. Other
+    /// settings of `Run` are reached through `command`.
+    fn run_program(
+        self,
+        program: impl Into<String>,
+        args: impl IntoIterator<Item = impl Into<String>>,
+        capture_output: bool,
+    ) -> Self {
+        self.command(Command::Run {
+            program: program.into(),
+            args: args.into_iter().map(Into::into).collect(),
+            cwd: None,
+            env: HashMap::new(),
+            capture_output,
+            timeout_ms: None,
+        })
+    }
+
+    fn normalize_window(
+        self,
+        window: TargetWindow,
+        (x, y): (i32, i32),
+        (width, height): (i32, i32),
+        restore_after: bool,
+    ) -> Self {
+        self.command(Command::NormalizeWindow {
+            title: window.title,
+            class: window.class,
+            process: window.process,
+            current_desktop_only: window.current_desktop_only,
+            x,
+            y,
+            width,
+            height,
+            restore_after,
+        })
+    }
+
+    fn store_window_origin(self, window: TargetWindow) -> Self {
+        self.command(Command::StoreWindowOrigin {
+            title: window.title,
+            class: window.class,
+            process: window.process,
+            current_desktop_only: window.current_desktop_only,
+        })
+    }
+
+    fn switch_to_window_desktop(self, window: TargetWindow) -> Self {
+        self.command(Command::SwitchToWindowDesktop {
+            title: window.title,
+            class: window.class,
+            process: window.process,
+        })
+    }
+
+    /// Types the secret held in the environment variable `variable`.
+    fn secret_from_env(self, variable: impl Into<String>) -> Self {
+        self.command(Command::TextInputSecret {
+            from_env: Some(variable.into()),
+            text: None,
+        })
+    }
+
+    /// Types `text`, in which `${cred:<target>}` stands for a stored credential.
+    fn secret_text(self, text: impl Into<String>) -> Self {
+        self.command(Command::TextInputSecret {
+            from_env: None,
+            text: Some(text.into()),
+        })
+    }
+
+    fn text_input_credential(self, target: impl Into<String>) -> Self {
+        self.command(Command::TextInputCredential {
+            target: target.into(),
+        })
+    }
+
+    fn set_lock_key(self, key: LockKey, state: LockState) -> Self {
+        self.command(Command::SetLockKey { key, state })
+    }
+
+    fn assert_key_state(self, key: Key, state: KeyState, fix: bool) -> Self {
+        self.command(Command::AssertKeyState { key, state, fix })
+    }
+
+    fn assert_not_blocked(self) -> Self {
+        self.command(Command::AssertNotBlocked)
+    }
+
+    fn breakpoint(self) -> Self {
+        self.command(Command::Breakpoint)
+    }
+}
+
+impl BuildCommands for CommandsBuilder {
+    fn command(mut self, command: Command) -> Self {
+        self.commands.push(command);
+        self
+    }
+}
+
+/// Builds a `Macro` in code, see `Macro::builder`. Settings are checked by `build`, as they would
+/// be when loading a config.
+#[derive(Debug)]
+pub struct MacroBuilder {
+    macro_name: String,
+    settings: serde_yaml::Mapping,
+    commands: Vec<Command>,
+    /// The first setting that could not be turned into config, reported by `build`.
+    error: Option<anyhow::Error>,
+}
+
+impl MacroBuilder {
+    /// Sets `name` as it would be written in a config, for settings without a method of their
+    /// own, e.g. `.setting("trigger_on", "release")`.
+    pub fn setting(mut self, name: &str, value: impl Serialize) -> Self {
+        match serde_yaml::to_value(value) {
+            Ok(value) => {
+                self.settings.insert(name.into(), value);
+            }
+            Err(e) => {
+                self.error.get_or_insert_with(|| {
+                    anyhow::anyhow!("{}: invalid {}: {}", self.macro_name, name, e)
+                });
+            }
+        }
+        self
+    }
+
+    pub fn hotkey(self, keys: impl IntoIterator<Item = Key>) -> Self {
+        self.setting("macro_hotkey", keys.into_iter().collect::<Vec<_>>())
+    }
+
+    pub fn disabled(self) -> Self {
+        self.setting("enabled", false)
+    }
+
+    pub fn profile(self, profile: &str) -> Self {
+        self.setting("profile", profile)
+    }
+
+    pub fn cooldown_ms(self, ms: u64) -> Self {
+        self.setting("cooldown_ms", ms)
+    }
+
+    pub fn jitter_ms(self, ms: u64) -> Self {
+        self.setting("jitter_ms", ms)
+    }
+
+    pub fn jitter_px(self, px: i32) -> Self {
+        self.setting("jitter_px", px)
+    }
+
+    pub fn priority(self, priority: u8) -> Self {
+        self.setting("priority", priority)
+    }
+
+    pub fn mutex(self, mutex: &str) -> Self {
+        self.setting("mutex", mutex)
+    }
+
+    pub fn on_success(self, macro_name: &str) -> Self {
+        self.setting("on_success", macro_name)
+    }
+
+    pub fn on_failure(self, macro_name: &str) -> Self {
+        self.setting("on_failure", macro_name)
+    }
+
+    /// Checks the macro as loading it from a config would, with the default `max_combo_keys`.
+    /// What depends on other macros, such as `CallMacro` targets, is checked by
+    /// `MacroConfigBuilder::build`.
+    pub fn build(self) -> Result<Macro, anyhow::Error> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+
+        let mut settings = self.settings;
+        settings.insert("macro_name".into(), self.macro_name.as_str().into());
+        settings.insert("commands".into(), serde_yaml::Value::Sequence(Vec::new()));
+        let mut current_macro: Macro = serde_yaml::from_value(serde_yaml::Value::Mapping(settings))
+            .map_err(|e| anyhow::anyhow!("{}: {}", self.macro_name, e))?;
+        current_macro.commands = self.commands;
+        current_macro.validate(default_max_combo_keys())?;
+
+        Ok(current_macro)
+    }
+}
+
+impl BuildCommands for MacroBuilder {
+    fn command(mut self, command: Command) -> Self {
+        self.commands.push(command);
+        self
+    }
+}
+
+impl Macro {
+    /// Starts building a macro named `macro_name`, to be run with `MacroRunner` or written out as
+    /// config.
+    pub fn builder(macro_name: &str) -> MacroBuilder {
+        MacroBuilder {
+            macro_name: macro_name.to_string(),
+            settings: serde_yaml::Mapping::new(),
+            commands: Vec::new(),
+            error: None,
+        }
+    }
+}
+
+/// Builds a `MacroConfig` in code, see `MacroConfig::builder`.
+#[derive(Debug, Default)]
+pub struct MacroConfigBuilder {
+    settings: serde_yaml::Mapping,
+    macros: Vec<Macro>,
+    error: Option<anyhow::Error>,
+}
+
+impl MacroConfigBuilder {
+    /// Sets `name` as it would be written in a config, for settings without a method of their
+    /// own, e.g. `.setting("max_events_per_second", 200)`.
+    pub fn setting(mut self, name: &str, value: impl Serialize) -> Self {
+        match serde_yaml::to_value(value) {
+            Ok(value) => {
+                self.settings.insert(name.into(), value);
+            }
+            Err(e) => {
+                self.error
+                    .get_or_insert_with(|| anyhow::anyhow!("Invalid {}: {}", name, e));
+            }
+        }
+        self
+    }
+
+    /// The keys that, held together, stop every macro and the runner.
+    pub fn program_hotkey(self, keys: impl IntoIterator<Item = Key>) -> Self {
+        self.setting("program_hotkey", keys.into_iter().collect::<Vec<_>>())
+    }
+
+    pub fn max_macro_threads(self, threads: usize) -> Self {
+        self.setting("max_macro_threads", threads)
+    }
+
+    pub fn seed(self, seed: u64) -> Self {
+        self.setting("seed", seed)
+    }
+
+    pub fn add_macro(mut self, current_macro: Macro) -> Self {
+        self.macros.push(current_macro);
+        self
+    }
+
+    /// Checks the config as loading it from a file would.
+    pub fn build(self) -> Result<MacroConfig, anyhow::Error> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+
+        let mut settings = self.settings;
+        settings.insert("macros".into(), serde_yaml::Value::Sequence(Vec::new()));
+        let mut macro_config: MacroConfig =
+            serde_yaml::from_value(serde_yaml::Value::Mapping(settings))?;
+        macro_config.macros = self.macros;
+        macro_config.validate()?;
+
+        Ok(macro_config)
+    }
+}
+
+impl MacroConfig {
+    /// Starts building a config in code. It needs at least a `program_hotkey`.
+    pub fn builder() -> MacroConfigBuilder {
+        MacroConfigBuilder::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::*;
+    use crate::{
+        backend::{SimulatedInput, SimulatedScreen},
+        clock::VirtualClock,
+        diff_run,
+        events::{EventBus, TriggerSource},
+        executor::Executor,
+    };
+
+    fn login() -> MacroBuilder {
+        Macro::builder("login")
+            .hotkey([Key::LeftControl, Key::LeftMenu, Key::L])
+            .press(Key::Tab)
+            .text("me")
+            .wait_ms(250)
+            .key_combo([Key::LeftControl, Key::Return])
+    }
+
+    #[test]
+    fn builds_the_macro_a_config_would_load() {
+        let built = login().build().unwrap();
+        let loaded: Macro = serde_yaml::from_str(
+            "{macro_name: login, macro_hotkey: [LeftControl, LeftMenu, L], commands: [!PressKey \
+             Tab, !TextInput me, !Wait 250, !PressKeyCombo [LeftControl, Return]]}",
+        )
+        .unwrap();
+
+        assert_eq!(
+            serde_yaml::to_string(&built).unwrap(),
+            serde_yaml::to_string(&loaded).unwrap()
+        );
+    }
+
+    #[test]
+    fn nested_blocks_build_in_place() {
+        let built = Macro::builder("nested")
+            .hotkey([Key::LeftControl, Key::F3])
+            .repeat(2, |body| {
+                body.press(Key::A).if_key_held(
+                    Key::LeftShift,
+                    |then| then.press(Key::B),
+                    |otherwise| otherwise,
+                )
+            })
+            .build()
+            .unwrap();
+
+        match built.commands.as_slice() {
+            [Command::Loop(2, body)] => match body.as_slice() {
+                [Command::PressKey(Key::A), Command::IfKeyHeld {
+                    key: Key::LeftShift,
+                    then,
+                    r#else,
+                }] => {
+                    assert!(matches!(then.as_slice(), [Command::PressKey(Key::B)]));
+                    assert!(r#else.is_empty());
+                }
+                other => panic!("unexpected loop body {:?}", other),
+            },
+            other => panic!("unexpected commands {:?}", other),
+        }
+    }
+
+    #[test]
+    fn build_rejects_what_loading_would() {
+        let no_hotkey = Macro::builder("no_hotkey").press(Key::A).build();
+        assert!(no_hotkey.unwrap_err().to_string().contains("no_hotkey"));
+
+        let stray_break = Macro::builder("stray")
+            .hotkey([Key::LeftControl, Key::F3])
+            .break_loop(None)
+            .build();
+        assert!(stray_break.is_err());
+
+        let bad_setting = Macro::builder("bad")
+            .hotkey([Key::LeftControl, Key::F3])
+            .setting("trigger_on", "sideways")
+            .build();
+        assert!(bad_setting.unwrap_err().to_string().starts_with("bad: "));
+
+        let missing_call = MacroConfig::builder()
+            .program_hotkey([Key::LeftShift, Key::F6])
+            .add_macro(
+                Macro::builder("caller")
+                    .hotkey([Key::LeftControl, Key::F3])
+                    .call_macro("missing", [("name", "me")])
+                    .build()
+                    .unwrap(),
+            )
+            .build();
+        assert!(missing_call
+            .unwrap_err()
+            .to_string()
+            .contains("called macro missing does not exist"));
+
+        let no_program_hotkey = MacroConfig::builder()
+            .add_macro(login().build().unwrap())
+            .build();
+        assert!(no_program_hotkey.is_err());
+    }
+
+    #[test]
+    fn built_config_runs_on_the_simulated_backend() {
+        let macro_config = MacroConfig::builder()
+            .program_hotkey([Key::LeftShift, Key::F6])
+            .add_macro(
+                Macro::builder("submit")
+                    .hotkey([Key::LeftControl, Key::F3])
+                    .press(Key::Tab)
+                    .wait_ms(250)
+                    .key_combo([Key::LeftControl, Key::Return])
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        let index = macro_config.macro_index("submit").unwrap();
+
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60)));
+        let mut executor = Executor::new(
+            macro_config.macros,
+            macro_config.max_macro_threads,
+            macro_config.worker_threads,
+            Arc::new(EventBus::default()),
+            Arc::new(SimulatedInput),
+            Arc::new(SimulatedScreen),
+            clock.clone(),
+        );
+        let (succeeded, recorded) = diff_run::record_for_test(clock.clone(), || {
+            executor.run_inline(index, TriggerSource::Cli)
+        });
+
+        assert_eq!(succeeded, Some(true));
+        // Execution events are recorded alongside the input
+        let inputs: Vec<&str> = recorded
+            .iter()
+            .map(String::as_str)
+            .filter(|event| event.starts_with("key_"))
+            .collect();
+        assert_eq!(
+            inputs,
+            [
+                "key_down Tab",
+                "key_up Tab",
+                "key_down LeftControl",
+                "key_down Return",
+                "key_up Return",
+                "key_up LeftControl",
+            ]
+        );
+        assert!(clock.elapsed_total() >= Duration::from_millis(250));
+    }
+}
@@ -0,0 +1,247 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    thread::sleep,
+    time::Duration,
+};
+
+use serde_yaml::{
+    value::{Tag, TaggedValue},
+    Mapping, Value,
+};
+
+use super::{
+    backup::{self, BackupConfig},
+    get_cursor_pos, key_held, set_cursor_pos, window, Key,
+};
+
+const CALIBRATE_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Keys that drive calibration, watched for presses.
+const CALIBRATE_KEYS: [Key; 6] = [
+    Key::Left,
+    Key::Right,
+    Key::Up,
+    Key::Down,
+    Key::Return,
+    Key::Escape,
+];
+
+/// Where the calibrated copy of `path` is written, e.g. `macro_config.calibrated.yaml`.
+fn calibrated_path(path: &Path) -> PathBuf {
+    path.with_extension("calibrated.yaml")
+}
+
+/// Moves the cursor to `(x, y)` and lets the user correct it, with the arrow keys (10 px at a
+/// time with Shift held) or by moving the mouse. Returns the accepted point, or `None` if the
+/// target was skipped with Escape.
+fn adjust_point(label: &str, x: i32, y: i32) -> Result<Option<(i32, i32)>, anyhow::Error> {
+    println!(
+        "{} at ({}, {}): arrows to nudge, Shift for 10 px, Enter to accept, Escape to keep",
+        label, x, y
+    );
+    set_cursor_pos(x, y)?;
+
+    // Keys still held from the previous target must be released before they count again
+    let mut previously_held: HashSet<Key> = CALIBRATE_KEYS
+        .into_iter()
+        .filter(|key| key_held(key.virtual_key()))
+        .collect();
+
+    loop {
+        sleep(CALIBRATE_POLL_INTERVAL);
+
+        let held: HashSet<Key> = CALIBRATE_KEYS
+            .into_iter()
+            .filter(|key| key_held(key.virtual_key()))
+            .collect();
+        let pressed: Vec<Key> = held.difference(&previously_held).copied().collect();
+        previously_held = held;
+
+        let step = if key_held(Key::Shift.virtual_key()) {
+            10
+        } else {
+            1
+        };
+
+        for key in pressed {
+            let (dx, dy) = match key {
+                Key::Left => (-step, 0),
+                Key::Right => (step, 0),
+                Key::Up => (0, -step),
+                Key::Down => (0, step),
+                Key::Return => {
+                    let point = get_cursor_pos()?;
+                    return Ok(Some((point.x, point.y)));
+                }
+                _ => return Ok(None),
+            };
+
+            let point = get_cursor_pos()?;
+            set_cursor_pos(point.x + dx, point.y + dy)?;
+        }
+    }
+}
+
+/// Calls `visit` with the x and y values of every mouse target under `value`, and whether the
+/// target accepts expressions (`SetMousePos` does, `MousePath` points do not).
+fn visit_targets(
+    value: &mut Value,
+    visit: &mut dyn FnMut(&mut Value, &mut Value, bool) -> Result<(), anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    match value {
+        Value::Tagged(tagged) => {
+            if tagged.tag == "SetMousePos" {
+                if let Some([x, y]) = tagged
+                    .value
+                    .as_sequence_mut()
+                    .map(|point| point.as_mut_slice())
+                {
+                    visit(x, y, true)?;
+                }
+            } else if tagged.tag == "MousePath" {
+                let points = tagged
+                    .value
+                    .get_mut("points")
+                    .and_then(Value::as_sequence_mut);
+                for point in points.into_iter().flatten() {
+                    if let Some([x, y, _]) =
+                        point.as_sequence_mut().map(|point| point.as_mut_slice())
+                    {
+                        visit(x, y, false)?;
+                    }
+                }
+            } else {
+                visit_targets(&mut tagged.value, visit)?;
+            }
+        }
+        Value::Sequence(sequence) => {
+            for item in sequence.iter_mut() {
+                visit_targets(item, visit)?;
+            }
+        }
+        Value::Mapping(mapping) => {
+            for (_, item) in mapping.iter_mut() {
+                visit_targets(item, visit)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// The `StoreWindowOrigin` command that anchored coordinates rely on.
+fn store_window_origin(title: &str) -> Value {
+    let mut mapping = Mapping::new();
+    mapping.insert("title".into(), title.into());
+
+    Value::Tagged(Box::new(TaggedValue {
+        tag: Tag::new("StoreWindowOrigin"),
+        value: Value::Mapping(mapping),
+    }))
+}
+
+/// Writes `value` as an offset expression from `origin_variable`, e.g. `${window_x} + 120`.
+fn offset_expression(origin_variable: &str, value: i32, origin: i32) -> Value {
+    let offset = value - origin;
+
+    if offset < 0 {
+        format!("${{{}}} - {}", origin_variable, -offset).into()
+    } else {
+        format!("${{{}}} + {}", origin_variable, offset).into()
+    }
+}
+
+/// Walks every literal mouse target of the config at `path` and writes the corrected config next
+/// to it. With `anchor`, `SetMousePos` targets are written as offsets from the top-left corner of
+/// the window with that title, found now and again by each macro when it runs.
+///
+/// Comments and formatting of the original file are not kept, so the result goes to a separate
+/// file to be compared and copied over by hand.
+pub fn calibrate(path: &Path, anchor: Option<&str>) -> Result<(), anyhow::Error> {
+    let config_string = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read config {}: {}", path.display(), e))?;
+    let mut config_value: Value = serde_yaml::from_str(&config_string)
+        .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+
+    let origin = match anchor {
+        Some(title) => Some(window::window_origin(window::find_window(
+            &window::WindowSelector::title(title),
+        )?)?),
+        None => None,
+    };
+
+    let macros = config_value
+        .get_mut("macros")
+        .and_then(Value::as_sequence_mut);
+    let mut target_count = 0;
+
+    for macro_value in macros.into_iter().flatten() {
+        let macro_name = macro_value
+            .get("macro_name")
+            .and_then(Value::as_str)
+            .unwrap_or("<unnamed>")
+            .to_string();
+        let commands = match macro_value.get_mut("commands") {
+            Some(commands) => commands,
+            None => continue,
+        };
+
+        let mut anchored = false;
+        visit_targets(commands, &mut |x_value, y_value, accepts_expressions| {
+            let (x, y) = match (x_value.as_i64(), y_value.as_i64()) {
+                (Some(x), Some(y)) => (x as i32, y as i32),
+                _ => {
+                    println!("{}: skipping a target given as an expression", macro_name);
+                    return Ok(());
+                }
+            };
+
+            target_count += 1;
+            let label = format!("{} target {}", macro_name, target_count);
+            let (x, y) = adjust_point(&label, x, y)?.unwrap_or((x, y));
+
+            match origin {
+                Some((origin_x, origin_y)) if accepts_expressions => {
+                    *x_value = offset_expression("window_x", x, origin_x);
+                    *y_value = offset_expression("window_y", y, origin_y);
+                    anchored = true;
+                }
+                _ => {
+                    *x_value = x.into();
+                    *y_value = y.into();
+                }
+            }
+
+            Ok(())
+        })?;
+
+        if let (true, Some(title), Some(commands)) = (anchored, anchor, commands.as_sequence_mut())
+        {
+            let origin_command = store_window_origin(title);
+            if commands.first() != Some(&origin_command) {
+                commands.insert(0, origin_command);
+            }
+        }
+    }
+
+    if target_count == 0 {
+        println!("{} has no literal mouse targets", path.display());
+        return Ok(());
+    }
+
+    let output_path = calibrated_path(path);
+    backup::write_config(
+        &output_path,
+        &serde_yaml::to_string(&config_value)?,
+        &BackupConfig::from_config_value(&config_value),
+    )?;
+    println!(
+        "Wrote {} calibrated target(s) to {}",
+        target_count,
+        output_path.display()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,166 @@
+use std::{cell::RefCell, collections::HashSet};
+
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::WindowsAndMessaging::{
+    CallNextHookEx, DispatchMessageW, GetForegroundWindow, GetMessageW, PostQuitMessage,
+    SetWindowsHookExW, TranslateMessage, UnhookWindowsHookEx, HHOOK, KBDLLHOOKSTRUCT,
+    LLKHF_EXTENDED, LLKHF_INJECTED, LLKHF_UP, MSG, WH_KEYBOARD_LL,
+};
+
+use super::{duration::DurationMs, keys::format_keys, window, Key};
+
+/// What `capture_keys` has seen so far. The hook runs on the thread that installed it, so the
+/// state lives there.
+#[derive(Default)]
+struct Capture {
+    combo: bool,
+    held: HashSet<Key>,
+    pressed: HashSet<Key>,
+}
+
+thread_local! {
+    static CAPTURE: RefCell<Capture> = RefCell::new(Capture::default());
+}
+
+/// The key a hook event is for. Windows reports the numpad Enter as `Return` with the extended
+/// flag, which is `NumpadEnter` to us.
+fn event_key(event: &KBDLLHOOKSTRUCT) -> Key {
+    let key = Key::from(event.vkCode as i32);
+    if key == Key::Return && event.flags.0 & LLKHF_EXTENDED.0 != 0 {
+        Key::NumpadEnter
+    } else {
+        key
+    }
+}
+
+/// Handles one key event, returning whether capturing is done.
+fn handle_event(capture: &mut Capture, event: &KBDLLHOOKSTRUCT) -> bool {
+    let key = event_key(event);
+    let up = event.flags.0 & LLKHF_UP.0 != 0;
+
+    if !capture.combo {
+        println!(
+            "{:<4}\tVK 0x{:02X}\t{}\tscan 0x{:02X}{}{}",
+            if up { "up" } else { "down" },
+            event.vkCode,
+            if key.is_raw() {
+                format!("unmapped VK 0x{:02X}, write {}", event.vkCode, key.name())
+            } else {
+                key.name()
+            },
+            event.scanCode,
+            if event.flags.0 & LLKHF_EXTENDED.0 != 0 {
+                "\textended"
+            } else {
+                ""
+            },
+            if event.flags.0 & LLKHF_INJECTED.0 != 0 {
+                "\tinjected"
+            } else {
+                ""
+            },
+        );
+        return key == Key::Escape && !up;
+    }
+
+    if up {
+        capture.held.remove(&key);
+        if capture.held.is_empty() && !capture.pressed.is_empty() {
+            let keys = std::mem::take(&mut capture.pressed);
+            println!("macro_hotkey: {}", format_keys(&keys));
+            return true;
+        }
+    } else {
+        // Escape on its own cancels, as part of a combination it is captured like any other key
+        if key == Key::Escape && capture.held.is_empty() {
+            println!("Cancelled");
+            return true;
+        }
+        capture.held.insert(key);
+        capture.pressed.insert(key);
+    }
+
+    false
+}
+
+unsafe extern "system" fn capture_hook(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    if code >= 0 {
+        let event = &*(lparam.0 as *const KBDLLHOOKSTRUCT);
+        if CAPTURE.with(|capture| handle_event(&mut capture.borrow_mut(), event)) {
+            PostQuitMessage(0);
+        }
+    }
+
+    CallNextHookEx(HHOOK::default(), code, wparam, lparam)
+}
+
+/// Prints every key event, with its virtual-key code, the name configs use for it, its scan code
+/// and flags, until Escape is pressed. With `combo`, instead waits for one combination to be
+/// pressed and released and prints it as a `macro_hotkey`. Keys still reach other programs.
+pub fn capture_keys(combo: bool) -> Result<(), anyhow::Error> {
+    CAPTURE.with(|capture| {
+        *capture.borrow_mut() = Capture {
+            combo,
+            ..Default::default()
+        }
+    });
+
+    if combo {
+        println!("Press and release the combination, or Escape to cancel");
+    } else {
+        println!("Press keys to see their names, Escape to stop");
+    }
+
+    let module = unsafe { GetModuleHandleW(PCWSTR::null()) }?;
+    let hook = unsafe { SetWindowsHookExW(WH_KEYBOARD_LL, Some(capture_hook), module, 0) }
+        .map_err(|e| anyhow::anyhow!("Failed to install the keyboard hook: {}", e))?;
+
+    // Low-level hooks are called from this thread's message loop
+    let mut message = MSG::default();
+    while unsafe { GetMessageW(&mut message, HWND::default(), 0, 0) }.as_bool() {
+        unsafe {
+            TranslateMessage(&message);
+            DispatchMessageW(&message);
+        }
+    }
+
+    unsafe { UnhookWindowsHookEx(hook) };
+
+    Ok(())
+}
+
+/// Waits `delay` for the user to switch to a window, then prints the title, class, process and
+/// rectangle of the window in the foreground, and a selector for it to paste into window
+/// commands or `allowed_targets`.
+pub fn capture_window(delay: DurationMs) -> Result<(), anyhow::Error> {
+    println!("Switch to the window, capturing it in {}", delay);
+    std::thread::sleep(delay.as_duration());
+
+    let hwnd = unsafe { GetForegroundWindow() };
+    if hwnd.0 == 0 {
+        return Err(anyhow::anyhow!("No window is in the foreground"));
+    }
+
+    let info = window::window_info(hwnd)?;
+    let rect = window::window_rect(hwnd)?;
+
+    println!("title:   {:?}", info.title);
+    println!("class:   {:?}", info.class);
+    println!("process: {}", info.process);
+    println!(
+        "rect:    x {} y {} width {} height {}",
+        rect.left,
+        rect.top,
+        rect.right - rect.left,
+        rect.bottom - rect.top
+    );
+    // Titles often name the open document, the class and process stay put
+    println!(
+        "selector: {{ class: {:?}, process: {:?} }}",
+        info.class, info.process
+    );
+
+    Ok(())
+}
@@ -0,0 +1,559 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use serde::{de::IntoDeserializer, Deserialize};
+
+use super::{
+    backend::BackendKind, context::DebugMode, duration::DurationMs, keys::deserialize_keys, Key,
+};
+
+/// Hotkey the generated auto-clicker repeats on while held, unless `--hold-hotkey` says otherwise.
+const DEFAULT_CLICK_HOTKEY: &str = "F8";
+/// Hotkey that stops the auto-clicker, unless `--exit-hotkey` says otherwise.
+const DEFAULT_CLICK_EXIT_HOTKEY: &str = "LeftControl+LeftShift+F6";
+const DEFAULT_CLICK_INTERVAL: DurationMs = DurationMs(100);
+/// Time `capture-window` gives to switch to the window, unless `--delay` says otherwise.
+const DEFAULT_CAPTURE_WINDOW_DELAY: DurationMs = DurationMs(3000);
+
+/// Mouse button clicked by the `click` subcommand.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ClickButton {
+    Left,
+    Right,
+    Middle,
+}
+
+/// What the program was asked to do on the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Subcommand {
+    /// Listen for hotkeys and run macros (the default).
+    Run,
+    /// Print the configured macros and exit. With `stats`, add each macro's runs, failures,
+    /// average duration and last run from the history. With `watch`, instead show the macros a
+    /// live runner is running, refreshed every second until interrupted.
+    List {
+        timing: bool,
+        stats: bool,
+        watch: bool,
+    },
+    /// Run a single macro, passing it `--arg name=value` arguments, and exit once it finishes.
+    /// `--step` stops before every command and `--debug` after every `Breakpoint`.
+    RunMacro {
+        name: String,
+        args: Vec<(String, String)>,
+        debug_mode: DebugMode,
+    },
+    /// Run commands written in the shorthand of a macro's `commands`, e.g.
+    /// `"move(500, 500); click()"`, as a macro of their own and exit once it finishes.
+    RunInline { commands: String },
+    /// Run `macros` one after another, `repeat` times over, without listening for hotkeys, print
+    /// how each went and exit with the number that failed. `config` replaces `--config`.
+    RunBatch {
+        config: Option<PathBuf>,
+        macros: Vec<String>,
+        stop_on_failure: bool,
+        repeat: u32,
+    },
+    /// Ask a running runner, through its HTTP endpoint, to act as if `hotkey` had been pressed,
+    /// and print the macros that matched it.
+    Press { hotkey: HashSet<Key> },
+    /// Load and validate the config, reporting every problem, and exit.
+    Validate,
+    /// Print likely mistakes in the config that `validate` lets through, each with its rule and
+    /// a suggested fix where there is one, and exit. With `fix`, apply the fixes to the config.
+    Lint { fix: bool },
+    /// Run the macro `name` against a simulated keyboard, screen and clock and print how what it
+    /// did differs from its recorded baseline, failing if it does. With `update_baseline`, record
+    /// the run as the new baseline instead. Timing differences up to `tolerance` are ignored.
+    DiffRun {
+        name: String,
+        update_baseline: bool,
+        tolerance: DurationMs,
+    },
+    /// Print the config as it was loaded, with every default filled in, and exit.
+    ShowConfig,
+    /// Print a JSON description of the config format and every command, for editor tooling, and
+    /// exit.
+    Schema,
+    /// Print the recorded execution history, optionally only the last `last` executions of one
+    /// macro, and exit.
+    History {
+        last: Option<usize>,
+        macro_name: Option<String>,
+    },
+    /// Step through the config's mouse targets, correcting each one by hand, and write the result
+    /// next to the config. With `anchor`, targets become offsets from that window's corner.
+    Calibrate { anchor: Option<String> },
+    /// Put the backup `name`, or else the newest backup, of the config back in its place, or with
+    /// `list`, print the backups there are.
+    RestoreBackup { list: bool, name: Option<String> },
+    /// Check that this machine allows input injection and hooks, then exit.
+    Doctor,
+    /// Print every key pressed with its name, codes and flags until Escape is pressed. With
+    /// `combo`, wait for one combination and print it as a `macro_hotkey`.
+    CaptureKey { combo: bool },
+    /// Print the title, class, process and rectangle of the window in the foreground once `delay`
+    /// has passed, with a selector for window commands and `allowed_targets`.
+    CaptureWindow { delay: DurationMs },
+    /// Prompt, without echo, for the password of the generic Windows Credential Manager entry
+    /// `target` and store it there, for `${cred:<target>}`.
+    CredSet { target: String },
+    /// Show the connected game controllers and the buttons held on each until interrupted.
+    ListGamepads,
+    /// Print the version and the git commit it was built from, and exit.
+    Version,
+    /// Ask the update URL, `url` or the config's `update_url`, whether a newer release exists.
+    /// Nothing is downloaded.
+    CheckUpdate { url: Option<String> },
+    /// Run a generated auto-clicker instead of the config: click `button` every `interval` while
+    /// `hold_hotkey` is held, until `exit_hotkey` is pressed.
+    Click {
+        interval: DurationMs,
+        button: ClickButton,
+        hold_hotkey: HashSet<Key>,
+        exit_hotkey: HashSet<Key>,
+        jitter_ms: u64,
+        jitter_px: i32,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Cli {
+    pub subcommand: Subcommand,
+    /// Config file to load instead of the default.
+    pub config: Option<PathBuf>,
+    /// Relaunch through UAC when the config declares `needs_elevation: true`.
+    pub request_elevation: bool,
+    /// Write every execution event to stdout as newline-delimited JSON, and log to stderr.
+    pub events_stdout: bool,
+    /// Log, at most once a second per macro, why each macro whose hotkey is touched does or
+    /// does not fire.
+    pub trace_triggers: bool,
+    /// Load the config despite macro name or hotkey collisions across files.
+    pub force: bool,
+    /// Seed for the randomness of every macro, to repeat a run whose seed was logged.
+    pub seed: Option<u64>,
+    /// `--set path=value` overrides of config values, in the order given.
+    pub overrides: Vec<(String, String)>,
+    /// `--backend windows|uinput`, the input backend to use instead of the detected one.
+    pub backend: Option<BackendKind>,
+}
+
+impl Cli {
+    pub fn parse_from(args: impl IntoIterator<Item = String>) -> Result<Self, anyhow::Error> {
+        let mut args: Vec<String> = args.into_iter().collect();
+
+        // Global options may appear anywhere, so pull them out before looking at the subcommand
+        let request_elevation = take_flag(&mut args, "--request-elevation");
+        let events_stdout = take_flag(&mut args, "--events-stdout");
+        let trace_triggers = take_flag(&mut args, "--trace-triggers");
+        let force = take_flag(&mut args, "--force");
+        let version = take_flag(&mut args, "--version");
+        let config = take_option(&mut args, "--config")?.map(PathBuf::from);
+        let seed = take_option(&mut args, "--seed")?
+            .map(|seed| {
+                seed.parse()
+                    .map_err(|_| anyhow::anyhow!("--seed expects a number, got {}", seed))
+            })
+            .transpose()?;
+        let backend = take_option(&mut args, "--backend")?
+            .map(|backend| backend.parse())
+            .transpose()?;
+        let mut overrides = Vec::new();
+        while let Some(set) = take_option(&mut args, "--set")? {
+            let (path, value) = set
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("--set expects path=value, got {}", set))?;
+            overrides.push((path.to_string(), value.to_string()));
+        }
+
+        let mut args = args.into_iter();
+
+        let subcommand = match args.next().as_deref() {
+            _ if version => Subcommand::Version,
+            None | Some("run") => Subcommand::Run,
+            Some("list") => {
+                let mut timing = false;
+                let mut stats = false;
+                let mut watch = false;
+                for arg in args.by_ref() {
+                    match arg.as_str() {
+                        "--timing" => timing = true,
+                        "--stats" => stats = true,
+                        "--watch" => watch = true,
+                        other => return Err(anyhow::anyhow!("Unknown list option: {}", other)),
+                    }
+                }
+                Subcommand::List {
+                    timing,
+                    stats,
+                    watch,
+                }
+            }
+            Some("run-macro") => {
+                let name = args
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("run-macro requires a macro name"))?;
+
+                let mut macro_args = Vec::new();
+                let mut debug_mode = DebugMode::Off;
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--step" => debug_mode = DebugMode::Step,
+                        "--debug" if debug_mode == DebugMode::Off => {
+                            debug_mode = DebugMode::Breakpoints
+                        }
+                        "--debug" => {}
+                        "--arg" => {
+                            let value = args
+                                .next()
+                                .ok_or_else(|| anyhow::anyhow!("--arg requires name=value"))?;
+                            let (name, value) = value.split_once('=').ok_or_else(|| {
+                                anyhow::anyhow!("--arg expects name=value, got {}", value)
+                            })?;
+                            macro_args.push((name.to_string(), value.to_string()));
+                        }
+                        other => {
+                            return Err(anyhow::anyhow!("Unknown run-macro option: {}", other))
+                        }
+                    }
+                }
+
+                Subcommand::RunMacro {
+                    name,
+                    args: macro_args,
+                    debug_mode,
+                }
+            }
+            Some("run-inline") => {
+                let commands = args
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("run-inline requires commands"))?;
+                if let Some(other) = args.next() {
+                    return Err(anyhow::anyhow!("Unknown run-inline option: {}", other));
+                }
+
+                Subcommand::RunInline { commands }
+            }
+            Some("run-batch") => {
+                let mut config = None;
+                let mut macros = Vec::new();
+                let mut stop_on_failure = false;
+                let mut repeat = 1;
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--macros" => {
+                            let value = args.next().ok_or_else(|| {
+                                anyhow::anyhow!("--macros requires a comma separated list")
+                            })?;
+                            macros.extend(
+                                value
+                                    .split(',')
+                                    .map(str::trim)
+                                    .filter(|name| !name.is_empty())
+                                    .map(str::to_string),
+                            );
+                        }
+                        "--stop-on-failure" => stop_on_failure = true,
+                        "--repeat" => {
+                            let value = args
+                                .next()
+                                .ok_or_else(|| anyhow::anyhow!("--repeat requires a number"))?;
+                            repeat = value.parse().map_err(|_| {
+                                anyhow::anyhow!("--repeat expects a number, got {}", value)
+                            })?;
+                        }
+                        other if !other.starts_with("--") && config.is_none() => {
+                            config = Some(PathBuf::from(other));
+                        }
+                        other => {
+                            return Err(anyhow::anyhow!("Unknown run-batch option: {}", other))
+                        }
+                    }
+                }
+                if macros.is_empty() {
+                    return Err(anyhow::anyhow!("run-batch requires --macros"));
+                }
+                Subcommand::RunBatch {
+                    config,
+                    macros,
+                    stop_on_failure,
+                    repeat,
+                }
+            }
+            Some("press") => {
+                let hotkey = args.next().ok_or_else(|| {
+                    anyhow::anyhow!("press requires a hotkey, e.g. LeftControl+F9")
+                })?;
+                Subcommand::Press {
+                    hotkey: parse_hotkey(&hotkey)?,
+                }
+            }
+            Some("validate") => Subcommand::Validate,
+            Some("lint") => {
+                let mut fix = false;
+                for arg in args.by_ref() {
+                    match arg.as_str() {
+                        "--fix" => fix = true,
+                        other => return Err(anyhow::anyhow!("Unknown lint option: {}", other)),
+                    }
+                }
+                Subcommand::Lint { fix }
+            }
+            Some("diff-run") => {
+                let name = args
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("diff-run requires a macro name"))?;
+
+                let mut update_baseline = false;
+                let mut tolerance = super::diff_run::DEFAULT_TOLERANCE;
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--update-baseline" => update_baseline = true,
+                        "--tolerance" => {
+                            let value = args.next().ok_or_else(|| {
+                                anyhow::anyhow!("--tolerance requires a duration")
+                            })?;
+                            tolerance = DurationMs::deserialize(value.as_str().into_deserializer())
+                                .map_err(|e: serde::de::value::Error| {
+                                    anyhow::anyhow!("--tolerance: {}", e)
+                                })?;
+                        }
+                        other => return Err(anyhow::anyhow!("Unknown diff-run option: {}", other)),
+                    }
+                }
+
+                Subcommand::DiffRun {
+                    name,
+                    update_baseline,
+                    tolerance,
+                }
+            }
+            Some("show-config") => Subcommand::ShowConfig,
+            Some("schema") => Subcommand::Schema,
+            Some("history") => {
+                let mut last = None;
+                let mut macro_name = None;
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--last" => {
+                            let value = args
+                                .next()
+                                .ok_or_else(|| anyhow::anyhow!("--last requires a number"))?;
+                            last = Some(value.parse().map_err(|_| {
+                                anyhow::anyhow!("--last expects a number, got {}", value)
+                            })?);
+                        }
+                        "--macro" => {
+                            macro_name = Some(
+                                args.next()
+                                    .ok_or_else(|| anyhow::anyhow!("--macro requires a name"))?,
+                            );
+                        }
+                        other => return Err(anyhow::anyhow!("Unknown history option: {}", other)),
+                    }
+                }
+                Subcommand::History { last, macro_name }
+            }
+            Some("calibrate") => {
+                let mut anchor = None;
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--anchor" => {
+                            anchor = Some(args.next().ok_or_else(|| {
+                                anyhow::anyhow!("--anchor requires a window title")
+                            })?);
+                        }
+                        other => {
+                            return Err(anyhow::anyhow!("Unknown calibrate option: {}", other))
+                        }
+                    }
+                }
+                Subcommand::Calibrate { anchor }
+            }
+            Some("restore-backup") => {
+                let mut list = false;
+                let mut name = None;
+                for arg in args.by_ref() {
+                    match arg.as_str() {
+                        "--list" => list = true,
+                        other if other.starts_with("--") => {
+                            return Err(anyhow::anyhow!("Unknown restore-backup option: {}", other))
+                        }
+                        _ if name.is_some() => {
+                            return Err(anyhow::anyhow!("restore-backup takes one backup name"))
+                        }
+                        _ => name = Some(arg),
+                    }
+                }
+                Subcommand::RestoreBackup { list, name }
+            }
+            Some("doctor") => Subcommand::Doctor,
+            Some("capture-key") => {
+                let mut combo = false;
+                for arg in args.by_ref() {
+                    match arg.as_str() {
+                        "--combo" => combo = true,
+                        other => {
+                            return Err(anyhow::anyhow!("Unknown capture-key option: {}", other))
+                        }
+                    }
+                }
+                Subcommand::CaptureKey { combo }
+            }
+            Some("capture-window") => {
+                let mut delay = DEFAULT_CAPTURE_WINDOW_DELAY;
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--delay" => {
+                            let value = args
+                                .next()
+                                .ok_or_else(|| anyhow::anyhow!("--delay requires a duration"))?;
+                            delay = DurationMs::deserialize(value.as_str().into_deserializer())
+                                .map_err(|e: serde::de::value::Error| {
+                                    anyhow::anyhow!("--delay: {}", e)
+                                })?;
+                        }
+                        other => {
+                            return Err(anyhow::anyhow!("Unknown capture-window option: {}", other))
+                        }
+                    }
+                }
+                Subcommand::CaptureWindow { delay }
+            }
+            Some("cred") => match args.next().as_deref() {
+                Some("set") => Subcommand::CredSet {
+                    target: args
+                        .next()
+                        .ok_or_else(|| anyhow::anyhow!("cred set requires a target name"))?,
+                },
+                Some(other) => return Err(anyhow::anyhow!("Unknown cred command: {}", other)),
+                None => return Err(anyhow::anyhow!("cred requires a command, e.g. set")),
+            },
+            Some("list-gamepads") => Subcommand::ListGamepads,
+            Some("version") => Subcommand::Version,
+            Some("check-update") => {
+                let mut url = None;
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--url" => {
+                            url = Some(
+                                args.next()
+                                    .ok_or_else(|| anyhow::anyhow!("--url requires a URL"))?,
+                            );
+                        }
+                        other => {
+                            return Err(anyhow::anyhow!("Unknown check-update option: {}", other))
+                        }
+                    }
+                }
+                Subcommand::CheckUpdate { url }
+            }
+            Some("click") => {
+                let mut interval = DEFAULT_CLICK_INTERVAL;
+                let mut button = ClickButton::Left;
+                let mut hold_hotkey = parse_hotkey(DEFAULT_CLICK_HOTKEY)?;
+                let mut exit_hotkey = parse_hotkey(DEFAULT_CLICK_EXIT_HOTKEY)?;
+                let mut jitter_ms = 0;
+                let mut jitter_px = 0;
+                while let Some(arg) = args.next() {
+                    let mut value = || {
+                        args.next()
+                            .ok_or_else(|| anyhow::anyhow!("{} requires a value", arg))
+                    };
+                    match arg.as_str() {
+                        "--interval" => {
+                            let value = value()?;
+                            interval = DurationMs::deserialize(value.as_str().into_deserializer())
+                                .map_err(|e: serde::de::value::Error| {
+                                    anyhow::anyhow!("--interval: {}", e)
+                                })?;
+                        }
+                        "--button" => {
+                            button = match value()?.as_str() {
+                                "left" => ClickButton::Left,
+                                "right" => ClickButton::Right,
+                                "middle" => ClickButton::Middle,
+                                other => {
+                                    return Err(anyhow::anyhow!(
+                                        "--button expects left, right or middle, got {}",
+                                        other
+                                    ))
+                                }
+                            };
+                        }
+                        "--hold-hotkey" => hold_hotkey = parse_hotkey(&value()?)?,
+                        "--exit-hotkey" => exit_hotkey = parse_hotkey(&value()?)?,
+                        "--jitter-ms" => {
+                            let value = value()?;
+                            jitter_ms = value.parse().map_err(|_| {
+                                anyhow::anyhow!("--jitter-ms expects a number, got {}", value)
+                            })?;
+                        }
+                        "--jitter-px" => {
+                            let value = value()?;
+                            jitter_px = value.parse().map_err(|_| {
+                                anyhow::anyhow!("--jitter-px expects a number, got {}", value)
+                            })?;
+                        }
+                        other => return Err(anyhow::anyhow!("Unknown click option: {}", other)),
+                    }
+                }
+                Subcommand::Click {
+                    interval,
+                    button,
+                    hold_hotkey,
+                    exit_hotkey,
+                    jitter_ms,
+                    jitter_px,
+                }
+            }
+            Some(other) => return Err(anyhow::anyhow!("Unknown subcommand: {}", other)),
+        };
+
+        if let Some(extra) = args.next() {
+            return Err(anyhow::anyhow!("Unexpected argument: {}", extra));
+        }
+
+        Ok(Cli {
+            subcommand,
+            config,
+            request_elevation,
+            events_stdout,
+            trace_triggers,
+            force,
+            seed,
+            overrides,
+            backend,
+        })
+    }
+}
+
+/// Parses a hotkey written the way configs write it, e.g. `LeftControl+F8`.
+fn parse_hotkey(text: &str) -> Result<HashSet<Key>, anyhow::Error> {
+    deserialize_keys(text.into_deserializer())
+        .map_err(|e: serde::de::value::Error| anyhow::anyhow!("Invalid hotkey {}: {}", text, e))
+}
+
+/// Removes every occurrence of `flag` from `args`, returning whether it was present.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let before = args.len();
+    args.retain(|arg| arg != flag);
+    args.len() != before
+}
+
+/// Removes `option` and the value following it from `args`, returning the value.
+fn take_option(args: &mut Vec<String>, option: &str) -> Result<Option<String>, anyhow::Error> {
+    let position = match args.iter().position(|arg| arg == option) {
+        Some(position) => position,
+        None => return Ok(None),
+    };
+
+    if position + 1 >= args.len() {
+        return Err(anyhow::anyhow!("{} requires a value", option));
+    }
+
+    let value = args.remove(position + 1);
+    args.remove(position);
+
+    Ok(Some(value))
+}
@@ -0,0 +1,119 @@
+use super::get_last_windows_error;
+
+/// The `CF_UNICODETEXT` clipboard format: UTF-16 text ending in a null.
+const CF_UNICODETEXT: u32 = 13;
+
+/// Puts `text` on the clipboard, replacing whatever was there.
+#[cfg(windows)]
+pub fn set_text(text: &str) -> Result<(), anyhow::Error> {
+    use windows::Win32::Foundation::{HANDLE, HWND};
+    use windows::Win32::System::DataExchange::{
+        CloseClipboard, EmptyClipboard, OpenClipboard, SetClipboardData,
+    };
+    use windows::Win32::System::Memory::{
+        GlobalAlloc, GlobalFree, GlobalLock, GlobalUnlock, GMEM_MOVEABLE,
+    };
+
+    let units: Vec<u16> = text.encode_utf16().chain(std::iter::once(0)).collect();
+
+    let memory = unsafe { GlobalAlloc(GMEM_MOVEABLE, units.len() * std::mem::size_of::<u16>()) };
+    if memory == 0 {
+        return Err(anyhow::anyhow!(
+            "Failed to allocate clipboard memory: {}",
+            get_last_windows_error()
+        ));
+    }
+
+    let destination = unsafe { GlobalLock(memory) } as *mut u16;
+    if destination.is_null() {
+        unsafe { GlobalFree(memory) };
+        return Err(anyhow::anyhow!(
+            "Failed to lock clipboard memory: {}",
+            get_last_windows_error()
+        ));
+    }
+    unsafe {
+        std::ptr::copy_nonoverlapping(units.as_ptr(), destination, units.len());
+        GlobalUnlock(memory);
+    }
+
+    if !unsafe { OpenClipboard(HWND::default()) }.as_bool() {
+        unsafe { GlobalFree(memory) };
+        return Err(anyhow::anyhow!(
+            "Failed to open the clipboard: {}",
+            get_last_windows_error()
+        ));
+    }
+
+    let result = unsafe {
+        EmptyClipboard();
+        SetClipboardData(CF_UNICODETEXT, HANDLE(memory))
+    };
+    unsafe { CloseClipboard() };
+
+    // The clipboard only takes ownership of the memory if setting it succeeded
+    if let Err(e) = result {
+        unsafe { GlobalFree(memory) };
+        return Err(anyhow::anyhow!("Failed to set clipboard text: {}", e));
+    }
+
+    Ok(())
+}
+
+/// How many times `text` tries to open the clipboard, which the app that just wrote it may still
+/// hold open.
+const OPEN_ATTEMPTS: u32 = 10;
+/// How long `text` waits between attempts to open the clipboard.
+const OPEN_RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_millis(25);
+
+/// A number that changes every time the clipboard contents do, whatever their format.
+#[cfg(windows)]
+pub fn sequence_number() -> u32 {
+    unsafe { windows::Win32::System::DataExchange::GetClipboardSequenceNumber() }
+}
+
+/// The text on the clipboard, or `None` if it holds something else, such as an image.
+#[cfg(windows)]
+pub fn text() -> Result<Option<String>, anyhow::Error> {
+    use windows::Win32::Foundation::HWND;
+    use windows::Win32::System::DataExchange::{
+        CloseClipboard, GetClipboardData, IsClipboardFormatAvailable, OpenClipboard,
+    };
+    use windows::Win32::System::Memory::{GlobalLock, GlobalSize, GlobalUnlock};
+
+    if !unsafe { IsClipboardFormatAvailable(CF_UNICODETEXT) }.as_bool() {
+        return Ok(None);
+    }
+
+    let mut attempts = 0;
+    while !unsafe { OpenClipboard(HWND::default()) }.as_bool() {
+        attempts += 1;
+        if attempts >= OPEN_ATTEMPTS {
+            return Err(anyhow::anyhow!(
+                "Failed to open the clipboard: {}",
+                get_last_windows_error()
+            ));
+        }
+        std::thread::sleep(OPEN_RETRY_INTERVAL);
+    }
+
+    let text = unsafe { GetClipboardData(CF_UNICODETEXT) }.and_then(|memory| unsafe {
+        let source = GlobalLock(memory.0) as *const u16;
+        if source.is_null() {
+            return Err(windows::core::Error::from_win32());
+        }
+
+        // The text ends at its null, or else at the end of the memory
+        let capacity = GlobalSize(memory.0) / std::mem::size_of::<u16>();
+        let units = std::slice::from_raw_parts(source, capacity);
+        let length = units.iter().position(|unit| *unit == 0).unwrap_or(capacity);
+        let text = String::from_utf16_lossy(&units[..length]);
+
+        GlobalUnlock(memory.0);
+        Ok(text)
+    });
+    unsafe { CloseClipboard() };
+
+    text.map(Some)
+        .map_err(|e| anyhow::anyhow!("Failed to read clipboard text: {}", e))
+}
@@ -0,0 +1,127 @@
+use std::{
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use super::context::CancellationToken;
+
+/// Where timing reads the time from and sleeps through. Waits, cooldowns and timeouts go through
+/// this rather than `Instant` and `thread::sleep` directly, so that time can be swapped out.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Sleeps until `deadline`, waking early if `cancellation` is cancelled. Returns whether the
+    /// deadline was reached.
+    fn sleep_until(&self, deadline: Instant, cancellation: &CancellationToken) -> bool;
+
+    fn sleep(&self, duration: Duration, cancellation: &CancellationToken) -> bool {
+        self.sleep_until(self.now() + duration, cancellation)
+    }
+
+    fn elapsed(&self, since: Instant) -> Duration {
+        self.now().saturating_duration_since(since)
+    }
+}
+
+/// Real time.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant, cancellation: &CancellationToken) -> bool {
+        cancellation.sleep(deadline.saturating_duration_since(Instant::now()))
+    }
+}
+
+/// Time that only moves when slept through, and then at once, for `diff-run`: waits take no real
+/// time and every run sees the same timings. Once `limit` has passed, sleeping cancels the
+/// execution instead, so that endless loops end.
+pub struct VirtualClock {
+    start: Instant,
+    elapsed: Mutex<Duration>,
+    limit: Duration,
+}
+
+impl VirtualClock {
+    pub fn new(limit: Duration) -> Self {
+        VirtualClock {
+            start: Instant::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+            limit,
+        }
+    }
+
+    /// How much time has passed since the clock was made.
+    pub fn elapsed_total(&self) -> Duration {
+        *self.elapsed.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed_total()
+    }
+
+    fn sleep_until(&self, deadline: Instant, cancellation: &CancellationToken) -> bool {
+        if cancellation.is_cancelled() {
+            return false;
+        }
+
+        let mut elapsed = self.elapsed.lock().unwrap_or_else(PoisonError::into_inner);
+        let target = deadline.saturating_duration_since(self.start);
+        if target > self.limit {
+            *elapsed = self.limit;
+            cancellation.cancel();
+            return false;
+        }
+
+        *elapsed = (*elapsed).max(target);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn virtual_time_moves_only_when_slept_through() {
+        let clock = VirtualClock::new(Duration::from_secs(60 * 60));
+        let cancellation = CancellationToken::default();
+        let start = clock.now();
+
+        assert!(clock.sleep(Duration::from_secs(10 * 60), &cancellation));
+        assert_eq!(clock.elapsed(start), Duration::from_secs(10 * 60));
+
+        // A deadline already passed leaves the time where it is
+        assert!(clock.sleep_until(start + Duration::from_secs(60), &cancellation));
+        assert_eq!(clock.elapsed_total(), Duration::from_secs(10 * 60));
+    }
+
+    #[test]
+    fn virtual_sleeps_past_the_limit_cancel_at_the_limit() {
+        let clock = VirtualClock::new(Duration::from_secs(3 * 60));
+        let cancellation = CancellationToken::default();
+
+        assert!(!clock.sleep(Duration::from_secs(10 * 60), &cancellation));
+        assert!(cancellation.is_cancelled());
+        assert_eq!(clock.elapsed_total(), Duration::from_secs(3 * 60));
+    }
+
+    #[test]
+    fn cancelled_sleeps_return_at_once() {
+        let cancellation = CancellationToken::default();
+        cancellation.cancel();
+
+        let clock = VirtualClock::new(Duration::from_secs(60));
+        assert!(!clock.sleep(Duration::from_secs(10), &cancellation));
+        assert_eq!(clock.elapsed_total(), Duration::ZERO);
+
+        let started = Instant::now();
+        assert!(!SystemClock.sleep(Duration::from_secs(10 * 60), &cancellation));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,683 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+
+use super::{
+    duration::DurationMs,
+    events::TriggerSource,
+    logger, profile, rate_limit,
+    schedule::{ActiveHours, Weekday},
+    CooldownFrom, Key, Macro, MacroConfig, NumlockPolicy, TriggerOn,
+};
+
+/// Config file looked for in the working directory when no `--config` is given.
+pub const DEFAULT_CONFIG_PATH: &str = "macro_config.yaml";
+
+/// The parts of a config file that may be contributed through `include`.
+#[derive(Debug, Deserialize)]
+struct IncludedConfig {
+    #[serde(default)]
+    macros: Vec<Macro>,
+}
+
+/// The `defaults:` block of a config file: values for every macro, in that file and the files it
+/// includes, that does not set the field itself. They are filled in while loading, so the rest of
+/// the program only ever sees complete macros.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MacroDefaults {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trigger_on: Option<TriggerOn>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    confirm: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cooldown_ms: Option<DurationMs>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cooldown_from: Option<CooldownFrom>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    jitter_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    jitter_px: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ensure_numlock: Option<NumlockPolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    capslock_off_for_text: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wait_for_clean_modifiers: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    modifier_grace_ms: Option<DurationMs>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    neutralize_modifiers: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    strict_coordinates: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    active_hours: Option<ActiveHours>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    active_days: Option<Vec<Weekday>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ignore_guards_for: Option<Vec<TriggerSource>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    profile: Option<String>,
+}
+
+impl MacroDefaults {
+    /// Takes the `defaults` block out of a parsed config file, if it has one.
+    fn take_from(config_value: &mut Value, path: &Path) -> Result<Option<Self>, anyhow::Error> {
+        match config_value
+            .as_mapping_mut()
+            .and_then(|mapping| mapping.remove("defaults"))
+        {
+            Some(defaults) => serde_yaml::from_value(defaults)
+                .map(Some)
+                .map_err(|e| anyhow::anyhow!("{}: defaults: {}", path.display(), e)),
+            None => Ok(None),
+        }
+    }
+
+    /// Adds every default to each macro under `macros` that does not set it.
+    fn apply(&self, config_value: &mut Value) -> Result<(), anyhow::Error> {
+        let defaults = match serde_yaml::to_value(self)? {
+            Value::Mapping(defaults) => defaults,
+            _ => return Ok(()),
+        };
+
+        let macros = config_value
+            .get_mut("macros")
+            .and_then(Value::as_sequence_mut);
+
+        for macro_value in macros.into_iter().flatten() {
+            if let Some(macro_mapping) = macro_value.as_mapping_mut() {
+                for (key, value) in defaults.iter() {
+                    if !macro_mapping.contains_key(key) {
+                        macro_mapping.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The config file `load_config` reads, `None` when it falls back to the built-in config.
+pub fn resolve_config_path(path: Option<&Path>) -> Option<PathBuf> {
+    match path {
+        Some(path) => Some(path.to_path_buf()),
+        None => Some(PathBuf::from(DEFAULT_CONFIG_PATH)).filter(|path| path.exists()),
+    }
+}
+
+/// Reads, merges and validates the config. Without an explicit path the default file in the
+/// working directory is used if present, falling back to the config built into the binary.
+/// Conflicting macro definitions across files are an error unless `force` is set, in which case
+/// they are only warned about. `overrides` from `--set` are applied just before validation.
+pub fn load_config(
+    path: Option<&Path>,
+    force: bool,
+    overrides: &[(String, String)],
+) -> Result<MacroConfig, anyhow::Error> {
+    let path = resolve_config_path(path);
+
+    let (mut macro_config, defaults) = match &path {
+        Some(path) => {
+            let macro_config_string = std::fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("Failed to read config {}: {}", path.display(), e))?;
+            parse_config(&macro_config_string, path)?
+        }
+        None => {
+            log::info!(
+                "No {} found, using the built-in config",
+                DEFAULT_CONFIG_PATH
+            );
+            let path = PathBuf::from(DEFAULT_CONFIG_PATH);
+            parse_config(include_str!("../macro_config.yaml"), &path)?
+        }
+    };
+
+    let base_dir = path
+        .as_deref()
+        .and_then(Path::parent)
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    merge_includes(&mut macro_config, defaults.as_ref(), &base_dir)?;
+    report_collisions(&find_collisions(&macro_config.macros), force)?;
+
+    let macro_config = apply_overrides(macro_config, overrides)?;
+    macro_config.validate()?;
+    logger::set_format(macro_config.log_format);
+    rate_limit::configure(macro_config.max_events_per_second);
+
+    Ok(macro_config)
+}
+
+/// Applies each `--set path=value` to the loaded config, in order. Path segments name fields,
+/// except under `macros`, where they name a macro, its index, or `*` for every macro, e.g.
+/// `macros.login.enabled`. Values are read as YAML, so `false`, `2.5` and `skip` come out as a
+/// boolean, a number and a string. The `defaults` block has already been folded into the macros
+/// by now, so `macros.*.<field>` takes its place.
+fn apply_overrides(
+    mut macro_config: MacroConfig,
+    overrides: &[(String, String)],
+) -> Result<MacroConfig, anyhow::Error> {
+    for (path, text) in overrides.iter() {
+        let fail = |e: anyhow::Error| anyhow::anyhow!("--set {}={}: {}", path, text, e);
+        let new_value: Value =
+            serde_yaml::from_str(text).unwrap_or_else(|_| Value::String(text.clone()));
+        let segments: Vec<&str> = path.split('.').collect();
+
+        // Where the macros came from is not serialized, so it is carried across by hand
+        let sources: Vec<_> = macro_config
+            .macros
+            .iter()
+            .map(|current_macro| (current_macro.source.clone(), current_macro.source_line))
+            .collect();
+
+        let mut value = serde_yaml::to_value(&macro_config)?;
+        let mut added = false;
+        visit_path(&mut value, &segments, "", &mut |mapping, key| {
+            added |= !mapping.contains_key(key);
+            mapping.insert(key.into(), new_value.clone());
+            Ok(())
+        })
+        .map_err(fail)?;
+
+        macro_config = serde_yaml::from_value(value).map_err(|e| fail(e.into()))?;
+        for (current_macro, (source, source_line)) in macro_config.macros.iter_mut().zip(sources) {
+            current_macro.source = source;
+            current_macro.source_line = source_line;
+        }
+
+        // A field the config does not have is dropped when deserializing, so it goes missing on
+        // the way back out. Fields left out because they hold their default do too, hence null.
+        if added && !new_value.is_null() {
+            let mut round_trip = serde_yaml::to_value(&macro_config)?;
+            visit_path(&mut round_trip, &segments, "", &mut |mapping, key| {
+                if mapping.contains_key(key) {
+                    Ok(())
+                } else {
+                    Err(unknown_child(mapping, key))
+                }
+            })
+            .map_err(fail)?;
+        }
+    }
+
+    Ok(macro_config)
+}
+
+/// An error naming `key` as unknown, listing the keys `mapping` does have.
+fn unknown_child(mapping: &Mapping, key: &str) -> anyhow::Error {
+    let children: Vec<&str> = mapping.keys().filter_map(Value::as_str).collect();
+    anyhow::anyhow!("no {}, expected one of: {}", key, children.join(", "))
+}
+
+/// Walks `segments` down from `value`, calling `visit` with the mapping that holds the last
+/// segment and that segment, once for every macro a `*` matches. `parent` is the path walked so
+/// far, for errors.
+fn visit_path(
+    value: &mut Value,
+    segments: &[&str],
+    parent: &str,
+    visit: &mut dyn FnMut(&mut Mapping, &str) -> Result<(), anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => return Err(anyhow::anyhow!("empty path")),
+    };
+    let path = if parent.is_empty() {
+        segment.to_string()
+    } else {
+        format!("{}.{}", parent, segment)
+    };
+    let location = if parent.is_empty() { "the top" } else { parent };
+
+    match value {
+        Value::Mapping(mapping) if rest.is_empty() => {
+            visit(mapping, segment).map_err(|e| anyhow::anyhow!("at {}: {}", location, e))
+        }
+        Value::Mapping(mapping) => match mapping.get_mut(*segment) {
+            Some(child) => visit_path(child, rest, &path, visit),
+            None => Err(anyhow::anyhow!(
+                "at {}: {}",
+                location,
+                unknown_child(mapping, segment)
+            )),
+        },
+        Value::Sequence(items) if !rest.is_empty() => {
+            let names: Vec<String> = items
+                .iter()
+                .map(|item| {
+                    item.get("macro_name")
+                        .and_then(Value::as_str)
+                        .unwrap_or_default()
+                        .to_string()
+                })
+                .collect();
+            let matches: Vec<usize> = match (*segment, segment.parse::<usize>()) {
+                ("*", _) => (0..items.len()).collect(),
+                (_, Ok(index)) if index < items.len() => vec![index],
+                _ => names
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, name)| name == segment)
+                    .map(|(index, _)| index)
+                    .collect(),
+            };
+
+            if matches.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "at {}: no macro {}, expected *, an index or one of: {}",
+                    parent,
+                    segment,
+                    names.join(", ")
+                ));
+            }
+
+            for index in matches {
+                visit_path(&mut items[index], rest, &path, visit)?;
+            }
+            Ok(())
+        }
+        _ => Err(anyhow::anyhow!(
+            "at {}: {} has no fields to set",
+            location,
+            segment
+        )),
+    }
+}
+
+/// Parses a config file, returning its `defaults` block separately so it can be applied to the
+/// files it includes as well.
+fn parse_config(
+    macro_config_string: &str,
+    path: &Path,
+) -> Result<(MacroConfig, Option<MacroDefaults>), anyhow::Error> {
+    let mut config_value: Value = serde_yaml::from_str(macro_config_string)
+        .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+    let defaults = MacroDefaults::take_from(&mut config_value, path)?;
+
+    // Going through a `Value` loses line numbers in errors, so only do it when there is
+    // something to fill in
+    let mut macro_config: MacroConfig = match &defaults {
+        Some(defaults) => {
+            defaults.apply(&mut config_value)?;
+            serde_yaml::from_value(config_value)
+        }
+        None => serde_yaml::from_str(macro_config_string),
+    }
+    .map_err(|e| {
+        anyhow::anyhow!(
+            "{}: {}",
+            path.display(),
+            describe_error(e, macro_config_string)
+        )
+    })?;
+
+    set_sources(&mut macro_config.macros, path, macro_config_string);
+
+    Ok((macro_config, defaults))
+}
+
+/// Expands every `include` pattern relative to `base_dir` and appends the macros of each matched
+/// file, in sorted path order, to `macro_config`, applying the including file's `defaults`.
+fn merge_includes(
+    macro_config: &mut MacroConfig,
+    defaults: Option<&MacroDefaults>,
+    base_dir: &Path,
+) -> Result<(), anyhow::Error> {
+    for pattern in std::mem::take(&mut macro_config.include) {
+        let full_pattern = base_dir.join(&pattern);
+
+        let mut paths = glob::glob(&full_pattern.to_string_lossy())
+            .map_err(|e| anyhow::anyhow!("Invalid include pattern {}: {}", pattern, e))?
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.sort();
+
+        if paths.is_empty() {
+            log::warn!("Include pattern {} matched no files", pattern);
+        }
+
+        for path in paths {
+            let included_string = std::fs::read_to_string(&path).map_err(|e| {
+                anyhow::anyhow!("Failed to read included config {}: {}", path.display(), e)
+            })?;
+            let included: IncludedConfig = match defaults {
+                Some(defaults) => {
+                    let mut included_value: Value = serde_yaml::from_str(&included_string)
+                        .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+                    defaults.apply(&mut included_value)?;
+                    serde_yaml::from_value(included_value)
+                }
+                None => serde_yaml::from_str(&included_string),
+            }
+            .map_err(|e| {
+                anyhow::anyhow!(
+                    "{}: {}",
+                    path.display(),
+                    describe_error(e, &included_string)
+                )
+            })?;
+
+            let mut included_macros = included.macros;
+            set_sources(&mut included_macros, &path, &included_string);
+            macro_config.macros.append(&mut included_macros);
+        }
+    }
+
+    Ok(())
+}
+
+/// The message of `e`, from reading the config file `contents`, led by the name of the macro it
+/// is about, since serde only gives the macro's position, e.g. `login: macros[2].cooldown_ms:
+/// invalid duration "2x"`.
+fn describe_error(e: serde_yaml::Error, contents: &str) -> String {
+    let message = e.to_string();
+    let macro_name = message
+        .strip_prefix("macros[")
+        .and_then(|rest| rest.split_once(']'))
+        .and_then(|(index, _)| index.parse::<usize>().ok())
+        .and_then(|index| {
+            let config_value: Value = serde_yaml::from_str(contents).ok()?;
+            config_value
+                .get("macros")?
+                .get(index)?
+                .get("macro_name")?
+                .as_str()
+                .map(str::to_string)
+        });
+
+    match macro_name {
+        Some(macro_name) => format!("{}: {}", macro_name, message),
+        None => message,
+    }
+}
+
+/// Records `path` as the source of `macros`, and the line each starts on when a scan of the file
+/// finds exactly one `macro_name:` line per macro.
+fn set_sources(macros: &mut [Macro], path: &Path, contents: &str) {
+    let lines: Vec<usize> = contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| {
+            line.trim_start()
+                .trim_start_matches('-')
+                .trim_start()
+                .starts_with("macro_name:")
+        })
+        .map(|(index, _)| index + 1)
+        .collect();
+    let lines_found = lines.len() == macros.len();
+
+    for (index, current_macro) in macros.iter_mut().enumerate() {
+        current_macro.source = Some(path.to_path_buf());
+        current_macro.source_line = lines_found.then(|| lines[index]);
+    }
+}
+
+/// Two or more macros that share a name, or an enabled hotkey.
+#[derive(Debug)]
+pub struct Collision {
+    what: String,
+    /// Where each of the macros is defined.
+    sites: Vec<String>,
+    /// Whether the macros come from different files. The same name or hotkey used twice in one
+    /// file is tolerated, but across files it is almost certainly a mistake.
+    hard: bool,
+}
+
+impl fmt::Display for Collision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is defined at {}", self.what, self.sites.join(", "))
+    }
+}
+
+/// Every name, and every hotkey of an enabled macro, used by more than one macro whose profiles
+/// can be active together.
+pub fn find_collisions(macros: &[Macro]) -> Vec<Collision> {
+    let mut by_name: BTreeMap<&str, Vec<&Macro>> = BTreeMap::new();
+    let mut by_hotkey: BTreeMap<Vec<Key>, Vec<&Macro>> = BTreeMap::new();
+
+    for current_macro in macros.iter() {
+        by_name
+            .entry(&current_macro.macro_name)
+            .or_default()
+            .push(current_macro);
+
+        // The keys of a `mouse_trigger` macro are only modifiers for its click
+        if current_macro.enabled
+            && !current_macro.macro_hotkey.is_empty()
+            && current_macro.mouse_trigger.is_none()
+        {
+            let mut hotkey: Vec<Key> = current_macro.macro_hotkey.iter().copied().collect();
+            hotkey.sort();
+            by_hotkey.entry(hotkey).or_default().push(current_macro);
+        }
+    }
+
+    let names = by_name
+        .into_iter()
+        .map(|(name, macros)| (format!("Macro name {}", name), macros));
+    let hotkeys = by_hotkey.into_iter().map(|(hotkey, macros)| {
+        let keys: Vec<String> = hotkey.iter().map(|key| format!("{:?}", key)).collect();
+        (format!("Hotkey {}", keys.join("+")), macros)
+    });
+
+    // A hotkey may be shared by macros of different profiles, which are never active together
+    let hotkeys = hotkeys.map(|(what, macros)| {
+        let overlapping: Vec<&Macro> = macros
+            .iter()
+            .copied()
+            .filter(|a| {
+                macros
+                    .iter()
+                    .any(|b| !std::ptr::eq(*a, *b) && profile::profiles_overlap(a, b))
+            })
+            .collect();
+        (what, overlapping)
+    });
+
+    names
+        .chain(hotkeys)
+        .filter(|(_, macros)| macros.len() > 1)
+        .map(|(what, macros)| Collision {
+            what,
+            sites: macros
+                .iter()
+                .map(|current_macro| display_site(current_macro))
+                .collect(),
+            hard: macros
+                .iter()
+                .any(|current_macro| current_macro.source != macros[0].source),
+        })
+        .collect()
+}
+
+/// Warns about every collision, then fails if any is hard and `force` is not set.
+fn report_collisions(collisions: &[Collision], force: bool) -> Result<(), anyhow::Error> {
+    for collision in collisions.iter() {
+        log::warn!("{}", collision);
+    }
+
+    let hard_count = collisions.iter().filter(|collision| collision.hard).count();
+    if hard_count > 0 && !force {
+        return Err(anyhow::anyhow!(
+            "{} macro name or hotkey collision(s) across config files, pass --force to load anyway",
+            hard_count
+        ));
+    }
+
+    Ok(())
+}
+
+/// `file:line` of a macro, or just the file when the line is unknown.
+fn display_site(current_macro: &Macro) -> String {
+    let source = display_source(&current_macro.source);
+
+    match current_macro.source_line {
+        Some(line) => format!("{}:{}", source, line),
+        None => source,
+    }
+}
+
+fn display_source(source: &Option<PathBuf>) -> String {
+    source
+        .as_ref()
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|| "<unknown>".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(yaml: &str) -> Result<MacroConfig, anyhow::Error> {
+        parse_config(yaml, Path::new("test.yaml")).map(|(macro_config, _)| macro_config)
+    }
+
+    #[test]
+    fn invalid_durations_name_the_field_and_the_macro() {
+        let e = parse(
+            "program_hotkey: [LeftShift, F6]\n\
+             macros:\n\
+             - {macro_name: first, macro_hotkey: [LeftControl, F7], commands: []}\n\
+             - {macro_name: login, macro_hotkey: [LeftControl, F8], cooldown_ms: 2x, \
+             commands: []}\n",
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(
+            e.starts_with("test.yaml: login: macros[1].cooldown_ms: invalid duration \"2x\""),
+            "{}",
+            e
+        );
+
+        let e = parse(
+            "program_hotkey: [LeftShift, F6]\n\
+             macros:\n\
+             - macro_name: hold\n  \
+             macro_hotkey: [LeftControl, F7]\n  \
+             commands:\n  \
+             - !HoldKey {key: A, duration_ms: 5 sec}\n",
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(
+            e.starts_with(
+                "test.yaml: hold: macros[0].commands[0].duration_ms: invalid duration \"5 sec\""
+            ),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn durations_load_in_any_form() {
+        let macro_config = parse(
+            "program_hotkey: [LeftShift, F6]\n\
+             macros:\n\
+             - {macro_name: a, macro_hotkey: [LeftControl, F7], cooldown_ms: 1m30s, \
+             commands: [!Wait 1.5s, !Wait 250]}\n",
+        )
+        .unwrap();
+        let current_macro = &macro_config.macros[0];
+
+        assert_eq!(current_macro.cooldown_ms, DurationMs(90_000));
+        assert_eq!(
+            current_macro.commands,
+            crate::tests::commands("[!Wait 1500, !Wait 250ms]")
+        );
+    }
+
+    const OVERRIDDEN: &str = "program_hotkey: [LeftShift, F6]\n\
+                              macros:\n\
+                              - {macro_name: first, macro_hotkey: [LeftControl, F7], commands: []}\n\
+                              - {macro_name: login, macro_hotkey: [LeftControl, F8], commands: []}\n";
+
+    fn set(path: &str, value: &str) -> Result<MacroConfig, anyhow::Error> {
+        apply_overrides(
+            parse(OVERRIDDEN).unwrap(),
+            &[(path.to_string(), value.to_string())],
+        )
+    }
+
+    #[test]
+    fn overrides_set_booleans_numbers_and_enums() {
+        let macro_config = apply_overrides(
+            parse(OVERRIDDEN).unwrap(),
+            &[
+                ("macros.login.enabled".to_string(), "false".to_string()),
+                ("macros.*.mutex_policy".to_string(), "queue".to_string()),
+                ("max_macro_threads".to_string(), "4".to_string()),
+            ],
+        )
+        .unwrap();
+
+        assert!(macro_config.macros[0].enabled);
+        assert!(!macro_config.macros[1].enabled);
+        assert!(macro_config
+            .macros
+            .iter()
+            .all(|current_macro| current_macro.mutex_policy == crate::MutexPolicy::Queue));
+        assert_eq!(macro_config.max_macro_threads, 4);
+    }
+
+    #[test]
+    fn overrides_of_the_wrong_type_are_errors() {
+        for (path, value, expected) in [
+            ("macros.login.enabled", "maybe", "expected a boolean"),
+            ("max_macro_threads", "-1", "expected usize"),
+            ("max_macro_threads", "many", "expected usize"),
+            (
+                "macros.login.mutex_policy",
+                "sometimes",
+                "unknown variant `sometimes`",
+            ),
+        ] {
+            let e = set(path, value).unwrap_err().to_string();
+            assert!(
+                e.starts_with(&format!("--set {}={}: ", path, value)) && e.contains(expected),
+                "{}",
+                e
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_paths_list_the_valid_children() {
+        let e = set("macros.login.enabeld", "false")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            e.starts_with(
+                "--set macros.login.enabeld=false: at macros.login: no enabeld, \
+                 expected one of: macro_name, macro_hotkey, enabled, "
+            ),
+            "{}",
+            e
+        );
+
+        assert_eq!(
+            set("macros.logn.enabled", "false").unwrap_err().to_string(),
+            "--set macros.logn.enabled=false: at macros: no macro logn, \
+             expected *, an index or one of: first, login"
+        );
+
+        let e = set("program.hotkey", "F9").unwrap_err().to_string();
+        assert!(
+            e.starts_with("--set program.hotkey=F9: at the top: no program, expected one of: "),
+            "{}",
+            e
+        );
+        assert!(e.contains("program_hotkey"), "{}", e);
+
+        assert_eq!(
+            set("max_macro_threads.count", "4").unwrap_err().to_string(),
+            "--set max_macro_threads.count=4: at max_macro_threads: count has no fields to set"
+        );
+    }
+}
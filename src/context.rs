@@ -0,0 +1,853 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+use rand::{rngs::StdRng, SeedableRng};
+
+use super::{
+    backend::{GdiScreen, InputBackend, InputEvent, ScreenBackend},
+    clock::{Clock, SystemClock},
+    diff_run,
+    error::MacroError,
+    events::{EventBus, ExecutionEvent, ExecutionEventKind},
+    input_block::InputBlock,
+    jitter::Jitter,
+    key_up,
+    process::{ProcessMetrics, WindowsProcessMetrics},
+    repeat::{KeyRepeat, KeyRepeater},
+    secret::Secret,
+    window::AllowedTarget,
+    Key, Macro,
+};
+
+/// How long a foreground window found in `allowed_targets` is trusted before it is checked again.
+const TARGET_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How far a running macro has got, shared with the executor for status reports.
+#[derive(Debug, Default)]
+pub struct Progress {
+    /// Top-level command the macro is on.
+    command_index: AtomicUsize,
+    /// Characters typed so far and in total by the text command running, if any.
+    text: Mutex<Option<(usize, usize)>>,
+    /// Stopped at a `Pause` command, waiting for its resume key.
+    awaiting_resume: AtomicBool,
+}
+
+impl Progress {
+    pub fn command_index(&self) -> usize {
+        self.command_index.load(Ordering::SeqCst)
+    }
+
+    pub fn text(&self) -> Option<(usize, usize)> {
+        *self.text.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn awaiting_resume(&self) -> bool {
+        self.awaiting_resume.load(Ordering::SeqCst)
+    }
+}
+
+/// Shared flag used to ask a running macro to stop. Waits taken through `sleep` wake up as soon
+/// as it is set.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<(Mutex<bool>, Condvar)>);
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        let (cancelled, wake) = &*self.0;
+        *cancelled.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        wake.notify_all();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        let (cancelled, _) = &*self.0;
+        *cancelled.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Sleeps for `duration` unless cancelled first. Returns whether the whole duration passed.
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let (cancelled, wake) = &*self.0;
+        let cancelled = cancelled.lock().unwrap_or_else(PoisonError::into_inner);
+        let (cancelled, _) = wake
+            .wait_timeout_while(cancelled, duration, |cancelled| !*cancelled)
+            .unwrap_or_else(PoisonError::into_inner);
+        !*cancelled
+    }
+}
+
+/// Shared flag used to hold running macros at their next command boundary, e.g. while the
+/// workstation is locked.
+#[derive(Debug, Clone, Default)]
+pub struct PauseToken(Arc<AtomicBool>);
+
+impl PauseToken {
+    pub fn pause(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Whether an execution stops for the user between commands, set by `run-macro --step` and
+/// `--debug`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum DebugMode {
+    #[default]
+    Off,
+    /// Stop before the command following each `Breakpoint`.
+    Breakpoints,
+    /// Stop before every command.
+    Step,
+}
+
+/// How often a paused macro checks whether it may carry on.
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The iteration a `Loop` is currently on, used to resolve `${loop_index}`.
+struct LoopFrame {
+    name: Option<String>,
+    index: u64,
+}
+
+/// State belonging to a single run of a macro.
+pub struct ExecutionContext {
+    pub macro_name: String,
+    pub execution_id: u64,
+    events: Arc<EventBus>,
+    cancellation: CancellationToken,
+    pause: PauseToken,
+    backend: Arc<dyn InputBackend>,
+    clock: Arc<dyn Clock>,
+    screen: Arc<dyn ScreenBackend>,
+    process_metrics: Arc<dyn ProcessMetrics>,
+    /// Every configured macro, for `CallMacro`.
+    macros: Arc<Vec<Macro>>,
+    /// How many `CallMacro`s deep execution currently is.
+    call_depth: usize,
+    /// Keys this execution has put down and not yet released.
+    pressed_keys: HashSet<Key>,
+    key_repeaters: HashMap<Key, KeyRepeater>,
+    /// How many nested `WithKeysHeld` blocks currently hold each key.
+    key_holds: HashMap<Key, usize>,
+    jitter: Option<Jitter>,
+    /// Seed of `rng`, reported when the macro starts so the run can be repeated.
+    seed: u64,
+    /// Source of every random choice the macro makes.
+    rng: StdRng,
+    /// Turn CapsLock off before each `TextInput`.
+    capslock_off_for_text: bool,
+    /// Fail mouse commands aimed off-screen instead of letting them clamp.
+    strict_coordinates: bool,
+    /// Directory relative file paths are taken from, that of the macro's config file.
+    base_dir: PathBuf,
+    /// The only windows input may be sent to, any window when empty.
+    allowed_targets: Arc<Vec<AllowedTarget>>,
+    /// Until when the foreground window is taken to still be one of `allowed_targets`.
+    target_allowed_until: Option<Instant>,
+    /// Keeps the user's input blocked for the macro's `block_user_input`.
+    input_block: Option<InputBlock>,
+    /// How many retries the whole execution may make, unlimited when `None`.
+    retry_budget: Option<u32>,
+    /// Retries made so far, by every `RetryBlock` of the execution.
+    retries: u32,
+    progress: Arc<Progress>,
+    /// Values set by commands, such as the captured output of `Run`.
+    variables: HashMap<String, String>,
+    /// Enclosing loops, innermost last.
+    loop_frames: Vec<LoopFrame>,
+    /// Cleanup to run when the macro ends, however it ends.
+    deferred: Vec<Box<dyn FnOnce() + Send>>,
+    debug_mode: DebugMode,
+    /// A `Breakpoint` was passed, so the next command waits for the user.
+    break_pending: bool,
+}
+
+impl ExecutionContext {
+    pub fn new(
+        macro_name: String,
+        execution_id: u64,
+        events: Arc<EventBus>,
+        cancellation: CancellationToken,
+        pause: PauseToken,
+        backend: Arc<dyn InputBackend>,
+        macros: Arc<Vec<Macro>>,
+    ) -> Self {
+        let seed = rand::random();
+
+        ExecutionContext {
+            macro_name,
+            execution_id,
+            events,
+            cancellation,
+            pause,
+            backend,
+            clock: Arc::new(SystemClock),
+            screen: Arc::new(GdiScreen),
+            process_metrics: Arc::new(WindowsProcessMetrics),
+            macros,
+            call_depth: 0,
+            pressed_keys: HashSet::new(),
+            key_repeaters: HashMap::new(),
+            key_holds: HashMap::new(),
+            jitter: None,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            capslock_off_for_text: false,
+            strict_coordinates: false,
+            base_dir: PathBuf::new(),
+            allowed_targets: Arc::default(),
+            target_allowed_until: None,
+            input_block: None,
+            retry_budget: None,
+            retries: 0,
+            progress: Arc::default(),
+            variables: HashMap::new(),
+            loop_frames: Vec::new(),
+            deferred: Vec::new(),
+            debug_mode: DebugMode::Off,
+            break_pending: false,
+        }
+    }
+
+    pub fn publish(&self, kind: ExecutionEventKind) {
+        // A diff-run stops the execution once its recording is full
+        if !diff_run::record_execution(&kind) {
+            self.cancellation.cancel();
+        }
+        self.events.publish(ExecutionEvent::new(
+            &self.macro_name,
+            self.execution_id,
+            kind,
+        ));
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+
+    pub fn cancellation(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// Sleeps for `duration`, cut short if the macro is cancelled in the meantime.
+    pub fn sleep(&self, duration: Duration) {
+        self.clock.sleep(duration, &self.cancellation);
+    }
+
+    /// Sleeps until `deadline`, cut short if the macro is cancelled in the meantime.
+    pub fn sleep_until(&self, deadline: Instant) {
+        self.clock.sleep_until(deadline, &self.cancellation);
+    }
+
+    pub fn set_jitter(&mut self, jitter: Jitter) {
+        self.jitter = Some(jitter);
+    }
+
+    /// Replaces the real monitor layout that screen coordinates are checked against.
+    pub fn set_screen(&mut self, screen: Arc<dyn ScreenBackend>) {
+        self.screen = screen;
+    }
+
+    pub fn screen(&self) -> &dyn ScreenBackend {
+        self.screen.as_ref()
+    }
+
+    pub fn process_metrics(&self) -> &dyn ProcessMetrics {
+        self.process_metrics.as_ref()
+    }
+
+    /// Replaces the real processes that `WaitForProcessIdle` reads the CPU usage of.
+    pub fn set_process_metrics(&mut self, process_metrics: Arc<dyn ProcessMetrics>) {
+        self.process_metrics = process_metrics;
+    }
+
+    /// Replaces the real clock that waits and timeouts go by.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Replaces the random seed, which is otherwise drawn from entropy.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn set_capslock_off_for_text(&mut self, capslock_off_for_text: bool) {
+        self.capslock_off_for_text = capslock_off_for_text;
+    }
+
+    pub fn capslock_off_for_text(&self) -> bool {
+        self.capslock_off_for_text
+    }
+
+    pub fn set_strict_coordinates(&mut self, strict_coordinates: bool) {
+        self.strict_coordinates = strict_coordinates;
+    }
+
+    pub fn strict_coordinates(&self) -> bool {
+        self.strict_coordinates
+    }
+
+    pub fn set_base_dir(&mut self, base_dir: PathBuf) {
+        self.base_dir = base_dir;
+    }
+
+    /// Interpolates `path` and, if it is relative, puts it under `base_dir` rather than the
+    /// working directory, which is wherever the runner happened to be started from.
+    pub fn resolve_path(&self, path: &str) -> Result<PathBuf, anyhow::Error> {
+        Ok(self.base_dir.join(self.interpolate(path)?))
+    }
+
+    pub fn set_retry_budget(&mut self, retry_budget: Option<u32>) {
+        self.retry_budget = retry_budget;
+    }
+
+    pub fn retry_budget(&self) -> Option<u32> {
+        self.retry_budget
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    /// Counts a retry against the budget, returning `false`, without counting it, if the budget
+    /// is used up.
+    pub fn take_retry(&mut self) -> bool {
+        if self.retry_budget_exhausted() {
+            return false;
+        }
+        self.retries += 1;
+        true
+    }
+
+    /// Whether every retry of the budget has been made, after which failures end the macro.
+    pub fn retry_budget_exhausted(&self) -> bool {
+        self.retry_budget
+            .is_some_and(|retry_budget| self.retries >= retry_budget)
+    }
+
+    pub fn set_debug_mode(&mut self, debug_mode: DebugMode) {
+        self.debug_mode = debug_mode;
+    }
+
+    pub fn debug_mode(&self) -> DebugMode {
+        self.debug_mode
+    }
+
+    /// Makes the next command wait for the user, as after a `Breakpoint`.
+    pub fn break_before_next(&mut self) {
+        self.break_pending = true;
+    }
+
+    /// Whether the next command should wait for the user, clearing a pending breakpoint.
+    pub fn take_break(&mut self) -> bool {
+        let break_pending = std::mem::take(&mut self.break_pending);
+        match self.debug_mode {
+            DebugMode::Off => false,
+            DebugMode::Breakpoints => break_pending,
+            DebugMode::Step => true,
+        }
+    }
+
+    pub fn set_allowed_targets(&mut self, allowed_targets: Arc<Vec<AllowedTarget>>) {
+        self.allowed_targets = allowed_targets;
+    }
+
+    /// Blocks the user's input until the execution ends, see `InputBlock`.
+    pub fn block_user_input(&mut self) -> Result<(), anyhow::Error> {
+        if self.input_block.is_none() {
+            self.input_block = Some(InputBlock::start()?);
+        }
+        Ok(())
+    }
+
+    /// Fails with a `SafetyViolation` unless the foreground window is one of `allowed_targets`,
+    /// or no foreground window can be inspected. A window found allowed is trusted for
+    /// `TARGET_CHECK_INTERVAL`, so a burst of input costs a single check.
+    pub fn check_allowed_target(&mut self) -> Result<(), anyhow::Error> {
+        if self.allowed_targets.is_empty() {
+            return Ok(());
+        }
+
+        let now = self.now();
+        if self.target_allowed_until.is_some_and(|until| now < until) {
+            return Ok(());
+        }
+        self.target_allowed_until = None;
+
+        let window = match self.screen.foreground_window() {
+            Ok(Some(window)) => window,
+            Ok(None) => {
+                return Err(MacroError::SafetyViolation {
+                    window: "no window".to_string(),
+                }
+                .into())
+            }
+            Err(e) => {
+                return Err(MacroError::SafetyViolation {
+                    window: format!("a window that could not be inspected ({})", e),
+                }
+                .into())
+            }
+        };
+
+        if !self
+            .allowed_targets
+            .iter()
+            .any(|target| target.matches(&window))
+        {
+            return Err(MacroError::SafetyViolation {
+                window: window.to_string(),
+            }
+            .into());
+        }
+
+        self.target_allowed_until = Some(now + TARGET_CHECK_INTERVAL);
+        Ok(())
+    }
+
+    /// Sleeps for a random part of the macro's `jitter_ms`, if it has any.
+    pub fn jitter_delay(&mut self) {
+        if let Some(jitter) = self.jitter.as_ref() {
+            let delay = jitter.delay(&mut self.rng);
+            self.sleep(delay);
+        }
+    }
+
+    /// Moves a point by a random offset within the macro's `jitter_px`, if it has any.
+    pub fn jitter_point(&mut self, x: i32, y: i32) -> (i32, i32) {
+        match self.jitter.as_ref() {
+            Some(jitter) => {
+                let (dx, dy) = jitter.offset(&mut self.rng);
+                (x.saturating_add(dx), y.saturating_add(dy))
+            }
+            None => (x, y),
+        }
+    }
+
+    pub fn set_command_index(&self, command_index: usize) {
+        self.progress
+            .command_index
+            .store(command_index, Ordering::SeqCst);
+    }
+
+    /// Records and publishes how many of the `total` characters of the running text command
+    /// have been typed, or clears it with `None` once the command is done.
+    pub fn set_text_progress(&self, text: Option<(usize, usize)>) {
+        *self
+            .progress
+            .text
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = text;
+
+        if let Some((typed_chars, total_chars)) = text {
+            self.publish(ExecutionEventKind::TextProgress {
+                typed_chars,
+                total_chars,
+            });
+        }
+    }
+
+    /// The progress as it changes, for reading from other threads.
+    pub fn progress_handle(&self) -> Arc<Progress> {
+        self.progress.clone()
+    }
+
+    pub fn macros(&self) -> Arc<Vec<Macro>> {
+        self.macros.clone()
+    }
+
+    pub fn call_depth(&self) -> usize {
+        self.call_depth
+    }
+
+    pub fn enter_call(&mut self) {
+        self.call_depth += 1;
+    }
+
+    pub fn exit_call(&mut self) {
+        self.call_depth -= 1;
+    }
+
+    /// Blocks while macros are paused, returning early if this one is cancelled.
+    pub fn wait_while_paused(&self) {
+        while self.pause.is_paused() && !self.is_cancelled() {
+            self.sleep(PAUSE_POLL_INTERVAL);
+        }
+    }
+
+    /// Blocks until `keys` are all down together, having first waited for them to be let go if
+    /// they already are, as the hotkey that started the macro may still be. Shows as awaiting
+    /// resume in the progress meanwhile, and lets the user's input through if it is blocked.
+    /// Fails if the macro is cancelled while waiting.
+    pub fn wait_for_resume(&mut self, keys: &HashSet<Key>) -> Result<(), anyhow::Error> {
+        self.wait_for_keys(std::slice::from_ref(keys)).map(|_| ())
+    }
+
+    /// Like `wait_for_resume`, but for whichever of `choices` is pressed first, returning its
+    /// index.
+    pub fn wait_for_keys(&mut self, choices: &[HashSet<Key>]) -> Result<usize, anyhow::Error> {
+        self.progress.awaiting_resume.store(true, Ordering::SeqCst);
+        let input_block = self.input_block.take();
+
+        let mut released = false;
+        let result = loop {
+            if self.is_cancelled() {
+                break Err(MacroError::Cancelled.into());
+            }
+
+            let pressed = choices
+                .iter()
+                .position(|keys| keys.iter().all(|key| self.is_key_held(*key)));
+            match pressed {
+                None => released = true,
+                Some(index) if released => break Ok(index),
+                Some(_) => {}
+            }
+
+            self.sleep(PAUSE_POLL_INTERVAL);
+        };
+
+        self.input_block = input_block;
+        self.progress.awaiting_resume.store(false, Ordering::SeqCst);
+        result
+    }
+
+    pub fn is_key_held(&self, key: Key) -> bool {
+        self.backend.is_key_held(key)
+    }
+
+    /// Sends `events` through the input backend.
+    pub fn send_input(&self, events: &[InputEvent]) -> Result<(), anyhow::Error> {
+        self.backend.send(events)
+    }
+
+    /// Records that `key` is down, optionally auto-repeating it until `key_released`.
+    pub fn key_pressed(&mut self, key: Key, repeat: Option<KeyRepeat>) {
+        self.pressed_keys.insert(key);
+
+        if let Some(repeat) = repeat {
+            self.key_repeaters.insert(
+                key,
+                KeyRepeater::start(key, repeat, self.cancellation.clone()),
+            );
+        }
+    }
+
+    /// Records that `key` is up again, stopping its auto-repeat.
+    pub fn key_released(&mut self, key: Key) {
+        self.key_repeaters.remove(&key);
+        self.pressed_keys.remove(&key);
+    }
+
+    /// Records that a block wants `key` held. Returns whether the key still has to be put down,
+    /// i.e. no enclosing block is already holding it.
+    pub fn hold_key(&mut self, key: Key) -> bool {
+        let holds = self.key_holds.entry(key).or_default();
+        *holds += 1;
+
+        if *holds == 1 {
+            self.key_pressed(key, None);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Undoes a `hold_key`. Returns whether this was the last hold, so the key should be let up.
+    pub fn release_held_key(&mut self, key: Key) -> bool {
+        match self.key_holds.get_mut(&key) {
+            Some(holds) if *holds > 1 => {
+                *holds -= 1;
+                false
+            }
+            Some(_) => {
+                self.key_holds.remove(&key);
+                self.key_released(key);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Registers `action` to run when the macro ends. Deferred actions run in reverse order of
+    /// registration.
+    pub fn defer(&mut self, action: impl FnOnce() + Send + 'static) {
+        self.deferred.push(Box::new(action));
+    }
+
+    pub fn stop_key_repeats(&mut self) {
+        self.key_repeaters.clear();
+    }
+
+    /// Polls `condition` every `interval` until it holds or `timeout` passes. Returns whether
+    /// the condition was met, or an error if the macro was cancelled while waiting.
+    pub fn wait_for(
+        &self,
+        timeout: Duration,
+        interval: Duration,
+        mut condition: impl FnMut() -> Result<bool, anyhow::Error>,
+    ) -> Result<bool, anyhow::Error> {
+        let deadline = self.now() + timeout;
+
+        loop {
+            if self.is_cancelled() {
+                return Err(MacroError::Cancelled.into());
+            }
+
+            if condition()? {
+                return Ok(true);
+            }
+
+            let now = self.now();
+            if now >= deadline {
+                return Ok(false);
+            }
+
+            self.sleep_until(deadline.min(now + interval));
+        }
+    }
+
+    pub fn enter_loop(&mut self, name: Option<String>) {
+        self.loop_frames.push(LoopFrame { name, index: 0 });
+    }
+
+    pub fn set_loop_index(&mut self, index: u64) {
+        if let Some(frame) = self.loop_frames.last_mut() {
+            frame.index = index;
+        }
+    }
+
+    pub fn exit_loop(&mut self) {
+        self.loop_frames.pop();
+    }
+
+    /// Every variable set by commands so far, to be put back with `set_variables`.
+    pub fn variables(&self) -> HashMap<String, String> {
+        self.variables.clone()
+    }
+
+    pub fn set_variables(&mut self, variables: HashMap<String, String>) {
+        self.variables = variables;
+    }
+
+    pub fn set_variable(&mut self, name: &str, value: String) {
+        self.variables.insert(name.to_string(), value);
+    }
+
+    pub fn unset_variable(&mut self, name: &str) {
+        self.variables.remove(name);
+    }
+
+    /// Looks up a variable by name. `loop_index` (zero-based) and `loop_index1` (one-based) refer
+    /// to the innermost loop, `loop:<name>` to the zero-based index of the named enclosing loop.
+    /// `env:<name>` to an environment variable. Anything else is looked up among the arguments,
+    /// the built-ins set at macro start (see `screen::builtin_variables`, and `trigger`) and the
+    /// variables set by commands.
+    pub fn variable(&self, name: &str) -> Option<String> {
+        match name {
+            "loop_index" => self.loop_frames.last().map(|frame| frame.index.to_string()),
+            "loop_index1" => self
+                .loop_frames
+                .last()
+                .map(|frame| (frame.index + 1).to_string()),
+            _ => {
+                if let Some(loop_name) = name.strip_prefix("loop:") {
+                    self.loop_frames
+                        .iter()
+                        .rev()
+                        .find(|frame| frame.name.as_deref() == Some(loop_name))
+                        .map(|frame| frame.index.to_string())
+                } else if let Some(env_name) = name.strip_prefix("env:") {
+                    std::env::var(env_name).ok()
+                } else {
+                    self.variables.get(name).cloned()
+                }
+            }
+        }
+    }
+
+    /// Replaces every `${name}` in `text` with the value of that variable. Credentials may not be
+    /// used here, see `interpolate_secret`.
+    pub fn interpolate(&self, text: &str) -> Result<String, anyhow::Error> {
+        let mut result = String::with_capacity(text.len());
+        self.interpolate_into(text, &mut result, false)?;
+        Ok(result)
+    }
+
+    /// Like `interpolate`, but `${cred:<target>}` is also replaced, with the password of that
+    /// generic Windows Credential Manager entry, read now. The result is kept as a `Secret` so
+    /// that it never reaches the logs.
+    pub fn interpolate_secret(&self, text: &str) -> Result<Secret, anyhow::Error> {
+        // Built in place, so that even a partial result is wiped if a later variable fails
+        let mut result = Secret::default();
+        self.interpolate_into(text, result.expose_mut(), true)?;
+        Ok(result)
+    }
+
+    fn interpolate_into(
+        &self,
+        text: &str,
+        result: &mut String,
+        credentials: bool,
+    ) -> Result<(), anyhow::Error> {
+        for part in parse_template(text)? {
+            match part {
+                TemplatePart::Text(text) => result.push_str(text),
+                TemplatePart::Variable(name) => {
+                    if let Some(target) = name.strip_prefix("cred:") {
+                        if !credentials {
+                            return Err(MacroError::Validation(format!(
+                                "Credential {} can only be used in TextInputSecret, ReplaceText \
+                                 and Run env",
+                                target
+                            ))
+                            .into());
+                        }
+                        result.push_str(Secret::from_credential(target)?.expose());
+                    } else {
+                        let value = self.variable(name).ok_or_else(|| {
+                            MacroError::Validation(format!("Unknown variable {:?}", name))
+                        })?;
+                        result.push_str(&value);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A piece of a text that `${}` variables are filled into.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TemplatePart<'a> {
+    Text(&'a str),
+    /// The name between `${` and `}`.
+    Variable(&'a str),
+}
+
+/// Splits `text` into plain text and the `${name}` variables in it. `$${` stands for a literal
+/// `${`.
+pub fn parse_template(text: &str) -> Result<Vec<TemplatePart<'_>>, MacroError> {
+    let mut parts = Vec::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            parts.push(TemplatePart::Text(&rest[..start - 1]));
+            parts.push(TemplatePart::Text("${"));
+            rest = &rest[start + 2..];
+            continue;
+        }
+        parts.push(TemplatePart::Text(&rest[..start]));
+
+        let end = rest[start..].find('}').ok_or_else(|| {
+            MacroError::Validation(format!("Unterminated variable in {:?}", text))
+        })?;
+        parts.push(TemplatePart::Variable(&rest[start + 2..start + end]));
+
+        rest = &rest[start + end + 1..];
+    }
+
+    parts.push(TemplatePart::Text(rest));
+    parts.retain(|part| *part != TemplatePart::Text(""));
+
+    Ok(parts)
+}
+
+impl Drop for ExecutionContext {
+    /// Never leaves keys stuck down, however the macro ended.
+    fn drop(&mut self) {
+        self.stop_key_repeats();
+
+        while let Some(action) = self.deferred.pop() {
+            action();
+        }
+
+        for key in self.pressed_keys.drain() {
+            if let Err(e) = key_up(key as i32) {
+                log::error!("Failed to release {:?}: {}", key, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(text: &str) -> Vec<TemplatePart<'_>> {
+        parse_template(text).unwrap()
+    }
+
+    #[test]
+    fn parse_template_finds_variables() {
+        assert_eq!(
+            parts("Hello ${name}, ${greeting}!"),
+            [
+                TemplatePart::Text("Hello "),
+                TemplatePart::Variable("name"),
+                TemplatePart::Text(", "),
+                TemplatePart::Variable("greeting"),
+                TemplatePart::Text("!"),
+            ]
+        );
+        assert_eq!(
+            parts("${a}${b}"),
+            [TemplatePart::Variable("a"), TemplatePart::Variable("b")]
+        );
+        assert_eq!(parts("no variables"), [TemplatePart::Text("no variables")]);
+        assert_eq!(parts(""), []);
+        assert_eq!(parts("$5 {x}"), [TemplatePart::Text("$5 {x}")]);
+    }
+
+    #[test]
+    fn parse_template_escapes() {
+        assert_eq!(
+            parts("$${name} is ${name}"),
+            [
+                TemplatePart::Text("${"),
+                TemplatePart::Text("name} is "),
+                TemplatePart::Variable("name"),
+            ]
+        );
+        assert_eq!(
+            parts("cost: $$${price}"),
+            [
+                TemplatePart::Text("cost: $"),
+                TemplatePart::Text("${"),
+                TemplatePart::Text("price}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_template_rejects_unterminated_variables() {
+        assert!(matches!(
+            parse_template("Hello ${name"),
+            Err(MacroError::Validation(message)) if message.contains("Unterminated")
+        ));
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashSet;
+
+use super::{
+    context::{parse_template, ExecutionContext, TemplatePart},
+    error::MacroError,
+    expr,
+    keys::Key,
+    lint, Command,
+};
+
+/// What the user chose to do with the command an execution stopped before.
+pub enum StepAction {
+    Execute,
+    Skip,
+}
+
+/// Stops before `command` if `--step` is on or a `Breakpoint` was just passed under `--debug`,
+/// shows it, and waits for Enter to execute it, S to skip it or Q to abort the macro.
+pub fn before_command(
+    command: &Command,
+    context: &mut ExecutionContext,
+) -> Result<StepAction, anyhow::Error> {
+    if matches!(command, Command::Breakpoint) || !context.take_break() {
+        return Ok(StepAction::Execute);
+    }
+
+    log::info!(
+        "{} stopped before {}",
+        context.macro_name,
+        lint::to_inline_yaml(command)
+    );
+    for line in describe(command, context) {
+        log::info!("  {}", line);
+    }
+    log::info!("Press Enter to execute it, S to skip it or Q to abort");
+
+    let choices = [Key::Return, Key::S, Key::Q].map(|key| HashSet::from([key]));
+    match context.wait_for_keys(&choices)? {
+        0 => Ok(StepAction::Execute),
+        1 => {
+            log::info!("{} skipped the command", context.macro_name);
+            Ok(StepAction::Skip)
+        }
+        _ => {
+            log::info!("{} aborted", context.macro_name);
+            Err(MacroError::Cancelled.into())
+        }
+    }
+}
+
+/// The values `command` will use: its `${}` variables as they are now and where it will move the
+/// mouse, before any jitter.
+fn describe(command: &Command, context: &ExecutionContext) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    let mut seen = HashSet::new();
+    for (text, _) in command.templates() {
+        for part in parse_template(text).unwrap_or_default() {
+            let name = match part {
+                TemplatePart::Variable(name) => name,
+                TemplatePart::Text(_) => continue,
+            };
+            if name.starts_with("cred:") || !seen.insert(name) {
+                continue;
+            }
+            match context.variable(name) {
+                Some(value) => lines.push(format!("${{{}}} = {:?}", name, value)),
+                None => lines.push(format!("${{{}}} is not set", name)),
+            }
+        }
+    }
+
+    if let Command::SetMousePos(x, y) = command {
+        match (
+            x.resolve(context, expr::Axis::X),
+            y.resolve(context, expr::Axis::Y),
+        ) {
+            (Ok(x), Ok(y)) => lines.push(format!("moves the mouse to ({}, {})", x, y)),
+            (Err(e), _) | (_, Err(e)) => {
+                lines.push(format!("the mouse target cannot be resolved: {}", e))
+            }
+        }
+    }
+
+    lines
+}
@@ -0,0 +1,105 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use windows::Win32::{
+    Foundation::{HWND, RPC_E_CHANGED_MODE},
+    System::Com::{
+        CoCreateInstance, CoInitializeEx, CoUninitialize, CLSCTX_ALL, COINIT_MULTITHREADED,
+    },
+    UI::{
+        Shell::{IVirtualDesktopManager, VirtualDesktopManager},
+        WindowsAndMessaging::SetForegroundWindow,
+    },
+};
+
+/// Set once it has been logged that virtual desktops cannot be told apart, so that every macro
+/// thread does not log it again.
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+/// The virtual desktop manager of a thread, with COM initialized for it.
+struct ThreadDesktops {
+    /// `None` where it could not be created, e.g. before Windows 10.
+    manager: Option<IVirtualDesktopManager>,
+    /// Whether COM was initialized here, and so must be uninitialized when the thread ends.
+    uninitialize: bool,
+}
+
+impl ThreadDesktops {
+    fn new() -> Self {
+        let (initialized, uninitialize) =
+            match unsafe { CoInitializeEx(std::ptr::null(), COINIT_MULTITHREADED) } {
+                Ok(()) => (Ok(()), true),
+                // The thread already has COM in the other threading model, which works as well
+                Err(e) if e.code() == RPC_E_CHANGED_MODE => (Ok(()), false),
+                Err(e) => (Err(e), false),
+            };
+
+        let manager = initialized.and_then(|()| unsafe {
+            CoCreateInstance::<_, IVirtualDesktopManager>(&VirtualDesktopManager, None, CLSCTX_ALL)
+        });
+        let manager = match manager {
+            Ok(manager) => Some(manager),
+            Err(e) => {
+                if !WARNED.swap(true, Ordering::Relaxed) {
+                    log::warn!(
+                        "Virtual desktops cannot be told apart ({}), so windows are found on \
+                         every desktop",
+                        e
+                    );
+                }
+                None
+            }
+        };
+
+        ThreadDesktops {
+            manager,
+            uninitialize,
+        }
+    }
+}
+
+impl Drop for ThreadDesktops {
+    fn drop(&mut self) {
+        // The manager must be released while COM is still up
+        self.manager = None;
+        if self.uninitialize {
+            unsafe { CoUninitialize() };
+        }
+    }
+}
+
+thread_local! {
+    /// Created the first time a thread asks about virtual desktops, since COM is initialized per
+    /// thread and most macro threads never need it.
+    static DESKTOPS: ThreadDesktops = ThreadDesktops::new();
+}
+
+/// Whether `hwnd` is on the virtual desktop being shown. Always true where virtual desktops
+/// cannot be told apart.
+pub fn is_on_current_desktop(hwnd: HWND) -> bool {
+    DESKTOPS.with(|desktops| match &desktops.manager {
+        Some(manager) => match unsafe { manager.IsWindowOnCurrentVirtualDesktop(hwnd) } {
+            Ok(on_current_desktop) => on_current_desktop.as_bool(),
+            Err(e) => {
+                log::debug!("Failed to look up the virtual desktop of a window: {}", e);
+                true
+            }
+        },
+        None => true,
+    })
+}
+
+/// Shows the virtual desktop `hwnd` is on by activating it there, which makes Windows switch
+/// desktops rather than bring the window over. Returns whether the desktop changed.
+pub fn switch_to_window_desktop(hwnd: HWND) -> Result<bool, anyhow::Error> {
+    if is_on_current_desktop(hwnd) {
+        return Ok(false);
+    }
+
+    if !unsafe { SetForegroundWindow(hwnd) }.as_bool() {
+        return Err(anyhow::anyhow!(
+            "Windows refused to activate the window on its desktop"
+        ));
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,466 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+    INPUT, INPUT_KEYBOARD, INPUT_MOUSE, KEYEVENTF_KEYUP, KEYEVENTF_UNICODE, MOUSEEVENTF_ABSOLUTE,
+    MOUSEEVENTF_HWHEEL, MOUSEEVENTF_LEFTDOWN, MOUSEEVENTF_LEFTUP, MOUSEEVENTF_MIDDLEDOWN,
+    MOUSEEVENTF_MIDDLEUP, MOUSEEVENTF_MOVE, MOUSEEVENTF_RIGHTDOWN, MOUSEEVENTF_RIGHTUP,
+    MOUSEEVENTF_WHEEL, MOUSEEVENTF_XDOWN, MOUSEEVENTF_XUP, MOUSE_EVENT_FLAGS,
+};
+
+use super::{
+    backend::{SimulatedInput, SimulatedScreen},
+    clock::VirtualClock,
+    duration::DurationMs,
+    estimate::DurationEstimate,
+    events::{EventBus, ExecutionEventKind, TriggerSource},
+    executor::Executor,
+    Key, MacroConfig,
+};
+
+/// Seed of every `diff-run`, so that jitter and other randomness come out the same each time.
+/// A macro's own `jitter_seed` still takes precedence.
+const SEED: u64 = 0;
+
+/// Most events a `diff-run` records before stopping the macro, for macros that never end.
+const MAX_EVENTS: usize = 5000;
+
+/// Simulated time after which a `diff-run` stops the macro.
+const MAX_DURATION: Duration = Duration::from_secs(60 * 60);
+
+/// Timing differences up to this much are not reported, unless `--tolerance` says otherwise.
+pub const DEFAULT_TOLERANCE: DurationMs = DurationMs(10);
+
+/// Unchanged events shown around each change.
+const CONTEXT: usize = 3;
+
+/// One thing the macro did, `at_ms` into the simulated run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct RecordedEvent {
+    at_ms: u64,
+    event: String,
+}
+
+/// What a `diff-run` compares against, kept as `<macro>.baseline.json` next to the config.
+#[derive(Debug, Serialize, Deserialize)]
+struct Baseline {
+    macro_name: String,
+    seed: u64,
+    events: Vec<RecordedEvent>,
+}
+
+struct Recording {
+    clock: Arc<VirtualClock>,
+    events: Vec<RecordedEvent>,
+}
+
+/// The run being recorded, if this process is a `diff-run`. Global rather than per thread, so
+/// that input sent from helper threads, such as key repeats, is caught as well.
+static RECORDING: Mutex<Option<Recording>> = Mutex::new(None);
+
+/// Whether this process is recording a `diff-run`, in which case no input reaches the system.
+pub fn recording() -> bool {
+    RECORDING
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .is_some()
+}
+
+/// Adds `events` to the recording. Returns whether there is one, and whether it still has room.
+fn record(events: impl IntoIterator<Item = String>) -> (bool, bool) {
+    let mut recording = RECORDING.lock().unwrap_or_else(PoisonError::into_inner);
+    let recording = match recording.as_mut() {
+        Some(recording) => recording,
+        None => return (false, true),
+    };
+
+    let at_ms = recording.clock.elapsed_total().as_millis() as u64;
+    for event in events {
+        if recording.events.len() >= MAX_EVENTS {
+            return (true, false);
+        }
+        recording.events.push(RecordedEvent { at_ms, event });
+    }
+
+    (true, true)
+}
+
+fn describe_mouse(flags: MOUSE_EVENT_FLAGS, dx: i32, dy: i32, data: i32) -> Vec<String> {
+    let buttons = [
+        (MOUSEEVENTF_LEFTDOWN, "button_down left"),
+        (MOUSEEVENTF_LEFTUP, "button_up left"),
+        (MOUSEEVENTF_RIGHTDOWN, "button_down right"),
+        (MOUSEEVENTF_RIGHTUP, "button_up right"),
+        (MOUSEEVENTF_MIDDLEDOWN, "button_down middle"),
+        (MOUSEEVENTF_MIDDLEUP, "button_up middle"),
+    ];
+
+    let mut events = Vec::new();
+    if flags.0 & MOUSEEVENTF_MOVE.0 != 0 {
+        events.push(if flags.0 & MOUSEEVENTF_ABSOLUTE.0 != 0 {
+            format!("mouse_move_to {} {} (of 65535)", dx, dy)
+        } else {
+            format!("mouse_move_by {} {}", dx, dy)
+        });
+    }
+    for (flag, event) in buttons {
+        if flags.0 & flag.0 != 0 {
+            events.push(event.to_string());
+        }
+    }
+    if flags.0 & MOUSEEVENTF_XDOWN.0 != 0 {
+        events.push(format!("button_down x{}", data));
+    }
+    if flags.0 & MOUSEEVENTF_XUP.0 != 0 {
+        events.push(format!("button_up x{}", data));
+    }
+    if flags.0 & MOUSEEVENTF_WHEEL.0 != 0 {
+        events.push(format!("wheel {}", data));
+    }
+    if flags.0 & MOUSEEVENTF_HWHEEL.0 != 0 {
+        events.push(format!("hwheel {}", data));
+    }
+
+    events
+}
+
+fn describe_input(input: &INPUT) -> Vec<String> {
+    if input.r#type == INPUT_KEYBOARD {
+        let keyboard = unsafe { input.Anonymous.ki };
+        let up = keyboard.dwFlags.0 & KEYEVENTF_KEYUP.0 != 0;
+
+        // Typed text shows as the characters, the matching releases add nothing
+        if keyboard.dwFlags.0 & KEYEVENTF_UNICODE.0 != 0 {
+            return match (up, char::from_u32(keyboard.wScan as u32)) {
+                (true, _) => Vec::new(),
+                (false, Some(c)) => vec![format!("type {:?}", c)],
+                (false, None) => vec![format!("type U+{:04X}", keyboard.wScan)],
+            };
+        }
+
+        let key = Key::from(keyboard.wVk.0 as i32);
+        return vec![format!(
+            "{} {:?}",
+            if up { "key_up" } else { "key_down" },
+            key
+        )];
+    }
+
+    if input.r#type == INPUT_MOUSE {
+        let mouse = unsafe { input.Anonymous.mi };
+        return describe_mouse(mouse.dwFlags, mouse.dx, mouse.dy, mouse.mouseData);
+    }
+
+    Vec::new()
+}
+
+/// Records `inputs` instead of sending them when recording a `diff-run`. Returns whether they
+/// were recorded.
+pub fn record_inputs(inputs: &[INPUT]) -> bool {
+    record(inputs.iter().flat_map(describe_input)).0
+}
+
+/// Records a move of the cursor to `x`, `y` instead of making it when recording a `diff-run`.
+/// Returns whether it was recorded.
+pub fn record_cursor(x: i32, y: i32) -> bool {
+    record([format!("cursor_to {} {}", x, y)]).0
+}
+
+/// Records an execution event when recording a `diff-run`. Returns whether the execution may go
+/// on, which it may not once the recording is full.
+pub fn record_execution(kind: &ExecutionEventKind) -> bool {
+    let event = serde_json::to_string(kind).unwrap_or_else(|_| format!("{:?}", kind));
+    record([event]).1
+}
+
+/// Held by tests while they record, since there is only the one recording.
+#[cfg(test)]
+static TEST_RECORDING: Mutex<()> = Mutex::new(());
+
+/// Runs `run` with everything it sends recorded against `clock` rather than reaching the system,
+/// and returns its result with the recorded events, for tests.
+#[cfg(test)]
+pub fn record_for_test<T>(clock: Arc<VirtualClock>, run: impl FnOnce() -> T) -> (T, Vec<String>) {
+    let _recording = TEST_RECORDING
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    *RECORDING.lock().unwrap_or_else(PoisonError::into_inner) = Some(Recording {
+        clock,
+        events: Vec::new(),
+    });
+
+    let result = run();
+
+    let events = RECORDING
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take()
+        .map(|recording| recording.events)
+        .unwrap_or_default();
+    (
+        result,
+        events.into_iter().map(|recorded| recorded.event).collect(),
+    )
+}
+
+/// Runs the macro at `index` against simulated input, screen and time, returning what it did and
+/// how much simulated time that took.
+fn record_run(macro_config: MacroConfig, index: usize) -> (Vec<RecordedEvent>, Duration) {
+    let clock = Arc::new(VirtualClock::new(MAX_DURATION));
+    *RECORDING.lock().unwrap_or_else(PoisonError::into_inner) = Some(Recording {
+        clock: clock.clone(),
+        events: Vec::new(),
+    });
+
+    let mut executor = Executor::new(
+        macro_config.macros,
+        macro_config.max_macro_threads,
+        macro_config.worker_threads,
+        Arc::new(EventBus::default()),
+        Arc::new(SimulatedInput),
+        Arc::new(SimulatedScreen),
+        clock.clone(),
+    );
+    executor.set_seed(Some(SEED));
+    executor.run_inline(index, TriggerSource::Cli);
+
+    let events = RECORDING
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take()
+        .map(|recording| recording.events)
+        .unwrap_or_default();
+    (events, clock.elapsed_total())
+}
+
+/// How long the run took against how long the macro was estimated to take, e.g. `took 1.5s of
+/// simulated time, estimated 1.5s`.
+fn timing_summary(took: Duration, estimate: DurationEstimate) -> String {
+    format!(
+        "took {} of simulated time, estimated {}",
+        DurationMs(took.as_millis() as u64),
+        estimate
+    )
+}
+
+/// `<macro>.baseline.json` in the directory of the config file the macro comes from.
+fn baseline_path(macro_config: &MacroConfig, index: usize) -> PathBuf {
+    let current_macro = &macro_config.macros[index];
+    let dir = current_macro
+        .source
+        .as_deref()
+        .and_then(Path::parent)
+        .unwrap_or_else(|| Path::new(""));
+    let file_name: String = current_macro
+        .macro_name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect();
+
+    dir.join(format!("{}.baseline.json", file_name))
+}
+
+/// One step of turning the baseline into this run.
+enum Change<'a> {
+    Same(&'a RecordedEvent, &'a RecordedEvent),
+    Removed(&'a RecordedEvent),
+    Added(&'a RecordedEvent),
+}
+
+/// The shortest edit from `baseline` to `current`, matching events by what happened, not when.
+fn diff<'a>(baseline: &'a [RecordedEvent], current: &'a [RecordedEvent]) -> Vec<Change<'a>> {
+    // Common ends are matched up front to keep the table small
+    let prefix = baseline
+        .iter()
+        .zip(current.iter())
+        .take_while(|(old, new)| old.event == new.event)
+        .count();
+    let suffix = baseline[prefix..]
+        .iter()
+        .rev()
+        .zip(current[prefix..].iter().rev())
+        .take_while(|(old, new)| old.event == new.event)
+        .count();
+    let old = &baseline[prefix..baseline.len() - suffix];
+    let new = &current[prefix..current.len() - suffix];
+
+    // lengths[i][j] is the longest common subsequence of old[i..] and new[j..]
+    let mut lengths = vec![vec![0u32; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if old[i].event == new[j].event {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut changes: Vec<Change> = baseline[..prefix]
+        .iter()
+        .zip(current[..prefix].iter())
+        .map(|(old, new)| Change::Same(old, new))
+        .collect();
+
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i].event == new[j].event {
+            changes.push(Change::Same(&old[i], &new[j]));
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || lengths[i][j + 1] >= lengths[i + 1][j]) {
+            changes.push(Change::Added(&new[j]));
+            j += 1;
+        } else {
+            changes.push(Change::Removed(&old[i]));
+            i += 1;
+        }
+    }
+
+    changes.extend(
+        baseline[baseline.len() - suffix..]
+            .iter()
+            .zip(current[current.len() - suffix..].iter())
+            .map(|(old, new)| Change::Same(old, new)),
+    );
+
+    changes
+}
+
+/// Prints `changes` as a unified diff, with `CONTEXT` unchanged events around each difference.
+/// Events that only moved in time by more than `tolerance` count as changed. Returns how many
+/// differences there are.
+fn print_diff(changes: &[Change], tolerance: DurationMs, macro_name: &str) -> usize {
+    let differs = |change: &Change| match change {
+        Change::Same(old, new) => old.at_ms.abs_diff(new.at_ms) > tolerance.0,
+        Change::Removed(_) | Change::Added(_) => true,
+    };
+
+    let differences: Vec<usize> = (0..changes.len())
+        .filter(|index| differs(&changes[*index]))
+        .collect();
+    if differences.is_empty() {
+        return 0;
+    }
+
+    println!("--- {} baseline", macro_name);
+    println!("+++ {} this run", macro_name);
+
+    let mut shown_until = 0;
+    for (position, index) in differences.iter().enumerate() {
+        let start = index.saturating_sub(CONTEXT).max(shown_until);
+        // A new hunk starts unless this difference is within reach of the last one
+        if position == 0 || start > shown_until {
+            println!("@@ event {} @@", start + 1);
+        }
+
+        // Up to the next difference, which picks up from there
+        let end = (index + CONTEXT + 1)
+            .min(
+                differences
+                    .get(position + 1)
+                    .copied()
+                    .unwrap_or(changes.len()),
+            )
+            .min(changes.len());
+        for change in changes[start..end].iter() {
+            match change {
+                Change::Same(old, new) if old.at_ms.abs_diff(new.at_ms) > tolerance.0 => println!(
+                    "~ {:>8}ms  {}  ({:+}ms from {}ms)",
+                    new.at_ms,
+                    new.event,
+                    new.at_ms as i64 - old.at_ms as i64,
+                    old.at_ms
+                ),
+                Change::Same(_, new) => println!("  {:>8}ms  {}", new.at_ms, new.event),
+                Change::Removed(old) => println!("- {:>8}ms  {}", old.at_ms, old.event),
+                Change::Added(new) => println!("+ {:>8}ms  {}", new.at_ms, new.event),
+            }
+        }
+        shown_until = end;
+    }
+
+    differences.len()
+}
+
+/// Runs the macro `macro_name` against a simulated keyboard, screen and clock, with a fixed
+/// seed, and compares what it did with its baseline, printing the differences as a diff. Fails
+/// when there are any. With `update_baseline`, writes what it did as the new baseline instead.
+/// Either way, the summary puts the simulated time the run took next to the macro's estimate.
+///
+/// Nothing reaches the system: input is recorded rather than sent, waits take no real time, and
+/// commands that would touch real windows, processes, files, the clipboard or credentials fail
+/// instead. `on_success` and `on_failure` follow-ups are not run.
+pub fn diff_run(
+    mut macro_config: MacroConfig,
+    macro_name: &str,
+    update_baseline: bool,
+    tolerance: DurationMs,
+) -> Result<(), anyhow::Error> {
+    let index = macro_config
+        .macro_index(macro_name)
+        .ok_or_else(|| anyhow::anyhow!("No macro named {}", macro_name))?;
+    // Follow-ups would start on threads of their own, outside the run being compared
+    for current_macro in macro_config.macros.iter_mut() {
+        current_macro.on_success = None;
+        current_macro.on_failure = None;
+    }
+
+    let path = baseline_path(&macro_config, index);
+    let estimate = macro_config.macros[index].estimated_duration();
+    let (events, took) = record_run(macro_config, index);
+
+    if update_baseline {
+        let baseline = Baseline {
+            macro_name: macro_name.to_string(),
+            seed: SEED,
+            events,
+        };
+        std::fs::write(&path, serde_json::to_string_pretty(&baseline)?)
+            .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", path.display(), e))?;
+        println!(
+            "Wrote a baseline of {} events to {} ({})",
+            baseline.events.len(),
+            path.display(),
+            timing_summary(took, estimate)
+        );
+        return Ok(());
+    }
+
+    let baseline: Baseline = match std::fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(anyhow::anyhow!(
+                "No baseline at {}, record one with --update-baseline",
+                path.display()
+            ))
+        }
+        Err(e) => return Err(anyhow::anyhow!("Failed to read {}: {}", path.display(), e)),
+    };
+
+    match print_diff(&diff(&baseline.events, &events), tolerance, macro_name) {
+        0 => {
+            println!(
+                "{} matches its baseline ({} events, {})",
+                macro_name,
+                events.len(),
+                timing_summary(took, estimate)
+            );
+            Ok(())
+        }
+        differences => Err(anyhow::anyhow!(
+            "{} differs from its baseline in {} event(s) ({})",
+            macro_name,
+            differences,
+            timing_summary(took, estimate)
+        )),
+    }
+}
@@ -0,0 +1,133 @@
+use super::{get_cursor_pos, get_last_windows_error, set_cursor_pos};
+
+/// Outcome of a single `doctor` check.
+struct Check {
+    name: &'static str,
+    result: Result<String, anyhow::Error>,
+    /// What the user can do about a failure.
+    remediation: &'static str,
+}
+
+/// Runs every environment check, prints a report and returns whether all of them passed.
+pub fn run_doctor() -> bool {
+    let checks = [
+        Check {
+            name: "Process elevation",
+            result: check_elevation(),
+            remediation: "Macros cannot send input to elevated windows unless this program is \
+                          also run as administrator",
+        },
+        Check {
+            name: "SendInput",
+            result: check_send_input(),
+            remediation: "Input injection is blocked. This usually means UIPI, a secure desktop \
+                          (UAC prompt, lock screen) or security software is intercepting input",
+        },
+        Check {
+            name: "Cursor position round-trip",
+            result: check_cursor_round_trip(),
+            remediation: "The cursor could not be read or moved. Check for remote desktop \
+                          sessions or software that locks the cursor",
+        },
+        Check {
+            name: "Low-level keyboard hook",
+            result: check_keyboard_hook(),
+            remediation: "Global hooks are disabled on this machine, usually by group policy or \
+                          security software",
+        },
+    ];
+
+    let mut healthy = true;
+
+    for check in checks.iter() {
+        match &check.result {
+            Ok(detail) => println!("[pass] {}: {}", check.name, detail),
+            Err(e) => {
+                healthy = false;
+                println!("[fail] {}: {}", check.name, e);
+                println!("       hint: {}", check.remediation);
+            }
+        }
+    }
+
+    healthy
+}
+
+#[cfg(windows)]
+fn check_elevation() -> Result<String, anyhow::Error> {
+    Ok(if super::elevation::is_current_process_elevated()? {
+        "running elevated".to_string()
+    } else {
+        "not elevated, elevated windows will ignore macro input".to_string()
+    })
+}
+
+/// Sends a relative mouse move of (0, 0), which has no visible effect.
+#[cfg(windows)]
+fn check_send_input() -> Result<String, anyhow::Error> {
+    use windows::Win32::UI::Input::KeyboardAndMouse::{
+        SendInput, INPUT, INPUT_0, INPUT_MOUSE, MOUSEEVENTF_MOVE,
+    };
+
+    let mut input = INPUT {
+        r#type: INPUT_MOUSE,
+        Anonymous: INPUT_0::default(),
+    };
+
+    let mouse_input = unsafe { &mut input.Anonymous.mi };
+    mouse_input.dwFlags = MOUSEEVENTF_MOVE;
+
+    if unsafe { SendInput(&[input], std::mem::size_of::<INPUT>() as i32) } != 1 {
+        return Err(anyhow::anyhow!(
+            "SendInput inserted no events: {}",
+            get_last_windows_error()
+        ));
+    }
+
+    Ok("no-op mouse move accepted".to_string())
+}
+
+#[cfg(windows)]
+fn check_cursor_round_trip() -> Result<String, anyhow::Error> {
+    let before = get_cursor_pos()?;
+    set_cursor_pos(before.x, before.y)?;
+    let after = get_cursor_pos()?;
+
+    if before != after {
+        return Err(anyhow::anyhow!(
+            "cursor moved from ({}, {}) to ({}, {})",
+            before.x,
+            before.y,
+            after.x,
+            after.y
+        ));
+    }
+
+    Ok(format!("cursor at ({}, {})", after.x, after.y))
+}
+
+#[cfg(windows)]
+pub fn check_keyboard_hook() -> Result<String, anyhow::Error> {
+    use windows::core::PCWSTR;
+    use windows::Win32::Foundation::{LPARAM, LRESULT, WPARAM};
+    use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+    use windows::Win32::UI::WindowsAndMessaging::{
+        CallNextHookEx, SetWindowsHookExW, UnhookWindowsHookEx, HHOOK, WH_KEYBOARD_LL,
+    };
+
+    unsafe extern "system" fn pass_through(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+        CallNextHookEx(HHOOK::default(), code, wparam, lparam)
+    }
+
+    let module = unsafe { GetModuleHandleW(PCWSTR::null()) }?;
+    let hook = unsafe { SetWindowsHookExW(WH_KEYBOARD_LL, Some(pass_through), module, 0) }?;
+
+    if !unsafe { UnhookWindowsHookEx(hook) }.as_bool() {
+        return Err(anyhow::anyhow!(
+            "hook installed but could not be removed: {}",
+            get_last_windows_error()
+        ));
+    }
+
+    Ok("installed and removed".to_string())
+}
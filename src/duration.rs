@@ -0,0 +1,194 @@
+use std::{fmt, time::Duration};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// A duration in milliseconds, written in the config either as a bare number of milliseconds or
+/// as a string such as `"500ms"`, `"1.5s"`, `"2m"` or `"1m30s"`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DurationMs(pub u64);
+
+impl DurationMs {
+    pub fn as_duration(self) -> Duration {
+        Duration::from_millis(self.0)
+    }
+}
+
+/// Parses a sequence of `<number><unit>` parts, with units `ms`, `s`, `m` and `h`, into a number
+/// of milliseconds. A string holding only digits is taken as milliseconds.
+fn parse_duration(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let invalid = || {
+        format!(
+            "invalid duration {:?}, expected e.g. 500ms, 1.5s or 1m30s",
+            text
+        )
+    };
+
+    if let Ok(millis) = text.parse() {
+        return Ok(millis);
+    }
+
+    let mut total = 0.0;
+    let mut rest = text;
+
+    while !rest.is_empty() {
+        let number_end = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .ok_or_else(invalid)?;
+        let number: f64 = rest[..number_end].parse().map_err(|_| invalid())?;
+        rest = &rest[number_end..];
+
+        let unit_end = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let unit_millis = match rest[..unit_end].trim() {
+            "ms" => 1.0,
+            "s" => 1_000.0,
+            "m" => 60_000.0,
+            "h" => 3_600_000.0,
+            _ => return Err(invalid()),
+        };
+        rest = rest[unit_end..].trim_start();
+
+        total += number * unit_millis;
+    }
+
+    if text.is_empty() || !total.is_finite() || total > u64::MAX as f64 {
+        return Err(invalid());
+    }
+
+    Ok(total.round() as u64)
+}
+
+impl fmt::Display for DurationMs {
+    /// Writes the duration in the largest units that describe it exactly, e.g. `1m30s`, `1.5s`
+    /// or `250ms`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let millis = self.0;
+
+        if millis < 1_000 {
+            return write!(f, "{}ms", millis);
+        }
+
+        let hours = millis / 3_600_000;
+        let minutes = millis % 3_600_000 / 60_000;
+        let seconds = millis % 60_000 / 1_000;
+        let fraction = millis % 1_000;
+
+        if hours > 0 {
+            write!(f, "{}h", hours)?;
+        }
+        if minutes > 0 {
+            write!(f, "{}m", minutes)?;
+        }
+        if fraction > 0 {
+            let fraction = format!("{:03}", fraction);
+            write!(f, "{}.{}s", seconds, fraction.trim_end_matches('0'))?;
+        } else if seconds > 0 {
+            write!(f, "{}s", seconds)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Serialize for DurationMs {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for DurationMs {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DurationMsVisitor;
+
+        impl<'de> de::Visitor<'de> for DurationMsVisitor {
+            type Value = DurationMs;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a number of milliseconds or a duration such as \"1m30s\"")
+            }
+
+            fn visit_u64<E: de::Error>(self, millis: u64) -> Result<DurationMs, E> {
+                Ok(DurationMs(millis))
+            }
+
+            fn visit_i64<E: de::Error>(self, millis: i64) -> Result<DurationMs, E> {
+                u64::try_from(millis)
+                    .map(DurationMs)
+                    .map_err(|_| E::custom("duration cannot be negative"))
+            }
+
+            fn visit_str<E: de::Error>(self, text: &str) -> Result<DurationMs, E> {
+                parse_duration(text).map(DurationMs).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(DurationMsVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(yaml: &str) -> Result<DurationMs, serde_yaml::Error> {
+        serde_yaml::from_str(yaml)
+    }
+
+    #[test]
+    fn durations_read_as_milliseconds_or_with_units() {
+        for (yaml, millis) in [
+            ("90000", 90_000),
+            ("\"1500\"", 1_500),
+            ("500ms", 500),
+            ("1.5s", 1_500),
+            ("2m", 120_000),
+            ("1m30s", 90_000),
+            ("1m 30s", 90_000),
+            ("1h2m3.04s", 3_723_040),
+            ("0.0015s", 2),
+        ] {
+            assert_eq!(parse(yaml).unwrap(), DurationMs(millis), "{}", yaml);
+        }
+    }
+
+    #[test]
+    fn durations_write_in_the_largest_exact_units() {
+        for (millis, written) in [
+            (0, "0ms"),
+            (250, "250ms"),
+            (1_000, "1s"),
+            (1_500, "1.5s"),
+            (1_050, "1.05s"),
+            (90_000, "1m30s"),
+            (120_000, "2m"),
+            (3_600_000, "1h"),
+            (3_723_040, "1h2m3.04s"),
+        ] {
+            assert_eq!(DurationMs(millis).to_string(), written);
+            assert_eq!(
+                serde_yaml::to_string(&DurationMs(millis)).unwrap(),
+                format!("{}\n", written)
+            );
+        }
+    }
+
+    #[test]
+    fn durations_round_trip() {
+        for millis in [0, 1, 999, 1_000, 1_001, 59_999, 60_000, 90_500, 86_400_000] {
+            let written = serde_yaml::to_string(&DurationMs(millis)).unwrap();
+            assert_eq!(parse(&written).unwrap(), DurationMs(millis), "{}", written);
+        }
+    }
+
+    #[test]
+    fn invalid_durations_are_rejected() {
+        for yaml in ["-5", "1.5q", "5 sec", "ms", "m30s", "1..5s", "\"\""] {
+            assert!(parse(yaml).is_err(), "{}", yaml);
+        }
+
+        let e = parse("2x").unwrap_err().to_string();
+        assert!(e.contains("invalid duration \"2x\""), "{}", e);
+    }
+}
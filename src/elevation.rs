@@ -0,0 +1,196 @@
+use windows::Win32::Foundation::HANDLE;
+
+use super::get_last_windows_error;
+
+/// Whether the process behind `process` runs with an elevated (administrator) token.
+#[cfg(windows)]
+pub fn is_process_elevated(process: HANDLE) -> Result<bool, anyhow::Error> {
+    use windows::Win32::Foundation::CloseHandle;
+    use windows::Win32::Security::{
+        GetTokenInformation, TokenElevation, TOKEN_ELEVATION, TOKEN_QUERY,
+    };
+    use windows::Win32::System::Threading::OpenProcessToken;
+
+    let mut token = HANDLE::default();
+
+    if !unsafe { OpenProcessToken(process, TOKEN_QUERY, &mut token) }.as_bool() {
+        return Err(anyhow::anyhow!(
+            "Failed to open process token: {}",
+            get_last_windows_error()
+        ));
+    }
+
+    let mut elevation = TOKEN_ELEVATION::default();
+    let mut returned_length = 0;
+
+    let succeeded = unsafe {
+        GetTokenInformation(
+            token,
+            TokenElevation,
+            &mut elevation as *mut _ as *mut std::ffi::c_void,
+            std::mem::size_of::<TOKEN_ELEVATION>() as u32,
+            &mut returned_length,
+        )
+    }
+    .as_bool();
+    let error = get_last_windows_error();
+
+    unsafe { CloseHandle(token) };
+
+    if !succeeded {
+        return Err(anyhow::anyhow!(
+            "Failed to query token elevation: {}",
+            error
+        ));
+    }
+
+    Ok(elevation.TokenIsElevated != 0)
+}
+
+#[cfg(windows)]
+pub fn is_current_process_elevated() -> Result<bool, anyhow::Error> {
+    is_process_elevated(unsafe { windows::Win32::System::Threading::GetCurrentProcess() })
+}
+
+/// Fails when the foreground window belongs to a process with a higher integrity level than
+/// ours. Windows (UIPI) silently discards input sent to such windows.
+#[cfg(windows)]
+pub fn check_foreground_not_elevated() -> Result<(), anyhow::Error> {
+    use windows::Win32::Foundation::CloseHandle;
+    use windows::Win32::System::Threading::{OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION};
+    use windows::Win32::UI::WindowsAndMessaging::{GetForegroundWindow, GetWindowThreadProcessId};
+
+    if is_current_process_elevated()? {
+        return Ok(());
+    }
+
+    let hwnd = unsafe { GetForegroundWindow() };
+    if hwnd.0 == 0 {
+        return Ok(());
+    }
+
+    let mut process_id = 0;
+    unsafe { GetWindowThreadProcessId(hwnd, &mut process_id) };
+
+    // A process we are not even allowed to query is running above our integrity level
+    let elevated =
+        match unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, process_id) } {
+            Ok(process) => {
+                let elevated = is_process_elevated(process).unwrap_or(true);
+                unsafe { CloseHandle(process) };
+                elevated
+            }
+            Err(_) => true,
+        };
+
+    if elevated {
+        return Err(anyhow::anyhow!(
+            "Target window is elevated (process {}), input from this non-elevated runner would be \
+             discarded. Run as administrator or use --request-elevation",
+            process_id
+        ));
+    }
+
+    Ok(())
+}
+
+/// Starts a new, elevated copy of this program through the UAC prompt with the same arguments.
+#[cfg(windows)]
+pub fn relaunch_elevated() -> Result<(), anyhow::Error> {
+    use windows::core::{HSTRING, PCWSTR};
+    use windows::Win32::Foundation::HWND;
+    use windows::Win32::UI::Shell::ShellExecuteW;
+    use windows::Win32::UI::WindowsAndMessaging::SW_SHOWNORMAL;
+
+    let executable = std::env::current_exe()?;
+    let parameters = std::env::args()
+        .skip(1)
+        .map(|arg| quote_arg(&arg))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let result = unsafe {
+        ShellExecuteW(
+            HWND::default(),
+            &HSTRING::from("runas"),
+            &HSTRING::from(executable.as_os_str()),
+            &HSTRING::from(parameters),
+            PCWSTR::null(),
+            SW_SHOWNORMAL.0 as i32,
+        )
+    };
+
+    // ShellExecute reports success with any value above 32
+    if result.0 <= 32 {
+        return Err(anyhow::anyhow!(
+            "Failed to relaunch elevated: {}",
+            get_last_windows_error()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Quotes `arg` so that the C runtime, and `CommandLineToArgvW`, split it back out of a command
+/// line unchanged. Backslashes only escape a quote, so those before a quote, including the
+/// closing one, are doubled, and others are left alone.
+fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '\n', '\x0b', '"']) {
+        return arg.to_string();
+    }
+
+    let mut quoted = String::with_capacity(arg.len() + 2);
+    quoted.push('"');
+
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                quoted.extend(std::iter::repeat_n('\\', backslashes * 2 + 1));
+                quoted.push('"');
+                backslashes = 0;
+            }
+            _ => {
+                quoted.extend(std::iter::repeat_n('\\', backslashes));
+                quoted.push(c);
+                backslashes = 0;
+            }
+        }
+    }
+
+    quoted.extend(std::iter::repeat_n('\\', backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_arguments_are_left_alone() {
+        assert_eq!(quote_arg("--request-elevation"), "--request-elevation");
+        assert_eq!(quote_arg(r"C:\macros\work.yaml"), r"C:\macros\work.yaml");
+    }
+
+    #[test]
+    fn arguments_with_spaces_or_quotes_are_quoted() {
+        assert_eq!(quote_arg(""), r#""""#);
+        assert_eq!(
+            quote_arg(r"C:\My Macros\work.yaml"),
+            r#""C:\My Macros\work.yaml""#
+        );
+        assert_eq!(quote_arg(r#"say "hi""#), r#""say \"hi\"""#);
+    }
+
+    #[test]
+    fn backslashes_before_quotes_are_escaped() {
+        // A trailing backslash would otherwise escape the closing quote
+        assert_eq!(quote_arg(r"C:\My Macros\"), r#""C:\My Macros\\""#);
+        assert_eq!(quote_arg(r#"a\"b c"#), r#""a\\\"b c""#);
+        assert_eq!(quote_arg(r#"a\\"b c"#), r#""a\\\\\"b c""#);
+        // Backslashes elsewhere are taken literally
+        assert_eq!(quote_arg(r"a\\b c"), r#""a\\b c""#);
+    }
+}
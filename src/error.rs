@@ -0,0 +1,141 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Failures of command execution that callers may want to tell apart. Commands still return
+/// `anyhow::Error`, which carries one of these whenever the failure has a known kind, so that
+/// `kind_of` can recover it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MacroError {
+    /// A Win32 call failed, `code` is what `GetLastError` returned.
+    Win32 {
+        function: &'static str,
+        code: u32,
+    },
+    /// `SendInput` would not take every event of a batch, even after retrying.
+    InputDropped {
+        description: String,
+        dropped: usize,
+        total: usize,
+        code: u32,
+    },
+    /// Waited `waited_ms` for `what` without it happening.
+    Timeout {
+        what: String,
+        waited_ms: u64,
+    },
+    /// No window matches `pattern`, a `WindowSelector`.
+    WindowNotFound {
+        pattern: String,
+    },
+    /// The macro asked for something that can never work as written, such as an unknown variable
+    /// or an off-screen point. Running it again will not help.
+    Validation(String),
+    /// Input was about to reach `window`, which is not one of the config's `allowed_targets`.
+    SafetyViolation {
+        window: String,
+    },
+    /// Macros asked to inject input far faster than the config's `max_events_per_second` allows.
+    RateLimited {
+        max_events_per_second: u32,
+    },
+    Cancelled,
+}
+
+/// What kind of `MacroError` a failure was, as recorded in events and the history, and as
+/// matched by `RetryBlock`'s `retry_on`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Win32,
+    InputDropped,
+    Timeout,
+    WindowNotFound,
+    Validation,
+    SafetyViolation,
+    RateLimited,
+    Cancelled,
+    /// Any failure without a kind of its own.
+    Other,
+}
+
+impl MacroError {
+    /// A failure of the Win32 `function` just called, with the thread's last error code.
+    pub fn win32(function: &'static str) -> Self {
+        MacroError::Win32 {
+            function,
+            code: super::get_last_windows_error(),
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            MacroError::Win32 { .. } => ErrorKind::Win32,
+            MacroError::InputDropped { .. } => ErrorKind::InputDropped,
+            MacroError::Timeout { .. } => ErrorKind::Timeout,
+            MacroError::WindowNotFound { .. } => ErrorKind::WindowNotFound,
+            MacroError::Validation(_) => ErrorKind::Validation,
+            MacroError::SafetyViolation { .. } => ErrorKind::SafetyViolation,
+            MacroError::RateLimited { .. } => ErrorKind::RateLimited,
+            MacroError::Cancelled => ErrorKind::Cancelled,
+        }
+    }
+}
+
+impl fmt::Display for MacroError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MacroError::Win32 { function, code } => {
+                write!(f, "{} failed with error {}", function, code)
+            }
+            MacroError::InputDropped {
+                description,
+                dropped,
+                total,
+                code,
+            } => write!(
+                f,
+                "Failed to send {}: {} of {} events dropped: {}",
+                description, dropped, total, code
+            ),
+            MacroError::Timeout { what, waited_ms } => {
+                write!(f, "Timed out after {}ms waiting for {}", waited_ms, what)
+            }
+            MacroError::WindowNotFound { pattern } => {
+                write!(f, "No window found with {}", pattern)
+            }
+            MacroError::Validation(message) => f.write_str(message),
+            MacroError::SafetyViolation { window } => write!(
+                f,
+                "Stopped before sending input to {}, which is not in allowed_targets",
+                window
+            ),
+            MacroError::RateLimited {
+                max_events_per_second,
+            } => write!(
+                f,
+                "Stopped injecting input far faster than max_events_per_second ({})",
+                max_events_per_second
+            ),
+            MacroError::Cancelled => f.write_str("Cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for MacroError {}
+
+impl ErrorKind {
+    /// Whether a failure of this kind stops the macro at once, without retries, since going on
+    /// would defeat a safety limit.
+    pub fn aborts(self) -> bool {
+        matches!(self, ErrorKind::SafetyViolation | ErrorKind::RateLimited)
+    }
+}
+
+/// The kind of the `MacroError` behind `error`, `Other` if there is none.
+pub fn kind_of(error: &anyhow::Error) -> ErrorKind {
+    error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<MacroError>())
+        .map_or(ErrorKind::Other, MacroError::kind)
+}
@@ -0,0 +1,89 @@
+use std::{fmt, ops::Add, time::Duration};
+
+/// Rough cost assigned to commands that complete effectively instantly (clicks, key presses).
+pub const INSTANT_COMMAND_ESTIMATE: Duration = Duration::from_millis(1);
+
+/// How long a command or macro is expected to take.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DurationEstimate {
+    Exact(Duration),
+    Range { min: Duration, max: Duration },
+    Unbounded,
+}
+
+impl DurationEstimate {
+    pub fn zero() -> Self {
+        DurationEstimate::Exact(Duration::ZERO)
+    }
+
+    /// The estimate for running something with this estimate `count` times in a row.
+    pub fn repeated(self, count: u32) -> Self {
+        match self {
+            DurationEstimate::Exact(duration) => DurationEstimate::Exact(duration * count),
+            DurationEstimate::Range { min, max } => DurationEstimate::Range {
+                min: min * count,
+                max: max * count,
+            },
+            DurationEstimate::Unbounded => DurationEstimate::Unbounded,
+        }
+    }
+
+    /// The estimate for running exactly one of two alternatives.
+    pub fn either(self, other: Self) -> Self {
+        match (self, other) {
+            (DurationEstimate::Unbounded, _) | (_, DurationEstimate::Unbounded) => {
+                DurationEstimate::Unbounded
+            }
+            (a, b) if a == b => a,
+            (a, b) => DurationEstimate::Range {
+                min: a.min().min(b.min()).unwrap_or_default(),
+                max: a.max().max(b.max()).unwrap_or_default(),
+            },
+        }
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        match self {
+            DurationEstimate::Exact(duration) => Some(*duration),
+            DurationEstimate::Range { min, .. } => Some(*min),
+            DurationEstimate::Unbounded => None,
+        }
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        match self {
+            DurationEstimate::Exact(duration) => Some(*duration),
+            DurationEstimate::Range { max, .. } => Some(*max),
+            DurationEstimate::Unbounded => None,
+        }
+    }
+}
+
+impl Add for DurationEstimate {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        match (self, rhs) {
+            (DurationEstimate::Unbounded, _) | (_, DurationEstimate::Unbounded) => {
+                DurationEstimate::Unbounded
+            }
+            (DurationEstimate::Exact(a), DurationEstimate::Exact(b)) => {
+                DurationEstimate::Exact(a + b)
+            }
+            (a, b) => DurationEstimate::Range {
+                min: a.min().unwrap_or_default() + b.min().unwrap_or_default(),
+                max: a.max().unwrap_or_default() + b.max().unwrap_or_default(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for DurationEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DurationEstimate::Exact(duration) => write!(f, "{:?}", duration),
+            DurationEstimate::Range { min, max } => write!(f, "{:?} - {:?}", min, max),
+            DurationEstimate::Unbounded => write!(f, "unbounded"),
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    io::Write,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    error::ErrorKind,
+    logger::{self, LogFields},
+};
+
+/// Something that happened while running a macro.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExecutionEvent {
+    pub macro_name: String,
+    pub execution_id: u64,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    #[serde(flatten)]
+    pub kind: ExecutionEventKind,
+}
+
+/// What set a macro execution off.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerSource {
+    Hotkey,
+    Http,
+    /// Started by another macro's `on_success` or `on_failure`.
+    Chain,
+    Cli,
+    /// Started by the macro's `on_idle`.
+    Idle,
+    /// Picked from the list opened by `palette_hotkey`.
+    Palette,
+}
+
+impl TriggerSource {
+    /// The name used in the config, e.g. `cli`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TriggerSource::Hotkey => "hotkey",
+            TriggerSource::Http => "http",
+            TriggerSource::Chain => "chain",
+            TriggerSource::Cli => "cli",
+            TriggerSource::Idle => "idle",
+            TriggerSource::Palette => "palette",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ExecutionEventKind {
+    MacroStarted {
+        trigger: TriggerSource,
+        /// Seed of the execution's randomness, to repeat it with `--seed`.
+        seed: u64,
+    },
+    CommandStarted {
+        command_index: usize,
+    },
+    CommandFailed {
+        command_index: usize,
+        error: String,
+        kind: ErrorKind,
+    },
+    /// A text command has typed another chunk of its text.
+    TextProgress {
+        typed_chars: usize,
+        total_chars: usize,
+    },
+    MacroCompleted {
+        succeeded: bool,
+        /// Input events that had to be sent again because the input queue was full.
+        retried_events: u64,
+        /// Input events that could not be sent at all.
+        dropped_events: u64,
+        /// Retries made by `RetryBlock`s.
+        #[serde(default)]
+        retries: u32,
+        /// The macro's `retry_budget`, if it has one.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        retry_budget: Option<u32>,
+    },
+    MacroCancelled {
+        reason: String,
+    },
+}
+
+impl ExecutionEvent {
+    pub fn new(macro_name: &str, execution_id: u64, kind: ExecutionEventKind) -> Self {
+        ExecutionEvent {
+            macro_name: macro_name.to_string(),
+            execution_id,
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_millis() as u64)
+                .unwrap_or_default(),
+            kind,
+        }
+    }
+}
+
+/// Fans every published event out to all current subscribers.
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Mutex<Vec<Sender<ExecutionEvent>>>,
+}
+
+impl EventBus {
+    pub fn subscribe(&self) -> Receiver<ExecutionEvent> {
+        let (tx, rx) = unbounded();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    pub fn publish(&self, event: ExecutionEvent) {
+        // Subscribers that have gone away are dropped on the next publish
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
+
+/// Writes each event to the log, with the macro, execution and command it is about as
+/// `LogFields`. Runs until the bus is dropped.
+pub fn log_events(rx: Receiver<ExecutionEvent>) {
+    for event in rx {
+        let name = &event.macro_name;
+        let mut fields = LogFields::execution(name, event.execution_id);
+        match &event.kind {
+            ExecutionEventKind::CommandStarted { command_index } => {
+                fields.command_index = Some(*command_index)
+            }
+            ExecutionEventKind::CommandFailed {
+                command_index,
+                kind,
+                ..
+            } => {
+                fields.command_index = Some(*command_index);
+                fields.error_kind = Some(*kind);
+            }
+            _ => {}
+        }
+        let _scope = logger::scope(fields);
+
+        match &event.kind {
+            ExecutionEventKind::MacroStarted { trigger, seed } => {
+                log::info!("Running {} ({:?}, seed {})", name, trigger, seed)
+            }
+            ExecutionEventKind::CommandStarted { command_index } => {
+                log::debug!("{}: command {}", name, command_index)
+            }
+            ExecutionEventKind::CommandFailed {
+                command_index,
+                error,
+                ..
+            } => log::error!("{}: command {} failed: {}", name, command_index, error),
+            ExecutionEventKind::TextProgress {
+                typed_chars,
+                total_chars,
+            } => log::debug!(
+                "{}: typed {} of {} characters",
+                name,
+                typed_chars,
+                total_chars
+            ),
+            ExecutionEventKind::MacroCompleted {
+                succeeded,
+                retried_events,
+                dropped_events,
+                retries,
+                retry_budget,
+            } => {
+                let mut input = if *retried_events > 0 || *dropped_events > 0 {
+                    format!(
+                        " ({} input events retried, {} dropped)",
+                        retried_events, dropped_events
+                    )
+                } else {
+                    String::new()
+                };
+                if let Some(retry_budget) = retry_budget {
+                    input.push_str(&format!(
+                        " ({} of {} budgeted retries used)",
+                        retries, retry_budget
+                    ));
+                }
+
+                if *succeeded && *dropped_events == 0 {
+                    log::info!("{} completed{}", name, input)
+                } else {
+                    log::warn!("{} completed with errors{}", name, input)
+                }
+            }
+            ExecutionEventKind::MacroCancelled { reason } => {
+                log::error!("{} aborted: {}", name, reason)
+            }
+        }
+    }
+}
+
+/// Writes each event to stdout as one line of JSON, with logging moved to stderr by
+/// `logger::log_to_stderr` so that nothing else lands between them. Runs until the bus is dropped.
+pub fn write_events_ndjson(rx: Receiver<ExecutionEvent>) {
+    let stdout = std::io::stdout();
+
+    for event in rx {
+        match serde_json::to_string(&event) {
+            Ok(line) => {
+                let mut stdout = stdout.lock();
+                let _ = writeln!(stdout, "{}", line);
+                let _ = stdout.flush();
+            }
+            Err(e) => log::error!("Failed to serialize event: {}", e),
+        }
+    }
+}
@@ -0,0 +1,1273 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::Path,
+    sync::{Arc, Mutex, PoisonError},
+    thread::{spawn, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crossbeam::channel::{bounded, unbounded, Receiver, Sender};
+
+use super::{
+    apply_numlock_policy,
+    backend::{InputBackend, ScreenBackend},
+    clock::Clock,
+    context::{CancellationToken, DebugMode, ExecutionContext, PauseToken, Progress},
+    diff_run, elevation, error,
+    events::*,
+    jitter::Jitter,
+    logger::{self, LogFields},
+    rate_limit, screen, settle_modifiers, take_input_stats,
+    window::AllowedTarget,
+    Command, Macro, MacroMode, MutexPolicy,
+};
+
+/// Longest allowed run of `on_success`/`on_failure` follow-ups started from a single trigger.
+pub const MAX_CHAIN_DEPTH: u32 = 8;
+
+/// A macro that is currently running, or queued for a pool worker.
+struct Execution {
+    execution_id: u64,
+    /// How many chained follow-ups led to this execution, 0 for a direct trigger.
+    chain_depth: u32,
+    trigger: TriggerSource,
+    cancellation: CancellationToken,
+    started_at: Instant,
+    progress: Arc<Progress>,
+    /// Resolves to whether every command succeeded.
+    handle: ExecutionHandle,
+}
+
+enum ExecutionHandle {
+    /// The execution has a thread of its own.
+    Thread(JoinHandle<bool>),
+    /// The execution runs on a pool worker, which sends the result once it is done.
+    Pooled(Receiver<Option<bool>>),
+}
+
+impl ExecutionHandle {
+    fn is_finished(&self) -> bool {
+        match self {
+            ExecutionHandle::Thread(handle) => handle.is_finished(),
+            ExecutionHandle::Pooled(done) => !done.is_empty(),
+        }
+    }
+
+    /// Waits for the result, or `None` if the macro panicked.
+    fn join(self) -> Option<bool> {
+        match self {
+            ExecutionHandle::Thread(handle) => handle.join().ok(),
+            ExecutionHandle::Pooled(done) => done.recv().ok().flatten(),
+        }
+    }
+}
+
+/// An execution waiting for a pool worker.
+struct Job {
+    context: ExecutionContext,
+    index: usize,
+    trigger: TriggerSource,
+    /// The macro's `mutex`, held until the execution is over.
+    lock: Option<MacroLockGuard>,
+    /// Receives the result, `None` if the macro panicked.
+    done: Sender<Option<bool>>,
+}
+
+/// An execution waiting for another macro to release its `mutex`.
+struct QueuedExecution {
+    index: usize,
+    chain_depth: u32,
+    trigger: TriggerSource,
+    cancellation: CancellationToken,
+    context: Box<ExecutionContext>,
+}
+
+/// The named locks of `Macro::mutex` that running executions hold.
+#[derive(Debug, Clone, Default)]
+struct MacroLocks(Arc<Mutex<HashSet<String>>>);
+
+impl MacroLocks {
+    /// Takes the lock called `name`, unless another execution holds it.
+    fn try_acquire(&self, name: &str) -> Option<MacroLockGuard> {
+        let mut held = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if !held.insert(name.to_string()) {
+            return None;
+        }
+
+        Some(MacroLockGuard {
+            locks: self.clone(),
+            name: name.to_string(),
+        })
+    }
+
+    fn is_held(&self, name: &str) -> bool {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains(name)
+    }
+}
+
+/// Holds one of the `MacroLocks` until dropped, which also happens when the macro panics.
+#[derive(Debug)]
+struct MacroLockGuard {
+    locks: MacroLocks,
+    name: String,
+}
+
+impl Drop for MacroLockGuard {
+    fn drop(&mut self) {
+        self.locks
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.name);
+        log::debug!("Released mutex {}", self.name);
+    }
+}
+
+/// A fixed set of threads that run executions off a shared queue, so rapidly repeated triggers
+/// do not each pay for a new thread.
+struct WorkerPool {
+    jobs: Sender<Job>,
+    threads: usize,
+}
+
+impl WorkerPool {
+    fn new(threads: usize, macros: Arc<Vec<Macro>>) -> Self {
+        let (jobs, queue) = unbounded::<Job>();
+
+        for _ in 0..threads {
+            let queue = queue.clone();
+            let macros = macros.clone();
+            spawn(move || {
+                for job in queue {
+                    let Job {
+                        mut context,
+                        index,
+                        trigger,
+                        lock,
+                        done,
+                    } = job;
+                    // A panicking macro must not take the worker down with it
+                    let succeeded = catch_unwind(AssertUnwindSafe(|| {
+                        run_execution(&mut context, &macros, index, trigger)
+                    }))
+                    .ok();
+                    // Clean up, then release the mutex, before the result lets queued macros
+                    // try for it
+                    drop(context);
+                    drop(lock);
+                    let _ = done.send(succeeded);
+                }
+            });
+        }
+
+        WorkerPool { jobs, threads }
+    }
+}
+
+/// Starts macros, on their own threads or a worker pool, and keeps track of the ones still running.
+pub struct Executor {
+    macros: Arc<Vec<Macro>>,
+    max_macro_threads: usize,
+    running: HashMap<usize, Execution>,
+    /// Executions waiting for a `mutex` or a free worker, highest `priority` first, then oldest.
+    queued: VecDeque<QueuedExecution>,
+    /// Triggers of `defer_when_fullscreen` macros held back while a fullscreen app has the
+    /// focus, oldest first.
+    deferred: Vec<(usize, TriggerSource)>,
+    locks: MacroLocks,
+    /// When each macro last finished running.
+    completed_at: HashMap<usize, Instant>,
+    next_execution_id: u64,
+    events: Arc<EventBus>,
+    backend: Arc<dyn InputBackend>,
+    screen: Arc<dyn ScreenBackend>,
+    clock: Arc<dyn Clock>,
+    /// Shared by every execution, see `pause_all`.
+    pause: PauseToken,
+    /// Runs executions when `worker_threads` is configured, otherwise each gets its own thread.
+    pool: Option<WorkerPool>,
+    /// Random seed for every execution of a macro without a `jitter_seed` of its own.
+    seed: Option<u64>,
+    /// The only windows any macro may send input to, any window when empty.
+    allowed_targets: Arc<Vec<AllowedTarget>>,
+    /// Whether executions stop for the user between commands.
+    debug_mode: DebugMode,
+}
+
+impl Executor {
+    pub fn new(
+        macros: Vec<Macro>,
+        max_macro_threads: usize,
+        worker_threads: Option<usize>,
+        events: Arc<EventBus>,
+        backend: Arc<dyn InputBackend>,
+        screen: Arc<dyn ScreenBackend>,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        let macros = Arc::new(macros);
+
+        Executor {
+            pool: worker_threads.map(|threads| WorkerPool::new(threads, macros.clone())),
+            macros,
+            max_macro_threads,
+            running: HashMap::new(),
+            queued: VecDeque::new(),
+            deferred: Vec::new(),
+            locks: MacroLocks::default(),
+            completed_at: HashMap::new(),
+            next_execution_id: 1,
+            events,
+            backend,
+            screen,
+            clock,
+            pause: PauseToken::default(),
+            seed: None,
+            allowed_targets: Arc::default(),
+            debug_mode: DebugMode::Off,
+        }
+    }
+
+    /// Seeds every execution from now on with `seed` instead of entropy, so runs can be repeated.
+    pub fn set_seed(&mut self, seed: Option<u64>) {
+        self.seed = seed;
+    }
+
+    pub fn set_allowed_targets(&mut self, allowed_targets: Vec<AllowedTarget>) {
+        self.allowed_targets = Arc::new(allowed_targets);
+    }
+
+    pub fn set_debug_mode(&mut self, debug_mode: DebugMode) {
+        self.debug_mode = debug_mode;
+    }
+
+    pub fn macros(&self) -> &[Macro] {
+        &self.macros
+    }
+
+    pub fn backend(&self) -> &Arc<dyn InputBackend> {
+        &self.backend
+    }
+
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    pub fn screen(&self) -> &Arc<dyn ScreenBackend> {
+        &self.screen
+    }
+
+    pub fn is_running(&self, index: usize) -> bool {
+        self.running.contains_key(&index)
+    }
+
+    /// Whether the macro at `index` is waiting for another macro to release its `mutex`.
+    pub fn is_queued(&self, index: usize) -> bool {
+        self.queued.iter().any(|queued| queued.index == index)
+    }
+
+    /// Whether the macro at `index` was triggered while a fullscreen app had the focus and will
+    /// start once it has not.
+    pub fn is_deferred(&self, index: usize) -> bool {
+        self.deferred.iter().any(|(deferred, _)| *deferred == index)
+    }
+
+    /// Holds back a trigger of the macro at `index` by `trigger` until no fullscreen app has the
+    /// focus. A macro is only deferred once.
+    pub fn defer(&mut self, index: usize, trigger: TriggerSource) {
+        if self.is_deferred(index) {
+            return;
+        }
+
+        log::info!(
+            "Deferring {} until no fullscreen app has the focus",
+            self.macros[index].macro_name
+        );
+        self.deferred.push((index, trigger));
+    }
+
+    /// Starts the deferred triggers once no fullscreen app has the focus.
+    fn start_deferred(&mut self) {
+        if self.deferred.is_empty() || self.screen.foreground_fullscreen() {
+            return;
+        }
+
+        for (index, trigger) in std::mem::take(&mut self.deferred) {
+            if let Some(execution_id) = self.start(index, 0, trigger) {
+                log::info!(
+                    "[#{}] Starting deferred {}",
+                    execution_id,
+                    self.macros[index].macro_name
+                );
+            }
+        }
+    }
+
+    /// How far the macro at `index` has got and how long it has been running, if it is running.
+    pub fn progress(&self, index: usize) -> Option<(Arc<Progress>, Duration)> {
+        self.running.get(&index).map(|execution| {
+            (
+                execution.progress.clone(),
+                self.clock.elapsed(execution.started_at),
+            )
+        })
+    }
+
+    pub fn running_count(&self) -> usize {
+        self.running.len()
+    }
+
+    pub fn queued_count(&self) -> usize {
+        self.queued.len()
+    }
+
+    pub fn max_macro_threads(&self) -> usize {
+        self.max_macro_threads
+    }
+
+    pub fn last_completed(&self, index: usize) -> Option<Instant> {
+        self.completed_at.get(&index).copied()
+    }
+
+    /// Starts the macro at `index` unless it is already running or queued, or the thread cap is
+    /// reached. A macro whose `mutex` is held is queued or skipped per its `mutex_policy`, and one
+    /// that finds every worker busy is queued. A `preempt` macro first asks the lower-priority
+    /// executions in its way to stop, and is queued until they have. Returns the new execution
+    /// id, which a queued execution keeps once it starts.
+    pub fn start(&mut self, index: usize, chain_depth: u32, trigger: TriggerSource) -> Option<u64> {
+        self.start_with_args(index, chain_depth, trigger, HashMap::new())
+    }
+
+    /// Like `start`, binding `args` to the macro's parameters. Parameters not given take their
+    /// defaults.
+    pub fn start_with_args(
+        &mut self,
+        index: usize,
+        chain_depth: u32,
+        trigger: TriggerSource,
+        args: HashMap<String, String>,
+    ) -> Option<u64> {
+        let current_macro = &self.macros[index];
+
+        if self.running.contains_key(&index) || self.is_queued(index) {
+            log::warn!("Command already executing");
+            // TODO: Just warn or kill the thread?
+            return None;
+        }
+
+        let preempted = current_macro.preempt && self.preempt_for(index);
+
+        if self.running.len() >= self.max_macro_threads && !preempted {
+            log::warn!(
+                "Skipping {}: {} macro threads already running (max_macro_threads)",
+                current_macro.macro_name,
+                self.running.len()
+            );
+            return None;
+        }
+
+        let mutex_held = current_macro
+            .mutex
+            .as_deref()
+            .is_some_and(|mutex| self.locks.is_held(mutex));
+        if mutex_held && current_macro.mutex_policy == MutexPolicy::Skip && !preempted {
+            log::info!(
+                "Skipping {}: another macro holds mutex {}",
+                current_macro.macro_name,
+                current_macro.mutex.as_deref().unwrap_or_default()
+            );
+            return None;
+        }
+
+        let (context, cancellation) = self.build_context(index, &args)?;
+        let execution_id = context.execution_id;
+
+        let queued = QueuedExecution {
+            index,
+            chain_depth,
+            trigger,
+            cancellation,
+            context: Box::new(context),
+        };
+
+        if mutex_held || preempted || self.pool_full() {
+            let current_macro = &self.macros[index];
+            match current_macro.mutex.as_deref() {
+                Some(mutex) if mutex_held => log::info!(
+                    "[#{}] Queueing {} until mutex {} is released",
+                    execution_id,
+                    current_macro.macro_name,
+                    mutex
+                ),
+                _ => log::info!(
+                    "[#{}] Queueing {} until a thread is free",
+                    execution_id,
+                    current_macro.macro_name
+                ),
+            }
+            self.enqueue(queued);
+            return Some(execution_id);
+        }
+
+        self.launch(queued).ok().flatten()
+    }
+
+    /// Queues `queued` behind every queued execution of the same or higher priority.
+    fn enqueue(&mut self, queued: QueuedExecution) {
+        let priority = self.macros[queued.index].priority;
+        let position = self
+            .queued
+            .iter()
+            .position(|other| self.macros[other.index].priority < priority)
+            .unwrap_or(self.queued.len());
+        self.queued.insert(position, queued);
+    }
+
+    /// Whether every worker of the pool, if there is one, is taken.
+    fn pool_full(&self) -> bool {
+        self.pool
+            .as_ref()
+            .is_some_and(|pool| self.running.len() >= pool.threads)
+    }
+
+    /// Asks the running executions of lower priority than the macro at `index` that keep it from
+    /// starting to stop: those holding its `mutex` and, if no thread or worker is free, the one
+    /// of lowest priority. Returns whether any was asked.
+    fn preempt_for(&self, index: usize) -> bool {
+        let current_macro = &self.macros[index];
+        let lower: Vec<(usize, &Execution)> = self
+            .running
+            .iter()
+            .filter(|(running, execution)| {
+                self.macros[**running].priority < current_macro.priority
+                    && !execution.cancellation.is_cancelled()
+            })
+            .map(|(running, execution)| (*running, execution))
+            .collect();
+
+        let mut victims: Vec<(usize, &Execution)> = lower
+            .iter()
+            .filter(|(running, _)| {
+                current_macro.mutex.is_some() && self.macros[*running].mutex == current_macro.mutex
+            })
+            .copied()
+            .collect();
+
+        if self.running.len() >= self.max_macro_threads || self.pool_full() {
+            if let Some(lowest) = lower
+                .iter()
+                .min_by_key(|(running, _)| self.macros[*running].priority)
+            {
+                victims.push(*lowest);
+            }
+        }
+
+        for (running, execution) in victims.iter() {
+            log::info!(
+                "[#{}] Preempting {} for {}",
+                execution.execution_id,
+                self.macros[*running].macro_name,
+                current_macro.macro_name
+            );
+            execution.cancellation.cancel();
+        }
+
+        !victims.is_empty()
+    }
+
+    /// Sets up a fresh execution of the macro at `index` with `args` bound to its parameters.
+    fn build_context(
+        &mut self,
+        index: usize,
+        args: &HashMap<String, String>,
+    ) -> Option<(ExecutionContext, CancellationToken)> {
+        let current_macro = &self.macros[index];
+        let execution_id = self.next_execution_id;
+        self.next_execution_id += 1;
+
+        let cancellation = CancellationToken::default();
+        let mut context = ExecutionContext::new(
+            current_macro.macro_name.clone(),
+            execution_id,
+            self.events.clone(),
+            cancellation.clone(),
+            self.pause.clone(),
+            self.backend.clone(),
+            self.macros.clone(),
+        );
+        context.set_clock(self.clock.clone());
+        context.set_screen(self.screen.clone());
+        match current_macro.bind_args(args) {
+            Ok(bound) => context.set_variables(bound),
+            Err(e) => {
+                log::error!("Not starting {}: {}", current_macro.macro_name, e);
+                return None;
+            }
+        }
+        context.set_capslock_off_for_text(current_macro.capslock_off_for_text);
+        context.set_retry_budget(current_macro.retry_budget);
+        context.set_strict_coordinates(current_macro.strict_coordinates);
+        context.set_allowed_targets(self.allowed_targets.clone());
+        context.set_debug_mode(self.debug_mode);
+        if let Some(base_dir) = current_macro.source.as_deref().and_then(Path::parent) {
+            context.set_base_dir(base_dir.to_path_buf());
+        }
+        if let Some(seed) = current_macro.jitter_seed.or(self.seed) {
+            context.set_seed(seed);
+        }
+        if current_macro.jitter_ms > 0 || current_macro.jitter_px > 0 {
+            context.set_jitter(Jitter::new(
+                current_macro.jitter_ms,
+                current_macro.jitter_px,
+            ));
+        }
+
+        Some((context, cancellation))
+    }
+
+    /// Runs the macro at `index` to completion on the calling thread, for batch runs where
+    /// nothing else is running, so its mutex is not taken. Its `on_success`/`on_failure`
+    /// follow-up is started as usual once it is done. Returns whether every command succeeded,
+    /// or `None` if it could not be started.
+    pub fn run_inline(&mut self, index: usize, trigger: TriggerSource) -> Option<bool> {
+        let (mut context, _) = self.build_context(index, &HashMap::new())?;
+        let execution_id = context.execution_id;
+
+        let macros = self.macros.clone();
+        let succeeded = catch_unwind(AssertUnwindSafe(|| {
+            run_execution(&mut context, &macros, index, trigger)
+        }))
+        .unwrap_or_else(|_| {
+            log::error!(
+                "[#{}] Macro {} panicked",
+                execution_id,
+                self.macros[index].macro_name
+            );
+            false
+        });
+
+        self.completed_at.insert(index, self.clock.now());
+        self.start_follow_up(index, succeeded, execution_id, 0);
+
+        Some(succeeded)
+    }
+
+    /// Takes the macro's mutex and runs the execution, on a pool worker or a thread of its own.
+    /// Hands the execution back if the mutex has been taken in the meantime, and returns no id if
+    /// it could not be started at all.
+    fn launch(&mut self, queued: QueuedExecution) -> Result<Option<u64>, QueuedExecution> {
+        if self.pool_full() {
+            return Err(queued);
+        }
+
+        let QueuedExecution {
+            index,
+            chain_depth,
+            trigger,
+            cancellation,
+            context,
+        } = queued;
+        let current_macro = &self.macros[index];
+        let execution_id = context.execution_id;
+
+        let lock = match current_macro.mutex.as_deref() {
+            Some(mutex) => match self.locks.try_acquire(mutex) {
+                Some(lock) => {
+                    log::debug!("[#{}] Took mutex {}", execution_id, mutex);
+                    Some(lock)
+                }
+                None => {
+                    return Err(QueuedExecution {
+                        index,
+                        chain_depth,
+                        trigger,
+                        cancellation,
+                        context,
+                    })
+                }
+            },
+            None => None,
+        };
+
+        let mut context = *context;
+        let progress = context.progress_handle();
+        let handle = match &self.pool {
+            Some(pool) => {
+                let (done, done_rx) = bounded(1);
+                let job = Job {
+                    context,
+                    index,
+                    trigger,
+                    lock,
+                    done,
+                };
+                if pool.jobs.send(job).is_err() {
+                    log::error!(
+                        "Not starting {}: worker pool is gone",
+                        current_macro.macro_name
+                    );
+                    return Ok(None);
+                }
+                ExecutionHandle::Pooled(done_rx)
+            }
+            None => {
+                let macros = self.macros.clone();
+                ExecutionHandle::Thread(spawn(move || {
+                    // Moved in so that it is released however the thread ends
+                    let _lock = lock;
+                    let succeeded = run_execution(&mut context, &macros, index, trigger);
+                    // Clean up before the mutex is released
+                    drop(context);
+                    succeeded
+                }))
+            }
+        };
+
+        self.running.insert(
+            index,
+            Execution {
+                execution_id,
+                chain_depth,
+                trigger,
+                cancellation,
+                started_at: self.clock.now(),
+                progress,
+                handle,
+            },
+        );
+
+        Ok(Some(execution_id))
+    }
+
+    /// Starts, in order, the queued executions whose mutex has been released, as far as
+    /// `max_macro_threads` and the free workers allow.
+    fn start_queued(&mut self) {
+        for _ in 0..self.queued.len() {
+            if self.running.len() >= self.max_macro_threads {
+                return;
+            }
+
+            let queued = match self.queued.pop_front() {
+                Some(queued) => queued,
+                None => return,
+            };
+
+            if queued.cancellation.is_cancelled() {
+                log::info!(
+                    "[#{}] Dropping queued {}: cancelled",
+                    queued.context.execution_id,
+                    self.macros[queued.index].macro_name
+                );
+                continue;
+            }
+
+            if let Err(queued) = self.launch(queued) {
+                self.queued.push_back(queued);
+            }
+        }
+    }
+
+    /// Asks every running macro to stop at its next command, and drops every queued one.
+    pub fn cancel_all(&mut self) {
+        for execution in self.running.values() {
+            execution.cancellation.cancel();
+        }
+        self.queued.clear();
+        self.deferred.clear();
+    }
+
+    /// Asks the macro at `index` to stop at its next command if `source` started it, e.g. for
+    /// `mode: while_held` once the hotkey is released.
+    pub fn cancel_if_triggered_by(&self, index: usize, source: TriggerSource) {
+        if let Some(execution) = self.running.get(&index) {
+            if execution.trigger == source {
+                execution.cancellation.cancel();
+            }
+        }
+
+        for queued in self.queued.iter() {
+            if queued.index == index && queued.trigger == source {
+                queued.cancellation.cancel();
+            }
+        }
+    }
+
+    /// Holds every running macro, and any started from now on, at its next command boundary until
+    /// `resume_all`.
+    pub fn pause_all(&self) {
+        self.pause.pause();
+    }
+
+    pub fn resume_all(&self) {
+        self.pause.resume();
+    }
+
+    /// Joins every macro thread that has finished running so that only live threads are tracked,
+    /// then starts any `on_success`/`on_failure` follow-ups of the finished macros.
+    pub fn reap(&mut self) {
+        let finished: Vec<usize> = self
+            .running
+            .iter()
+            .filter(|(_, execution)| execution.handle.is_finished())
+            .map(|(index, _)| *index)
+            .collect();
+
+        for index in finished {
+            let execution = match self.running.remove(&index) {
+                Some(execution) => execution,
+                None => continue,
+            };
+
+            self.completed_at.insert(index, self.clock.now());
+
+            let succeeded = match execution.handle.join() {
+                Some(succeeded) => succeeded,
+                None => {
+                    log::error!(
+                        "[#{}] Macro {} panicked",
+                        execution.execution_id,
+                        self.macros[index].macro_name
+                    );
+                    false
+                }
+            };
+
+            self.start_follow_up(
+                index,
+                succeeded,
+                execution.execution_id,
+                execution.chain_depth,
+            );
+        }
+
+        self.start_queued();
+        self.start_deferred();
+    }
+
+    /// Starts the `on_success` or `on_failure` follow-up of the macro at `index`, if it has one.
+    fn start_follow_up(
+        &mut self,
+        index: usize,
+        succeeded: bool,
+        execution_id: u64,
+        chain_depth: u32,
+    ) {
+        let follow_up = if succeeded {
+            self.macros[index].on_success.clone()
+        } else {
+            self.macros[index].on_failure.clone()
+        };
+
+        if let Some(follow_up) = follow_up {
+            self.start_chained(&follow_up, execution_id, chain_depth);
+        }
+    }
+
+    fn start_chained(&mut self, macro_name: &str, parent_execution_id: u64, parent_depth: u32) {
+        if parent_depth >= MAX_CHAIN_DEPTH {
+            log::error!(
+                "[#{}] Not chaining into {}: chain depth limit of {} reached",
+                parent_execution_id,
+                macro_name,
+                MAX_CHAIN_DEPTH
+            );
+            return;
+        }
+
+        let index = match self
+            .macros
+            .iter()
+            .position(|current_macro| current_macro.macro_name == macro_name)
+        {
+            Some(index) => index,
+            None => {
+                log::error!(
+                    "[#{}] Chained macro {} does not exist",
+                    parent_execution_id,
+                    macro_name
+                );
+                return;
+            }
+        };
+
+        if !self.macros[index].enabled {
+            log::info!(
+                "[#{}] Not chaining into {}: macro is disabled",
+                parent_execution_id,
+                macro_name
+            );
+            return;
+        }
+
+        if let Some(execution_id) = self.start(index, parent_depth + 1, TriggerSource::Chain) {
+            log::info!(
+                "[#{}] Chained {} from #{} (depth {})",
+                execution_id,
+                macro_name,
+                parent_execution_id,
+                parent_depth + 1
+            );
+        }
+    }
+}
+
+/// Runs the macro at `index` from start to finish on the current thread. Returns whether all
+/// commands succeeded.
+fn run_execution(
+    context: &mut ExecutionContext,
+    macros: &[Macro],
+    index: usize,
+    trigger: TriggerSource,
+) -> bool {
+    let current_macro = &macros[index];
+    // Everything logged on this thread until the execution ends is about it
+    let _scope = logger::scope(LogFields::execution(
+        &current_macro.macro_name,
+        context.execution_id,
+    ));
+    // Waits for the input rate limit end when the execution is cancelled
+    let _cancellation = rate_limit::cancel_with(context.cancellation().clone());
+
+    // Arguments of the same name take precedence over the built-ins
+    let mut variables = screen::builtin_variables(context.screen());
+    variables.insert("trigger".to_string(), trigger.as_str().to_string());
+    variables.extend(context.variables());
+    context.set_variables(variables);
+
+    // A diff-run leaves the real lock keys and the user's input alone
+    let recording = diff_run::recording();
+    if !recording {
+        if let Err(e) = apply_numlock_policy(current_macro.ensure_numlock, context) {
+            log::error!("{}", e);
+        }
+    }
+
+    if let Err(e) = settle_modifiers(
+        current_macro.wait_for_clean_modifiers,
+        current_macro.modifier_grace_ms.as_duration(),
+        current_macro.neutralize_modifiers,
+        context,
+    ) {
+        log::error!("{}", e);
+    }
+
+    if current_macro.block_user_input && !recording {
+        if let Err(e) = context.block_user_input() {
+            log::error!("{}, running without it", e);
+        }
+    }
+
+    let repeat = current_macro.mode == MacroMode::WhileHeld && trigger == TriggerSource::Hotkey;
+
+    execute_macro(context, &current_macro.commands, trigger, repeat)
+}
+
+/// Runs every command of a macro, publishing events as it goes, over and over until cancelled
+/// with `repeat`. Returns whether all commands succeeded.
+fn execute_macro(
+    context: &mut ExecutionContext,
+    commands: &[Command],
+    trigger: TriggerSource,
+    repeat: bool,
+) -> bool {
+    context.publish(ExecutionEventKind::MacroStarted {
+        trigger,
+        seed: context.seed(),
+    });
+
+    // A diff-run sends nothing to the foreground window
+    if !diff_run::recording() {
+        if let Err(e) = elevation::check_foreground_not_elevated() {
+            context.publish(ExecutionEventKind::MacroCancelled {
+                reason: e.to_string(),
+            });
+            return false;
+        }
+    }
+
+    // Anything counted before belongs to an earlier execution on this thread
+    take_input_stats();
+
+    let mut succeeded = true;
+
+    loop {
+        for (command_index, command) in commands.iter().enumerate() {
+            context.wait_while_paused();
+
+            if context.is_cancelled() {
+                // Being stopped is how a repeating macro normally ends
+                if repeat {
+                    publish_completed(context, succeeded);
+                    return succeeded;
+                }
+
+                context.publish(ExecutionEventKind::MacroCancelled {
+                    reason: "cancelled".to_string(),
+                });
+                return false;
+            }
+
+            context.set_command_index(command_index);
+            logger::set_command_index(Some(command_index));
+            context.publish(ExecutionEventKind::CommandStarted { command_index });
+
+            if let Err(e) = command.execute(context) {
+                succeeded = false;
+                context.stop_key_repeats();
+
+                // Input must not go on once focus has left the allowed windows, or once the macro
+                // floods the input queue
+                let kind = error::kind_of(&e);
+                if kind.aborts() {
+                    context.publish(ExecutionEventKind::MacroCancelled {
+                        reason: e.to_string(),
+                    });
+                    return false;
+                }
+
+                context.publish(ExecutionEventKind::CommandFailed {
+                    command_index,
+                    error: e.to_string(),
+                    kind,
+                });
+
+                if context.retry_budget_exhausted() {
+                    log::warn!(
+                        "{}: retry budget used up, skipping the remaining commands",
+                        context.macro_name
+                    );
+                    publish_completed(context, false);
+                    return false;
+                }
+            }
+        }
+
+        if !repeat || commands.is_empty() {
+            break;
+        }
+    }
+
+    publish_completed(context, succeeded);
+
+    succeeded
+}
+
+/// Publishes the end of an execution along with the input events it had to retry or dropped and
+/// the retries it made.
+fn publish_completed(context: &ExecutionContext, succeeded: bool) {
+    let (retried_events, dropped_events) = take_input_stats();
+
+    context.publish(ExecutionEventKind::MacroCompleted {
+        succeeded,
+        retried_events,
+        dropped_events,
+        retries: context.retries(),
+        retry_budget: context.retry_budget(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::{SimulatedInput, SimulatedScreen},
+        builder::BuildCommands,
+        clock::{SystemClock, VirtualClock},
+        ErrorKind, Key, KeyState,
+    };
+
+    /// A macro `name` on `hotkey` that queues for the `ui` mutex, with `priority`.
+    fn queueing(name: &str, hotkey: Key, priority: u8) -> crate::builder::MacroBuilder {
+        Macro::builder(name)
+            .hotkey([Key::LeftControl, hotkey])
+            .mutex("ui")
+            .setting("mutex_policy", "queue")
+            .priority(priority)
+    }
+
+    /// An executor for `macros` on the simulated backend and the system clock, so that macros
+    /// keep running, and each other waiting, for real.
+    fn executor(macros: Vec<Macro>, max_macro_threads: usize, events: Arc<EventBus>) -> Executor {
+        Executor::new(
+            macros,
+            max_macro_threads,
+            None,
+            events,
+            Arc::new(SimulatedInput),
+            Arc::new(SimulatedScreen),
+            Arc::new(SystemClock),
+        )
+    }
+
+    /// Reaps `executor` until nothing is left running or queued.
+    fn run_out(executor: &mut Executor) {
+        let started = Instant::now();
+        while executor.running_count() > 0 || !executor.queued.is_empty() {
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "macros never finished"
+            );
+            executor.reap();
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn keys_down(recorded: &[String]) -> Vec<&str> {
+        recorded
+            .iter()
+            .filter_map(|event| event.strip_prefix("key_down "))
+            .collect()
+    }
+
+    #[test]
+    fn higher_priority_triggers_jump_the_queue() {
+        let macros = vec![
+            queueing("batch", Key::F1, 0)
+                .wait_ms(300)
+                .press(Key::B)
+                .build()
+                .unwrap(),
+            queueing("low", Key::F2, 0).press(Key::L).build().unwrap(),
+            queueing("later_low", Key::F3, 0)
+                .press(Key::M)
+                .build()
+                .unwrap(),
+            queueing("high", Key::F4, 5).press(Key::H).build().unwrap(),
+        ];
+        let mut executor = executor(macros, 4, Arc::default());
+
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60)));
+        let (_, recorded) = diff_run::record_for_test(clock, || {
+            for index in 0..4 {
+                assert!(executor.start(index, 0, TriggerSource::Cli).is_some());
+            }
+            assert!(executor.is_running(0));
+            assert!((1..4).all(|index| executor.is_queued(index)));
+
+            let queued: Vec<usize> = executor.queued.iter().map(|queued| queued.index).collect();
+            assert_eq!(queued, [3, 1, 2]);
+
+            run_out(&mut executor);
+        });
+
+        assert_eq!(keys_down(&recorded), ["B", "H", "L", "M"]);
+    }
+
+    #[test]
+    fn preempted_macros_clean_up_before_the_preemptor_starts() {
+        let macros = vec![
+            Macro::builder("batch")
+                .hotkey([Key::LeftControl, Key::F1])
+                .key_down(Key::LeftShift, None)
+                .wait_ms(10_000)
+                .press(Key::B)
+                .build()
+                .unwrap(),
+            Macro::builder("mute")
+                .hotkey([Key::LeftControl, Key::F2])
+                .priority(9)
+                .setting("preempt", true)
+                .press(Key::VolumeMute)
+                .build()
+                .unwrap(),
+        ];
+        let events = Arc::new(EventBus::default());
+        let batch_events = events.subscribe();
+        let mut executor = executor(macros, 1, events);
+
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60)));
+        let started = Instant::now();
+        let (_, recorded) = diff_run::record_for_test(clock, || {
+            executor.start(0, 0, TriggerSource::Cli).unwrap();
+            // Shift is down once the wait has started
+            while !matches!(
+                batch_events
+                    .recv_timeout(Duration::from_secs(5))
+                    .unwrap()
+                    .kind,
+                ExecutionEventKind::CommandStarted { command_index: 1 }
+            ) {}
+
+            assert!(executor.start(1, 0, TriggerSource::Cli).is_some());
+            // Waiting for the batch to let go, not skipped for want of a thread
+            assert!(executor.is_queued(1));
+
+            run_out(&mut executor);
+        });
+
+        let keys: Vec<&str> = recorded
+            .iter()
+            .map(String::as_str)
+            .filter(|event| event.starts_with("key_"))
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                "key_down LeftShift",
+                "key_up LeftShift",
+                "key_down VolumeMute",
+                "key_up VolumeMute",
+            ]
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn held_mutexes_skip_or_queue_only_their_own_macros() {
+        let macros = vec![
+            queueing("first", Key::F1, 0)
+                .wait_ms(300)
+                .press(Key::A)
+                .build()
+                .unwrap(),
+            queueing("second", Key::F2, 0)
+                .press(Key::B)
+                .build()
+                .unwrap(),
+            Macro::builder("skipped")
+                .hotkey([Key::LeftControl, Key::F3])
+                .mutex("ui")
+                .press(Key::S)
+                .build()
+                .unwrap(),
+            Macro::builder("unrelated")
+                .hotkey([Key::LeftControl, Key::F4])
+                .wait_ms(100)
+                .press(Key::U)
+                .build()
+                .unwrap(),
+        ];
+        let mut executor = executor(macros, 4, Arc::default());
+
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60)));
+        let (_, recorded) = diff_run::record_for_test(clock, || {
+            assert!(executor.start(0, 0, TriggerSource::Cli).is_some());
+            assert!(executor.start(1, 0, TriggerSource::Cli).is_some());
+            assert_eq!(executor.start(2, 0, TriggerSource::Cli), None);
+            assert!(executor.start(3, 0, TriggerSource::Cli).is_some());
+
+            assert!(executor.is_queued(1));
+            assert!(!executor.is_running(2) && !executor.is_queued(2));
+            // Runs alongside the macro holding the mutex
+            assert!(executor.is_running(0) && executor.is_running(3));
+
+            run_out(&mut executor);
+        });
+
+        assert_eq!(keys_down(&recorded), ["U", "A", "B"]);
+        assert!(!executor.locks.is_held("ui"));
+    }
+
+    #[test]
+    fn mutexes_are_released_when_the_macro_panics() {
+        let locks = MacroLocks::default();
+
+        let holder = {
+            let locks = locks.clone();
+            spawn(move || {
+                let _lock = locks.try_acquire("ui").unwrap();
+                panic!("macro panicked");
+            })
+        };
+        assert!(holder.join().is_err());
+
+        assert!(!locks.is_held("ui"));
+        let lock = locks.try_acquire("ui");
+        assert!(lock.is_some());
+        assert!(locks.try_acquire("ui").is_none());
+        drop(lock);
+        assert!(!locks.is_held("ui"));
+    }
+
+    #[test]
+    fn thousands_of_triggers_leave_nothing_behind() {
+        let macros = vec![Macro::builder("tap")
+            .hotkey([Key::LeftControl, Key::F1])
+            .press(Key::A)
+            .build()
+            .unwrap()];
+        let mut executor = executor(macros, 4, Arc::default());
+
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60)));
+        let (started, recorded) = diff_run::record_for_test(clock, || {
+            let mut started = 0;
+            for _ in 0..5000 {
+                if executor.start(0, 0, TriggerSource::Cli).is_some() {
+                    started += 1;
+                }
+                executor.reap();
+                assert!(executor.running_count() <= 1);
+            }
+            run_out(&mut executor);
+            started
+        });
+
+        assert!(started > 0);
+        assert_eq!(keys_down(&recorded).len(), started);
+        assert_eq!(executor.running_count(), 0);
+        assert!(executor.last_completed(0).is_some());
+        assert_eq!(executor.completed_at.len(), 1);
+    }
+
+    #[test]
+    fn failing_retries_publish_every_step_in_order() {
+        let macros = vec![Macro::builder("flaky")
+            .hotkey([Key::LeftControl, Key::F1])
+            .setting("retry_budget", 5)
+            .press(Key::A)
+            .retry(3, 0, 1.0, [], |block| {
+                block.assert_key_state(Key::B, KeyState::Down, false)
+            })
+            .build()
+            .unwrap()];
+        let events = Arc::new(EventBus::default());
+        let subscriber = events.subscribe();
+        let mut executor = executor(macros, 1, events);
+
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60)));
+        let (succeeded, _) =
+            diff_run::record_for_test(clock, || executor.run_inline(0, TriggerSource::Cli));
+        assert_eq!(succeeded, Some(false));
+
+        let kinds: Vec<ExecutionEventKind> =
+            subscriber.try_iter().map(|event| event.kind).collect();
+        assert_eq!(kinds.len(), 5, "{:?}", kinds);
+        assert!(matches!(
+            kinds[0],
+            ExecutionEventKind::MacroStarted {
+                trigger: TriggerSource::Cli,
+                ..
+            }
+        ));
+        assert!(matches!(
+            kinds[1],
+            ExecutionEventKind::CommandStarted { command_index: 0 }
+        ));
+        assert!(matches!(
+            kinds[2],
+            ExecutionEventKind::CommandStarted { command_index: 1 }
+        ));
+        assert!(matches!(
+            &kinds[3],
+            ExecutionEventKind::CommandFailed {
+                command_index: 1,
+                error,
+                kind: ErrorKind::Other,
+            } if error == "Expected B to be Down but it is not"
+        ));
+        // Two retries after the first attempt, both taken from the budget
+        assert!(matches!(
+            kinds[4],
+            ExecutionEventKind::MacroCompleted {
+                succeeded: false,
+                retries: 2,
+                retry_budget: Some(5),
+                ..
+            }
+        ));
+    }
+}
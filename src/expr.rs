@@ -0,0 +1,218 @@
+use serde::{Deserialize, Serialize};
+
+use super::context::ExecutionContext;
+
+/// A coordinate given either as a plain number or as an arithmetic expression over variables,
+/// e.g. `"200 + 32 * ${loop_index}"`. An expression may also be a percentage of the primary
+/// screen, e.g. `"50%"`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Coordinate {
+    Value(i32),
+    Expression(String),
+}
+
+/// Which screen dimension a percentage coordinate is taken of.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+}
+
+impl Axis {
+    fn extent_variable(self) -> &'static str {
+        match self {
+            Axis::X => "screen_width",
+            Axis::Y => "screen_height",
+        }
+    }
+}
+
+impl From<i32> for Coordinate {
+    fn from(value: i32) -> Self {
+        Coordinate::Value(value)
+    }
+}
+
+impl From<&str> for Coordinate {
+    fn from(expression: &str) -> Self {
+        Coordinate::Expression(expression.to_string())
+    }
+}
+
+impl Coordinate {
+    pub fn resolve(&self, context: &ExecutionContext, axis: Axis) -> Result<i32, anyhow::Error> {
+        match self {
+            Coordinate::Value(value) => Ok(*value),
+            Coordinate::Expression(expression) => {
+                let interpolated = context.interpolate(expression)?;
+
+                if let Some(percentage) = interpolated.trim().strip_suffix('%') {
+                    if let Ok(percentage) = percentage.trim().parse::<f64>() {
+                        let extent: f64 = context
+                            .variable(axis.extent_variable())
+                            .and_then(|extent| extent.parse().ok())
+                            .ok_or_else(|| anyhow::anyhow!("Screen size is unknown"))?;
+                        return Ok((extent * percentage / 100.0).round() as i32);
+                    }
+                }
+
+                let value = evaluate(&interpolated)?;
+                i32::try_from(value).map_err(|_| {
+                    anyhow::anyhow!(
+                        "{} evaluates to {}, which is out of range",
+                        expression,
+                        value
+                    )
+                })
+            }
+        }
+    }
+}
+
+/// Evaluates an integer expression made of numbers, `+ - * / %`, unary minus and parentheses.
+pub fn evaluate(expression: &str) -> Result<i64, anyhow::Error> {
+    let mut parser = Parser {
+        input: expression.as_bytes(),
+        position: 0,
+    };
+
+    let value = parser.expression()?;
+    parser.skip_whitespace();
+
+    if parser.position != parser.input.len() {
+        return Err(anyhow::anyhow!(
+            "Unexpected {:?} at position {} in {:?}",
+            parser.input[parser.position] as char,
+            parser.position,
+            expression
+        ));
+    }
+
+    Ok(value)
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.position < self.input.len() && self.input[self.position].is_ascii_whitespace() {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.input.get(self.position).copied()
+    }
+
+    fn error(&self, expected: &str) -> anyhow::Error {
+        anyhow::anyhow!(
+            "Expected {} at position {} in {:?}",
+            expected,
+            self.position,
+            String::from_utf8_lossy(self.input)
+        )
+    }
+
+    /// expression := term (('+' | '-') term)*
+    fn expression(&mut self) -> Result<i64, anyhow::Error> {
+        let mut value = self.term()?;
+
+        while let Some(operator @ (b'+' | b'-')) = self.peek() {
+            self.position += 1;
+            let rhs = self.term()?;
+            value = if operator == b'+' {
+                value.checked_add(rhs)
+            } else {
+                value.checked_sub(rhs)
+            }
+            .ok_or_else(|| anyhow::anyhow!("Arithmetic overflow"))?;
+        }
+
+        Ok(value)
+    }
+
+    /// term := factor (('*' | '/' | '%') factor)*
+    fn term(&mut self) -> Result<i64, anyhow::Error> {
+        let mut value = self.factor()?;
+
+        while let Some(operator @ (b'*' | b'/' | b'%')) = self.peek() {
+            self.position += 1;
+            let rhs = self.factor()?;
+            value = match operator {
+                b'*' => value.checked_mul(rhs),
+                b'/' => value.checked_div(rhs),
+                _ => value.checked_rem(rhs),
+            }
+            .ok_or_else(|| anyhow::anyhow!("Arithmetic overflow or division by zero"))?;
+        }
+
+        Ok(value)
+    }
+
+    /// factor := '-' factor | '(' expression ')' | number
+    fn factor(&mut self) -> Result<i64, anyhow::Error> {
+        match self.peek() {
+            Some(b'-') => {
+                self.position += 1;
+                self.factor()?
+                    .checked_neg()
+                    .ok_or_else(|| anyhow::anyhow!("Arithmetic overflow"))
+            }
+            Some(b'(') => {
+                self.position += 1;
+                let value = self.expression()?;
+                if self.peek() != Some(b')') {
+                    return Err(self.error("')'"));
+                }
+                self.position += 1;
+                Ok(value)
+            }
+            Some(b'0'..=b'9') => {
+                let start = self.position;
+                while self.position < self.input.len() && self.input[self.position].is_ascii_digit()
+                {
+                    self.position += 1;
+                }
+                std::str::from_utf8(&self.input[start..self.position])?
+                    .parse()
+                    .map_err(|e| anyhow::anyhow!("Invalid number: {}", e))
+            }
+            _ => Err(self.error("a number")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evaluate_follows_precedence() {
+        assert_eq!(evaluate("200 + 32 * 3").unwrap(), 296);
+        assert_eq!(evaluate("(200 + 32) * 3").unwrap(), 696);
+        assert_eq!(evaluate("-5 - -5").unwrap(), 0);
+        assert_eq!(evaluate("7 % 4 / 2").unwrap(), 1);
+    }
+
+    #[test]
+    fn negation_overflow_is_an_error() {
+        assert_eq!(evaluate("-9223372036854775807 - 1").unwrap(), i64::MIN);
+        assert_eq!(
+            evaluate("-(-9223372036854775807 - 1)")
+                .unwrap_err()
+                .to_string(),
+            "Arithmetic overflow"
+        );
+    }
+
+    #[test]
+    fn division_by_zero_is_an_error() {
+        assert!(evaluate("1 / 0").is_err());
+        assert!(evaluate("1 % 0").is_err());
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    sync::{Mutex, MutexGuard},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use windows::Win32::UI::Input::XboxController::{
+    XInputGetState, XINPUT_GAMEPAD_TRIGGER_THRESHOLD, XINPUT_STATE, XUSER_MAX_COUNT,
+};
+
+use super::Key;
+
+/// Controller state older than this is read again, so that every poll of the input listener sees
+/// fresh state while the many keys it polls share one read.
+const STATE_MAX_AGE: Duration = Duration::from_millis(10);
+/// How often empty controller slots are checked for a newly connected controller. Reading an empty
+/// slot is slow, so they are not read on every poll.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
+/// How often `list-gamepads` redraws the button state.
+const LIST_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Buttons held on one controller.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GamepadState {
+    buttons: u16,
+    left_trigger: bool,
+    right_trigger: bool,
+}
+
+impl GamepadState {
+    /// Reads the controller in slot `index`, `None` if no controller is connected there.
+    fn read(index: u32) -> Option<Self> {
+        let mut state = XINPUT_STATE::default();
+        if unsafe { XInputGetState(index, &mut state) } != 0 {
+            return None;
+        }
+
+        let pulled = |trigger: u8| u32::from(trigger) > XINPUT_GAMEPAD_TRIGGER_THRESHOLD;
+        Some(GamepadState {
+            buttons: state.Gamepad.wButtons,
+            left_trigger: pulled(state.Gamepad.bLeftTrigger),
+            right_trigger: pulled(state.Gamepad.bRightTrigger),
+        })
+    }
+
+    pub fn is_held(&self, key: Key) -> bool {
+        match key {
+            Key::GamepadLeftTrigger => self.left_trigger,
+            Key::GamepadRightTrigger => self.right_trigger,
+            key => key
+                .gamepad_button()
+                .is_some_and(|mask| self.buttons & mask != 0),
+        }
+    }
+
+    pub fn held_keys(&self) -> Vec<Key> {
+        (Key::GamepadA as i32..=Key::GamepadRightStick as i32)
+            .map(Key::from)
+            .filter(|key| self.is_held(*key))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Slot {
+    state: Option<GamepadState>,
+    checked_at: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+struct Snapshot {
+    read_at: Option<Instant>,
+    slots: [Slot; XUSER_MAX_COUNT as usize],
+}
+
+/// The XInput controllers, read at most once per `STATE_MAX_AGE` however many keys are polled.
+/// Controllers may come and go at any time, which is logged once per change.
+#[derive(Debug, Default)]
+pub struct Gamepads {
+    snapshot: Mutex<Snapshot>,
+}
+
+impl Gamepads {
+    fn refresh(&self) -> MutexGuard<'_, Snapshot> {
+        let mut snapshot = self
+            .snapshot
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let now = Instant::now();
+        if snapshot
+            .read_at
+            .is_some_and(|read_at| now.duration_since(read_at) < STATE_MAX_AGE)
+        {
+            return snapshot;
+        }
+        snapshot.read_at = Some(now);
+
+        for (index, slot) in snapshot.slots.iter_mut().enumerate() {
+            let recently_empty = slot.state.is_none()
+                && slot
+                    .checked_at
+                    .is_some_and(|checked_at| now.duration_since(checked_at) < RECONNECT_INTERVAL);
+            if recently_empty {
+                continue;
+            }
+
+            let state = GamepadState::read(index as u32);
+            match (slot.state, state) {
+                (None, Some(_)) => log::info!("Controller {} connected", index),
+                (Some(_), None) => log::warn!("Controller {} disconnected", index),
+                _ => {}
+            }
+
+            slot.state = state;
+            slot.checked_at = Some(now);
+        }
+
+        snapshot
+    }
+
+    /// Whether `key` is held on any connected controller.
+    pub fn is_held(&self, key: Key) -> bool {
+        self.refresh()
+            .slots
+            .iter()
+            .filter_map(|slot| slot.state)
+            .any(|state| state.is_held(key))
+    }
+
+    /// Every connected controller by slot.
+    pub fn connected(&self) -> Vec<(usize, GamepadState)> {
+        self.refresh()
+            .slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| slot.state.map(|state| (index, state)))
+            .collect()
+    }
+}
+
+/// Shows the connected controllers and the buttons held on each, refreshed until interrupted, for
+/// finding the names to put in a `macro_hotkey`.
+pub fn list_gamepads() -> Result<(), anyhow::Error> {
+    let gamepads = Gamepads::default();
+
+    loop {
+        // Clear the screen and move to the top left
+        print!("\x1b[2J\x1b[H");
+
+        let connected = gamepads.connected();
+        if connected.is_empty() {
+            println!("No controllers connected");
+        }
+
+        for (index, state) in connected {
+            let held: Vec<String> = state
+                .held_keys()
+                .iter()
+                .map(|key| format!("{:?}", key))
+                .collect();
+            println!("Controller {}\t{}", index, held.join("+"));
+        }
+
+        std::io::Write::flush(&mut std::io::stdout())?;
+        sleep(LIST_INTERVAL);
+    }
+}
@@ -0,0 +1,357 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crossbeam::channel::Receiver;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    duration::DurationMs,
+    error::ErrorKind,
+    events::{ExecutionEvent, ExecutionEventKind, TriggerSource},
+};
+
+/// Where to keep a record of every macro execution.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryConfig {
+    pub path: PathBuf,
+    /// Once the file grows past this size, the oldest records are dropped until it is half as big.
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: u64,
+}
+
+fn default_max_bytes() -> u64 {
+    1024 * 1024
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// Only the start was recorded, the macro is still running or the runner stopped before it
+    /// finished.
+    Started,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+/// One line of the history file. A record is written when a macro starts and again, complete,
+/// when it ends, and the later line for the same execution replaces the earlier one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryRecord {
+    pub execution_id: u64,
+    pub macro_name: String,
+    pub trigger: TriggerSource,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Milliseconds since the Unix epoch.
+    pub started_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ended_ms: Option<u64>,
+    pub outcome: Outcome,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+    /// The kind of each of `errors`, in the same order. Empty in records written before kinds
+    /// were recorded.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub error_kinds: Vec<ErrorKind>,
+    /// How many retries the execution made, recorded for macros with a `retry_budget`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    /// The macro's `retry_budget`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_budget: Option<u32>,
+}
+
+impl HistoryRecord {
+    /// Execution ids restart with every run of the program, the start time tells them apart.
+    fn key(&self) -> (u64, u64) {
+        (self.started_ms, self.execution_id)
+    }
+}
+
+fn open_for_append(path: &Path) -> Result<BufWriter<File>, anyhow::Error> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map(BufWriter::new)
+        .map_err(|e| anyhow::anyhow!("Failed to open history {}: {}", path.display(), e))
+}
+
+fn append_record(
+    writer: &mut BufWriter<File>,
+    record: &HistoryRecord,
+) -> Result<(), anyhow::Error> {
+    writeln!(writer, "{}", serde_json::to_string(record)?)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Drops the oldest lines of the history file until it is at most half of `max_bytes`, if it
+/// has grown past `max_bytes`. Returns whether the file was rewritten.
+fn truncate_history(path: &Path, max_bytes: u64) -> Result<bool, anyhow::Error> {
+    if std::fs::metadata(path)?.len() <= max_bytes {
+        return Ok(false);
+    }
+
+    let contents = std::fs::read_to_string(path)?;
+    let mut kept = Vec::new();
+    let mut kept_bytes = 0;
+
+    for line in contents.lines().rev() {
+        kept_bytes += line.len() as u64 + 1;
+        if kept_bytes > max_bytes / 2 {
+            break;
+        }
+        kept.push(line);
+    }
+
+    kept.reverse();
+    let mut truncated = kept.join("\n");
+    if !truncated.is_empty() {
+        truncated.push('\n');
+    }
+    std::fs::write(path, truncated)?;
+
+    Ok(true)
+}
+
+/// Records each execution to the history file. Runs until the bus is dropped.
+pub fn write_history(rx: Receiver<ExecutionEvent>, config: HistoryConfig) {
+    let mut writer = match open_for_append(&config.path) {
+        Ok(writer) => writer,
+        Err(e) => {
+            log::error!("{}", e);
+            return;
+        }
+    };
+    let mut in_progress: HashMap<u64, HistoryRecord> = HashMap::new();
+
+    for event in rx {
+        let record = match event.kind {
+            ExecutionEventKind::MacroStarted { trigger, seed } => {
+                let record = HistoryRecord {
+                    execution_id: event.execution_id,
+                    macro_name: event.macro_name,
+                    trigger,
+                    seed: Some(seed),
+                    started_ms: event.timestamp_ms,
+                    ended_ms: None,
+                    outcome: Outcome::Started,
+                    errors: Vec::new(),
+                    error_kinds: Vec::new(),
+                    retries: None,
+                    retry_budget: None,
+                };
+                in_progress.insert(event.execution_id, record.clone());
+                record
+            }
+            ExecutionEventKind::CommandFailed { error, kind, .. } => {
+                if let Some(record) = in_progress.get_mut(&event.execution_id) {
+                    record.errors.push(error);
+                    record.error_kinds.push(kind);
+                }
+                continue;
+            }
+            ExecutionEventKind::MacroCompleted {
+                succeeded,
+                retries,
+                retry_budget,
+                ..
+            } => match in_progress.remove(&event.execution_id) {
+                Some(mut record) => {
+                    record.ended_ms = Some(event.timestamp_ms);
+                    record.retries = retry_budget.map(|_| retries);
+                    record.retry_budget = retry_budget;
+                    record.outcome = if succeeded {
+                        Outcome::Succeeded
+                    } else {
+                        Outcome::Failed
+                    };
+                    record
+                }
+                None => continue,
+            },
+            ExecutionEventKind::MacroCancelled { reason } => {
+                match in_progress.remove(&event.execution_id) {
+                    Some(mut record) => {
+                        record.ended_ms = Some(event.timestamp_ms);
+                        record.outcome = Outcome::Cancelled;
+                        record.errors.push(reason);
+                        record.error_kinds.push(ErrorKind::Cancelled);
+                        record
+                    }
+                    None => continue,
+                }
+            }
+            ExecutionEventKind::CommandStarted { .. } | ExecutionEventKind::TextProgress { .. } => {
+                continue
+            }
+        };
+
+        if let Err(e) = append_record(&mut writer, &record) {
+            log::error!("Failed to write history: {}", e);
+        }
+
+        if record.outcome != Outcome::Started {
+            match truncate_history(&config.path, config.max_bytes) {
+                Ok(true) => match open_for_append(&config.path) {
+                    Ok(reopened) => writer = reopened,
+                    Err(e) => log::error!("{}", e),
+                },
+                Ok(false) => {}
+                Err(e) => log::error!("Failed to truncate history: {}", e),
+            }
+        }
+    }
+}
+
+/// Every execution in the history file, oldest first, each with its latest record.
+fn read_history(path: &Path) -> Result<Vec<HistoryRecord>, anyhow::Error> {
+    let file = File::open(path)
+        .map_err(|e| anyhow::anyhow!("Failed to open history {}: {}", path.display(), e))?;
+
+    let mut records: Vec<HistoryRecord> = Vec::new();
+    let mut positions = HashMap::new();
+
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record: HistoryRecord = match serde_json::from_str(&line) {
+            Ok(record) => record,
+            Err(e) => {
+                log::warn!("Skipping unreadable history line: {}", e);
+                continue;
+            }
+        };
+
+        match positions.get(&record.key()) {
+            Some(position) => records[*position] = record,
+            None => {
+                positions.insert(record.key(), records.len());
+                records.push(record);
+            }
+        }
+    }
+
+    Ok(records)
+}
+
+/// Totals over the recorded executions of one macro.
+#[derive(Debug, Clone, Default)]
+pub struct MacroStats {
+    pub runs: usize,
+    /// Executions that failed or were cancelled.
+    pub failures: usize,
+    /// Summed duration of the executions that have finished, and how many those are.
+    finished_ms: u64,
+    finished: usize,
+    /// Milliseconds since the Unix epoch.
+    pub last_started_ms: Option<u64>,
+}
+
+impl MacroStats {
+    pub fn average_duration(&self) -> Option<DurationMs> {
+        (self.finished > 0).then(|| DurationMs(self.finished_ms / self.finished as u64))
+    }
+}
+
+/// Statistics for every macro that appears in the history, by macro name.
+pub fn macro_stats(path: &Path) -> Result<HashMap<String, MacroStats>, anyhow::Error> {
+    let mut stats: HashMap<String, MacroStats> = HashMap::new();
+
+    for record in read_history(path)? {
+        let macro_stats = stats.entry(record.macro_name).or_default();
+
+        macro_stats.runs += 1;
+        if matches!(record.outcome, Outcome::Failed | Outcome::Cancelled) {
+            macro_stats.failures += 1;
+        }
+        if let Some(ended_ms) = record.ended_ms {
+            macro_stats.finished_ms += ended_ms.saturating_sub(record.started_ms);
+            macro_stats.finished += 1;
+        }
+        macro_stats.last_started_ms = macro_stats.last_started_ms.max(Some(record.started_ms));
+    }
+
+    Ok(stats)
+}
+
+/// Formats milliseconds since the Unix epoch as `YYYY-MM-DD HH:MM:SS` in UTC.
+pub fn format_timestamp(timestamp_ms: u64) -> String {
+    let seconds = timestamp_ms / 1000;
+    let days = (seconds / 86_400) as i64;
+    let time_of_day = seconds % 86_400;
+
+    // Civil date from days since the epoch, after Howard Hinnant's `civil_from_days`
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time_of_day / 3_600,
+        time_of_day % 3_600 / 60,
+        time_of_day % 60
+    )
+}
+
+/// Prints the history, optionally only the last `last` executions of `macro_name`.
+pub fn print_history(
+    path: &Path,
+    last: Option<usize>,
+    macro_name: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    let records: Vec<HistoryRecord> = read_history(path)?
+        .into_iter()
+        .filter(|record| macro_name.is_none_or(|name| record.macro_name == name))
+        .collect();
+    let skip = last.map_or(0, |last| records.len().saturating_sub(last));
+
+    for record in records.iter().skip(skip) {
+        let duration = record
+            .ended_ms
+            .map(|ended_ms| DurationMs(ended_ms.saturating_sub(record.started_ms)).to_string())
+            .unwrap_or_else(|| "-".to_string());
+
+        println!(
+            "{} UTC\t{}\t{}\t{:?}\t{:?}",
+            format_timestamp(record.started_ms),
+            duration,
+            record.macro_name,
+            record.trigger,
+            record.outcome
+        );
+
+        if let (Some(retries), Some(retry_budget)) = (record.retries, record.retry_budget) {
+            println!("\t{} of {} budgeted retries used", retries, retry_budget);
+        }
+
+        for error in record.errors.iter() {
+            println!("\t{}", error);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,398 @@
+use std::{
+    collections::HashSet,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        mpsc::{channel, Sender},
+        Arc,
+    },
+    time::Duration,
+};
+
+use serde::{de::IntoDeserializer, Deserialize, Serialize};
+
+use super::{
+    events::TriggerSource,
+    keys::{deserialize_keys, format_keys},
+    profile::ActiveProfile,
+    startup::StartupReport,
+    Key, Message,
+};
+
+/// How long a request waits for the input listener to answer.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Opt-in HTTP endpoint for listing and triggering macros remotely.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpConfig {
+    /// Address to listen on, e.g. `127.0.0.1:7878`.
+    pub bind: String,
+    /// Every request must carry `Authorization: Bearer <token>`.
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MacroStatus {
+    pub name: String,
+    pub enabled: bool,
+    pub running: bool,
+    /// Waiting for another macro to release its `mutex`.
+    pub queued: bool,
+    /// Triggered while a fullscreen app had the focus, waiting for it to lose the focus.
+    #[serde(default)]
+    pub deferred: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mutex: Option<String>,
+    /// Zero-based index of the top-level command a running macro is on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command_index: Option<usize>,
+    /// How long a running macro has been running, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elapsed_ms: Option<u64>,
+    /// Stopped at a `Pause` command, waiting for its resume key.
+    #[serde(default)]
+    pub paused: bool,
+    /// Percentage of its text a running text command has typed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub typed_percent: Option<u8>,
+}
+
+/// A macro whose hotkey matched a simulated press, see `POST /press`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PressMatch {
+    pub macro_name: String,
+    /// Id of the execution that was started.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub execution_id: Option<u64>,
+    /// Why the macro was not started.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rejected: Option<String>,
+}
+
+/// What became of a remote trigger.
+#[derive(Debug, Clone)]
+pub enum TriggerOutcome {
+    Started,
+    /// Held back until no fullscreen app has the focus, see `defer_when_fullscreen`.
+    Deferred,
+    NotFound,
+    /// The macro exists but may not run right now, e.g. because it is cooling down.
+    Rejected(String),
+}
+
+struct Request {
+    method: String,
+    path: String,
+    authorization: Option<String>,
+}
+
+fn read_request(stream: &TcpStream) -> Result<Request, anyhow::Error> {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Err(anyhow::anyhow!("Malformed request line {:?}", request_line)),
+    };
+
+    let mut authorization = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("authorization") {
+                authorization = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    Ok(Request {
+        method,
+        path,
+        authorization,
+    })
+}
+
+/// Decodes the `%XX` escapes in a path segment, `None` when they are malformed or do not make
+/// UTF-8.
+fn percent_decode(segment: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(segment.len());
+    let mut rest = segment.as_bytes();
+    while let Some((&byte, after)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = after.get(..2)?;
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            bytes.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            rest = &after[2..];
+        } else {
+            bytes.push(byte);
+            rest = after;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Escapes everything in `segment` but unreserved characters, for use as a path segment.
+fn percent_encode(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'+' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Compares `a` and `b` in time that depends only on their lengths, so that timing answers
+/// reveal nothing about how much of a guessed token was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn write_response(
+    mut stream: &TcpStream,
+    status: &str,
+    body: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let body = body.unwrap_or_default();
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+
+    Ok(())
+}
+
+fn error_body(message: &str) -> Option<String> {
+    Some(serde_json::json!({ "error": message }).to_string())
+}
+
+fn handle(
+    stream: &TcpStream,
+    config: &HttpConfig,
+    tx: &Sender<Message>,
+    startup_report: &StartupReport,
+) -> Result<(), anyhow::Error> {
+    stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+
+    let request = read_request(stream)?;
+    log::debug!("HTTP {} {}", request.method, request.path);
+
+    let expected = format!("Bearer {}", config.token);
+    let authorized = request
+        .authorization
+        .as_deref()
+        .is_some_and(|authorization| {
+            constant_time_eq(authorization.as_bytes(), expected.as_bytes())
+        });
+    if !authorized {
+        return write_response(stream, "401 Unauthorized", error_body("invalid token"));
+    }
+
+    let segments: Option<Vec<String>> = request
+        .path
+        .trim_matches('/')
+        .split('/')
+        .map(percent_decode)
+        .collect();
+    let segments = match segments {
+        Some(segments) => segments,
+        None => return write_response(stream, "400 Bad Request", error_body("malformed path")),
+    };
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["status"]) => write_response(
+            stream,
+            "200 OK",
+            Some(serde_json::to_string(startup_report)?),
+        ),
+        ("GET", ["macros"]) => {
+            let (reply_tx, reply_rx) = channel();
+            tx.send(Message::ListMacros(reply_tx))?;
+            let statuses = reply_rx.recv_timeout(REPLY_TIMEOUT)?;
+            write_response(stream, "200 OK", Some(serde_json::to_string(&statuses)?))
+        }
+        ("GET", ["profile"]) => {
+            let (reply_tx, reply_rx) = channel();
+            tx.send(Message::ActiveProfile(reply_tx))?;
+            let active = reply_rx.recv_timeout(REPLY_TIMEOUT)?;
+            write_response(stream, "200 OK", Some(serde_json::to_string(&active)?))
+        }
+        ("POST", ["macros", macro_name, "trigger"]) => {
+            let (reply_tx, reply_rx) = channel();
+            tx.send(Message::Trigger {
+                macro_name: macro_name.to_string(),
+                source: TriggerSource::Http,
+                reply: reply_tx,
+            })?;
+
+            match reply_rx.recv_timeout(REPLY_TIMEOUT)? {
+                TriggerOutcome::Started | TriggerOutcome::Deferred => {
+                    write_response(stream, "202 Accepted", None)
+                }
+                TriggerOutcome::NotFound => {
+                    write_response(stream, "404 Not Found", error_body("no such macro"))
+                }
+                TriggerOutcome::Rejected(reason) => {
+                    write_response(stream, "409 Conflict", error_body(&reason))
+                }
+            }
+        }
+        ("POST", ["press", hotkey]) => {
+            let keys = match deserialize_keys(hotkey.into_deserializer()) {
+                Ok(keys) => keys,
+                Err(e @ serde::de::value::Error { .. }) => {
+                    return write_response(stream, "400 Bad Request", error_body(&e.to_string()))
+                }
+            };
+
+            let (reply_tx, reply_rx) = channel();
+            tx.send(Message::SimulatePress {
+                keys,
+                reply: reply_tx,
+            })?;
+            let matches = reply_rx.recv_timeout(REPLY_TIMEOUT)?;
+            write_response(stream, "200 OK", Some(serde_json::to_string(&matches)?))
+        }
+        ("POST", ["pause"]) => {
+            tx.send(Message::Pause)?;
+            write_response(stream, "204 No Content", None)
+        }
+        ("POST", ["resume"]) => {
+            tx.send(Message::Resume)?;
+            write_response(stream, "204 No Content", None)
+        }
+        _ => write_response(stream, "404 Not Found", error_body("no such endpoint")),
+    }
+}
+
+/// Sends a bodyless request to a running endpoint, as a client, and returns the body of a
+/// `200 OK` answer.
+fn request(config: &HttpConfig, method: &str, path: &str) -> Result<String, anyhow::Error> {
+    // An endpoint bound to every interface is reached through the loopback one
+    let address = match config.bind.strip_prefix("0.0.0.0:") {
+        Some(port) => format!("127.0.0.1:{}", port),
+        None => config.bind.clone(),
+    };
+
+    let mut stream = TcpStream::connect(&address)?;
+    stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nAuthorization: Bearer {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        method, path, address, config.token
+    )?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| anyhow::anyhow!("Malformed response"))?;
+    let status_line = head.lines().next().unwrap_or_default();
+    if status_line.split_whitespace().nth(1) != Some("200") {
+        return Err(anyhow::anyhow!("Endpoint answered {}", status_line));
+    }
+
+    Ok(body.to_string())
+}
+
+/// Asks a running endpoint for its `GET /macros` list, as a client.
+pub fn fetch_statuses(config: &HttpConfig) -> Result<Vec<MacroStatus>, anyhow::Error> {
+    Ok(serde_json::from_str(&request(config, "GET", "/macros")?)?)
+}
+
+/// Asks a running endpoint for its `GET /profile`, as a client.
+pub fn fetch_profile(config: &HttpConfig) -> Result<ActiveProfile, anyhow::Error> {
+    Ok(serde_json::from_str(&request(config, "GET", "/profile")?)?)
+}
+
+/// Asks a running endpoint to act as if `keys` had been pressed, as a client, and returns the
+/// macros that matched.
+pub fn press(config: &HttpConfig, keys: &HashSet<Key>) -> Result<Vec<PressMatch>, anyhow::Error> {
+    let path = format!("/press/{}", percent_encode(&format_keys(keys)));
+    Ok(serde_json::from_str(&request(config, "POST", &path)?)?)
+}
+
+/// Serves the HTTP endpoint, one connection at a time, until the listener goes away.
+pub fn serve(
+    config: HttpConfig,
+    tx: Sender<Message>,
+    startup_report: Arc<StartupReport>,
+) -> Result<(), anyhow::Error> {
+    let listener = TcpListener::bind(&config.bind)
+        .map_err(|e| anyhow::anyhow!("Failed to listen on {}: {}", config.bind, e))?;
+    log::info!("Serving HTTP on {}", config.bind);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!("Failed to accept HTTP connection: {}", e);
+                continue;
+            }
+        };
+
+        if let Err(e) = handle(&stream, &config, &tx, &startup_report) {
+            log::warn!("Failed to handle HTTP request: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_decode_decodes_escapes() {
+        assert_eq!(percent_decode("Test%202").as_deref(), Some("Test 2"));
+        assert_eq!(percent_decode("caf%C3%A9").as_deref(), Some("café"));
+        assert_eq!(percent_decode("Ctrl+A").as_deref(), Some("Ctrl+A"));
+        assert_eq!(percent_decode("%2f%2F").as_deref(), Some("//"));
+    }
+
+    #[test]
+    fn percent_decode_rejects_malformed_escapes() {
+        assert_eq!(percent_decode("%"), None);
+        assert_eq!(percent_decode("%2"), None);
+        assert_eq!(percent_decode("%zz"), None);
+        assert_eq!(percent_decode("%+1"), None);
+        assert_eq!(percent_decode("%FF"), None);
+    }
+
+    #[test]
+    fn percent_encode_round_trips() {
+        for segment in ["Test 2", "Ctrl+Shift+A", "a/b?c#d%e", "café"] {
+            assert_eq!(
+                percent_decode(&percent_encode(segment)).as_deref(),
+                Some(segment)
+            );
+        }
+        assert_eq!(percent_encode("Test 2"), "Test%202");
+    }
+
+    #[test]
+    fn constant_time_eq_compares_bytes() {
+        assert!(constant_time_eq(b"Bearer secret", b"Bearer secret"));
+        assert!(!constant_time_eq(b"Bearer secret", b"Bearer secreT"));
+        assert!(!constant_time_eq(b"Bearer secret", b"Bearer secre"));
+        assert!(!constant_time_eq(b"", b"x"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
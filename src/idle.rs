@@ -0,0 +1,256 @@
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::duration::DurationMs;
+
+/// Runs a macro once nobody has touched the keyboard or mouse for `after`, and again every
+/// `repeat_every` for as long as that lasts. Real input cancels the macro.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdleTrigger {
+    pub after: DurationMs,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat_every: Option<DurationMs>,
+}
+
+/// Tick count at which this process last injected input, 0 before it ever has.
+static LAST_INJECTED_INPUT: AtomicU32 = AtomicU32::new(0);
+
+/// Input reported within this long of our own injected input is taken to be ours. Real input
+/// landing in that window, or just before input of ours, goes unnoticed.
+const INJECTED_INPUT_SLACK_MS: u32 = 100;
+
+/// Notes that this process has just injected input, so that it is not mistaken for the user's.
+pub fn record_injected_input() {
+    LAST_INJECTED_INPUT.store(tick_count(), Ordering::SeqCst);
+}
+
+#[cfg(windows)]
+fn tick_count() -> u32 {
+    use windows::Win32::System::SystemInformation::GetTickCount;
+
+    unsafe { GetTickCount() }
+}
+
+/// Tick count of the last keyboard or mouse input, real or injected, to reach the system.
+#[cfg(windows)]
+fn last_input_tick() -> Option<u32> {
+    use windows::Win32::UI::Input::KeyboardAndMouse::{GetLastInputInfo, LASTINPUTINFO};
+
+    let mut info = LASTINPUTINFO {
+        cbSize: std::mem::size_of::<LASTINPUTINFO>() as u32,
+        dwTime: 0,
+    };
+
+    if !unsafe { GetLastInputInfo(&mut info) }.as_bool() {
+        return None;
+    }
+
+    Some(info.dwTime)
+}
+
+/// Whether tick `a` comes after tick `b`, allowing for the tick count wrapping around.
+fn is_after(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+/// How long ago the last keyboard or mouse input, real or injected, reached the system.
+pub fn time_since_last_input() -> Duration {
+    let times = SystemInputTimes;
+    times
+        .last_input_tick()
+        .map(|tick| times.ticks_since(tick))
+        .unwrap_or(Duration::ZERO)
+}
+
+/// Where idle time is measured from: a millisecond tick count, wrapping around, and the ticks at
+/// which input last reached the system and this process last injected any.
+pub trait InputTimes: Send + Sync {
+    fn tick_count(&self) -> u32;
+    /// Tick count of the last keyboard or mouse input, real or injected.
+    fn last_input_tick(&self) -> Option<u32>;
+    /// Tick count at which this process last injected input, 0 before it ever has.
+    fn last_injected_tick(&self) -> u32;
+
+    fn ticks_since(&self, tick: u32) -> Duration {
+        Duration::from_millis(u64::from(self.tick_count().wrapping_sub(tick)))
+    }
+}
+
+/// The system's tick count and last input, and the input `record_injected_input` noted.
+pub struct SystemInputTimes;
+
+impl InputTimes for SystemInputTimes {
+    fn tick_count(&self) -> u32 {
+        tick_count()
+    }
+
+    fn last_input_tick(&self) -> Option<u32> {
+        last_input_tick()
+    }
+
+    fn last_injected_tick(&self) -> u32 {
+        LAST_INJECTED_INPUT.load(Ordering::SeqCst)
+    }
+}
+
+/// Follows how long the user, as opposed to this process, has left the machine alone.
+pub struct IdleTracker {
+    times: Arc<dyn InputTimes>,
+    /// Tick count of the last input that was not ours.
+    last_real_input: u32,
+}
+
+impl IdleTracker {
+    pub fn new() -> Self {
+        Self::with_times(Arc::new(SystemInputTimes))
+    }
+
+    pub fn with_times(times: Arc<dyn InputTimes>) -> Self {
+        IdleTracker {
+            last_real_input: times
+                .last_input_tick()
+                .unwrap_or_else(|| times.tick_count()),
+            times,
+        }
+    }
+
+    /// Looks at the latest input, returning whether the user has given any since the last poll.
+    pub fn poll(&mut self) -> bool {
+        let last_input = match self.times.last_input_tick() {
+            Some(last_input) => last_input,
+            None => return false,
+        };
+
+        if !is_after(last_input, self.last_real_input) {
+            return false;
+        }
+
+        let last_injected = self.times.last_injected_tick();
+        if last_injected != 0
+            && !is_after(
+                last_input,
+                last_injected.wrapping_add(INJECTED_INPUT_SLACK_MS),
+            )
+        {
+            return false;
+        }
+
+        self.last_real_input = last_input;
+        true
+    }
+
+    pub fn idle_for(&self) -> Duration {
+        self.times.ticks_since(self.last_real_input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Mutex, PoisonError},
+        time::Instant,
+    };
+
+    use super::*;
+    use crate::{
+        backend::{InputBackend, InputEvent, SimulatedInput},
+        clock::{Clock, VirtualClock},
+        diff_run,
+        tests::{commands, test_context},
+        Key,
+    };
+
+    /// Ticks of a virtual clock, with input given when a test says so.
+    struct VirtualInputTimes {
+        clock: Arc<VirtualClock>,
+        start: Instant,
+        /// The last input, real or injected, and the last injected input.
+        inputs: Mutex<(Option<u32>, u32)>,
+    }
+
+    impl VirtualInputTimes {
+        fn new(clock: Arc<VirtualClock>) -> Self {
+            VirtualInputTimes {
+                start: clock.now(),
+                clock,
+                inputs: Mutex::new((None, 0)),
+            }
+        }
+
+        fn inputs(&self) -> std::sync::MutexGuard<'_, (Option<u32>, u32)> {
+            self.inputs.lock().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        fn user_input(&self) {
+            self.inputs().0 = Some(self.tick_count());
+        }
+
+        fn injected_input(&self) {
+            let tick = self.tick_count();
+            *self.inputs() = (Some(tick), tick);
+        }
+    }
+
+    impl InputTimes for VirtualInputTimes {
+        // Starts at 1, since a last injected tick of 0 means none
+        fn tick_count(&self) -> u32 {
+            self.clock.elapsed(self.start).as_millis() as u32 + 1
+        }
+
+        fn last_input_tick(&self) -> Option<u32> {
+            self.inputs().0
+        }
+
+        fn last_injected_tick(&self) -> u32 {
+            self.inputs().1
+        }
+    }
+
+    /// The simulated keyboard, with what it sends counted as injected input.
+    struct Injecting(Arc<VirtualInputTimes>);
+
+    impl InputBackend for Injecting {
+        fn is_key_held(&self, key: Key) -> bool {
+            SimulatedInput.is_key_held(key)
+        }
+
+        fn send(&self, events: &[InputEvent]) -> Result<(), anyhow::Error> {
+            self.0.injected_input();
+            SimulatedInput.send(events)
+        }
+    }
+
+    #[test]
+    fn our_own_input_leaves_the_idle_time_running() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60 * 60)));
+        let times = Arc::new(VirtualInputTimes::new(clock.clone()));
+        times.user_input();
+        let mut tracker = IdleTracker::with_times(times.clone());
+        let mut context = test_context(Arc::new(Injecting(times.clone())), clock.clone());
+
+        let (result, recorded) = diff_run::record_for_test(clock.clone(), || {
+            crate::run_block(
+                &commands("[!Wait 2m, !KeyDown {key: A}, !Wait 1s, !KeyUp A, !LeftClick]"),
+                &mut context,
+            )
+        });
+        result.unwrap();
+        assert_eq!(recorded.len(), 4);
+
+        assert!(!tracker.poll());
+        assert_eq!(tracker.idle_for(), Duration::from_secs(2 * 60 + 1));
+
+        // The user's input, once clear of ours, starts it over
+        context.sleep(Duration::from_secs(1));
+        times.user_input();
+        assert!(tracker.poll());
+        assert_eq!(tracker.idle_for(), Duration::ZERO);
+    }
+}
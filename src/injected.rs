@@ -0,0 +1,97 @@
+use std::{
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+    INPUT, INPUT_KEYBOARD, INPUT_MOUSE, KEYEVENTF_KEYUP, KEYEVENTF_UNICODE, MOUSEEVENTF_LEFTDOWN,
+    MOUSEEVENTF_LEFTUP, MOUSEEVENTF_MIDDLEDOWN, MOUSEEVENTF_MIDDLEUP, MOUSEEVENTF_RIGHTDOWN,
+    MOUSEEVENTF_RIGHTUP,
+};
+
+use super::Key;
+
+/// How long a key stays taken for a macro's after the macro lets it go, so that a listener poll
+/// landing between the release being sent and Windows applying it does not see a fresh press.
+pub const RELEASE_GRACE: Duration = Duration::from_millis(200);
+
+/// Keys macros have put down, with when they let them go again, `None` while still down.
+static INJECTED: Mutex<Vec<(Key, Option<Instant>)>> = Mutex::new(Vec::new());
+
+/// The keys `input` puts down or lets go, and whether it lets them go. Typed characters press no
+/// key of their own.
+fn input_keys(input: &INPUT) -> (Vec<Key>, bool) {
+    match input.r#type {
+        INPUT_KEYBOARD => {
+            let keyboard = unsafe { input.Anonymous.ki };
+            if keyboard.dwFlags.0 & KEYEVENTF_UNICODE.0 != 0 {
+                return (Vec::new(), false);
+            }
+            (
+                vec![Key::from(keyboard.wVk.0 as i32)],
+                keyboard.dwFlags.0 & KEYEVENTF_KEYUP.0 != 0,
+            )
+        }
+        INPUT_MOUSE => {
+            let mouse = unsafe { input.Anonymous.mi };
+            let buttons = [
+                (MOUSEEVENTF_LEFTDOWN, MOUSEEVENTF_LEFTUP, Key::LeftButton),
+                (MOUSEEVENTF_RIGHTDOWN, MOUSEEVENTF_RIGHTUP, Key::RightButton),
+                (
+                    MOUSEEVENTF_MIDDLEDOWN,
+                    MOUSEEVENTF_MIDDLEUP,
+                    Key::MiddleButton,
+                ),
+            ];
+
+            let mut keys = Vec::new();
+            let mut up = false;
+            for (down_flag, up_flag, key) in buttons {
+                if mouse.dwFlags.0 & (down_flag.0 | up_flag.0) != 0 {
+                    keys.push(key);
+                    up = mouse.dwFlags.0 & up_flag.0 != 0;
+                }
+            }
+            (keys, up)
+        }
+        _ => (Vec::new(), false),
+    }
+}
+
+/// Notes the keys `inputs` put down and let go, before they are sent.
+pub fn record(inputs: &[INPUT]) {
+    let now = Instant::now();
+    let mut injected = INJECTED.lock().unwrap_or_else(PoisonError::into_inner);
+    injected.retain(|(_, released)| {
+        released.is_none_or(|released| now.saturating_duration_since(released) < RELEASE_GRACE)
+    });
+
+    for input in inputs {
+        let (keys, up) = input_keys(input);
+        for key in keys {
+            let released = up.then_some(now);
+            match injected
+                .iter_mut()
+                .find(|(injected_key, _)| *injected_key == key)
+            {
+                Some(entry) => entry.1 = released,
+                None => injected.push((key, released)),
+            }
+        }
+    }
+}
+
+/// Whether `key` is down because a macro put it down, or came up from that only just now. A
+/// generic modifier such as `Shift` counts as put down when either of its sides was.
+pub fn is_injected(key: Key) -> bool {
+    let now = Instant::now();
+    INJECTED
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .any(|(injected_key, released)| {
+            (*injected_key == key || injected_key.generic_modifier() == Some(key))
+                && released
+                    .is_none_or(|released| now.saturating_duration_since(released) < RELEASE_GRACE)
+        })
+}
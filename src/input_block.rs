@@ -0,0 +1,136 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::channel,
+        OnceLock,
+    },
+};
+
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::WindowsAndMessaging::{
+    CallNextHookEx, DispatchMessageW, GetMessageW, SetWindowsHookExW, TranslateMessage,
+    UnhookWindowsHookEx, HHOOK, KBDLLHOOKSTRUCT, LLKHF_INJECTED, LLKHF_UP, LLMHF_INJECTED, MSG,
+    MSLLHOOKSTRUCT, WH_KEYBOARD_LL, WH_MOUSE_LL, WM_LBUTTONUP, WM_MBUTTONUP, WM_RBUTTONUP,
+    WM_XBUTTONUP,
+};
+
+use super::Key;
+
+/// How many executions currently want the user's input blocked.
+static BLOCKERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Virtual-key codes that are never blocked, the keys of the program hotkey.
+static ALLOWED_KEYS: OnceLock<HashSet<u32>> = OnceLock::new();
+
+/// Whether the hooks could be installed, decided the first time input is blocked.
+static HOOKS: OnceLock<Result<(), String>> = OnceLock::new();
+
+/// Lets `keys` through even while input is blocked, so that the program hotkey can always stop
+/// the runner. Only the first call has any effect.
+pub fn set_allowed_keys(keys: &HashSet<Key>) {
+    let _ = ALLOWED_KEYS.set(keys.iter().map(|key| key.virtual_key() as u32).collect());
+}
+
+/// Whether a key event for `vk_code` must go through: one of `ALLOWED_KEYS`, counting left and
+/// right modifiers as the generic one.
+fn is_allowed_key(vk_code: u32) -> bool {
+    let allowed = match ALLOWED_KEYS.get() {
+        Some(allowed) => allowed,
+        None => return false,
+    };
+
+    allowed.contains(&vk_code)
+        || Key::from(vk_code as i32)
+            .generic_modifier()
+            .is_some_and(|generic| allowed.contains(&(generic as u32)))
+}
+
+unsafe extern "system" fn keyboard_hook(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    if code >= 0 && BLOCKERS.load(Ordering::SeqCst) > 0 {
+        let event = &*(lparam.0 as *const KBDLLHOOKSTRUCT);
+        // Releases always go through, so that no key the user held is left down
+        let swallow =
+            event.flags.0 & (LLKHF_INJECTED.0 | LLKHF_UP.0) == 0 && !is_allowed_key(event.vkCode);
+        if swallow {
+            return LRESULT(1);
+        }
+    }
+
+    CallNextHookEx(HHOOK::default(), code, wparam, lparam)
+}
+
+unsafe extern "system" fn mouse_hook(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    if code >= 0 && BLOCKERS.load(Ordering::SeqCst) > 0 {
+        let event = &*(lparam.0 as *const MSLLHOOKSTRUCT);
+        let release = matches!(
+            wparam.0 as u32,
+            WM_LBUTTONUP | WM_RBUTTONUP | WM_MBUTTONUP | WM_XBUTTONUP
+        );
+        if event.flags & LLMHF_INJECTED == 0 && !release {
+            return LRESULT(1);
+        }
+    }
+
+    CallNextHookEx(HHOOK::default(), code, wparam, lparam)
+}
+
+/// Installs the keyboard and mouse hooks on a thread of their own, which serves them for the
+/// rest of the program. They let everything through while nothing is blocking.
+fn install_hooks() -> Result<(), String> {
+    let (tx, rx) = channel();
+
+    std::thread::spawn(move || {
+        let installed = unsafe {
+            GetModuleHandleW(PCWSTR::null()).and_then(|module| {
+                let keyboard = SetWindowsHookExW(WH_KEYBOARD_LL, Some(keyboard_hook), module, 0)?;
+                SetWindowsHookExW(WH_MOUSE_LL, Some(mouse_hook), module, 0).inspect_err(|_| {
+                    UnhookWindowsHookEx(keyboard);
+                })
+            })
+        };
+        let failed = installed.is_err();
+        let _ = tx.send(installed.map(|_| ()).map_err(|e| e.to_string()));
+        if failed {
+            return;
+        }
+
+        // Low-level hooks are called from this thread's message loop
+        let mut message = MSG::default();
+        while unsafe { GetMessageW(&mut message, HWND::default(), 0, 0) }.as_bool() {
+            unsafe {
+                TranslateMessage(&message);
+                DispatchMessageW(&message);
+            }
+        }
+    });
+
+    rx.recv()
+        .unwrap_or_else(|_| Err("the hook thread stopped".to_string()))
+}
+
+/// Keeps the user's keyboard and mouse input from reaching any window while it is alive, so it
+/// cannot interleave with a macro's. Injected input, key and button releases and the keys given
+/// to `set_allowed_keys` still go through. Input is let through again once every `InputBlock`
+/// has been dropped, however the execution holding it ends.
+pub struct InputBlock(());
+
+impl InputBlock {
+    pub fn start() -> Result<Self, anyhow::Error> {
+        HOOKS
+            .get_or_init(install_hooks)
+            .clone()
+            .map_err(|e| anyhow::anyhow!("Failed to install the input blocking hooks: {}", e))?;
+
+        BLOCKERS.fetch_add(1, Ordering::SeqCst);
+        Ok(InputBlock(()))
+    }
+}
+
+impl Drop for InputBlock {
+    fn drop(&mut self) {
+        BLOCKERS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
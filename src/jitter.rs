@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use rand::{rngs::StdRng, Rng};
+
+/// The largest `jitter_px` a macro may have. Offsets beyond this no longer land on the target.
+pub const MAX_RADIUS_PX: i32 = 500;
+/// The largest `jitter_ms` a macro may have, so a typo cannot stall every command for minutes.
+pub const MAX_DELAY_MS: u64 = 5000;
+
+/// Random variation applied to a macro's input so it is not replayed with machine precision. The
+/// randomness comes from the execution's RNG, so a seeded run varies the same way every time.
+pub struct Jitter {
+    max_delay_ms: u64,
+    radius_px: i32,
+}
+
+impl Jitter {
+    /// Delays of up to `max_delay_ms` and offsets of up to `radius_px`.
+    pub fn new(max_delay_ms: u64, radius_px: i32) -> Self {
+        Jitter {
+            max_delay_ms,
+            radius_px,
+        }
+    }
+
+    pub fn delay(&self, rng: &mut StdRng) -> Duration {
+        Duration::from_millis(rng.gen_range(0..=self.max_delay_ms))
+    }
+
+    /// A random offset within a circle of `radius_px`.
+    pub fn offset(&self, rng: &mut StdRng) -> (i32, i32) {
+        let radius = self.radius_px.saturating_abs();
+
+        loop {
+            let dx = rng.gen_range(-radius..=radius);
+            let dy = rng.gen_range(-radius..=radius);
+
+            // Squared in i64, where no i32 radius can overflow
+            let (x, y, r) = (i64::from(dx), i64::from(dy), i64::from(radius));
+            if x * x + y * y <= r * r {
+                return (dx, dy);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::SeedableRng;
+
+    #[test]
+    fn offsets_stay_within_the_radius() {
+        let mut rng = StdRng::seed_from_u64(7);
+
+        for radius_px in [0, 1, 5, MAX_RADIUS_PX, 100_000, i32::MAX] {
+            let jitter = Jitter::new(0, radius_px);
+            let radius = i64::from(radius_px);
+            for _ in 0..100 {
+                let (dx, dy) = jitter.offset(&mut rng);
+                let (dx, dy) = (i64::from(dx), i64::from(dy));
+                assert!(dx * dx + dy * dy <= radius * radius, "{} {}", dx, dy);
+            }
+        }
+    }
+
+    #[test]
+    fn seeded_jitter_repeats() {
+        let jitter = Jitter::new(50, 10);
+        let run = || {
+            let mut rng = StdRng::seed_from_u64(42);
+            (0..10)
+                .map(|_| (jitter.delay(&mut rng), jitter.offset(&mut rng)))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(), run());
+        assert!(run()
+            .iter()
+            .all(|(delay, _)| *delay <= Duration::from_millis(50)));
+    }
+}
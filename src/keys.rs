@@ -1,6 +1,15 @@
-use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fmt};
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+use serde::{
+    de::{self, IntoDeserializer},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+// The derived impls only know variant names, the `Serialize` and `Deserialize` impls below wrap
+// them to also read and write raw codes
+#[serde(remote = "Self")]
 pub enum Key {
     LeftButton = 0x01,
     RightButton = 0x02,
@@ -12,8 +21,16 @@ pub enum Key {
     Tab = 0x09,
     Clear = 0x0C,
     Return = 0x0D,
+    /// Not a virtual key of its own: Windows sends the numpad Enter as `Return` with the
+    /// extended-key flag set. 0x0E is unassigned, see `Key::virtual_key`.
+    NumpadEnter = 0x0E,
+    /// Also not a virtual key: AltGr is sent as LeftControl followed by RightMenu, which is how
+    /// Windows itself reports it.
+    AltGr = 0x0F,
     Shift = 0x10,
+    #[serde(alias = "Ctrl")]
     Control = 0x11,
+    #[serde(alias = "Alt")]
     Menu = 0x12,
     Pause = 0x13,
     Capital = 0x14,
@@ -97,11 +114,16 @@ pub enum Key {
     Numpad7 = 0x67,
     Numpad8 = 0x68,
     Numpad9 = 0x69,
+    #[serde(alias = "NumpadMultiply")]
     Multiply = 0x6A,
+    #[serde(alias = "NumpadAdd")]
     Add = 0x6B,
     Separator = 0x6C,
+    #[serde(alias = "NumpadSubtract")]
     Subtract = 0x6D,
+    #[serde(alias = "NumpadDot")]
     Decimal = 0x6E,
+    #[serde(alias = "NumpadDivide")]
     Divide = 0x6F,
     F1 = 0x70,
     F2 = 0x71,
@@ -161,6 +183,22 @@ pub enum Key {
     OemPeriod = 0xBE,
     Oem2 = 0xBF,
     Oem3 = 0xC0,
+    GamepadA = 0xC3,
+    GamepadB = 0xC4,
+    GamepadX = 0xC5,
+    GamepadY = 0xC6,
+    GamepadRightBumper = 0xC7,
+    GamepadLeftBumper = 0xC8,
+    GamepadLeftTrigger = 0xC9,
+    GamepadRightTrigger = 0xCA,
+    GamepadDPadUp = 0xCB,
+    GamepadDPadDown = 0xCC,
+    GamepadDPadLeft = 0xCD,
+    GamepadDPadRight = 0xCE,
+    GamepadStart = 0xCF,
+    GamepadBack = 0xD0,
+    GamepadLeftStick = 0xD1,
+    GamepadRightStick = 0xD2,
     Oem4 = 0xDB,
     Oem5 = 0xDC,
     Oem6 = 0xDD,
@@ -177,6 +215,467 @@ pub enum Key {
     Zoom = 0xFB,
     PA1 = 0xFD,
     OemClear = 0xFE,
+    // Codes without a name of their own, such as keys only some keyboards or layouts have. They
+    // are written `vk:0xE3` or `{ raw: 227 }` in configs, see `Key::parse`.
+    Vk07 = 0x07,
+    Vk0A = 0x0A,
+    Vk0B = 0x0B,
+    Vk15 = 0x15,
+    Vk16 = 0x16,
+    Vk17 = 0x17,
+    Vk18 = 0x18,
+    Vk19 = 0x19,
+    Vk1A = 0x1A,
+    Vk3A = 0x3A,
+    Vk3B = 0x3B,
+    Vk3C = 0x3C,
+    Vk3D = 0x3D,
+    Vk3E = 0x3E,
+    Vk3F = 0x3F,
+    Vk40 = 0x40,
+    Vk5E = 0x5E,
+    Vk88 = 0x88,
+    Vk89 = 0x89,
+    Vk8A = 0x8A,
+    Vk8B = 0x8B,
+    Vk8C = 0x8C,
+    Vk8D = 0x8D,
+    Vk8E = 0x8E,
+    Vk8F = 0x8F,
+    Vk92 = 0x92,
+    Vk93 = 0x93,
+    Vk94 = 0x94,
+    Vk95 = 0x95,
+    Vk96 = 0x96,
+    Vk98 = 0x98,
+    Vk99 = 0x99,
+    Vk9A = 0x9A,
+    Vk9B = 0x9B,
+    Vk9C = 0x9C,
+    Vk9D = 0x9D,
+    Vk9E = 0x9E,
+    Vk9F = 0x9F,
+    VkB8 = 0xB8,
+    VkB9 = 0xB9,
+    VkC1 = 0xC1,
+    VkC2 = 0xC2,
+    VkD3 = 0xD3,
+    VkD4 = 0xD4,
+    VkD5 = 0xD5,
+    VkD6 = 0xD6,
+    VkD7 = 0xD7,
+    VkD8 = 0xD8,
+    VkD9 = 0xD9,
+    VkDA = 0xDA,
+    VkE0 = 0xE0,
+    VkE1 = 0xE1,
+    VkE3 = 0xE3,
+    VkE4 = 0xE4,
+    VkE6 = 0xE6,
+    VkE8 = 0xE8,
+    VkE9 = 0xE9,
+    VkEA = 0xEA,
+    VkEB = 0xEB,
+    VkEC = 0xEC,
+    VkED = 0xED,
+    VkEE = 0xEE,
+    VkEF = 0xEF,
+    VkF0 = 0xF0,
+    VkF1 = 0xF1,
+    VkF2 = 0xF2,
+    VkF3 = 0xF3,
+    VkF4 = 0xF4,
+    VkF5 = 0xF5,
+    VkFC = 0xFC,
+}
+
+impl Key {
+    /// Whether the key is a keyboard modifier (Shift, Ctrl, Alt or Windows, sided or not).
+    pub fn is_modifier(&self) -> bool {
+        matches!(
+            self,
+            Key::Shift
+                | Key::Control
+                | Key::Menu
+                | Key::AltGr
+                | Key::LeftShift
+                | Key::RightShift
+                | Key::LeftControl
+                | Key::RightControl
+                | Key::LeftMenu
+                | Key::RightMenu
+                | Key::LeftWindows
+                | Key::RightWindows
+        )
+    }
+
+    /// The generic modifier a sided one stands for, e.g. `Shift` for `LeftShift`.
+    pub fn generic_modifier(&self) -> Option<Key> {
+        match self {
+            Key::LeftShift | Key::RightShift => Some(Key::Shift),
+            Key::LeftControl | Key::RightControl => Some(Key::Control),
+            Key::LeftMenu | Key::RightMenu => Some(Key::Menu),
+            _ => None,
+        }
+    }
+
+    /// Position of the key when pressing a combo: Ctrl, Shift, Alt, Windows, then the rest.
+    pub fn modifier_order(&self) -> u8 {
+        match self {
+            Key::Control | Key::LeftControl | Key::RightControl => 0,
+            Key::Shift | Key::LeftShift | Key::RightShift => 1,
+            Key::Menu | Key::LeftMenu | Key::RightMenu | Key::AltGr => 2,
+            Key::LeftWindows | Key::RightWindows => 3,
+            _ => 4,
+        }
+    }
+
+    /// The virtual-key code to send or poll for this key.
+    pub fn virtual_key(&self) -> i32 {
+        match self {
+            Key::NumpadEnter => Key::Return as i32,
+            Key::AltGr => Key::RightMenu as i32,
+            key => *key as i32,
+        }
+    }
+
+    /// Whether Windows expects the extended-key flag when injecting this key.
+    pub fn is_extended(&self) -> bool {
+        matches!(
+            self,
+            Key::NumpadEnter
+                | Key::Prior
+                | Key::Next
+                | Key::End
+                | Key::Home
+                | Key::Left
+                | Key::Up
+                | Key::Right
+                | Key::Down
+                | Key::Snapshot
+                | Key::Insert
+                | Key::Delete
+                | Key::LeftWindows
+                | Key::RightWindows
+                | Key::Applications
+                | Key::Divide
+                | Key::Numlock
+                | Key::RightControl
+                | Key::RightMenu
+                | Key::BrowserBack
+                | Key::BrowserForward
+                | Key::BrowserRefresh
+                | Key::BrowserStop
+                | Key::BrowserSearch
+                | Key::BrowserFavorites
+                | Key::BrowserHome
+                | Key::VolumeMute
+                | Key::VolumeDown
+                | Key::VolumeUp
+                | Key::MediaNextTrack
+                | Key::MediaPrevTrack
+                | Key::MediaStop
+                | Key::MediaPlayPause
+                | Key::LaunchMail
+                | Key::LaunchMediaSelect
+                | Key::LaunchApp1
+                | Key::LaunchApp2
+        )
+    }
+
+    /// Mouse buttons used for ordinary clicking, which must never be bound on their own.
+    pub fn is_primary_mouse_button(&self) -> bool {
+        matches!(self, Key::LeftButton | Key::RightButton)
+    }
+
+    /// The XInput button mask of a gamepad button, `None` for keyboard keys and mouse buttons.
+    /// The analog triggers have no mask and are read separately.
+    pub fn gamepad_button(&self) -> Option<u16> {
+        match self {
+            Key::GamepadDPadUp => Some(0x0001),
+            Key::GamepadDPadDown => Some(0x0002),
+            Key::GamepadDPadLeft => Some(0x0004),
+            Key::GamepadDPadRight => Some(0x0008),
+            Key::GamepadStart => Some(0x0010),
+            Key::GamepadBack => Some(0x0020),
+            Key::GamepadLeftStick => Some(0x0040),
+            Key::GamepadRightStick => Some(0x0080),
+            Key::GamepadLeftBumper => Some(0x0100),
+            Key::GamepadRightBumper => Some(0x0200),
+            Key::GamepadA => Some(0x1000),
+            Key::GamepadB => Some(0x2000),
+            Key::GamepadX => Some(0x4000),
+            Key::GamepadY => Some(0x8000),
+            _ => None,
+        }
+    }
+
+    /// Whether the key is read from a game controller rather than the keyboard or mouse.
+    pub fn is_gamepad(&self) -> bool {
+        (Key::GamepadA as i32..=Key::GamepadRightStick as i32).contains(&(*self as i32))
+    }
+
+    /// Whether the key is one of the codes without a name of its own.
+    pub fn is_raw(&self) -> bool {
+        matches!(
+            self,
+            Key::Vk07
+                | Key::Vk0A
+                | Key::Vk0B
+                | Key::Vk15
+                | Key::Vk16
+                | Key::Vk17
+                | Key::Vk18
+                | Key::Vk19
+                | Key::Vk1A
+                | Key::Vk3A
+                | Key::Vk3B
+                | Key::Vk3C
+                | Key::Vk3D
+                | Key::Vk3E
+                | Key::Vk3F
+                | Key::Vk40
+                | Key::Vk5E
+                | Key::Vk88
+                | Key::Vk89
+                | Key::Vk8A
+                | Key::Vk8B
+                | Key::Vk8C
+                | Key::Vk8D
+                | Key::Vk8E
+                | Key::Vk8F
+                | Key::Vk92
+                | Key::Vk93
+                | Key::Vk94
+                | Key::Vk95
+                | Key::Vk96
+                | Key::Vk98
+                | Key::Vk99
+                | Key::Vk9A
+                | Key::Vk9B
+                | Key::Vk9C
+                | Key::Vk9D
+                | Key::Vk9E
+                | Key::Vk9F
+                | Key::VkB8
+                | Key::VkB9
+                | Key::VkC1
+                | Key::VkC2
+                | Key::VkD3
+                | Key::VkD4
+                | Key::VkD5
+                | Key::VkD6
+                | Key::VkD7
+                | Key::VkD8
+                | Key::VkD9
+                | Key::VkDA
+                | Key::VkE0
+                | Key::VkE1
+                | Key::VkE3
+                | Key::VkE4
+                | Key::VkE6
+                | Key::VkE8
+                | Key::VkE9
+                | Key::VkEA
+                | Key::VkEB
+                | Key::VkEC
+                | Key::VkED
+                | Key::VkEE
+                | Key::VkEF
+                | Key::VkF0
+                | Key::VkF1
+                | Key::VkF2
+                | Key::VkF3
+                | Key::VkF4
+                | Key::VkF5
+                | Key::VkFC
+        )
+    }
+
+    /// The name configs use for the key: the variant name, or `vk:0xE3` for raw codes.
+    pub fn name(&self) -> String {
+        if self.is_raw() {
+            format!("vk:0x{:02X}", *self as i32)
+        } else {
+            format!("{:?}", self)
+        }
+    }
+
+    /// Reads a key name as configs write it: a variant name or alias, or `vk:<code>` with the
+    /// virtual-key code in hex or decimal.
+    pub fn parse(name: &str) -> Result<Key, String> {
+        let code = match name.strip_prefix("vk:") {
+            Some(code) => code.trim(),
+            None => {
+                return Key::deserialize(name.into_deserializer())
+                    .map_err(|e: de::value::Error| e.to_string())
+            }
+        };
+
+        let parsed = match code.strip_prefix("0x").or_else(|| code.strip_prefix("0X")) {
+            Some(hex) => u16::from_str_radix(hex, 16),
+            None => code.parse(),
+        };
+        match parsed {
+            Ok(code) => Key::from_raw(code),
+            Err(_) => Err(format!("{} is not a virtual-key code", code)),
+        }
+    }
+
+    /// The key with virtual-key code `code`. A code that has a name reads as that key, with a
+    /// warning, since the name says more about it.
+    pub fn from_raw(code: u16) -> Result<Key, String> {
+        if !(0x01..=0xFE).contains(&code) {
+            return Err(format!(
+                "0x{:02X} is not a virtual-key code between 0x01 and 0xFE",
+                code
+            ));
+        }
+
+        let key = Key::from(code as i32);
+        if !key.is_raw() {
+            log::warn!(
+                "Raw key code 0x{:02X} is {}, prefer the name",
+                code,
+                key.name()
+            );
+        }
+        Ok(key)
+    }
+
+    pub fn is_mouse_button(&self) -> bool {
+        matches!(
+            self,
+            Key::LeftButton | Key::RightButton | Key::MiddleButton | Key::XButton1 | Key::XButton2
+        )
+    }
+}
+
+impl Serialize for Key {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.is_raw() {
+            serializer.collect_str(&self.name())
+        } else {
+            Key::serialize(self, serializer)
+        }
+    }
+}
+
+/// Reads a key written as a name, as `vk:0xE3`, or as `{ raw: 227 }`.
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Key, D::Error> {
+        struct KeyVisitor;
+
+        impl<'de> de::Visitor<'de> for KeyVisitor {
+            type Value = Key;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a key name, vk:<code> or { raw: <code> }")
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<Key, E> {
+                Key::parse(name).map_err(E::custom)
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Key, A::Error> {
+                match map.next_key::<String>()?.as_deref() {
+                    Some("raw") => {
+                        let code = map.next_value::<u16>()?;
+                        Key::from_raw(code).map_err(de::Error::custom)
+                    }
+                    Some(other) => Err(de::Error::unknown_field(other, &["raw"])),
+                    None => Err(de::Error::missing_field("raw")),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(KeyVisitor)
+    }
+}
+
+/// Formats a set of keys as one `+`-joined string in the order they are pressed, modifiers first,
+/// e.g. `LeftControl+LeftShift+K`.
+pub fn format_keys(keys: &HashSet<Key>) -> String {
+    let mut sorted: Vec<&Key> = keys.iter().collect();
+    sorted.sort_by_key(|key| (key.modifier_order(), **key));
+
+    let names: Vec<String> = sorted.iter().map(|key| key.name()).collect();
+    names.join("+")
+}
+
+/// Serializes a set of keys with `format_keys`, so that the output is stable between runs.
+pub fn serialize_sorted_keys<S: Serializer>(
+    keys: &HashSet<Key>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&format_keys(keys))
+}
+
+/// Deserializes a set of keys written either as a list or as one `+`-joined string such as
+/// `Ctrl+Shift+K`, reading each name with `Key::parse`.
+pub fn deserialize_keys<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashSet<Key>, D::Error> {
+    struct KeysVisitor;
+
+    impl<'de> de::Visitor<'de> for KeysVisitor {
+        type Value = HashSet<Key>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a list of keys or keys joined with +, e.g. \"LeftControl+S\"")
+        }
+
+        fn visit_str<E: de::Error>(self, text: &str) -> Result<HashSet<Key>, E> {
+            text.split('+')
+                .map(|name| Key::parse(name.trim()).map_err(E::custom))
+                .collect()
+        }
+
+        fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<HashSet<Key>, A::Error> {
+            let mut keys = HashSet::new();
+            while let Some(key) = seq.next_element()? {
+                keys.insert(key);
+            }
+            Ok(keys)
+        }
+    }
+
+    deserializer.deserialize_any(KeysVisitor)
+}
+
+/// Volume, playback and browser navigation keys, named the way configs read naturally.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaAction {
+    PlayPause,
+    NextTrack,
+    PreviousTrack,
+    Stop,
+    VolumeUp,
+    VolumeDown,
+    Mute,
+    BrowserBack,
+    BrowserForward,
+    BrowserRefresh,
+    BrowserHome,
+}
+
+impl MediaAction {
+    pub fn key(&self) -> Key {
+        match self {
+            MediaAction::PlayPause => Key::MediaPlayPause,
+            MediaAction::NextTrack => Key::MediaNextTrack,
+            MediaAction::PreviousTrack => Key::MediaPrevTrack,
+            MediaAction::Stop => Key::MediaStop,
+            MediaAction::VolumeUp => Key::VolumeUp,
+            MediaAction::VolumeDown => Key::VolumeDown,
+            MediaAction::Mute => Key::VolumeMute,
+            MediaAction::BrowserBack => Key::BrowserBack,
+            MediaAction::BrowserForward => Key::BrowserForward,
+            MediaAction::BrowserRefresh => Key::BrowserRefresh,
+            MediaAction::BrowserHome => Key::BrowserHome,
+        }
+    }
 }
 
 // TODO: Add more conversions for symbols such as !@#$%^&*()_+{}|:"<>?
@@ -239,6 +738,8 @@ impl From<i32> for Key {
             0x09 => Key::Tab,
             0x0C => Key::Clear,
             0x0D => Key::Return,
+            0x0E => Key::NumpadEnter,
+            0x0F => Key::AltGr,
             0x10 => Key::Shift,
             0x11 => Key::Control,
             0x12 => Key::Menu,
@@ -388,6 +889,22 @@ impl From<i32> for Key {
             0xBE => Key::OemPeriod,
             0xBF => Key::Oem2,
             0xC0 => Key::Oem3,
+            0xC3 => Key::GamepadA,
+            0xC4 => Key::GamepadB,
+            0xC5 => Key::GamepadX,
+            0xC6 => Key::GamepadY,
+            0xC7 => Key::GamepadRightBumper,
+            0xC8 => Key::GamepadLeftBumper,
+            0xC9 => Key::GamepadLeftTrigger,
+            0xCA => Key::GamepadRightTrigger,
+            0xCB => Key::GamepadDPadUp,
+            0xCC => Key::GamepadDPadDown,
+            0xCD => Key::GamepadDPadLeft,
+            0xCE => Key::GamepadDPadRight,
+            0xCF => Key::GamepadStart,
+            0xD0 => Key::GamepadBack,
+            0xD1 => Key::GamepadLeftStick,
+            0xD2 => Key::GamepadRightStick,
             0xDB => Key::Oem4,
             0xDC => Key::Oem5,
             0xDD => Key::Oem6,
@@ -404,6 +921,76 @@ impl From<i32> for Key {
             0xFB => Key::Zoom,
             0xFD => Key::PA1,
             0xFE => Key::OemClear,
+            0x07 => Key::Vk07,
+            0x0A => Key::Vk0A,
+            0x0B => Key::Vk0B,
+            0x15 => Key::Vk15,
+            0x16 => Key::Vk16,
+            0x17 => Key::Vk17,
+            0x18 => Key::Vk18,
+            0x19 => Key::Vk19,
+            0x1A => Key::Vk1A,
+            0x3A => Key::Vk3A,
+            0x3B => Key::Vk3B,
+            0x3C => Key::Vk3C,
+            0x3D => Key::Vk3D,
+            0x3E => Key::Vk3E,
+            0x3F => Key::Vk3F,
+            0x40 => Key::Vk40,
+            0x5E => Key::Vk5E,
+            0x88 => Key::Vk88,
+            0x89 => Key::Vk89,
+            0x8A => Key::Vk8A,
+            0x8B => Key::Vk8B,
+            0x8C => Key::Vk8C,
+            0x8D => Key::Vk8D,
+            0x8E => Key::Vk8E,
+            0x8F => Key::Vk8F,
+            0x92 => Key::Vk92,
+            0x93 => Key::Vk93,
+            0x94 => Key::Vk94,
+            0x95 => Key::Vk95,
+            0x96 => Key::Vk96,
+            0x98 => Key::Vk98,
+            0x99 => Key::Vk99,
+            0x9A => Key::Vk9A,
+            0x9B => Key::Vk9B,
+            0x9C => Key::Vk9C,
+            0x9D => Key::Vk9D,
+            0x9E => Key::Vk9E,
+            0x9F => Key::Vk9F,
+            0xB8 => Key::VkB8,
+            0xB9 => Key::VkB9,
+            0xC1 => Key::VkC1,
+            0xC2 => Key::VkC2,
+            0xD3 => Key::VkD3,
+            0xD4 => Key::VkD4,
+            0xD5 => Key::VkD5,
+            0xD6 => Key::VkD6,
+            0xD7 => Key::VkD7,
+            0xD8 => Key::VkD8,
+            0xD9 => Key::VkD9,
+            0xDA => Key::VkDA,
+            0xE0 => Key::VkE0,
+            0xE1 => Key::VkE1,
+            0xE3 => Key::VkE3,
+            0xE4 => Key::VkE4,
+            0xE6 => Key::VkE6,
+            0xE8 => Key::VkE8,
+            0xE9 => Key::VkE9,
+            0xEA => Key::VkEA,
+            0xEB => Key::VkEB,
+            0xEC => Key::VkEC,
+            0xED => Key::VkED,
+            0xEE => Key::VkEE,
+            0xEF => Key::VkEF,
+            0xF0 => Key::VkF0,
+            0xF1 => Key::VkF1,
+            0xF2 => Key::VkF2,
+            0xF3 => Key::VkF3,
+            0xF4 => Key::VkF4,
+            0xF5 => Key::VkF5,
+            0xFC => Key::VkFC,
             _ => {
                 log::warn!("{} is not a valid key, mapping to Unassigned", n);
                 Key::Unassigned
@@ -411,3 +998,75 @@ impl From<i32> for Key {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Hotkey {
+        #[serde(
+            serialize_with = "serialize_sorted_keys",
+            deserialize_with = "deserialize_keys"
+        )]
+        keys: HashSet<Key>,
+    }
+
+    fn parse(yaml: &str) -> HashSet<Key> {
+        serde_yaml::from_str::<Hotkey>(yaml).unwrap().keys
+    }
+
+    fn write(keys: &[Key]) -> String {
+        serde_yaml::to_string(&Hotkey {
+            keys: keys.iter().copied().collect(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn hotkeys_read_as_a_string_or_a_list() {
+        let expected = HashSet::from([Key::Control, Key::Shift, Key::K]);
+
+        assert_eq!(parse("keys: Ctrl+Shift+K"), expected);
+        assert_eq!(parse("keys: Control + Shift + K"), expected);
+        assert_eq!(parse("keys: [K, Shift, Ctrl]"), expected);
+        assert_eq!(parse("keys: Alt+F4"), HashSet::from([Key::Menu, Key::F4]));
+    }
+
+    #[test]
+    fn hotkeys_write_modifiers_first_in_press_order() {
+        assert_eq!(
+            write(&[Key::K, Key::LeftShift, Key::LeftControl]),
+            "keys: LeftControl+LeftShift+K\n"
+        );
+        assert_eq!(
+            write(&[Key::S, Key::LeftWindows, Key::RightMenu]),
+            "keys: RightMenu+LeftWindows+S\n"
+        );
+        assert_eq!(write(&[Key::F7]), "keys: F7\n");
+    }
+
+    #[test]
+    fn hotkeys_round_trip() {
+        for yaml in [
+            "keys: Ctrl+Shift+K",
+            "keys: [LeftControl, LeftMenu, Delete]",
+            "keys: AltGr+Key2",
+            "keys: XButton1",
+        ] {
+            let parsed = parse(yaml);
+            let written = write(&parsed.iter().copied().collect::<Vec<_>>());
+            assert_eq!(parse(&written), parsed, "{}", yaml);
+            assert_eq!(
+                write(&parse(&written).into_iter().collect::<Vec<_>>()),
+                written
+            );
+        }
+    }
+
+    #[test]
+    fn hotkeys_reject_unknown_keys() {
+        let e = serde_yaml::from_str::<Hotkey>("keys: Ctrl+Banana").unwrap_err();
+        assert!(e.to_string().contains("Banana"), "{}", e);
+    }
+}
@@ -0,0 +1,5152 @@
+//! Runs keyboard and mouse macros on Windows when their hotkeys are pressed. The
+//! `input_macro_runner` program loads them from a YAML config, see `run_cli`; `Macro::builder`,
+//! `MacroConfig::builder` and `MacroRunner` do the same from code.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{sleep, spawn, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use windows::Win32::Foundation::POINT;
+
+use serde::{Deserialize, Serialize};
+
+mod backend;
+mod backup;
+#[doc(hidden)]
+pub mod bench;
+mod builder;
+mod calibrate;
+mod capture;
+mod cli;
+mod clipboard;
+mod clock;
+mod config;
+mod context;
+mod debugger;
+mod desktop;
+mod diff_run;
+mod doctor;
+mod duration;
+mod elevation;
+mod error;
+mod estimate;
+mod events;
+mod executor;
+mod expr;
+mod gamepad;
+mod history;
+mod http;
+mod idle;
+mod injected;
+mod input_block;
+mod jitter;
+mod keys;
+mod lint;
+mod listener;
+mod logger;
+mod mouse_hook;
+mod palette;
+mod process;
+mod profile;
+mod rate_limit;
+mod repeat;
+mod runner;
+mod schedule;
+mod schema;
+mod screen;
+mod secret;
+mod session;
+mod shorthand;
+mod startup;
+#[cfg(target_os = "linux")]
+mod uinput;
+mod update;
+mod watchdog;
+mod window;
+use cli::*;
+use estimate::*;
+use keys::*;
+
+pub use builder::{BuildCommands, CommandsBuilder, MacroBuilder, MacroConfigBuilder, TargetWindow};
+pub use duration::DurationMs;
+pub use error::ErrorKind;
+pub use expr::Coordinate;
+pub use keys::{Key, MediaAction};
+pub use repeat::KeyRepeat;
+pub use runner::{MacroRunner, RunnerHandle};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MacroConfig {
+    #[serde(
+        serialize_with = "serialize_sorted_keys",
+        deserialize_with = "deserialize_keys"
+    )]
+    program_hotkey: HashSet<Key>,
+    /// How long `program_hotkey` must be held without a break before the program exits, so that
+    /// brushing against it does nothing.
+    #[serde(default)]
+    program_hotkey_hold_ms: u64,
+    /// Opens a list of the enabled macros to pick one from by typing its name.
+    #[serde(
+        default,
+        skip_serializing_if = "HashSet::is_empty",
+        serialize_with = "serialize_sorted_keys",
+        deserialize_with = "deserialize_keys"
+    )]
+    palette_hotkey: HashSet<Key>,
+    /// Upper bound on the number of macro threads allowed to run at the same time. Triggers that
+    /// would exceed it are skipped with a warning.
+    #[serde(default = "default_max_macro_threads")]
+    max_macro_threads: usize,
+    /// Most keys a `PressKeyCombo` may hold at once. Many keyboards and applications drop the
+    /// extra keys of larger combos.
+    #[serde(default = "default_max_combo_keys")]
+    max_combo_keys: usize,
+    /// Run macros on a fixed pool of this many threads instead of a new thread per trigger, which
+    /// is cheaper for small macros fired in quick succession. Triggers beyond it wait their turn.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    worker_threads: Option<usize>,
+    /// Seed for the randomness of every macro, such as jitter, instead of a fresh one per
+    /// execution. Overridden by `--seed`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    /// Whether the macros need administrator rights to reach their target windows.
+    #[serde(default)]
+    needs_elevation: bool,
+    /// Glob patterns, relative to this file, of further config files whose macros are merged in.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    include: Vec<String>,
+    /// What happens to running macros when the workstation is locked. Triggers are ignored
+    /// while it stays locked either way.
+    #[serde(default)]
+    on_lock: OnLock,
+    /// Release modifiers left stuck down by macros once nothing has run, and no input has
+    /// arrived, for this many seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    modifier_watchdog_secs: Option<u64>,
+    /// Serve an HTTP endpoint for listing and triggering macros.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    http: Option<http::HttpConfig>,
+    /// Record every execution to a file, read back with the `history` subcommand.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    history: Option<history::HistoryConfig>,
+    /// Where config files are backed up before being written. The commands that write them read
+    /// this straight from the file, see `BackupConfig::from_config_value`.
+    #[serde(default)]
+    backup: backup::BackupConfig,
+    /// When not empty, macros may only send input while one of these windows has the focus, and
+    /// a macro is stopped as soon as focus moves anywhere else. Every command that sends input
+    /// checks, including those that post to a window by title.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    allowed_targets: Vec<window::AllowedTarget>,
+    /// Where `check-update` asks for the latest release, a GitHub releases API URL or any URL
+    /// that answers with a bare version. Defaults to this project's GitHub releases.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    update_url: Option<String>,
+    /// `json` writes the log as JSON lines, with the macro, execution and command of execution
+    /// events as fields, for log collectors. Takes effect once the config is loaded.
+    #[serde(default)]
+    log_format: logger::LogFormat,
+    /// Most keyboard and mouse events all macros together may inject per second. Injection
+    /// beyond it is slowed down, and a macro asking for several times as many is stopped with a
+    /// `rate_limited` failure. 0 turns the limit off.
+    #[serde(default = "default_max_events_per_second")]
+    max_events_per_second: u32,
+    /// Switches the active macro profile to that of the first rule matching the window with the
+    /// focus, once it has kept the focus for a moment, and to none when no rule matches.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    auto_profile: Vec<profile::ProfileRule>,
+    macros: Vec<Macro>,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum OnLock {
+    /// Hold running macros at their next command until the workstation is unlocked.
+    #[default]
+    Pause,
+    /// Cancel running macros.
+    Cancel,
+}
+
+impl MacroConfig {
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        validate_hotkey("program_hotkey", &self.program_hotkey)?;
+        if !self.palette_hotkey.is_empty() {
+            validate_hotkey("palette_hotkey", &self.palette_hotkey)?;
+        }
+
+        if self.worker_threads == Some(0) {
+            return Err(anyhow::anyhow!("worker_threads must be at least 1"));
+        }
+
+        if let Some(http) = &self.http {
+            if http.token.trim().is_empty() {
+                return Err(anyhow::anyhow!(
+                    "http: token must not be empty, or anyone could trigger macros"
+                ));
+            }
+        }
+
+        for target in self.allowed_targets.iter() {
+            if target.process.is_none() && target.class.is_none() && target.title_contains.is_none()
+            {
+                return Err(anyhow::anyhow!(
+                    "allowed_targets entries need a process, class or title_contains"
+                ));
+            }
+        }
+
+        for rule in self.auto_profile.iter() {
+            let window = &rule.window;
+            if window.process.is_none() && window.class.is_none() && window.title_contains.is_none()
+            {
+                return Err(anyhow::anyhow!(
+                    "auto_profile rules need a process, class or title_contains"
+                ));
+            }
+            if rule.profile == profile::GLOBAL_PROFILE {
+                return Err(anyhow::anyhow!(
+                    "auto_profile rules cannot switch to {}, it is always active",
+                    profile::GLOBAL_PROFILE
+                ));
+            }
+            if !self
+                .macros
+                .iter()
+                .any(|current_macro| current_macro.profile.as_deref() == Some(&rule.profile))
+            {
+                log::warn!("auto_profile: no macro is in profile {}", rule.profile);
+            }
+        }
+
+        for current_macro in self.macros.iter() {
+            current_macro.validate(self.max_combo_keys)?;
+        }
+
+        self.validate_chains()?;
+
+        let variables = self.defined_variables();
+        for current_macro in self.macros.iter() {
+            self.validate_calls(&current_macro.macro_name, &current_macro.commands)?;
+            self.validate_variables(
+                &current_macro.macro_name,
+                &current_macro.commands,
+                &variables,
+                &mut Vec::new(),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Every variable a command or a macro's parameters may set, whichever macro it is in, since
+    /// called macros see the variables of their caller.
+    fn defined_variables(&self) -> HashSet<&str> {
+        fn collect<'a>(commands: &'a [Command], variables: &mut HashSet<&'a str>) {
+            for command in commands.iter() {
+                match command {
+                    Command::GetWindowTitle { into }
+                    | Command::WaitForClipboardChange {
+                        into: Some(into), ..
+                    } => {
+                        variables.insert(into);
+                    }
+                    Command::StoreWindowOrigin { .. } => {
+                        variables.extend(["window_x", "window_y"]);
+                    }
+                    Command::Run {
+                        capture_output: true,
+                        ..
+                    } => {
+                        variables.insert("output");
+                    }
+                    _ => {}
+                }
+
+                for nested in command.nested_commands() {
+                    collect(nested, variables);
+                }
+            }
+        }
+
+        let mut variables = HashSet::from(["trigger"]);
+        for current_macro in self.macros.iter() {
+            variables.extend(current_macro.params.iter().map(MacroParam::name));
+            collect(&current_macro.commands, &mut variables);
+        }
+
+        variables
+    }
+
+    /// Checks that every `${}` variable used in `commands` is well-formed and is one that can be
+    /// set: a built-in, a parameter, one a command sets, or the index of an enclosing loop, so
+    /// that a misspelt name fails at load rather than halfway through a run. `enclosing_loops`
+    /// holds the names of the loops around `commands`. Credentials are only allowed where they
+    /// are filled in.
+    fn validate_variables<'a>(
+        &self,
+        macro_name: &str,
+        commands: &'a [Command],
+        variables: &HashSet<&str>,
+        enclosing_loops: &mut Vec<Option<&'a str>>,
+    ) -> Result<(), anyhow::Error> {
+        // A called macro may also use the loops around the call
+        let called = || {
+            self.macros
+                .iter()
+                .any(|current_macro| calls(&current_macro.commands, macro_name))
+        };
+        let check = |name: &str,
+                     credentials: bool,
+                     enclosing_loops: &[Option<&str>]|
+         -> Result<(), anyhow::Error> {
+            let known = if let Some(loop_name) = name.strip_prefix("loop:") {
+                enclosing_loops.contains(&Some(loop_name)) || called()
+            } else if let Some(target) = name.strip_prefix("cred:") {
+                if !credentials {
+                    return Err(anyhow::anyhow!(
+                        "{}: credential {} can only be used in TextInputSecret, ReplaceText and \
+                         Run env",
+                        macro_name,
+                        target
+                    ));
+                }
+                true
+            } else {
+                match name {
+                    "loop_index" | "loop_index1" => !enclosing_loops.is_empty() || called(),
+                    _ => {
+                        name.starts_with("env:")
+                            || variables.contains(name)
+                            || screen::is_builtin_variable(name)
+                    }
+                }
+            };
+
+            if !known {
+                return Err(anyhow::anyhow!(
+                    "{}: unknown variable {:?}",
+                    macro_name,
+                    name
+                ));
+            }
+            Ok(())
+        };
+
+        for command in commands.iter() {
+            for (text, credentials) in command.templates() {
+                for part in context::parse_template(text)
+                    .map_err(|e| anyhow::anyhow!("{}: {}", macro_name, e))?
+                {
+                    if let context::TemplatePart::Variable(name) = part {
+                        check(name, credentials, enclosing_loops)?;
+                    }
+                }
+            }
+            if let Command::IfVarMatches { var, .. } = command {
+                check(var, false, enclosing_loops)?;
+            }
+
+            match command {
+                Command::Loop(_, body) => {
+                    enclosing_loops.push(None);
+                    self.validate_variables(macro_name, body, variables, enclosing_loops)?;
+                    enclosing_loops.pop();
+                }
+                Command::NamedLoop { name, commands, .. } => {
+                    enclosing_loops.push(Some(name));
+                    self.validate_variables(macro_name, commands, variables, enclosing_loops)?;
+                    enclosing_loops.pop();
+                }
+                _ => {
+                    for nested in command.nested_commands() {
+                        self.validate_variables(macro_name, nested, variables, enclosing_loops)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that every `CallMacro` names an existing macro and only passes, and does not leave
+    /// out, its declared parameters.
+    fn validate_calls(&self, macro_name: &str, commands: &[Command]) -> Result<(), anyhow::Error> {
+        for command in commands.iter() {
+            if let Command::CallMacro { name, args } = command {
+                let target = self
+                    .macro_index(name)
+                    .map(|index| &self.macros[index])
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("{}: called macro {} does not exist", macro_name, name)
+                    })?;
+                target
+                    .bind_args(args)
+                    .map_err(|e| anyhow::anyhow!("{}: {}", macro_name, e))?;
+            }
+
+            for nested in command.nested_commands() {
+                self.validate_calls(macro_name, nested)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn macro_index(&self, macro_name: &str) -> Option<usize> {
+        self.macros
+            .iter()
+            .position(|current_macro| current_macro.macro_name == macro_name)
+    }
+
+    /// Checks that every `on_success`/`on_failure` target exists and that no chain loops back
+    /// on itself.
+    fn validate_chains(&self) -> Result<(), anyhow::Error> {
+        let mut follow_ups = Vec::with_capacity(self.macros.len());
+
+        for current_macro in self.macros.iter() {
+            let mut targets = Vec::new();
+            for target in [&current_macro.on_success, &current_macro.on_failure]
+                .into_iter()
+                .flatten()
+            {
+                match self.macro_index(target) {
+                    Some(index) => targets.push(index),
+                    None => {
+                        return Err(anyhow::anyhow!(
+                            "{}: chained macro {} does not exist",
+                            current_macro.macro_name,
+                            target
+                        ))
+                    }
+                }
+            }
+            follow_ups.push(targets);
+        }
+
+        // Depth-first search over the follow-up graph, a back edge to a macro still on the
+        // stack is a cycle
+        fn visit(
+            index: usize,
+            follow_ups: &[Vec<usize>],
+            on_stack: &mut Vec<usize>,
+            done: &mut HashSet<usize>,
+        ) -> Option<Vec<usize>> {
+            if let Some(position) = on_stack.iter().position(|i| *i == index) {
+                let mut cycle = on_stack[position..].to_vec();
+                cycle.push(index);
+                return Some(cycle);
+            }
+
+            if !done.insert(index) {
+                return None;
+            }
+
+            on_stack.push(index);
+            for next in follow_ups[index].iter() {
+                if let Some(cycle) = visit(*next, follow_ups, on_stack, done) {
+                    return Some(cycle);
+                }
+            }
+            on_stack.pop();
+
+            None
+        }
+
+        let mut done = HashSet::new();
+        for index in 0..self.macros.len() {
+            if let Some(cycle) = visit(index, &follow_ups, &mut Vec::new(), &mut done) {
+                return Err(anyhow::anyhow!(
+                    "Macro chain loops back on itself: {}",
+                    cycle
+                        .iter()
+                        .map(|index| self.macros[*index].macro_name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(" -> ")
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Rejects hotkeys that would make normal use of the keyboard or mouse impossible, such as a bare
+/// left click.
+fn validate_hotkey(name: &str, hotkey: &HashSet<Key>) -> Result<(), anyhow::Error> {
+    if hotkey.is_empty() {
+        return Err(anyhow::anyhow!(
+            "{}: hotkey must contain at least one key",
+            name
+        ));
+    }
+
+    if hotkey.iter().any(Key::is_primary_mouse_button) && !hotkey.iter().any(Key::is_modifier) {
+        return Err(anyhow::anyhow!(
+            "{}: hotkeys using the left or right mouse button must also include a keyboard modifier",
+            name
+        ));
+    }
+
+    Ok(())
+}
+
+/// Rejects key combos that cannot work: more keys than `max_combo_keys`, or a generic modifier
+/// together with its sided variant, such as `Shift` with `LeftShift`. Warns about combos of
+/// nothing but modifiers and `PressKey` on a modifier, which are almost always mistakes. Also
+/// rejects window commands that select no window at all. Diagnostics name the command by its
+/// 1-based position, e.g. `command 3.2` for the second command in the body of the third. Bodies
+/// of the same command are numbered through, `then` before `else`.
+fn validate_key_combos<'a>(
+    macro_name: &str,
+    commands: impl IntoIterator<Item = &'a Command>,
+    parent: &str,
+    max_combo_keys: usize,
+) -> Result<(), anyhow::Error> {
+    for (index, command) in commands.into_iter().enumerate() {
+        let position = format!("{}{}", parent, index + 1);
+
+        match command {
+            Command::PressKeyCombo(keys) => {
+                if keys.len() > max_combo_keys {
+                    return Err(anyhow::anyhow!(
+                        "{}: command {}: PressKeyCombo has {} keys, more than max_combo_keys ({})",
+                        macro_name,
+                        position,
+                        keys.len(),
+                        max_combo_keys
+                    ));
+                }
+
+                if let Some(key) = keys.iter().find(|key| {
+                    key.generic_modifier()
+                        .is_some_and(|generic| keys.contains(&generic))
+                }) {
+                    return Err(anyhow::anyhow!(
+                        "{}: command {}: PressKeyCombo has both {:?} and {:?}, keep only one",
+                        macro_name,
+                        position,
+                        key.generic_modifier().unwrap_or(*key),
+                        key
+                    ));
+                }
+
+                if keys.iter().all(Key::is_modifier) {
+                    log::warn!(
+                        "{}: command {}: PressKeyCombo holds nothing but modifiers",
+                        macro_name,
+                        position
+                    );
+                }
+            }
+            command
+                if command
+                    .window_selector()
+                    .is_some_and(|selector| selector.is_empty()) =>
+            {
+                return Err(anyhow::anyhow!(
+                    "{}: command {}: needs a title, class or process to find its window",
+                    macro_name,
+                    position
+                ));
+            }
+            Command::WaitForProcessIdle {
+                cpu_below_percent, ..
+            } if !(*cpu_below_percent > 0.0 && *cpu_below_percent <= 100.0) => {
+                return Err(anyhow::anyhow!(
+                    "{}: command {}: WaitForProcessIdle cpu_below_percent must be above 0 and at \
+                     most 100",
+                    macro_name,
+                    position
+                ));
+            }
+            Command::PressKey(key) if key.is_modifier() => {
+                log::warn!(
+                    "{}: command {}: PressKey on the modifier {:?} only taps it, use HoldKey, \
+                     WithKeysHeld or PressKeyCombo to combine it with other keys",
+                    macro_name,
+                    position,
+                    key
+                );
+            }
+            _ => {}
+        }
+
+        let nested: Vec<&Command> = command.nested_commands().into_iter().flatten().collect();
+        validate_key_combos(
+            macro_name,
+            nested,
+            &format!("{}.", position),
+            max_combo_keys,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Warns about `SetMousePos` and `MousePath` targets given as plain numbers that lie on none of
+/// this machine's monitors. Targets computed from expressions are only checked when they run.
+/// Commands are named by position as in `validate_key_combos`.
+fn warn_off_screen_coordinates<'a>(
+    macro_name: &str,
+    commands: impl IntoIterator<Item = &'a Command>,
+    parent: &str,
+) {
+    for (index, command) in commands.into_iter().enumerate() {
+        let position = format!("{}{}", parent, index + 1);
+
+        let targets = match command {
+            Command::SetMousePos(expr::Coordinate::Value(x), expr::Coordinate::Value(y)) => {
+                vec![(*x, *y)]
+            }
+            Command::MousePath { points, .. } => points.iter().map(|(x, y, _)| (*x, *y)).collect(),
+            _ => Vec::new(),
+        };
+        for (x, y) in targets {
+            if let Err(e) = screen::ensure_on_screen(&backend::GdiScreen, x, y) {
+                log::warn!("{}: command {}: {}", macro_name, position, e);
+            }
+        }
+
+        let nested: Vec<&Command> = command.nested_commands().into_iter().flatten().collect();
+        warn_off_screen_coordinates(macro_name, nested, &format!("{}.", position));
+    }
+}
+
+/// Checks what can be checked of `commands` and the blocks nested in them before they run: that
+/// every `Break`/`Continue` sits inside a loop and names an enclosing loop if it names one at
+/// all, that every `RetryBlock` has attempts to make and a usable multiplier, and that every
+/// fixed `IfVarMatches` regex compiles. `enclosing_loops` holds the names of the loops around
+/// `commands`.
+fn validate_commands<'a>(
+    macro_name: &str,
+    commands: &'a [Command],
+    enclosing_loops: &mut Vec<Option<&'a str>>,
+) -> Result<(), anyhow::Error> {
+    for command in commands.iter() {
+        match command {
+            Command::Loop(_, body) => {
+                enclosing_loops.push(None);
+                validate_commands(macro_name, body, enclosing_loops)?;
+                enclosing_loops.pop();
+            }
+            Command::NamedLoop { name, commands, .. } => {
+                enclosing_loops.push(Some(name));
+                validate_commands(macro_name, commands, enclosing_loops)?;
+                enclosing_loops.pop();
+            }
+            Command::WithKeysHeld { commands, .. } => {
+                validate_commands(macro_name, commands, enclosing_loops)?
+            }
+            Command::RetryBlock {
+                attempts,
+                multiplier,
+                commands,
+                ..
+            } => {
+                if *attempts == 0 {
+                    return Err(anyhow::anyhow!(
+                        "{}: RetryBlock needs at least one attempt",
+                        macro_name
+                    ));
+                }
+                if !(multiplier.is_finite() && *multiplier >= 0.0) {
+                    return Err(anyhow::anyhow!(
+                        "{}: RetryBlock multiplier must be a non-negative number",
+                        macro_name
+                    ));
+                }
+                validate_commands(macro_name, commands, enclosing_loops)?
+            }
+            Command::IfKeyHeld { then, r#else, .. }
+            | Command::IfFileExists { then, r#else, .. } => {
+                validate_commands(macro_name, then, enclosing_loops)?;
+                validate_commands(macro_name, r#else, enclosing_loops)?;
+            }
+            Command::IfVarMatches {
+                regex,
+                then,
+                r#else,
+                ..
+            } => {
+                // Patterns built from variables can only be checked once they are filled in
+                if !regex.contains("${") {
+                    regex::Regex::new(regex)
+                        .map_err(|e| anyhow::anyhow!("{}: invalid regex: {}", macro_name, e))?;
+                }
+                validate_commands(macro_name, then, enclosing_loops)?;
+                validate_commands(macro_name, r#else, enclosing_loops)?;
+            }
+            Command::Break(label) | Command::Continue(label) => {
+                if enclosing_loops.is_empty() {
+                    return Err(anyhow::anyhow!(
+                        "{}: Break and Continue can only be used inside a loop",
+                        macro_name
+                    ));
+                }
+
+                if let Some(label) = label {
+                    if !enclosing_loops.contains(&Some(label.as_str())) {
+                        return Err(anyhow::anyhow!(
+                            "{}: no enclosing loop is named {}",
+                            macro_name,
+                            label
+                        ));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `commands` call the macro `macro_name`, at any depth.
+fn calls(commands: &[Command], macro_name: &str) -> bool {
+    commands.iter().any(|command| {
+        matches!(command, Command::CallMacro { name, .. } if name == macro_name)
+            || command
+                .nested_commands()
+                .into_iter()
+                .any(|nested| calls(nested, macro_name))
+    })
+}
+
+fn default_retry_multiplier() -> f64 {
+    2.0
+}
+
+fn default_modifier_grace() -> duration::DurationMs {
+    duration::DurationMs(2000)
+}
+
+fn default_settle() -> duration::DurationMs {
+    duration::DurationMs(50)
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_max_macro_threads() -> usize {
+    16
+}
+
+fn default_max_combo_keys() -> usize {
+    4
+}
+
+fn default_max_events_per_second() -> u32 {
+    rate_limit::DEFAULT_MAX_EVENTS_PER_SECOND
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Macro {
+    macro_name: String,
+    /// May only be left out when the macro has another trigger, i.e. `on_idle` or
+    /// `mouse_trigger`. With `mouse_trigger`, only lists the keys that must be held.
+    #[serde(
+        default,
+        skip_serializing_if = "HashSet::is_empty",
+        serialize_with = "serialize_sorted_keys",
+        deserialize_with = "deserialize_keys"
+    )]
+    macro_hotkey: HashSet<Key>,
+    /// Disabled macros are loaded and validated but never triggered.
+    #[serde(default = "default_true")]
+    enabled: bool,
+    #[serde(default)]
+    trigger_on: TriggerOn,
+    #[serde(default)]
+    mode: MacroMode,
+    /// Also run the macro when the user has been away for a while.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    on_idle: Option<idle::IdleTrigger>,
+    /// Run the macro on a mouse click, double click or wheel notch, see `MouseTrigger`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mouse_trigger: Option<mouse_hook::MouseTrigger>,
+    /// Let input sent by macros, this one included, trigger the macro. Otherwise hotkey keys a
+    /// macro holds down, or has only just let go, do not count as pressed, and neither do clicks
+    /// macros send, so that a macro cannot trigger itself or another by pressing its hotkey.
+    #[serde(default)]
+    allow_injected_trigger: bool,
+    /// Profile the macro belongs to. Its hotkey, mouse and idle triggers only fire while the
+    /// profile is active, see `auto_profile`. Macros without one, or in `global`, are always
+    /// available. Other triggers, such as the palette or HTTP, are not affected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    profile: Option<String>,
+    /// Name of a lock that only one macro at a time may hold while it runs, for macros that must
+    /// never run at the same time as each other but may run alongside any other.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mutex: Option<String>,
+    /// What happens to a trigger while another macro holds the `mutex`.
+    #[serde(default)]
+    mutex_policy: MutexPolicy,
+    /// Executions waiting to start, for a `mutex` or a free worker, start in order of priority,
+    /// highest first, and so do macros whose hotkeys match at the same time.
+    #[serde(default)]
+    priority: u8,
+    /// Ask running macros of lower priority that keep this one from starting, by holding its
+    /// `mutex` or the last free thread or worker, to stop, and start once they have cleaned up.
+    #[serde(default)]
+    preempt: bool,
+    /// Require the hotkey to be pressed a second time within `CONFIRMATION_WINDOW` before running.
+    #[serde(default)]
+    confirm: bool,
+    /// Macro to run after this one finishes with every command succeeding.
+    #[serde(default)]
+    on_success: Option<String>,
+    /// Macro to run after this one finishes with at least one failed command.
+    #[serde(default)]
+    on_failure: Option<String>,
+    /// Ignore further triggers for this long after the macro was triggered (or finished, see
+    /// `cooldown_from`).
+    #[serde(default)]
+    cooldown_ms: duration::DurationMs,
+    #[serde(default)]
+    cooldown_from: CooldownFrom,
+    /// Parameters the macro accepts from `CallMacro` and `run-macro --arg`, available to its
+    /// commands as `${name}`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    params: Vec<MacroParam>,
+    /// Wait a random 0 to `jitter_ms` milliseconds before every command that sends input.
+    #[serde(default)]
+    jitter_ms: u64,
+    /// Move every `SetMousePos` target by a random offset of up to this many pixels.
+    #[serde(default)]
+    jitter_px: i32,
+    /// Seed for the macro's randomness, such as jitter, making it the same on every run. Takes
+    /// precedence over the config's `seed` and `--seed`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    jitter_seed: Option<u64>,
+    /// NumLock state the macro needs, e.g. so numpad digits are not read as arrows. It is put
+    /// back the way it was when the macro ends.
+    #[serde(default)]
+    ensure_numlock: NumlockPolicy,
+    /// Turn CapsLock off, checking that it took, before every `TextInput` so the text is not
+    /// typed with its case inverted.
+    #[serde(default)]
+    capslock_off_for_text: bool,
+    /// Keep the user's own keyboard and mouse input from reaching any window while the macro
+    /// runs, so that it cannot mix with the macro's, e.g. in the middle of a long `TextInput`.
+    /// Releases and the keys of `program_hotkey` still go through, and `Pause` lets everything
+    /// through while it waits.
+    #[serde(default)]
+    block_user_input: bool,
+    /// When the user is still holding Ctrl, Shift, Alt or Windows as the macro starts, e.g. the
+    /// modifiers of its own hotkey, wait up to `modifier_grace_ms` for them to be let go, so
+    /// that they do not mix with the macro's input. The macro runs anyway once the time is up.
+    #[serde(default)]
+    wait_for_clean_modifiers: bool,
+    #[serde(default = "default_modifier_grace")]
+    modifier_grace_ms: duration::DurationMs,
+    /// Release the modifiers the user holds as the macro starts, after `wait_for_clean_modifiers`
+    /// if that is set too, and press the very same keys, left or right, again once the macro
+    /// ends, however it ends. Leave both off for macros meant to build on held modifiers.
+    #[serde(default)]
+    neutralize_modifiers: bool,
+    /// Retries that `RetryBlock`s anywhere in the macro, including in macros it calls, may make
+    /// between them. Once they are used up, no more retries are made and the first failure ends
+    /// the macro. Unlimited when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retry_budget: Option<u32>,
+    /// Do not start while a fullscreen app, such as a game, has the focus.
+    #[serde(default)]
+    skip_when_fullscreen: bool,
+    /// Like `skip_when_fullscreen`, but a hotkey or remote trigger is kept and the macro started
+    /// once the fullscreen app loses the focus.
+    #[serde(default)]
+    defer_when_fullscreen: bool,
+    /// Fail mouse commands whose target is on no monitor instead of letting Windows clamp them to
+    /// the nearest edge, and warn at load about fixed targets that are off-screen on this machine.
+    #[serde(default)]
+    strict_coordinates: bool,
+    /// Only trigger during this part of the day.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    active_hours: Option<schedule::ActiveHours>,
+    /// Only trigger on these days of the week, e.g. `[mon, tue, wed, thu, fri]`. Empty means
+    /// every day.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    active_days: Vec<schedule::Weekday>,
+    /// Trigger sources, e.g. `[cli, http]`, that start the macro regardless of its active hours,
+    /// active days and cooldown.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    ignore_guards_for: Vec<events::TriggerSource>,
+    /// A list of commands, or for short macros a shorthand string such as
+    /// `"move(500, 500); click(); text('hello')"`, see `shorthand::parse`.
+    #[serde(deserialize_with = "shorthand::deserialize_commands")]
+    commands: Vec<Command>,
+    /// File the macro was loaded from.
+    #[serde(skip)]
+    source: Option<PathBuf>,
+    /// Line of that file the macro starts on, when it could be found.
+    #[serde(skip)]
+    source_line: Option<usize>,
+}
+
+/// A macro parameter, either just a name, which makes it required, or a name with a default.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum MacroParam {
+    Required(String),
+    Optional { name: String, default: String },
+}
+
+impl MacroParam {
+    fn name(&self) -> &str {
+        match self {
+            MacroParam::Required(name) | MacroParam::Optional { name, .. } => name,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum MutexPolicy {
+    /// Ignore the trigger.
+    #[default]
+    Skip,
+    /// Run the macro once the mutex is released.
+    Queue,
+}
+
+/// The moment a macro's cooldown is measured from.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum CooldownFrom {
+    #[default]
+    Trigger,
+    Completion,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum NumlockPolicy {
+    On,
+    Off,
+    /// Leave NumLock as it is.
+    #[default]
+    Ignore,
+}
+
+/// How long a macro with `confirm: true` waits for its hotkey to be pressed again.
+const CONFIRMATION_WINDOW: Duration = Duration::from_secs(3);
+
+/// How long a macro started by its hotkey keeps running.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum MacroMode {
+    /// Run the commands once.
+    #[default]
+    Once,
+    /// Repeat the commands for as long as the hotkey is held. The release is noticed at the next
+    /// 50 ms poll and the macro stops after the command it is on, so it may run up to one poll
+    /// interval plus one command longer than the key is held. Started any other way, e.g. over
+    /// HTTP, it runs once.
+    WhileHeld,
+}
+
+/// Which edge of the hotkey starts a macro.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum TriggerOn {
+    /// Run when the last key of the hotkey goes down.
+    #[default]
+    Press,
+    /// Run when the hotkey, having been fully held, is let go.
+    Release,
+}
+
+impl Macro {
+    /// Checks what can be checked of the macro on its own, without the rest of the config, such
+    /// as its hotkey and the commands' loop control and key combos.
+    fn validate(&self, max_combo_keys: usize) -> Result<(), anyhow::Error> {
+        if !(0..=jitter::MAX_RADIUS_PX).contains(&self.jitter_px) {
+            return Err(anyhow::anyhow!(
+                "{}: jitter_px must be between 0 and {}",
+                self.macro_name,
+                jitter::MAX_RADIUS_PX
+            ));
+        }
+        if self.jitter_ms > jitter::MAX_DELAY_MS {
+            return Err(anyhow::anyhow!(
+                "{}: jitter_ms must be at most {}",
+                self.macro_name,
+                jitter::MAX_DELAY_MS
+            ));
+        }
+        if let Some(mouse_trigger) = &self.mouse_trigger {
+            mouse_trigger
+                .validate()
+                .map_err(|e| anyhow::anyhow!("{}: {}", self.macro_name, e))?;
+            if self.mode == MacroMode::WhileHeld || self.trigger_on == TriggerOn::Release {
+                return Err(anyhow::anyhow!(
+                    "{}: mouse_trigger cannot be combined with mode while_held or \
+                     trigger_on: release",
+                    self.macro_name
+                ));
+            }
+        } else if !self.macro_hotkey.is_empty() || self.on_idle.is_none() {
+            validate_hotkey(&self.macro_name, &self.macro_hotkey)?;
+        }
+        if let Some(on_idle) = self.on_idle {
+            if on_idle.after.0 == 0 {
+                return Err(anyhow::anyhow!(
+                    "{}: on_idle after must be longer than 0",
+                    self.macro_name
+                ));
+            }
+        }
+        if self.mode == MacroMode::WhileHeld {
+            if self.macro_hotkey.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "{}: mode while_held needs a hotkey",
+                    self.macro_name
+                ));
+            }
+            if self.trigger_on == TriggerOn::Release {
+                return Err(anyhow::anyhow!(
+                    "{}: mode while_held cannot be combined with trigger_on: release",
+                    self.macro_name
+                ));
+            }
+            if self.commands.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "{}: mode while_held needs at least one command to repeat",
+                    self.macro_name
+                ));
+            }
+        }
+        validate_commands(&self.macro_name, &self.commands, &mut Vec::new())?;
+        validate_key_combos(&self.macro_name, self.commands.iter(), "", max_combo_keys)?;
+        if self.strict_coordinates {
+            warn_off_screen_coordinates(&self.macro_name, self.commands.iter(), "");
+        }
+
+        Ok(())
+    }
+
+    /// Checks `args` against the declared parameters, filling in defaults for the ones not given.
+    fn bind_args(
+        &self,
+        args: &HashMap<String, String>,
+    ) -> Result<HashMap<String, String>, anyhow::Error> {
+        if let Some(unknown) = args.keys().find(|name| {
+            !self
+                .params
+                .iter()
+                .any(|param| param.name() == name.as_str())
+        }) {
+            return Err(anyhow::anyhow!(
+                "{} has no parameter named {}",
+                self.macro_name,
+                unknown
+            ));
+        }
+
+        let mut bound = HashMap::with_capacity(self.params.len());
+        for param in self.params.iter() {
+            let value = match (args.get(param.name()), param) {
+                (Some(value), _) => value.clone(),
+                (None, MacroParam::Optional { default, .. }) => default.clone(),
+                (None, MacroParam::Required(name)) => {
+                    return Err(anyhow::anyhow!(
+                        "{} requires the parameter {}",
+                        self.macro_name,
+                        name
+                    ))
+                }
+            };
+            bound.insert(param.name().to_string(), value);
+        }
+
+        Ok(bound)
+    }
+
+    /// Whether the current local time is within `active_hours` and `active_days`.
+    #[cfg(windows)]
+    fn is_active_now(&self) -> bool {
+        schedule::is_active(
+            self.active_hours.as_ref(),
+            &self.active_days,
+            schedule::local_time(),
+        )
+    }
+
+    fn estimated_duration(&self) -> DurationEstimate {
+        self.commands
+            .iter()
+            .fold(DurationEstimate::zero(), |total, command| {
+                total + command.estimated_duration()
+            })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Command {
+    GetMousePos,
+    SetMousePos(expr::Coordinate, expr::Coordinate),
+    LeftClick,
+    MiddleClick,
+    RightClick,
+    /// Scrolls the wheel by this many lines under the user's lines-per-notch setting, up (away
+    /// from the user) for positive counts and down for negative ones.
+    ScrollLines(i32),
+    PressKey(Key),
+    #[serde(
+        serialize_with = "serialize_sorted_keys",
+        deserialize_with = "deserialize_keys"
+    )]
+    PressKeyCombo(HashSet<Key>),
+    TextInput(String), // TODO: Further validate functionality
+    Wait(duration::DurationMs),
+    /// Runs the commands the given number of times, or forever for 0. Inside the body,
+    /// `${loop_index}` is the zero-based iteration and `${loop_index1}` the one-based one.
+    Loop(u32, Vec<Self>),
+    /// A `Loop` whose index can also be reached from nested loops as `${loop:<name>}`.
+    NamedLoop {
+        name: String,
+        iterations: u32,
+        commands: Vec<Self>,
+    },
+    /// Leaves the innermost loop (`!Break`) or the enclosing loop with this name
+    /// (`!Break outer`).
+    Break(Option<String>),
+    /// Skips to the next iteration of the innermost or named enclosing loop.
+    Continue(Option<String>),
+    /// Posts a key press straight to the window with this exact title, class and/or process,
+    /// without stealing focus. Many applications ignore posted keystrokes, so this only works for
+    /// some targets.
+    SendKeyToWindow {
+        #[serde(default)]
+        title: Option<String>,
+        #[serde(default)]
+        class: Option<String>,
+        #[serde(default)]
+        process: Option<String>,
+        #[serde(default)]
+        current_desktop_only: bool,
+        key: Key,
+    },
+    /// Posts text to the window selected as for `SendKeyToWindow` as character messages. The same
+    /// caveats apply.
+    SendTextToWindow {
+        #[serde(default)]
+        title: Option<String>,
+        #[serde(default)]
+        class: Option<String>,
+        #[serde(default)]
+        process: Option<String>,
+        #[serde(default)]
+        current_desktop_only: bool,
+        text: String,
+    },
+    /// Holds a key down for `duration_ms`, optionally auto-repeating it like a physically held
+    /// key.
+    HoldKey {
+        key: Key,
+        duration_ms: duration::DurationMs,
+        #[serde(default)]
+        repeat: Option<repeat::KeyRepeat>,
+    },
+    /// Puts a key down until a matching `KeyUp` (or the end of the macro), optionally
+    /// auto-repeating it in the meantime.
+    KeyDown {
+        key: Key,
+        #[serde(default)]
+        repeat: Option<repeat::KeyRepeat>,
+    },
+    KeyUp(Key),
+    /// Holds `keys` down (pressed in order, released in reverse) while `commands` run, e.g. to
+    /// ctrl-click several items. The keys are released however the block ends.
+    WithKeysHeld {
+        keys: Vec<Key>,
+        commands: Vec<Self>,
+    },
+    /// Runs `commands`, and if any of them fails, starts the whole block over, up to `attempts`
+    /// runs in total. The delay before each retry starts at `backoff_ms` and is multiplied by
+    /// `multiplier` every time. With `retry_on`, only failures of those kinds, such as
+    /// `timeout`, are retried, and any other failure ends the block at once.
+    RetryBlock {
+        attempts: u32,
+        #[serde(default)]
+        backoff_ms: u64,
+        #[serde(default = "default_retry_multiplier")]
+        multiplier: f64,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        retry_on: Vec<error::ErrorKind>,
+        commands: Vec<Self>,
+    },
+    /// Runs another macro's commands as part of this one. `args` values may use `${}` variables.
+    CallMacro {
+        name: String,
+        #[serde(default)]
+        args: HashMap<String, String>,
+    },
+    /// Runs `then` if `key` is currently held down, and `else` otherwise.
+    IfKeyHeld {
+        key: Key,
+        then: Vec<Self>,
+        #[serde(default)]
+        r#else: Vec<Self>,
+    },
+    /// Replaces the contents of the focused text field: selects all, deletes, then enters `text`,
+    /// which may use `${}` variables and `${cred:<target>}` credentials, waiting `settle_ms`
+    /// between the steps. Pasting replaces the
+    /// clipboard contents.
+    ReplaceText {
+        text: String,
+        #[serde(default)]
+        method: ReplaceMethod,
+        #[serde(default = "default_settle")]
+        settle_ms: duration::DurationMs,
+    },
+    /// Stores the title of the foreground window in the variable `into`.
+    GetWindowTitle {
+        into: String,
+    },
+    /// Runs `then` if the value of the variable `var` matches `regex`, and `else` otherwise.
+    /// `regex` may use `${}` variables.
+    IfVarMatches {
+        var: String,
+        regex: String,
+        then: Vec<Self>,
+        #[serde(default)]
+        r#else: Vec<Self>,
+    },
+    /// Moves the mouse along a recorded path. Each point is `[x, y, ms]`, where `ms` is the time
+    /// since the start of the path at which the point is reached. With a button set, it is held
+    /// from the first point to the last, as in a drag.
+    MousePath {
+        points: Vec<(i32, i32, u64)>,
+        #[serde(default)]
+        button: Option<MouseButton>,
+    },
+    /// Presses a media or browser key, e.g. `!Media play_pause`.
+    Media(MediaAction),
+    /// Waits until a process with this executable name (e.g. `setup.exe`, case-insensitive) is
+    /// running or, for `Exited`, until none is. Fails after `timeout_ms`.
+    WaitForProcess {
+        name: String,
+        state: ProcessState,
+        timeout_ms: duration::DurationMs,
+    },
+    /// Waits until the processes with this executable name (e.g. `excel.exe`, case-insensitive)
+    /// together use less than `cpu_below_percent` of the CPU, as Task Manager counts it, for
+    /// `sustained_ms` in a row, e.g. once an export stops crunching. Fails after `timeout_ms`, or
+    /// right away if no such process is running.
+    WaitForProcessIdle {
+        process: String,
+        cpu_below_percent: f32,
+        sustained_ms: duration::DurationMs,
+        timeout_ms: duration::DurationMs,
+    },
+    /// Runs `then` if `path` exists, and `else` otherwise. `path` may use `${}` variables, and a
+    /// relative path is taken from the directory of the config file the macro is in.
+    IfFileExists {
+        path: String,
+        then: Vec<Self>,
+        #[serde(default)]
+        r#else: Vec<Self>,
+    },
+    /// Waits until `path` exists, e.g. the file written by an export started with `Run`. Fails
+    /// after `timeout_ms`. `path` is read as for `IfFileExists`.
+    WaitForFile {
+        path: String,
+        timeout_ms: duration::DurationMs,
+    },
+    /// Waits until the pixel at `x`, `y` has `color`, e.g. `"#3C8DBC"`, with every channel within
+    /// `tolerance` of it, e.g. once a button lights up. Fails after `timeout_ms`, or right away
+    /// if the point lies on no monitor.
+    WaitForPixel {
+        x: expr::Coordinate,
+        y: expr::Coordinate,
+        color: screen::Color,
+        #[serde(default)]
+        tolerance: u8,
+        timeout_ms: duration::DurationMs,
+    },
+    /// Waits until the clipboard contents change, e.g. once an app's copy button has done its
+    /// work, and with `into` stores the new text in that variable. Contents that are not text
+    /// still count as a change, but leave the variable unset. Fails after `timeout_ms`.
+    WaitForClipboardChange {
+        timeout_ms: duration::DurationMs,
+        #[serde(default)]
+        into: Option<String>,
+    },
+    /// Logs `message`, which may use `${}` variables, and waits for `resume_key`, or else the
+    /// running macro's hotkey, to be pressed, e.g. so a filled-in form can be reviewed before the
+    /// macro submits it. Cancelling the macro ends the wait.
+    Pause {
+        #[serde(default)]
+        message: Option<String>,
+        #[serde(default)]
+        resume_key: Option<Key>,
+    },
+    /// Launches a program. `args`, `cwd` and `env` values may use `${}` variables, and `env`
+    /// values `${cred:<target>}` credentials too. With `capture_output`, waits for it to exit and
+    /// stores its stdout in `${output}`, killing it if the macro is cancelled or it outlasts
+    /// `timeout_ms`.
+    Run {
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default)]
+        cwd: Option<String>,
+        #[serde(default)]
+        env: HashMap<String, String>,
+        #[serde(default)]
+        capture_output: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout_ms: Option<duration::DurationMs>,
+    },
+    /// Moves and resizes the window with this exact title, class and/or process so that
+    /// coordinate clicks land where expected. With `restore_after`, the original placement is put
+    /// back when the macro ends.
+    NormalizeWindow {
+        #[serde(default)]
+        title: Option<String>,
+        #[serde(default)]
+        class: Option<String>,
+        #[serde(default)]
+        process: Option<String>,
+        #[serde(default)]
+        current_desktop_only: bool,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        #[serde(default)]
+        restore_after: bool,
+    },
+    /// Stores the top-left corner of the window with this exact title, class and/or process in
+    /// `${window_x}` and `${window_y}`, for coordinates written as offsets from it.
+    StoreWindowOrigin {
+        #[serde(default)]
+        title: Option<String>,
+        #[serde(default)]
+        class: Option<String>,
+        #[serde(default)]
+        process: Option<String>,
+        #[serde(default)]
+        current_desktop_only: bool,
+    },
+    /// Switches to the virtual desktop of the window with this exact title, class and/or
+    /// process, leaving the window where it is, e.g. before clicking into an app kept on a
+    /// desktop of its own. Does nothing when it is on the current desktop already.
+    SwitchToWindowDesktop {
+        #[serde(default)]
+        title: Option<String>,
+        #[serde(default)]
+        class: Option<String>,
+        #[serde(default)]
+        process: Option<String>,
+    },
+    /// Types the secret held in the environment variable `from_env`, or else `text`, in which
+    /// `${cred:<target>}` stands for the password of a generic Windows Credential Manager entry.
+    /// Only the variable and target names are ever part of the config, so the secret itself never
+    /// reaches logs or `show-config`.
+    TextInputSecret {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        from_env: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        text: Option<String>,
+    },
+    /// Types the password of a generic Windows Credential Manager entry.
+    TextInputCredential {
+        target: String,
+    },
+    /// Turns CapsLock, NumLock or ScrollLock on or off, pressing it only if needed and checking
+    /// that the toggle took.
+    SetLockKey {
+        key: LockKey,
+        state: LockState,
+    },
+    /// Checks that a key is up/down, or that a lock key is toggled on/off. With `fix`, a mismatch
+    /// is corrected (pressing the lock key, or sending the missing key up/down) instead of
+    /// failing the macro.
+    AssertKeyState {
+        key: Key,
+        state: KeyState,
+        #[serde(default)]
+        fix: bool,
+    },
+    /// Fails when the foreground window is elevated above this runner, since any input sent to
+    /// it would be silently discarded.
+    AssertNotBlocked,
+    /// Under `run-macro --debug`, stops before the next command until Enter, S or Q is pressed,
+    /// as `--step` does before every command. Does nothing otherwise.
+    Breakpoint,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeyState {
+    Up,
+    Down,
+    ToggledOn,
+    ToggledOff,
+}
+
+/// How `ReplaceText` enters the new text.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReplaceMethod {
+    /// Type it as Unicode characters, independent of the keyboard layout.
+    #[default]
+    TypeOver,
+    /// Put it on the clipboard and press Ctrl+V.
+    Paste,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[allow(clippy::enum_variant_names)]
+pub enum LockKey {
+    CapsLock,
+    NumLock,
+    ScrollLock,
+}
+
+impl LockKey {
+    fn key(self) -> Key {
+        match self {
+            LockKey::CapsLock => Key::Capital,
+            LockKey::NumLock => Key::Numlock,
+            LockKey::ScrollLock => Key::Scroll,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LockState {
+    On,
+    Off,
+}
+
+/// How many times a lock key is pressed before giving up on reaching the wanted state.
+const LOCK_KEY_ATTEMPTS: u32 = 3;
+
+/// How long to wait for a lock key press to show up in the toggle state before pressing again.
+const LOCK_KEY_SETTLE: Duration = Duration::from_millis(100);
+
+/// How long `SwitchToWindowDesktop` waits after switching for the new desktop to slide in.
+const DESKTOP_SWITCH_SETTLE: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProcessState {
+    Running,
+    Exited,
+}
+
+/// Deepest allowed nesting of `CallMacro`.
+const MAX_CALL_DEPTH: usize = 16;
+
+/// Most bytes of a `Run` command's stdout kept in `${output}`.
+const RUN_OUTPUT_LIMIT: usize = 4096;
+/// How often `Run` with `capture_output` checks whether the program has exited.
+const RUN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How often `WaitForProcess` looks at the process list.
+const PROCESS_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How often `WaitForProcessIdle` reads the CPU time of the processes. Long enough for the
+/// readings, which Windows updates every clock tick, to add up to a usage worth comparing.
+const CPU_SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+/// How often `WaitForFile` looks for the file.
+const FILE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How often `WaitForPixel` reads the pixel.
+const PIXEL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How often `WaitForClipboardChange` looks at the clipboard. Reading its sequence number is
+/// cheap, and the wait is usually short.
+const CLIPBOARD_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How execution should continue after a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Flow {
+    Normal,
+    /// Leave the innermost loop, or the enclosing loop with this name.
+    Break(Option<String>),
+    /// Skip to the next iteration of the innermost loop, or the enclosing loop with this name.
+    Continue(Option<String>),
+}
+
+impl Command {
+    fn estimated_duration(&self) -> DurationEstimate {
+        match self {
+            Command::Wait(wait_time) => DurationEstimate::Exact(wait_time.as_duration()),
+            Command::HoldKey { duration_ms, .. } => {
+                DurationEstimate::Exact(duration_ms.as_duration())
+            }
+            Command::ReplaceText {
+                text,
+                method,
+                settle_ms,
+            } => {
+                let settles = match method {
+                    ReplaceMethod::TypeOver => 2,
+                    ReplaceMethod::Paste => 3,
+                };
+                DurationEstimate::Exact(
+                    settle_ms.as_duration() * settles
+                        + INSTANT_COMMAND_ESTIMATE * text.chars().count() as u32,
+                )
+            }
+            Command::MousePath { points, .. } => DurationEstimate::Exact(Duration::from_millis(
+                points
+                    .last()
+                    .map(|(_, _, at_ms)| *at_ms)
+                    .unwrap_or_default(),
+            )),
+            Command::Loop(0, _) | Command::NamedLoop { iterations: 0, .. } => {
+                DurationEstimate::Unbounded
+            }
+            Command::Run {
+                capture_output: true,
+                timeout_ms,
+                ..
+            } => match timeout_ms {
+                Some(timeout_ms) => DurationEstimate::Range {
+                    min: Duration::ZERO,
+                    max: timeout_ms.as_duration(),
+                },
+                None => DurationEstimate::Unbounded,
+            },
+            // The called macro is not known here
+            Command::CallMacro { .. } => DurationEstimate::Unbounded,
+            Command::Pause { .. } => DurationEstimate::Unbounded,
+            Command::WaitForProcessIdle {
+                sustained_ms,
+                timeout_ms,
+                ..
+            } => DurationEstimate::Range {
+                min: sustained_ms.as_duration(),
+                max: timeout_ms.as_duration(),
+            },
+            Command::WaitForProcess { timeout_ms, .. }
+            | Command::WaitForFile { timeout_ms, .. }
+            | Command::WaitForPixel { timeout_ms, .. }
+            | Command::WaitForClipboardChange { timeout_ms, .. } => DurationEstimate::Range {
+                min: Duration::ZERO,
+                max: timeout_ms.as_duration(),
+            },
+            Command::Loop(iterations, commands)
+            | Command::NamedLoop {
+                iterations,
+                commands,
+                ..
+            } => commands
+                .iter()
+                .fold(DurationEstimate::zero(), |total, command| {
+                    total + command.estimated_duration()
+                })
+                .repeated(*iterations),
+            Command::WithKeysHeld { keys, commands } => commands.iter().fold(
+                DurationEstimate::Exact(INSTANT_COMMAND_ESTIMATE * 2 * keys.len() as u32),
+                |total, command| total + command.estimated_duration(),
+            ),
+            Command::RetryBlock {
+                attempts,
+                backoff_ms,
+                multiplier,
+                commands,
+                ..
+            } => {
+                let body = commands
+                    .iter()
+                    .fold(DurationEstimate::zero(), |total, command| {
+                        total + command.estimated_duration()
+                    });
+
+                match (body.min(), body.max()) {
+                    (Some(min), Some(max)) => DurationEstimate::Range {
+                        min,
+                        max: retry_delays(*attempts, *backoff_ms, *multiplier)
+                            .fold(max.saturating_mul(*attempts), Duration::saturating_add),
+                    },
+                    _ => DurationEstimate::Unbounded,
+                }
+            }
+            Command::IfKeyHeld { then, r#else, .. }
+            | Command::IfVarMatches { then, r#else, .. }
+            | Command::IfFileExists { then, r#else, .. } => {
+                let branch_estimate = |commands: &[Self]| {
+                    commands
+                        .iter()
+                        .fold(DurationEstimate::zero(), |total, command| {
+                            total + command.estimated_duration()
+                        })
+                };
+
+                branch_estimate(then).either(branch_estimate(r#else))
+            }
+            Command::TextInput(text) | Command::SendTextToWindow { text, .. } => {
+                DurationEstimate::Exact(INSTANT_COMMAND_ESTIMATE * text.chars().count() as u32)
+            }
+            Command::GetMousePos
+            | Command::SetMousePos(_, _)
+            | Command::LeftClick
+            | Command::MiddleClick
+            | Command::RightClick
+            | Command::ScrollLines(_)
+            | Command::PressKey(_)
+            | Command::PressKeyCombo(_)
+            | Command::SendKeyToWindow { .. }
+            | Command::KeyDown { .. }
+            | Command::KeyUp(_)
+            | Command::Media(_)
+            | Command::NormalizeWindow { .. }
+            | Command::StoreWindowOrigin { .. }
+            | Command::SwitchToWindowDesktop { .. }
+            | Command::GetWindowTitle { .. }
+            | Command::SetLockKey { .. }
+            | Command::AssertKeyState { .. }
+            | Command::Run { .. }
+            | Command::TextInputSecret { .. }
+            | Command::TextInputCredential { .. }
+            | Command::AssertNotBlocked => DurationEstimate::Exact(INSTANT_COMMAND_ESTIMATE),
+            Command::Break(_) | Command::Continue(_) | Command::Breakpoint => {
+                DurationEstimate::zero()
+            }
+        }
+    }
+
+    /// The texts `${}` variables are filled into when the command runs, each with whether
+    /// `${cred:<target>}` credentials may be used in it.
+    fn templates(&self) -> Vec<(&str, bool)> {
+        match self {
+            Command::SetMousePos(x, y) | Command::WaitForPixel { x, y, .. } => [x, y]
+                .into_iter()
+                .filter_map(|coordinate| match coordinate {
+                    expr::Coordinate::Expression(expression) => Some((expression.as_str(), false)),
+                    expr::Coordinate::Value(_) => None,
+                })
+                .collect(),
+            Command::TextInput(text) => vec![(text, false)],
+            Command::TextInputSecret {
+                text: Some(text), ..
+            }
+            | Command::ReplaceText { text, .. } => vec![(text, true)],
+            Command::IfVarMatches { regex, .. } => vec![(regex, false)],
+            Command::IfFileExists { path, .. } | Command::WaitForFile { path, .. } => {
+                vec![(path, false)]
+            }
+            Command::Pause {
+                message: Some(message),
+                ..
+            } => vec![(message, false)],
+            Command::CallMacro { args, .. } => {
+                args.values().map(|value| (value.as_str(), false)).collect()
+            }
+            Command::Run { args, cwd, env, .. } => args
+                .iter()
+                .chain(cwd)
+                .map(|text| (text.as_str(), false))
+                .chain(env.values().map(|value| (value.as_str(), true)))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The command lists contained in this command, such as a loop body.
+    fn nested_commands(&self) -> Vec<&[Self]> {
+        match self {
+            Command::Loop(_, commands)
+            | Command::NamedLoop { commands, .. }
+            | Command::WithKeysHeld { commands, .. }
+            | Command::RetryBlock { commands, .. } => vec![commands],
+            Command::IfKeyHeld { then, r#else, .. }
+            | Command::IfVarMatches { then, r#else, .. }
+            | Command::IfFileExists { then, r#else, .. } => {
+                vec![then, r#else]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Mutable `nested_commands`, in the same order.
+    fn nested_commands_mut(&mut self) -> Vec<&mut Vec<Self>> {
+        match self {
+            Command::Loop(_, commands)
+            | Command::NamedLoop { commands, .. }
+            | Command::WithKeysHeld { commands, .. }
+            | Command::RetryBlock { commands, .. } => vec![commands],
+            Command::IfKeyHeld { then, r#else, .. }
+            | Command::IfVarMatches { then, r#else, .. }
+            | Command::IfFileExists { then, r#else, .. } => {
+                vec![then, r#else]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Which window the command acts on, for those that act on one.
+    fn window_selector(&self) -> Option<window::WindowSelector<'_>> {
+        match self {
+            Command::SendKeyToWindow {
+                title,
+                class,
+                process,
+                current_desktop_only,
+                ..
+            }
+            | Command::SendTextToWindow {
+                title,
+                class,
+                process,
+                current_desktop_only,
+                ..
+            }
+            | Command::NormalizeWindow {
+                title,
+                class,
+                process,
+                current_desktop_only,
+                ..
+            }
+            | Command::StoreWindowOrigin {
+                title,
+                class,
+                process,
+                current_desktop_only,
+            } => Some(window::WindowSelector {
+                title: title.as_deref(),
+                class: class.as_deref(),
+                process: process.as_deref(),
+                current_desktop_only: *current_desktop_only,
+            }),
+            Command::SwitchToWindowDesktop {
+                title,
+                class,
+                process,
+            } => Some(window::WindowSelector {
+                title: title.as_deref(),
+                class: class.as_deref(),
+                process: process.as_deref(),
+                current_desktop_only: false,
+            }),
+            _ => None,
+        }
+    }
+
+    /// The window the command acts on, see `window_selector`.
+    #[cfg(windows)]
+    fn find_window(&self) -> Result<windows::Win32::Foundation::HWND, anyhow::Error> {
+        window::find_window(&self.window_selector().unwrap_or_default())
+    }
+
+    /// Whether the command sends keyboard or mouse input, as opposed to waiting, control flow or
+    /// bookkeeping. Only these are subject to jitter.
+    fn sends_input(&self) -> bool {
+        match self {
+            Command::SetMousePos(_, _)
+            | Command::LeftClick
+            | Command::MiddleClick
+            | Command::RightClick
+            | Command::ScrollLines(_)
+            | Command::PressKey(_)
+            | Command::PressKeyCombo(_)
+            | Command::TextInput(_)
+            | Command::TextInputSecret { .. }
+            | Command::TextInputCredential { .. }
+            | Command::SendKeyToWindow { .. }
+            | Command::SendTextToWindow { .. }
+            | Command::HoldKey { .. }
+            | Command::KeyDown { .. }
+            | Command::KeyUp(_)
+            | Command::MousePath { .. }
+            | Command::Media(_)
+            | Command::SetLockKey { .. }
+            | Command::ReplaceText { .. } => true,
+            Command::GetMousePos
+            | Command::Wait(_)
+            | Command::Loop(_, _)
+            | Command::NamedLoop { .. }
+            | Command::Break(_)
+            | Command::Continue(_)
+            | Command::Breakpoint
+            | Command::WithKeysHeld { .. }
+            | Command::RetryBlock { .. }
+            | Command::IfKeyHeld { .. }
+            | Command::IfVarMatches { .. }
+            | Command::GetWindowTitle { .. }
+            | Command::CallMacro { .. }
+            | Command::WaitForProcess { .. }
+            | Command::WaitForProcessIdle { .. }
+            | Command::IfFileExists { .. }
+            | Command::WaitForFile { .. }
+            | Command::WaitForPixel { .. }
+            | Command::WaitForClipboardChange { .. }
+            | Command::Pause { .. }
+            | Command::NormalizeWindow { .. }
+            | Command::StoreWindowOrigin { .. }
+            | Command::SwitchToWindowDesktop { .. }
+            | Command::Run { .. }
+            | Command::AssertKeyState { .. }
+            | Command::AssertNotBlocked => false,
+        }
+    }
+
+    /// Whether `diff-run` can run the command against its simulated input, screen and clock.
+    /// Commands that reach real windows, processes, files, the clipboard, credentials or the lock
+    /// key lights cannot be run there the same way twice.
+    fn simulated(&self) -> bool {
+        match self {
+            Command::SendKeyToWindow { .. }
+            | Command::SendTextToWindow { .. }
+            | Command::GetWindowTitle { .. }
+            | Command::WaitForProcess { .. }
+            | Command::WaitForProcessIdle { .. }
+            | Command::WaitForFile { .. }
+            | Command::WaitForClipboardChange { .. }
+            | Command::Pause { .. }
+            | Command::NormalizeWindow { .. }
+            | Command::StoreWindowOrigin { .. }
+            | Command::SwitchToWindowDesktop { .. }
+            | Command::Run { .. }
+            | Command::TextInputSecret { .. }
+            | Command::TextInputCredential { .. }
+            | Command::SetLockKey { .. }
+            | Command::AssertNotBlocked => false,
+            Command::AssertKeyState { state, .. } => {
+                matches!(state, KeyState::Up | KeyState::Down)
+            }
+            Command::ReplaceText { text, method, .. } => {
+                *method != ReplaceMethod::Paste && !text.contains("${cred:")
+            }
+            Command::GetMousePos
+            | Command::SetMousePos(_, _)
+            | Command::LeftClick
+            | Command::MiddleClick
+            | Command::RightClick
+            | Command::ScrollLines(_)
+            | Command::PressKey(_)
+            | Command::PressKeyCombo(_)
+            | Command::TextInput(_)
+            | Command::HoldKey { .. }
+            | Command::KeyDown { .. }
+            | Command::KeyUp(_)
+            | Command::MousePath { .. }
+            | Command::Media(_)
+            | Command::Wait(_)
+            | Command::Loop(_, _)
+            | Command::NamedLoop { .. }
+            | Command::Break(_)
+            | Command::Continue(_)
+            | Command::Breakpoint
+            | Command::WithKeysHeld { .. }
+            | Command::RetryBlock { .. }
+            | Command::IfKeyHeld { .. }
+            | Command::IfVarMatches { .. }
+            | Command::CallMacro { .. }
+            | Command::IfFileExists { .. }
+            | Command::WaitForPixel { .. } => true,
+        }
+    }
+
+    fn execute(&self, context: &mut context::ExecutionContext) -> Result<Flow, anyhow::Error> {
+        if diff_run::recording() && !self.simulated() {
+            let name: String = format!("{:?}", self)
+                .chars()
+                .take_while(|c| c.is_alphanumeric())
+                .collect();
+            return Err(error::MacroError::Validation(format!(
+                "{} cannot run in a diff-run",
+                name
+            ))
+            .into());
+        }
+
+        if let debugger::StepAction::Skip = debugger::before_command(self, context)? {
+            return Ok(Flow::Normal);
+        }
+
+        if self.sends_input() {
+            context.jitter_delay();
+            context.check_allowed_target()?;
+        }
+
+        match self {
+            Command::GetMousePos => {
+                let point = get_cursor_pos()?;
+                println!("{:?}", point);
+            }
+            Command::SetMousePos(x, y) => {
+                let (x, y) = context.jitter_point(
+                    x.resolve(context, expr::Axis::X)?,
+                    y.resolve(context, expr::Axis::Y)?,
+                );
+                if context.strict_coordinates() {
+                    screen::ensure_on_screen(context.screen(), x, y)?;
+                }
+                context.send_input(&[backend::InputEvent::MoveTo(x, y)])?
+            }
+            Command::LeftClick => context.send_input(&click_events(MouseButton::Left))?,
+            Command::MiddleClick => context.send_input(&click_events(MouseButton::Middle))?,
+            Command::RightClick => context.send_input(&click_events(MouseButton::Right))?,
+            Command::ScrollLines(lines) => {
+                context.send_input(&[backend::InputEvent::Wheel(*lines)])?
+            }
+            Command::PressKey(key) => press_key(*key as i32)?,
+            Command::PressKeyCombo(keys) => {
+                press_key_combo(keys)?;
+            }
+            Command::Wait(wait_time) => context.sleep(wait_time.as_duration()),
+            Command::Loop(iterations, commands) => {
+                return run_loop(None, *iterations, commands, context)
+            }
+            Command::NamedLoop {
+                name,
+                iterations,
+                commands,
+            } => return run_loop(Some(name), *iterations, commands, context),
+            Command::TextInput(text) => {
+                // The real Caps Lock state would make recordings differ from machine to machine
+                if context.capslock_off_for_text() && !diff_run::recording() {
+                    set_lock_key(Key::Capital, false)?;
+                }
+                type_in_chunks(&context.interpolate(text)?, context, type_text)?
+            }
+            Command::TextInputSecret { from_env, text } => {
+                let secret = match (from_env, text) {
+                    (Some(from_env), None) => secret::Secret::from_env(from_env)?,
+                    (None, Some(text)) => context.interpolate_secret(text)?,
+                    _ => {
+                        return Err(error::MacroError::Validation(
+                            "TextInputSecret needs exactly one of from_env and text".to_string(),
+                        )
+                        .into())
+                    }
+                };
+                type_in_chunks(secret.expose(), context, type_unicode)?
+            }
+            Command::TextInputCredential { target } => type_in_chunks(
+                secret::Secret::from_credential(target)?.expose(),
+                context,
+                type_unicode,
+            )?,
+            Command::SendKeyToWindow { key, .. } => {
+                window::post_key(self.find_window()?, key.virtual_key())?
+            }
+            Command::SendTextToWindow { text, .. } => window::post_text(self.find_window()?, text)?,
+            Command::HoldKey {
+                key,
+                duration_ms,
+                repeat,
+            } => {
+                context.send_input(&[backend::InputEvent::KeyDown(*key)])?;
+                context.key_pressed(*key, *repeat);
+                context.sleep(duration_ms.as_duration());
+                context.key_released(*key);
+                context.send_input(&[backend::InputEvent::KeyUp(*key)])?;
+            }
+            Command::KeyDown { key, repeat } => {
+                context.send_input(&[backend::InputEvent::KeyDown(*key)])?;
+                context.key_pressed(*key, *repeat);
+            }
+            Command::KeyUp(key) => {
+                context.key_released(*key);
+                context.send_input(&[backend::InputEvent::KeyUp(*key)])?;
+            }
+            Command::WithKeysHeld { keys, commands } => {
+                return run_with_keys_held(keys, commands, context)
+            }
+            Command::RetryBlock {
+                attempts,
+                backoff_ms,
+                multiplier,
+                retry_on,
+                commands,
+            } => {
+                let delays = retry_delays(*attempts, *backoff_ms, *multiplier);
+                return run_retry_block(*attempts, delays, retry_on, commands, context);
+            }
+            Command::CallMacro { name, args } => call_macro(name, args, context)?,
+            Command::IfKeyHeld { key, then, r#else } => {
+                let branch = if context.is_key_held(*key) {
+                    then
+                } else {
+                    r#else
+                };
+
+                return run_block(branch, context);
+            }
+            Command::ReplaceText {
+                text,
+                method,
+                settle_ms,
+            } => replace_text(
+                context.interpolate_secret(text)?.expose(),
+                *method,
+                settle_ms.as_duration(),
+            )?,
+            Command::GetWindowTitle { into } => {
+                context.set_variable(into, window::foreground_window_title()?)
+            }
+            Command::IfVarMatches {
+                var,
+                regex,
+                then,
+                r#else,
+            } => {
+                let value = context.variable(var).ok_or_else(|| {
+                    error::MacroError::Validation(format!("Unknown variable {:?}", var))
+                })?;
+                let regex = regex::Regex::new(&context.interpolate(regex)?)?;
+                let branch = if regex.is_match(&value) { then } else { r#else };
+
+                return run_block(branch, context);
+            }
+            Command::MousePath { points, button } => {
+                if context.strict_coordinates() {
+                    for (x, y, _) in points.iter() {
+                        screen::ensure_on_screen(context.screen(), *x, *y)?;
+                    }
+                }
+                follow_mouse_path(points, *button, context)?
+            }
+            Command::Media(action) => press_key(action.key() as i32)?,
+            Command::Run {
+                program,
+                args,
+                cwd,
+                env,
+                capture_output,
+                timeout_ms,
+            } => run_program(
+                program,
+                args,
+                cwd.as_deref(),
+                env,
+                *capture_output,
+                *timeout_ms,
+                context,
+            )?,
+            Command::WaitForProcess {
+                name,
+                state,
+                timeout_ms,
+            } => {
+                let reached =
+                    context.wait_for(timeout_ms.as_duration(), PROCESS_POLL_INTERVAL, || {
+                        Ok(process::process_running(name)? == (*state == ProcessState::Running))
+                    })?;
+
+                if !reached {
+                    return Err(error::MacroError::Timeout {
+                        what: format!("process {} to be {:?}", name, state),
+                        waited_ms: timeout_ms.0,
+                    }
+                    .into());
+                }
+            }
+            Command::WaitForProcessIdle {
+                process,
+                cpu_below_percent,
+                sustained_ms,
+                timeout_ms,
+            } => {
+                let mut sampler = process::CpuSampler::new(process);
+                let mut idle_since = None;
+                let idle =
+                    context.wait_for(timeout_ms.as_duration(), CPU_SAMPLE_INTERVAL, || {
+                        let now = context.now();
+                        match sampler.sample(context.process_metrics(), now)? {
+                            Some(usage) if usage < *cpu_below_percent => {
+                                let since = *idle_since.get_or_insert(now);
+                                Ok(now.saturating_duration_since(since)
+                                    >= sustained_ms.as_duration())
+                            }
+                            Some(usage) => {
+                                log::debug!("{} is using {:.1}% of the CPU", process, usage);
+                                idle_since = None;
+                                Ok(false)
+                            }
+                            None => Ok(false),
+                        }
+                    })?;
+
+                if !idle {
+                    return Err(error::MacroError::Timeout {
+                        what: format!(
+                            "process {} to stay below {}% CPU for {}ms",
+                            process, cpu_below_percent, sustained_ms.0
+                        ),
+                        waited_ms: timeout_ms.0,
+                    }
+                    .into());
+                }
+            }
+            Command::IfFileExists { path, then, r#else } => {
+                let branch = if context.resolve_path(path)?.exists() {
+                    then
+                } else {
+                    r#else
+                };
+
+                return run_block(branch, context);
+            }
+            Command::WaitForFile { path, timeout_ms } => {
+                let path = context.resolve_path(path)?;
+                let appeared =
+                    context.wait_for(timeout_ms.as_duration(), FILE_POLL_INTERVAL, || {
+                        Ok(path.exists())
+                    })?;
+
+                if !appeared {
+                    return Err(error::MacroError::Timeout {
+                        what: format!("file {}", path.display()),
+                        waited_ms: timeout_ms.0,
+                    }
+                    .into());
+                }
+            }
+            Command::WaitForPixel {
+                x,
+                y,
+                color,
+                tolerance,
+                timeout_ms,
+            } => {
+                let (x, y) = (
+                    x.resolve(context, expr::Axis::X)?,
+                    y.resolve(context, expr::Axis::Y)?,
+                );
+                screen::ensure_on_screen(context.screen(), x, y)?;
+
+                let matched =
+                    context.wait_for(timeout_ms.as_duration(), PIXEL_POLL_INTERVAL, || {
+                        Ok(context.screen().pixel(x, y)?.matches(*color, *tolerance))
+                    })?;
+
+                if !matched {
+                    return Err(error::MacroError::Timeout {
+                        what: format!("the pixel at ({}, {}) to be {}", x, y, color),
+                        waited_ms: timeout_ms.0,
+                    }
+                    .into());
+                }
+            }
+            Command::WaitForClipboardChange { timeout_ms, into } => {
+                let sequence_number = clipboard::sequence_number();
+                let changed =
+                    context.wait_for(timeout_ms.as_duration(), CLIPBOARD_POLL_INTERVAL, || {
+                        Ok(clipboard::sequence_number() != sequence_number)
+                    })?;
+
+                if !changed {
+                    return Err(error::MacroError::Timeout {
+                        what: "the clipboard to change".to_string(),
+                        waited_ms: timeout_ms.0,
+                    }
+                    .into());
+                }
+
+                if let Some(into) = into {
+                    match clipboard::text()? {
+                        Some(text) => context.set_variable(into, text),
+                        None => {
+                            log::warn!(
+                                "The clipboard changed to something other than text, leaving \
+                                 {} unset",
+                                into
+                            );
+                            context.unset_variable(into);
+                        }
+                    }
+                }
+            }
+            Command::Pause {
+                message,
+                resume_key,
+            } => {
+                let resume_keys = match resume_key {
+                    Some(key) => HashSet::from([*key]),
+                    None => context
+                        .macros()
+                        .iter()
+                        .find(|current_macro| current_macro.macro_name == context.macro_name)
+                        .map(|current_macro| current_macro.macro_hotkey.clone())
+                        .unwrap_or_default(),
+                };
+                if resume_keys.is_empty() {
+                    return Err(error::MacroError::Validation(
+                        "Pause needs a resume_key in a macro without a hotkey".to_string(),
+                    )
+                    .into());
+                }
+
+                match message {
+                    Some(message) => log::info!(
+                        "{} paused: {} (press {} to resume)",
+                        context.macro_name,
+                        context.interpolate(message)?,
+                        format_keys(&resume_keys)
+                    ),
+                    None => log::info!(
+                        "{} paused, press {} to resume",
+                        context.macro_name,
+                        format_keys(&resume_keys)
+                    ),
+                }
+                context.wait_for_resume(&resume_keys)?;
+                log::info!("{} resumed", context.macro_name);
+            }
+            Command::NormalizeWindow {
+                x,
+                y,
+                width,
+                height,
+                restore_after,
+                ..
+            } => {
+                let hwnd = self.find_window()?;
+                let placement = window::move_window(hwnd, *x, *y, *width, *height)?;
+
+                if *restore_after {
+                    let selector = self.window_selector().unwrap_or_default().to_string();
+                    context.defer(move || {
+                        if let Err(e) = window::restore_placement(hwnd, &placement) {
+                            log::error!("Failed to restore the window with {}: {}", selector, e);
+                        }
+                    });
+                }
+            }
+            Command::StoreWindowOrigin { .. } => {
+                let (x, y) = window::window_origin(self.find_window()?)?;
+                context.set_variable("window_x", x.to_string());
+                context.set_variable("window_y", y.to_string());
+            }
+            Command::SwitchToWindowDesktop { .. } => {
+                let hwnd = self.find_window()?;
+                if desktop::switch_to_window_desktop(hwnd)? {
+                    // Windows animates the switch before the new desktop takes input
+                    context.sleep(DESKTOP_SWITCH_SETTLE);
+                }
+            }
+            Command::SetLockKey { key, state } => set_lock_key(key.key(), *state == LockState::On)?,
+            Command::AssertKeyState { key, state, fix } => {
+                assert_key_state(*key, *state, *fix, context)?
+            }
+            Command::AssertNotBlocked => elevation::check_foreground_not_elevated()?,
+            Command::Breakpoint => {
+                if context.debug_mode() != context::DebugMode::Off {
+                    context.break_before_next();
+                }
+            }
+            Command::Break(label) => return Ok(Flow::Break(label.clone())),
+            Command::Continue(label) => return Ok(Flow::Continue(label.clone())),
+        }
+
+        Ok(Flow::Normal)
+    }
+}
+
+fn assert_key_state(
+    key: Key,
+    state: KeyState,
+    fix: bool,
+    context: &mut context::ExecutionContext,
+) -> Result<(), anyhow::Error> {
+    let vkey = key as i32;
+
+    let matches = match state {
+        KeyState::Up => !context.is_key_held(key),
+        KeyState::Down => context.is_key_held(key),
+        KeyState::ToggledOn => key_toggled(vkey),
+        KeyState::ToggledOff => !key_toggled(vkey),
+    };
+
+    if matches {
+        return Ok(());
+    }
+
+    if !fix {
+        return Err(anyhow::anyhow!(
+            "Expected {:?} to be {:?} but it is not",
+            key,
+            state
+        ));
+    }
+
+    log::info!("{:?} is not {:?}, fixing it", key, state);
+
+    match state {
+        KeyState::Up => {
+            context.key_released(key);
+            key_up(vkey)?;
+        }
+        KeyState::Down => {
+            key_down(vkey)?;
+            context.key_pressed(key, None);
+        }
+        KeyState::ToggledOn | KeyState::ToggledOff => press_key(vkey)?,
+    }
+
+    Ok(())
+}
+
+/// Launches `program`. With `capture_output`, waits for it to exit, for at most `timeout` if
+/// given, and stores its stdout in `${output}`.
+fn run_program(
+    program: &str,
+    args: &[String],
+    cwd: Option<&str>,
+    env: &HashMap<String, String>,
+    capture_output: bool,
+    timeout: Option<duration::DurationMs>,
+    context: &mut context::ExecutionContext,
+) -> Result<(), anyhow::Error> {
+    let mut command = std::process::Command::new(program);
+
+    for arg in args.iter() {
+        command.arg(context.interpolate(arg)?);
+    }
+
+    if let Some(cwd) = cwd {
+        let cwd = PathBuf::from(context.interpolate(cwd)?);
+        if !cwd.is_dir() {
+            return Err(anyhow::anyhow!(
+                "Working directory {} for {} does not exist",
+                cwd.display(),
+                program
+            ));
+        }
+        command.current_dir(cwd);
+    }
+
+    for (name, value) in env.iter() {
+        command.env(name, context.interpolate_secret(value)?.expose());
+    }
+
+    if !capture_output {
+        command
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("Failed to launch {}: {}", program, e))?;
+        return Ok(());
+    }
+
+    let mut child = command
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("Failed to run {}: {}", program, e))?;
+
+    // Read on a thread of its own, so that a program writing more than the pipe holds does not
+    // stall waiting for it to be read
+    let mut pipe = child.stdout.take();
+    let reader = spawn(move || {
+        use std::io::Read;
+
+        let mut stdout = Vec::new();
+        if let Some(pipe) = pipe.as_mut() {
+            // One byte over the limit tells that it was cut short, the rest is thrown away
+            let _ = pipe
+                .take(RUN_OUTPUT_LIMIT as u64 + 1)
+                .read_to_end(&mut stdout);
+            let _ = std::io::copy(pipe, &mut std::io::sink());
+        }
+        stdout
+    });
+
+    let started = context.now();
+    let status = loop {
+        let stopped = if context.is_cancelled() {
+            Some(error::MacroError::Cancelled.into())
+        } else {
+            timeout
+                .filter(|timeout| {
+                    context.now().saturating_duration_since(started) >= timeout.as_duration()
+                })
+                .map(|timeout| {
+                    anyhow::Error::from(error::MacroError::Timeout {
+                        what: format!("{} to exit", program),
+                        waited_ms: timeout.0,
+                    })
+                })
+        };
+
+        if let Some(e) = stopped {
+            if let Err(kill_error) = child.kill() {
+                log::warn!("Failed to stop {}: {}", program, kill_error);
+            }
+            let _ = child.wait();
+            return Err(e);
+        }
+
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) => context.sleep(RUN_POLL_INTERVAL),
+            Err(e) => return Err(anyhow::anyhow!("Failed to wait for {}: {}", program, e)),
+        }
+    };
+
+    let stdout = reader
+        .join()
+        .map_err(|_| anyhow::anyhow!("Failed to read the output of {}", program))?;
+
+    if !status.success() {
+        return Err(anyhow::anyhow!("{} exited with {}", program, status));
+    }
+
+    let mut stdout = String::from_utf8_lossy(&stdout).into_owned();
+    if stdout.len() > RUN_OUTPUT_LIMIT {
+        let mut end = RUN_OUTPUT_LIMIT;
+        while !stdout.is_char_boundary(end) {
+            end -= 1;
+        }
+        stdout.truncate(end);
+        log::warn!(
+            "Output of {} truncated to {} bytes",
+            program,
+            RUN_OUTPUT_LIMIT
+        );
+    }
+    context.set_variable("output", stdout.trim_end().to_string());
+
+    Ok(())
+}
+
+/// Runs `commands` `iterations` times (forever for 0), exposing the iteration number through the
+/// loop variables. Infinite loops end when the macro is cancelled. A `Break` or `Continue`
+/// aimed at an outer loop is passed back up to it.
+fn run_loop(
+    name: Option<&str>,
+    iterations: u32,
+    commands: &[Command],
+    context: &mut context::ExecutionContext,
+) -> Result<Flow, anyhow::Error> {
+    let targets_this_loop = |label: &Option<String>| label.is_none() || label.as_deref() == name;
+
+    context.enter_loop(name.map(str::to_string));
+
+    let result = (|| {
+        let mut index = 0u64;
+
+        'iterations: while iterations == 0 || index < u64::from(iterations) {
+            context.wait_while_paused();
+
+            if context.is_cancelled() {
+                return Err(error::MacroError::Cancelled.into());
+            }
+
+            context.set_loop_index(index);
+            index += 1;
+
+            for command in commands.iter() {
+                match command.execute(context)? {
+                    Flow::Normal => {}
+                    Flow::Continue(label) if targets_this_loop(&label) => continue 'iterations,
+                    Flow::Break(label) if targets_this_loop(&label) => break 'iterations,
+                    outer => return Ok(outer),
+                }
+            }
+        }
+
+        Ok(Flow::Normal)
+    })();
+
+    context.exit_loop();
+
+    result
+}
+
+/// Runs `commands` in order, stopping at the first error or `Break`/`Continue`.
+fn run_block(
+    commands: &[Command],
+    context: &mut context::ExecutionContext,
+) -> Result<Flow, anyhow::Error> {
+    for command in commands.iter() {
+        context.wait_while_paused();
+
+        if context.is_cancelled() {
+            return Err(error::MacroError::Cancelled.into());
+        }
+
+        match command.execute(context)? {
+            Flow::Normal => {}
+            flow => return Ok(flow),
+        }
+    }
+
+    Ok(Flow::Normal)
+}
+
+/// Runs the macro `macro_name` inline, with its parameters bound to `args` (interpolated in the
+/// caller's context) for the duration of the call.
+fn call_macro(
+    macro_name: &str,
+    args: &HashMap<String, String>,
+    context: &mut context::ExecutionContext,
+) -> Result<(), anyhow::Error> {
+    if context.call_depth() >= MAX_CALL_DEPTH {
+        return Err(error::MacroError::Validation(format!(
+            "Not calling {}: call depth limit of {} reached",
+            macro_name, MAX_CALL_DEPTH
+        ))
+        .into());
+    }
+
+    let macros = context.macros();
+    let target = macros
+        .iter()
+        .find(|current_macro| current_macro.macro_name == macro_name)
+        .ok_or_else(|| {
+            error::MacroError::Validation(format!("Called macro {} does not exist", macro_name))
+        })?;
+
+    let mut interpolated_args = HashMap::with_capacity(args.len());
+    for (name, value) in args.iter() {
+        interpolated_args.insert(name.clone(), context.interpolate(value)?);
+    }
+    let bound = target.bind_args(&interpolated_args)?;
+
+    let variables = context.variables();
+    let mut call_variables = variables.clone();
+    call_variables.extend(bound);
+    context.set_variables(call_variables);
+    context.enter_call();
+
+    // Loop control cannot cross into the caller, validation keeps it inside the callee's loops
+    let result = run_block(&target.commands, context);
+
+    context.exit_call();
+    context.set_variables(variables);
+
+    result.map(|_| ())
+}
+
+/// The delays before each retry of a `RetryBlock` with this many attempts.
+fn retry_delays(attempts: u32, backoff_ms: u64, multiplier: f64) -> impl Iterator<Item = Duration> {
+    (0..attempts.saturating_sub(1)).map(move |retry| {
+        Duration::try_from_secs_f64(backoff_ms as f64 / 1000.0 * multiplier.powi(retry as i32))
+            .unwrap_or(Duration::MAX)
+    })
+}
+
+/// Runs `commands` until they succeed, waiting out each of `delays` before trying again. Only
+/// failures whose kind is in `retry_on` are retried, every kind when it is empty.
+fn run_retry_block(
+    attempts: u32,
+    mut delays: impl Iterator<Item = Duration>,
+    retry_on: &[error::ErrorKind],
+    commands: &[Command],
+    context: &mut context::ExecutionContext,
+) -> Result<Flow, anyhow::Error> {
+    let variables = context.variables();
+    let mut attempt = 1;
+
+    loop {
+        let e = match run_block(commands, context) {
+            Ok(flow) => return Ok(flow),
+            Err(e) if context.is_cancelled() => return Err(e),
+            Err(e) => e,
+        };
+
+        let kind = error::kind_of(&e);
+        if kind.aborts() || !retry_on.is_empty() && !retry_on.contains(&kind) {
+            log::warn!(
+                "Attempt {}/{} failed: {}, not retrying {:?} failures",
+                attempt,
+                attempts,
+                e,
+                kind
+            );
+            return Err(e);
+        }
+
+        let delay = match delays.next() {
+            Some(delay) => delay,
+            None => {
+                log::warn!("Attempt {}/{} failed: {}, giving up", attempt, attempts, e);
+                return Err(e);
+            }
+        };
+
+        if !context.take_retry() {
+            log::warn!(
+                "Attempt {}/{} failed: {}, the macro's retry budget of {} is used up",
+                attempt,
+                attempts,
+                e,
+                context.retry_budget().unwrap_or_default()
+            );
+            return Err(e);
+        }
+
+        log::warn!(
+            "Attempt {}/{} failed: {}, retrying in {:?}",
+            attempt,
+            attempts,
+            e,
+            delay
+        );
+
+        context.stop_key_repeats();
+        context.set_variables(variables.clone());
+        context.wait_for(delay, Duration::from_millis(50), || Ok(false))?;
+        attempt += 1;
+    }
+}
+
+/// Runs `commands` with `keys` held down. A key that an enclosing block already holds stays down
+/// until that block ends.
+fn run_with_keys_held(
+    keys: &[Key],
+    commands: &[Command],
+    context: &mut context::ExecutionContext,
+) -> Result<Flow, anyhow::Error> {
+    let mut held = Vec::with_capacity(keys.len());
+
+    let result = (|| {
+        for key in keys.iter() {
+            held.push(*key);
+            if context.hold_key(*key) {
+                key_down(*key as i32)?;
+            }
+        }
+
+        run_block(commands, context)
+    })();
+
+    for key in held.into_iter().rev() {
+        if context.release_held_key(key) {
+            if let Err(e) = key_up(key as i32) {
+                log::error!("Failed to release {:?}: {}", key, e);
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(windows)]
+fn get_cursor_pos() -> Result<POINT, anyhow::Error> {
+    use windows::Win32::UI::WindowsAndMessaging::GetCursorPos;
+
+    let mut point = POINT::default();
+
+    if unsafe { GetCursorPos(&mut point) }.as_bool() {
+        Ok(point)
+    } else {
+        Err(error::MacroError::win32("GetCursorPos").into())
+    }
+}
+
+#[cfg(windows)]
+fn set_cursor_pos(x: i32, y: i32) -> Result<(), anyhow::Error> {
+    use windows::Win32::UI::WindowsAndMessaging::SetCursorPos;
+
+    if diff_run::record_cursor(x, y) {
+        return Ok(());
+    }
+    rate_limit::acquire(1)?;
+
+    if !unsafe { SetCursorPos(x, y) }.as_bool() {
+        return Err(error::MacroError::win32("SetCursorPos").into());
+    }
+    idle::record_injected_input();
+
+    Ok(())
+}
+
+#[cfg(windows)]
+fn press_key_combo(keys: &HashSet<Key>) -> Result<(), anyhow::Error> {
+    // Modifiers go down first, Ctrl before Alt so that Ctrl+RAlt reads as AltGr, and everything
+    // comes back up in reverse
+    let mut keys: Vec<i32> = {
+        let mut keys: Vec<Key> = keys.iter().copied().collect();
+        keys.sort_by_key(|key| (key.modifier_order(), *key));
+        keys.into_iter().map(|key| key as i32).collect()
+    };
+
+    if let Err(e) = send_key_events(&keys, false) {
+        // Never leave the modifiers that did go down stuck
+        keys.reverse();
+        let _ = send_key_events(&keys, true);
+        return Err(e);
+    }
+
+    keys.reverse();
+    send_key_events(&keys, true)
+}
+
+/// Selects everything in the focused field, deletes it and enters `text` in its place.
+fn replace_text(text: &str, method: ReplaceMethod, settle: Duration) -> Result<(), anyhow::Error> {
+    press_key_combo(&HashSet::from([Key::LeftControl, Key::A]))?;
+    sleep(settle);
+    press_key(Key::Delete as i32)?;
+    sleep(settle);
+
+    match method {
+        ReplaceMethod::TypeOver => type_unicode(text),
+        ReplaceMethod::Paste => {
+            clipboard::set_text(text)?;
+            sleep(settle);
+            press_key_combo(&HashSet::from([Key::LeftControl, Key::V]))
+        }
+    }
+}
+
+#[cfg(windows)]
+fn get_last_windows_error() -> u32 {
+    unsafe { windows::Win32::Foundation::GetLastError().0 }
+}
+
+/// Mouse buttons that commands can press and release.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
+
+/// How many times a partly inserted batch of input events is retried before giving up on the rest.
+const SEND_INPUT_ATTEMPTS: u32 = 4;
+/// Pause before each retry, times the attempt number, to let the input queue drain.
+const SEND_INPUT_BACKOFF: Duration = Duration::from_millis(5);
+
+thread_local! {
+    /// Events this thread had to retry and events it dropped, since `take_input_stats`.
+    static INPUT_STATS: std::cell::Cell<(u64, u64)> = const { std::cell::Cell::new((0, 0)) };
+}
+
+/// Returns how many input events the current thread had to retry and how many it dropped
+/// altogether, and starts counting again from zero. Macros run one at a time on a thread, so
+/// this is the count of the execution running on it.
+fn take_input_stats() -> (u64, u64) {
+    INPUT_STATS.with(|stats| stats.replace((0, 0)))
+}
+
+/// Sends `inputs` with as few `SendInput` calls as possible. When the system input queue takes
+/// only part of them, the rest is retried after a short backoff, and the events still left after
+/// `SEND_INPUT_ATTEMPTS` are reported as dropped.
+#[cfg(windows)]
+fn send_inputs(
+    inputs: &[windows::Win32::UI::Input::KeyboardAndMouse::INPUT],
+    description: &str,
+) -> Result<(), anyhow::Error> {
+    use windows::Win32::UI::Input::KeyboardAndMouse::{
+        SendInput, INPUT, INPUT_KEYBOARD, INPUT_MOUSE, KEYEVENTF_KEYUP, MOUSEEVENTF_LEFTUP,
+        MOUSEEVENTF_MIDDLEUP, MOUSEEVENTF_RIGHTUP, MOUSEEVENTF_XUP,
+    };
+
+    if diff_run::record_inputs(inputs) {
+        // The keys count as put down by a macro all the same, as they would have been
+        injected::record(inputs);
+        return Ok(());
+    }
+    // Releases are never held back, so that a rate-limited macro leaves nothing stuck down
+    let button_ups =
+        MOUSEEVENTF_LEFTUP | MOUSEEVENTF_MIDDLEUP | MOUSEEVENTF_RIGHTUP | MOUSEEVENTF_XUP;
+    let releases_only = inputs.iter().all(|input| match input.r#type {
+        INPUT_KEYBOARD => unsafe { input.Anonymous.ki }.dwFlags.0 & KEYEVENTF_KEYUP.0 != 0,
+        INPUT_MOUSE => {
+            let flags = unsafe { input.Anonymous.mi }.dwFlags;
+            flags.0 & button_ups.0 != 0 && flags.0 & !button_ups.0 == 0
+        }
+        _ => false,
+    });
+    if !releases_only {
+        rate_limit::acquire(inputs.len())?;
+    }
+    injected::record(inputs);
+
+    let mut remaining = inputs;
+
+    for attempt in 1..=SEND_INPUT_ATTEMPTS {
+        if attempt > 1 {
+            INPUT_STATS.with(|stats| {
+                let (retried, dropped) = stats.get();
+                stats.set((retried + remaining.len() as u64, dropped));
+            });
+            sleep(SEND_INPUT_BACKOFF * (attempt - 1));
+        }
+
+        let inserted =
+            unsafe { SendInput(remaining, std::mem::size_of::<INPUT>() as i32) } as usize;
+        if inserted > 0 {
+            idle::record_injected_input();
+        }
+
+        remaining = &remaining[inserted.min(remaining.len())..];
+        if remaining.is_empty() {
+            return Ok(());
+        }
+    }
+
+    INPUT_STATS.with(|stats| {
+        let (retried, dropped) = stats.get();
+        stats.set((retried, dropped + remaining.len() as u64));
+    });
+
+    Err(error::MacroError::InputDropped {
+        description: description.to_string(),
+        dropped: remaining.len(),
+        total: inputs.len(),
+        code: get_last_windows_error(),
+    }
+    .into())
+}
+
+#[cfg(windows)]
+fn send_mouse_input(
+    flags: windows::Win32::UI::Input::KeyboardAndMouse::MOUSE_EVENT_FLAGS,
+    description: &str,
+) -> Result<(), anyhow::Error> {
+    use windows::Win32::UI::Input::KeyboardAndMouse::{INPUT, INPUT_0, INPUT_MOUSE};
+
+    let mut input = INPUT {
+        r#type: INPUT_MOUSE,
+        Anonymous: INPUT_0::default(),
+    };
+
+    let mouse_input = unsafe { &mut input.Anonymous.mi };
+    mouse_input.dwFlags = flags;
+
+    send_inputs(&[input], description)
+}
+
+#[cfg(windows)]
+fn mouse_button_down(button: MouseButton) -> Result<(), anyhow::Error> {
+    use windows::Win32::UI::Input::KeyboardAndMouse::{
+        MOUSEEVENTF_LEFTDOWN, MOUSEEVENTF_MIDDLEDOWN, MOUSEEVENTF_RIGHTDOWN,
+    };
+
+    match button {
+        MouseButton::Left => send_mouse_input(MOUSEEVENTF_LEFTDOWN, "mouse left down"),
+        MouseButton::Right => send_mouse_input(MOUSEEVENTF_RIGHTDOWN, "mouse right down"),
+        MouseButton::Middle => send_mouse_input(MOUSEEVENTF_MIDDLEDOWN, "mouse middle down"),
+    }
+}
+
+#[cfg(windows)]
+fn mouse_button_up(button: MouseButton) -> Result<(), anyhow::Error> {
+    use windows::Win32::UI::Input::KeyboardAndMouse::{
+        MOUSEEVENTF_LEFTUP, MOUSEEVENTF_MIDDLEUP, MOUSEEVENTF_RIGHTUP,
+    };
+
+    match button {
+        MouseButton::Left => send_mouse_input(MOUSEEVENTF_LEFTUP, "mouse left up"),
+        MouseButton::Right => send_mouse_input(MOUSEEVENTF_RIGHTUP, "mouse right up"),
+        MouseButton::Middle => send_mouse_input(MOUSEEVENTF_MIDDLEUP, "mouse middle up"),
+    }
+}
+
+fn click_events(button: MouseButton) -> [backend::InputEvent; 2] {
+    [
+        backend::InputEvent::ButtonDown(button),
+        backend::InputEvent::ButtonUp(button),
+    ]
+}
+
+/// Sends `events` through `SendInput`, the way `WindowsBackend` does.
+#[cfg(windows)]
+fn send_input_events(events: &[backend::InputEvent]) -> Result<(), anyhow::Error> {
+    events.iter().try_for_each(|event| match *event {
+        backend::InputEvent::KeyDown(key) => key_down(key as i32),
+        backend::InputEvent::KeyUp(key) => key_up(key as i32),
+        backend::InputEvent::ButtonDown(button) => mouse_button_down(button),
+        backend::InputEvent::ButtonUp(button) => mouse_button_up(button),
+        backend::InputEvent::MoveTo(x, y) => set_cursor_pos(x, y),
+        backend::InputEvent::Wheel(lines) => scroll_lines(lines),
+    })
+}
+
+/// `SPI_GETWHEELSCROLLLINES` value meaning one notch scrolls a whole screen.
+const WHEEL_PAGESCROLL: u32 = u32::MAX;
+
+/// Scrolls `lines` lines, converting them to wheel notches with the user's lines-per-notch
+/// setting. Counts that are not a whole number of notches end with a partial notch, which
+/// applications that support smooth scrolling honor. With the setting at one screen per notch,
+/// presses PageUp or PageDown once instead.
+#[cfg(windows)]
+fn scroll_lines(lines: i32) -> Result<(), anyhow::Error> {
+    use windows::Win32::UI::Input::KeyboardAndMouse::{
+        INPUT, INPUT_0, INPUT_MOUSE, MOUSEEVENTF_WHEEL,
+    };
+    use windows::Win32::UI::WindowsAndMessaging::{
+        SystemParametersInfoW, SPI_GETWHEELSCROLLLINES, SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS,
+        WHEEL_DELTA,
+    };
+
+    if lines == 0 {
+        return Ok(());
+    }
+
+    let mut lines_per_notch = 0u32;
+    let succeeded = unsafe {
+        SystemParametersInfoW(
+            SPI_GETWHEELSCROLLLINES,
+            0,
+            &mut lines_per_notch as *mut u32 as *mut std::ffi::c_void,
+            SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0),
+        )
+    };
+    if !succeeded.as_bool() {
+        return Err(error::MacroError::win32("SystemParametersInfoW").into());
+    }
+
+    match lines_per_notch {
+        0 => {
+            log::warn!("Wheel scrolling is turned off in the system settings, not scrolling");
+            return Ok(());
+        }
+        WHEEL_PAGESCROLL => {
+            log::warn!(
+                "The wheel scrolls a screen at a time, pressing {} instead of scrolling {} lines",
+                if lines > 0 { "PageUp" } else { "PageDown" },
+                lines.abs()
+            );
+            let key = if lines > 0 { Key::Prior } else { Key::Next };
+            return press_key(key as i32);
+        }
+        _ => {}
+    }
+
+    // Rounded to the nearest unit of wheel delta
+    let total_delta = (i64::from(lines) * i64::from(WHEEL_DELTA) * 2 / i64::from(lines_per_notch)
+        + i64::from(lines.signum()))
+        / 2;
+    let notch = i64::from(WHEEL_DELTA) * i64::from(lines.signum());
+
+    let mut inputs = Vec::new();
+    let mut remaining = total_delta;
+    while remaining != 0 {
+        let delta = if remaining.abs() > notch.abs() {
+            notch
+        } else {
+            remaining
+        };
+        remaining -= delta;
+
+        let mut input = INPUT {
+            r#type: INPUT_MOUSE,
+            Anonymous: INPUT_0::default(),
+        };
+        let mouse_input = unsafe { &mut input.Anonymous.mi };
+        mouse_input.dwFlags = MOUSEEVENTF_WHEEL;
+        mouse_input.mouseData = delta as i32;
+        inputs.push(input);
+    }
+
+    send_inputs(&inputs, &format!("scroll of {} lines", lines))
+}
+
+/// Moves the cursor through `points`, each reached `ms` after the path started, holding `button`
+/// (if any) from the first point to the last. The button is released even if the path fails
+/// part way or the macro is cancelled.
+#[cfg(windows)]
+fn follow_mouse_path(
+    points: &[(i32, i32, u64)],
+    button: Option<MouseButton>,
+    context: &context::ExecutionContext,
+) -> Result<(), anyhow::Error> {
+    let (first_x, first_y, _) = match points.first() {
+        Some(first) => *first,
+        None => return Ok(()),
+    };
+
+    set_cursor_pos(first_x, first_y)?;
+
+    if let Some(button) = button {
+        mouse_button_down(button)?;
+    }
+
+    let start = context.now();
+
+    let result = points.iter().skip(1).try_for_each(|(x, y, at_ms)| {
+        if context.is_cancelled() {
+            return Err(error::MacroError::Cancelled.into());
+        }
+
+        context.sleep_until(start + Duration::from_millis(*at_ms));
+
+        set_cursor_pos(*x, *y)
+    });
+
+    if let Some(button) = button {
+        mouse_button_up(button)?;
+    }
+
+    result
+}
+
+#[cfg(windows)]
+fn press_key(key: i32) -> anyhow::Result<(), anyhow::Error> {
+    let mut inputs = keyboard_inputs(key, false);
+    inputs.extend(keyboard_inputs(key, true));
+    send_inputs(&inputs, &format!("key press for {:?}", Key::from(key)))?;
+
+    watchdog::record_key_down(key);
+    watchdog::record_key_up(key);
+
+    Ok(())
+}
+
+/// Keys such as the arrows, media and browser keys live on the extended part of the keyboard, and
+/// many applications only react to them when the extended-key flag is set.
+#[cfg(windows)]
+fn extended_key_flag(key: i32) -> windows::Win32::UI::Input::KeyboardAndMouse::KEYBD_EVENT_FLAGS {
+    use windows::Win32::UI::Input::KeyboardAndMouse::{KEYBD_EVENT_FLAGS, KEYEVENTF_EXTENDEDKEY};
+
+    if Key::from(key).is_extended() {
+        KEYEVENTF_EXTENDEDKEY
+    } else {
+        KEYBD_EVENT_FLAGS(0)
+    }
+}
+
+#[cfg(windows)]
+fn scan_code(key: i32) -> u16 {
+    use windows::Win32::UI::Input::KeyboardAndMouse::MapVirtualKeyW;
+    use windows::Win32::UI::WindowsAndMessaging::MAPVK_VK_TO_VSC;
+
+    unsafe { MapVirtualKeyW(key as u32, MAPVK_VK_TO_VSC) as u16 }
+}
+
+/// The keyboard events for one key going down or up. AltGr is sent as LeftControl with
+/// RightMenu, and comes back up in reverse.
+#[cfg(windows)]
+fn keyboard_inputs(key: i32, up: bool) -> Vec<windows::Win32::UI::Input::KeyboardAndMouse::INPUT> {
+    use windows::Win32::UI::Input::KeyboardAndMouse::{
+        INPUT, INPUT_0, INPUT_KEYBOARD, KEYBD_EVENT_FLAGS, KEYEVENTF_KEYUP, VIRTUAL_KEY,
+    };
+
+    if key == Key::AltGr as i32 {
+        let mut keys = [Key::LeftControl as i32, Key::RightMenu as i32];
+        if up {
+            keys.reverse();
+        }
+        return keys
+            .into_iter()
+            .flat_map(|key| keyboard_inputs(key, up))
+            .collect();
+    }
+
+    let mut input = INPUT {
+        r#type: INPUT_KEYBOARD,
+        Anonymous: INPUT_0::default(),
+    };
+
+    let virtual_key = Key::from(key).virtual_key();
+    let keyboard_input = unsafe { &mut input.Anonymous.ki };
+    keyboard_input.wVk = VIRTUAL_KEY(virtual_key as u16);
+    keyboard_input.wScan = scan_code(virtual_key);
+    keyboard_input.dwFlags = extended_key_flag(key)
+        | if up {
+            KEYEVENTF_KEYUP
+        } else {
+            KEYBD_EVENT_FLAGS(0)
+        };
+
+    vec![input]
+}
+
+/// Puts every one of `keys` down, or lets them up, in that order and in a single batch.
+#[cfg(windows)]
+fn send_key_events(keys: &[i32], up: bool) -> Result<(), anyhow::Error> {
+    let inputs: Vec<_> = keys
+        .iter()
+        .flat_map(|key| keyboard_inputs(*key, up))
+        .collect();
+    let names: Vec<String> = keys
+        .iter()
+        .map(|key| format!("{:?}", Key::from(*key)))
+        .collect();
+    let direction = if up { "up" } else { "down" };
+
+    send_inputs(
+        &inputs,
+        &format!("key {} for {}", direction, names.join("+")),
+    )?;
+
+    for key in keys.iter() {
+        if up {
+            watchdog::record_key_up(*key);
+        } else {
+            watchdog::record_key_down(*key);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(windows)]
+fn key_down(key: i32) -> anyhow::Result<(), anyhow::Error> {
+    send_key_events(&[key], false)
+}
+
+#[cfg(windows)]
+fn key_up(key: i32) -> anyhow::Result<(), anyhow::Error> {
+    send_key_events(&[key], true)
+}
+
+#[cfg(windows)]
+fn key_held(vkey: i32) -> bool {
+    (unsafe { windows::Win32::UI::Input::KeyboardAndMouse::GetAsyncKeyState(vkey) } & -0x8000i16
+        != 0)
+}
+
+/// Characters typed between two checks for cancellation by `type_in_chunks`.
+const TEXT_CHUNK_CHARS: usize = 64;
+
+/// Types `text` with `type_chunk` `TEXT_CHUNK_CHARS` characters at a time, reporting progress
+/// after each chunk and stopping between chunks once the macro is cancelled. Chunks are cut
+/// between `char`s, so a surrogate pair always reaches `type_chunk` whole. The error on
+/// cancellation says how many characters were typed, so the text can be resumed, but never
+/// includes the text itself.
+fn type_in_chunks(
+    text: &str,
+    context: &context::ExecutionContext,
+    type_chunk: fn(&str) -> Result<(), anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    let total = text.chars().count();
+    let mut typed = 0;
+    let mut rest = text;
+
+    let result = (|| {
+        while !rest.is_empty() {
+            if context.is_cancelled() {
+                log::warn!(
+                    "{}: cancelled after typing {} of {} characters",
+                    context.macro_name,
+                    typed,
+                    total
+                );
+                return Err(anyhow::anyhow!(
+                    "Text cancelled after {} of {} characters",
+                    typed,
+                    total
+                ));
+            }
+
+            let split = rest
+                .char_indices()
+                .nth(TEXT_CHUNK_CHARS)
+                .map_or(rest.len(), |(index, _)| index);
+            let (chunk, remaining) = rest.split_at(split);
+
+            type_chunk(chunk)?;
+            typed += chunk.chars().count();
+            rest = remaining;
+            context.set_text_progress(Some((typed, total)));
+        }
+
+        Ok(())
+    })();
+
+    context.set_text_progress(None);
+    result
+}
+
+/// Types `text` as the key presses that produce each character on the current keyboard layout,
+/// holding Shift and/or AltGr where the layout needs them. Characters the layout has no key for
+/// are typed as unicode instead.
+#[cfg(windows)]
+fn type_text(text: &str) -> Result<(), anyhow::Error> {
+    use windows::Win32::UI::Input::KeyboardAndMouse::VkKeyScanW;
+
+    for c in text.chars() {
+        // VkKeyScanW maps '\n' to Ctrl+Enter
+        if c == '\n' {
+            press_key(Key::Return as i32)?;
+            continue;
+        }
+
+        let mut utf16 = [0; 2];
+        let key_scan = match c.encode_utf16(&mut utf16) {
+            [unit] => unsafe { VkKeyScanW(*unit) },
+            _ => -1,
+        };
+
+        if key_scan == -1 {
+            type_unicode(c.encode_utf8(&mut [0; 4]))?;
+            continue;
+        }
+
+        let [virtual_key, shift_state] = key_scan.to_le_bytes();
+
+        let mut modifiers = Vec::new();
+        if shift_state & 0b110 == 0b110 {
+            modifiers.push(Key::AltGr);
+        } else if shift_state & 0b010 != 0 {
+            modifiers.push(Key::Control);
+        } else if shift_state & 0b100 != 0 {
+            modifiers.push(Key::Menu);
+        }
+        if shift_state & 0b001 != 0 {
+            modifiers.push(Key::Shift);
+        }
+
+        let mut held = Vec::with_capacity(modifiers.len());
+        let result = (|| {
+            for modifier in modifiers {
+                key_down(modifier as i32)?;
+                held.push(modifier);
+            }
+            press_key(i32::from(virtual_key))
+        })();
+
+        for modifier in held.into_iter().rev() {
+            key_up(modifier as i32)?;
+        }
+        result?;
+    }
+
+    Ok(())
+}
+
+/// Types `text` as unicode characters, so it comes out as-is whatever the keyboard layout or
+/// modifier state. Errors never include the text being typed, so this is safe for secrets.
+#[cfg(windows)]
+fn type_unicode(text: &str) -> Result<(), anyhow::Error> {
+    use windows::Win32::UI::Input::KeyboardAndMouse::{
+        INPUT, INPUT_0, INPUT_KEYBOARD, KEYEVENTF_KEYUP, KEYEVENTF_UNICODE,
+    };
+
+    let inputs: Vec<INPUT> = text
+        .encode_utf16()
+        .flat_map(|unit| {
+            [KEYEVENTF_UNICODE, KEYEVENTF_UNICODE | KEYEVENTF_KEYUP].map(|flags| {
+                let mut input = INPUT {
+                    r#type: INPUT_KEYBOARD,
+                    Anonymous: INPUT_0::default(),
+                };
+                let keyboard_input = unsafe { &mut input.Anonymous.ki };
+                keyboard_input.wScan = unit;
+                keyboard_input.dwFlags = flags;
+                input
+            })
+        })
+        .collect();
+
+    send_inputs(&inputs, "text")
+}
+
+/// Toggles NumLock into the state `policy` asks for, registering the toggle back for when the
+/// macro ends, however it ends.
+fn apply_numlock_policy(
+    policy: NumlockPolicy,
+    context: &mut context::ExecutionContext,
+) -> Result<(), anyhow::Error> {
+    let wanted = match policy {
+        NumlockPolicy::On => true,
+        NumlockPolicy::Off => false,
+        NumlockPolicy::Ignore => return Ok(()),
+    };
+
+    if key_toggled(Key::Numlock as i32) == wanted {
+        return Ok(());
+    }
+
+    set_lock_key(Key::Numlock, wanted)?;
+    context.defer(move || {
+        if let Err(e) = set_lock_key(Key::Numlock, !wanted) {
+            log::error!("Failed to restore NumLock: {}", e);
+        }
+    });
+
+    Ok(())
+}
+
+/// The modifiers `settle_modifiers` looks at, each side on its own so that exactly the keys that
+/// were held are pressed again, in the order a combo presses them.
+const SIDED_MODIFIERS: [Key; 8] = [
+    Key::LeftControl,
+    Key::RightControl,
+    Key::LeftShift,
+    Key::RightShift,
+    Key::LeftMenu,
+    Key::RightMenu,
+    Key::LeftWindows,
+    Key::RightWindows,
+];
+
+/// How often `settle_modifiers` checks whether the user has let go of the modifiers.
+const MODIFIER_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Unassigned virtual-key code tapped before releasing Alt or Windows, so that the release does
+/// not open the window menu or the Start menu.
+const MENU_MASK_KEY: i32 = 0xE8;
+
+/// Applies the macro's `wait_for_clean_modifiers` and `neutralize_modifiers` to the modifiers
+/// the user holds as it starts.
+fn settle_modifiers(
+    wait: bool,
+    grace: Duration,
+    neutralize: bool,
+    context: &mut context::ExecutionContext,
+) -> Result<(), anyhow::Error> {
+    if !wait && !neutralize {
+        return Ok(());
+    }
+
+    let held_modifiers = |context: &context::ExecutionContext| -> Vec<Key> {
+        SIDED_MODIFIERS
+            .iter()
+            .copied()
+            .filter(|key| context.is_key_held(*key))
+            .collect()
+    };
+
+    let mut held = held_modifiers(context);
+    if held.is_empty() {
+        return Ok(());
+    }
+
+    if wait {
+        log::info!(
+            "{}: waiting up to {:?} for {} to be released",
+            context.macro_name,
+            grace,
+            format_keys(&held.iter().copied().collect())
+        );
+        let deadline = context.now() + grace;
+        while !held.is_empty() && context.now() < deadline && !context.is_cancelled() {
+            context.sleep(MODIFIER_POLL_INTERVAL);
+            held = held_modifiers(context);
+        }
+    }
+
+    if held.is_empty() || context.is_cancelled() {
+        return Ok(());
+    }
+
+    let held_names = format_keys(&held.iter().copied().collect());
+    if !neutralize {
+        log::warn!(
+            "{}: {} still held, running anyway",
+            context.macro_name,
+            held_names
+        );
+        return Ok(());
+    }
+
+    log::info!(
+        "{}: releasing {} until it ends",
+        context.macro_name,
+        held_names
+    );
+    let mut keys: Vec<i32> = held.iter().map(Key::virtual_key).collect();
+    if held.iter().any(|key| {
+        matches!(
+            key,
+            Key::LeftMenu | Key::RightMenu | Key::LeftWindows | Key::RightWindows
+        )
+    }) {
+        press_key(MENU_MASK_KEY)?;
+    }
+    keys.reverse();
+    send_key_events(&keys, true)?;
+    keys.reverse();
+
+    let macro_name = context.macro_name.clone();
+    context.defer(move || {
+        if let Err(e) = send_key_events(&keys, false) {
+            log::error!(
+                "{}: failed to press {} again: {}",
+                macro_name,
+                held_names,
+                e
+            );
+        }
+    });
+
+    Ok(())
+}
+
+/// Presses the lock key `key` if it is not already toggled `on`, then waits for the toggle to
+/// show, pressing again if a press was lost, e.g. in a fast sequence of input.
+fn set_lock_key(key: Key, on: bool) -> Result<(), anyhow::Error> {
+    let vkey = key as i32;
+
+    for _ in 0..LOCK_KEY_ATTEMPTS {
+        if key_toggled(vkey) == on {
+            return Ok(());
+        }
+
+        press_key(vkey)?;
+
+        let pressed_at = Instant::now();
+        while key_toggled(vkey) != on && pressed_at.elapsed() < LOCK_KEY_SETTLE {
+            sleep(Duration::from_millis(10));
+        }
+    }
+
+    if key_toggled(vkey) == on {
+        return Ok(());
+    }
+
+    Err(anyhow::anyhow!(
+        "{:?} is still {} after {} presses",
+        key,
+        if on { "off" } else { "on" },
+        LOCK_KEY_ATTEMPTS
+    ))
+}
+
+/// Whether a lock key such as CapsLock is currently toggled on.
+#[cfg(windows)]
+fn key_toggled(vkey: i32) -> bool {
+    (unsafe { windows::Win32::UI::Input::KeyboardAndMouse::GetKeyState(vkey) } & 1 != 0)
+}
+
+enum Message {
+    Exit,
+    /// Run a macro as if its hotkey had been pressed.
+    Trigger {
+        macro_name: String,
+        source: events::TriggerSource,
+        reply: std::sync::mpsc::Sender<http::TriggerOutcome>,
+    },
+    ListMacros(std::sync::mpsc::Sender<Vec<http::MacroStatus>>),
+    /// Reply with the active profile and why it was selected.
+    ActiveProfile(std::sync::mpsc::Sender<profile::ActiveProfile>),
+    /// Run the hotkey matching as if `keys` had been pressed and released, replying with the
+    /// macros that matched.
+    SimulatePress {
+        keys: HashSet<Key>,
+        reply: std::sync::mpsc::Sender<Vec<http::PressMatch>>,
+    },
+    /// Stop taking triggers and hold running macros until `Resume`.
+    Pause,
+    Resume,
+}
+
+fn list(macro_config: &MacroConfig, timing: bool, stats: bool) -> Result<(), anyhow::Error> {
+    let macro_stats = if stats {
+        let history_config = macro_config
+            .history
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("--stats needs the config to set a history file"))?;
+        Some(history::macro_stats(&history_config.path)?)
+    } else {
+        None
+    };
+
+    for current_macro in macro_config.macros.iter() {
+        let hotkey = current_macro
+            .macro_hotkey
+            .iter()
+            .map(|key| format!("{:?}", key))
+            .collect::<Vec<_>>()
+            .join("+");
+
+        let macro_name = if current_macro.enabled {
+            current_macro.macro_name.clone()
+        } else {
+            format!("{} (disabled)", current_macro.macro_name)
+        };
+
+        let mut columns = vec![macro_name, hotkey];
+
+        if timing {
+            columns.push(current_macro.estimated_duration().to_string());
+        }
+
+        if let Some(macro_stats) = macro_stats.as_ref() {
+            let current_stats = macro_stats
+                .get(&current_macro.macro_name)
+                .cloned()
+                .unwrap_or_default();
+            columns.push(format!("{} runs", current_stats.runs));
+            columns.push(format!("{} failed", current_stats.failures));
+            columns.push(
+                current_stats
+                    .average_duration()
+                    .map_or_else(|| "-".to_string(), |average| format!("avg {}", average)),
+            );
+            columns.push(current_stats.last_started_ms.map_or_else(
+                || "never run".to_string(),
+                |last| format!("last {} UTC", history::format_timestamp(last)),
+            ));
+        }
+
+        println!("{}", columns.join("\t"));
+    }
+
+    Ok(())
+}
+
+/// How often `list --watch` refreshes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Has a live runner act as if `hotkey` had been pressed, through its HTTP endpoint, and prints
+/// each macro that matched with the id of its execution, or why it did not start.
+fn press(macro_config: &MacroConfig, hotkey: &HashSet<Key>) -> Result<(), anyhow::Error> {
+    let http_config = macro_config
+        .http
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("press needs the config to enable the http endpoint"))?;
+
+    let matches = http::press(http_config, hotkey)?;
+    if matches.is_empty() {
+        println!("no match");
+    }
+
+    for press_match in matches {
+        match (press_match.execution_id, press_match.rejected) {
+            (Some(execution_id), _) => {
+                println!("{}\t#{}", press_match.macro_name, execution_id)
+            }
+            (None, rejected) => println!(
+                "{}\tnot started: {}",
+                press_match.macro_name,
+                rejected.unwrap_or_default()
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+/// Shows the macros a live runner is running, through its HTTP endpoint, every `WATCH_INTERVAL`
+/// until the process is interrupted. An unreachable runner is reported and retried.
+fn watch(macro_config: &MacroConfig) -> Result<(), anyhow::Error> {
+    let http_config = macro_config
+        .http
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("--watch needs the config to enable the http endpoint"))?;
+
+    loop {
+        // Clear the screen and move to the top left
+        print!("\x1b[2J\x1b[H");
+
+        if !macro_config.auto_profile.is_empty() {
+            if let Ok(active) = http::fetch_profile(http_config) {
+                println!(
+                    "Profile {}: {}\n",
+                    active.profile.as_deref().unwrap_or(profile::GLOBAL_PROFILE),
+                    active.reason
+                );
+            }
+        }
+
+        match http::fetch_statuses(http_config) {
+            Ok(statuses) => {
+                let active: Vec<&http::MacroStatus> = statuses
+                    .iter()
+                    .filter(|status| status.running || status.queued || status.deferred)
+                    .collect();
+
+                if active.is_empty() {
+                    println!("No macros running");
+                }
+
+                for status in active {
+                    match (status.command_index, status.elapsed_ms) {
+                        (Some(command_index), Some(elapsed_ms)) if status.paused => println!(
+                            "{}\tpaused at command {}\t{}",
+                            status.name,
+                            command_index + 1,
+                            duration::DurationMs(elapsed_ms)
+                        ),
+                        (Some(command_index), Some(elapsed_ms)) => println!(
+                            "{}\tcommand {}\t{}{}",
+                            status.name,
+                            command_index + 1,
+                            duration::DurationMs(elapsed_ms),
+                            status
+                                .typed_percent
+                                .map(|percent| format!("\ttyping {}%", percent))
+                                .unwrap_or_default()
+                        ),
+                        _ if status.deferred => {
+                            println!("{}\tdeferred until fullscreen ends", status.name)
+                        }
+                        _ => println!("{}\tqueued", status.name),
+                    }
+                }
+            }
+            Err(e) => println!(
+                "Runner not reachable at {}, retrying: {}",
+                http_config.bind, e
+            ),
+        }
+
+        std::io::Write::flush(&mut std::io::stdout())?;
+        sleep(WATCH_INTERVAL);
+    }
+}
+
+/// Creates the event bus along with its log, stdout and history subscribers. Returns the handle of
+/// the history writer, which finishes once the bus has been dropped.
+fn event_bus(
+    macro_config: &MacroConfig,
+    events_stdout: bool,
+) -> (Arc<events::EventBus>, Option<JoinHandle<()>>) {
+    let events = Arc::new(events::EventBus::default());
+    let log_rx = events.subscribe();
+    spawn(move || events::log_events(log_rx));
+    if events_stdout {
+        let ndjson_rx = events.subscribe();
+        spawn(move || events::write_events_ndjson(ndjson_rx));
+    }
+
+    let history_handle = macro_config.history.clone().map(|history_config| {
+        let history_rx = events.subscribe();
+        spawn(move || history::write_history(history_rx, history_config))
+    });
+
+    (events, history_handle)
+}
+
+/// Shows the palette from its own thread and triggers the macro picked in it, if any. Does
+/// nothing while a palette is already open.
+fn open_palette(
+    macro_names: Arc<Vec<String>>,
+    tx: std::sync::mpsc::Sender<Message>,
+    palette_open: Arc<AtomicBool>,
+) {
+    if palette_open.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    spawn(move || {
+        let picked = palette::pick_macro(&macro_names);
+        palette_open.store(false, Ordering::SeqCst);
+
+        let macro_name = match picked {
+            Ok(Some(macro_name)) => macro_name,
+            Ok(None) => return,
+            Err(e) => {
+                log::error!("Failed to open the palette: {}", e);
+                return;
+            }
+        };
+
+        let (reply_tx, reply_rx) = std::sync::mpsc::channel();
+        let sent = tx.send(Message::Trigger {
+            macro_name: macro_name.clone(),
+            source: events::TriggerSource::Palette,
+            reply: reply_tx,
+        });
+        if sent.is_err() {
+            return;
+        }
+
+        match reply_rx.recv() {
+            Ok(http::TriggerOutcome::Rejected(reason)) => {
+                log::warn!("Not running {}: {}", macro_name, reason)
+            }
+            Ok(http::TriggerOutcome::NotFound) => log::warn!("Macro {} is gone", macro_name),
+            _ => {}
+        }
+    });
+}
+
+/// Runs the macros of `macro_config` until the program hotkey is held.
+fn run(
+    macro_config: MacroConfig,
+    config_path: Option<PathBuf>,
+    events_stdout: bool,
+    trace_triggers: bool,
+) -> Result<(), anyhow::Error> {
+    runner::start(macro_config, config_path, events_stdout, trace_triggers)?.wait()
+}
+
+/// Name of the macro `run-inline` runs its commands as.
+const INLINE_MACRO_NAME: &str = "inline";
+
+/// Runs the commands written in `shorthand` as a macro of their own, under the config's
+/// settings, and returns once it finishes.
+fn run_inline(
+    mut macro_config: MacroConfig,
+    shorthand: &str,
+    events_stdout: bool,
+) -> Result<(), anyhow::Error> {
+    if macro_config.macro_index(INLINE_MACRO_NAME).is_some() {
+        return Err(anyhow::anyhow!(
+            "The config already has a macro named {}, rename it to use run-inline",
+            INLINE_MACRO_NAME
+        ));
+    }
+
+    let commands = shorthand::parse(shorthand)?;
+    validate_commands(INLINE_MACRO_NAME, &commands, &mut Vec::new())?;
+    validate_key_combos(
+        INLINE_MACRO_NAME,
+        commands.iter(),
+        "",
+        macro_config.max_combo_keys,
+    )?;
+
+    let mut mapping = serde_yaml::Mapping::new();
+    mapping.insert("macro_name".into(), INLINE_MACRO_NAME.into());
+    mapping.insert("commands".into(), serde_yaml::Value::Sequence(Vec::new()));
+    let mut inline: Macro = serde_yaml::from_value(serde_yaml::Value::Mapping(mapping))?;
+    inline.commands = commands;
+    macro_config.macros.push(inline);
+    let inline = macro_config.macros.last().unwrap();
+    macro_config.validate_variables(
+        INLINE_MACRO_NAME,
+        &inline.commands,
+        &macro_config.defined_variables(),
+        &mut Vec::new(),
+    )?;
+
+    run_macro(
+        macro_config,
+        INLINE_MACRO_NAME,
+        HashMap::new(),
+        context::DebugMode::Off,
+        events_stdout,
+    )
+}
+
+/// Runs a single macro with the given arguments, without listening for hotkeys, and waits for it
+/// to finish.
+fn run_macro(
+    macro_config: MacroConfig,
+    macro_name: &str,
+    args: HashMap<String, String>,
+    debug_mode: context::DebugMode,
+    events_stdout: bool,
+) -> Result<(), anyhow::Error> {
+    let index = macro_config
+        .macro_index(macro_name)
+        .ok_or_else(|| anyhow::anyhow!("No macro named {}", macro_name))?;
+    macro_config.macros[index].bind_args(&args)?;
+    let clock: Arc<dyn clock::Clock> = Arc::new(clock::SystemClock);
+    if let Some(guard) = listener::blocking_guard(
+        &macro_config.macros[index],
+        events::TriggerSource::Cli,
+        None,
+        None,
+        clock.as_ref(),
+        &backend::GdiScreen,
+    ) {
+        return Err(anyhow::anyhow!(
+            "Not running {}: {}, add cli to its ignore_guards_for to run it anyway",
+            macro_name,
+            guard
+        ));
+    }
+
+    let (events, history_handle) = event_bus(&macro_config, events_stdout);
+
+    let mut executor = executor::Executor::new(
+        macro_config.macros,
+        macro_config.max_macro_threads,
+        macro_config.worker_threads,
+        events,
+        backend::input_backend()?,
+        Arc::new(backend::GdiScreen),
+        clock,
+    );
+    executor.set_seed(macro_config.seed);
+    executor.set_allowed_targets(macro_config.allowed_targets.clone());
+    executor.set_debug_mode(debug_mode);
+
+    if executor
+        .start_with_args(index, 0, events::TriggerSource::Cli, args)
+        .is_none()
+    {
+        return Err(anyhow::anyhow!("Failed to start {}", macro_name));
+    }
+
+    while executor.running_count() > 0 {
+        sleep(Duration::from_millis(50));
+        executor.reap();
+    }
+
+    // Let the history writer record the outcome before the process exits
+    drop(executor);
+    if let Some(history_handle) = history_handle {
+        let _ = history_handle.join();
+    }
+
+    Ok(())
+}
+
+/// Runs `macro_names` in order on this thread, `repeat` times over, then prints how each run
+/// went. Returns the number of runs that failed; with `stop_on_failure` the first failure ends
+/// the batch.
+fn run_batch(
+    macro_config: MacroConfig,
+    macro_names: &[String],
+    stop_on_failure: bool,
+    repeat: u32,
+    events_stdout: bool,
+) -> Result<usize, anyhow::Error> {
+    // Check every name up front rather than failing halfway through the batch
+    let indices = macro_names
+        .iter()
+        .map(|macro_name| {
+            macro_config
+                .macro_index(macro_name)
+                .ok_or_else(|| anyhow::anyhow!("No macro named {}", macro_name))
+        })
+        .collect::<Result<Vec<usize>, _>>()?;
+
+    let clock: Arc<dyn clock::Clock> = Arc::new(clock::SystemClock);
+    let (events, history_handle) = event_bus(&macro_config, events_stdout);
+
+    let macros = macro_config.macros.clone();
+    let mut executor = executor::Executor::new(
+        macro_config.macros,
+        macro_config.max_macro_threads,
+        macro_config.worker_threads,
+        events,
+        backend::input_backend()?,
+        Arc::new(backend::GdiScreen),
+        clock.clone(),
+    );
+    executor.set_seed(macro_config.seed);
+    executor.set_allowed_targets(macro_config.allowed_targets.clone());
+
+    let mut results = Vec::new();
+    let mut failures = 0;
+
+    'batch: for round in 1..=repeat {
+        for (position, &index) in indices.iter().enumerate() {
+            let macro_name = &macro_names[position];
+
+            // Guards such as active_hours are checked as each macro comes up
+            let guard = listener::blocking_guard(
+                &macros[index],
+                events::TriggerSource::Cli,
+                None,
+                None,
+                clock.as_ref(),
+                &backend::GdiScreen,
+            );
+            if let Some(guard) = guard {
+                log::error!("Not running {}: {}", macro_name, guard);
+                results.push((round, macro_name, "blocked", None));
+                failures += 1;
+            } else {
+                let started = Instant::now();
+                let succeeded = executor.run_inline(index, events::TriggerSource::Cli);
+
+                // Let any on_success/on_failure follow-up finish before the next macro
+                while executor.running_count() > 0 {
+                    sleep(Duration::from_millis(50));
+                    executor.reap();
+                }
+
+                let outcome = match succeeded {
+                    Some(true) => "succeeded",
+                    Some(false) => "failed",
+                    None => "not started",
+                };
+                if succeeded != Some(true) {
+                    failures += 1;
+                }
+                results.push((round, macro_name, outcome, Some(started.elapsed())));
+            }
+
+            if stop_on_failure && failures > 0 {
+                break 'batch;
+            }
+        }
+    }
+
+    // Let the history writer record the outcomes before the process exits
+    drop(executor);
+    if let Some(history_handle) = history_handle {
+        let _ = history_handle.join();
+    }
+
+    for (round, macro_name, outcome, elapsed) in results {
+        let duration = elapsed
+            .map(|elapsed| duration::DurationMs(elapsed.as_millis() as u64).to_string())
+            .unwrap_or_else(|| "-".to_string());
+        if repeat > 1 {
+            println!("{}\t{}\t{}\t{}", round, macro_name, outcome, duration);
+        } else {
+            println!("{}\t{}\t{}", macro_name, outcome, duration);
+        }
+    }
+    println!("{} failed", failures);
+
+    Ok(failures)
+}
+
+/// Builds a config holding nothing but an auto-clicker: a `while_held` macro on `hold_hotkey`
+/// that clicks `button` every `interval`, stopped by `exit_hotkey`.
+fn click_config(
+    interval: duration::DurationMs,
+    button: ClickButton,
+    hold_hotkey: HashSet<Key>,
+    exit_hotkey: HashSet<Key>,
+    jitter_ms: u64,
+    jitter_px: i32,
+) -> Result<MacroConfig, anyhow::Error> {
+    let click = match button {
+        ClickButton::Left => Command::LeftClick,
+        ClickButton::Right => Command::RightClick,
+        ClickButton::Middle => Command::MiddleClick,
+    };
+
+    // Everything not set here takes its default, exactly as if read from a config file
+    let mut click_macro: Macro = serde_json::from_value(serde_json::json!({
+        "macro_name": "click",
+        "macro_hotkey": [],
+        "mode": "while_held",
+        "commands": [],
+    }))?;
+    click_macro.macro_hotkey = hold_hotkey;
+    click_macro.jitter_ms = jitter_ms;
+    click_macro.jitter_px = jitter_px;
+    click_macro.commands = vec![click, Command::Wait(interval)];
+
+    let mut macro_config: MacroConfig = serde_json::from_value(serde_json::json!({
+        "program_hotkey": [],
+        "macros": [],
+    }))?;
+    macro_config.program_hotkey = exit_hotkey;
+    macro_config.macros.push(click_macro);
+    macro_config.validate()?;
+
+    Ok(macro_config)
+}
+
+/// Runs the command-line program: reads the arguments and runs the subcommand they name.
+pub fn run_cli() -> Result<(), anyhow::Error> {
+    // Initialize things
+    // logger, config
+    logger::init()?;
+
+    run_cli_with(std::env::args().skip(1))
+}
+
+/// Runs the command-line program on `args`, the arguments without the program name, e.g. to
+/// drive it from tests. Unlike `run_cli`, leaves setting up logging to the caller.
+pub fn run_cli_with(args: impl IntoIterator<Item = String>) -> Result<(), anyhow::Error> {
+    let cli = Cli::parse_from(args)?;
+    if cli.events_stdout {
+        // Stdout carries nothing but the events, one JSON object per line
+        logger::log_to_stderr();
+    }
+    if let Some(backend) = cli.backend {
+        backend::choose(backend);
+    }
+
+    match cli.subcommand {
+        Subcommand::Run => {
+            let mut macro_config =
+                config::load_config(cli.config.as_deref(), cli.force, &cli.overrides)?;
+            macro_config.seed = cli.seed.or(macro_config.seed);
+
+            if macro_config.needs_elevation && !elevation::is_current_process_elevated()? {
+                if cli.request_elevation {
+                    log::info!("Relaunching with administrator rights");
+                    return elevation::relaunch_elevated();
+                }
+
+                log::warn!(
+                    "This config needs elevation but the runner is not elevated, pass \
+                     --request-elevation to relaunch as administrator"
+                );
+            }
+
+            run(
+                macro_config,
+                config::resolve_config_path(cli.config.as_deref()),
+                cli.events_stdout,
+                cli.trace_triggers,
+            )
+        }
+        Subcommand::List {
+            timing,
+            stats,
+            watch: true,
+        } => {
+            if timing || stats {
+                log::warn!("--timing and --stats are ignored with --watch");
+            }
+            watch(&config::load_config(
+                cli.config.as_deref(),
+                cli.force,
+                &cli.overrides,
+            )?)
+        }
+        Subcommand::List { timing, stats, .. } => list(
+            &config::load_config(cli.config.as_deref(), cli.force, &cli.overrides)?,
+            timing,
+            stats,
+        ),
+        Subcommand::Press { hotkey } => press(
+            &config::load_config(cli.config.as_deref(), cli.force, &cli.overrides)?,
+            &hotkey,
+        ),
+        Subcommand::RunMacro {
+            name,
+            args,
+            debug_mode,
+        } => {
+            let mut macro_config =
+                config::load_config(cli.config.as_deref(), cli.force, &cli.overrides)?;
+            macro_config.seed = cli.seed.or(macro_config.seed);
+
+            run_macro(
+                macro_config,
+                &name,
+                args.into_iter().collect(),
+                debug_mode,
+                cli.events_stdout,
+            )
+        }
+        Subcommand::RunInline { commands } => {
+            let mut macro_config =
+                config::load_config(cli.config.as_deref(), cli.force, &cli.overrides)?;
+            macro_config.seed = cli.seed.or(macro_config.seed);
+
+            run_inline(macro_config, &commands, cli.events_stdout)
+        }
+        Subcommand::RunBatch {
+            config,
+            macros,
+            stop_on_failure,
+            repeat,
+        } => {
+            let config = config.or(cli.config);
+            let mut macro_config =
+                config::load_config(config.as_deref(), cli.force, &cli.overrides)?;
+            macro_config.seed = cli.seed.or(macro_config.seed);
+
+            let failures = run_batch(
+                macro_config,
+                &macros,
+                stop_on_failure,
+                repeat,
+                cli.events_stdout,
+            )?;
+            std::process::exit(failures.min(i32::MAX as usize) as i32)
+        }
+        Subcommand::Validate => {
+            // Soft collisions are reported as warnings on the way
+            config::load_config(cli.config.as_deref(), false, &cli.overrides)?;
+            println!("Config is valid");
+            Ok(())
+        }
+        Subcommand::Lint { fix } => lint::lint(
+            &config::load_config(cli.config.as_deref(), cli.force, &cli.overrides)?,
+            fix,
+        ),
+        Subcommand::DiffRun {
+            name,
+            update_baseline,
+            tolerance,
+        } => diff_run::diff_run(
+            config::load_config(cli.config.as_deref(), cli.force, &cli.overrides)?,
+            &name,
+            update_baseline,
+            tolerance,
+        ),
+        Subcommand::ShowConfig => {
+            print!(
+                "{}",
+                serde_yaml::to_string(&config::load_config(
+                    cli.config.as_deref(),
+                    cli.force,
+                    &cli.overrides
+                )?)?
+            );
+            Ok(())
+        }
+        Subcommand::Schema => schema::print_schema(),
+        Subcommand::History { last, macro_name } => {
+            let macro_config =
+                config::load_config(cli.config.as_deref(), cli.force, &cli.overrides)?;
+            let history_config = macro_config
+                .history
+                .ok_or_else(|| anyhow::anyhow!("The config does not set a history file"))?;
+            history::print_history(&history_config.path, last, macro_name.as_deref())
+        }
+        Subcommand::Calibrate { anchor } => {
+            let path = cli
+                .config
+                .unwrap_or_else(|| PathBuf::from(config::DEFAULT_CONFIG_PATH));
+            calibrate::calibrate(&path, anchor.as_deref())
+        }
+        Subcommand::RestoreBackup { list, name } => {
+            let path = config::resolve_config_path(cli.config.as_deref()).ok_or_else(|| {
+                anyhow::anyhow!("No {} found to restore", config::DEFAULT_CONFIG_PATH)
+            })?;
+
+            if list {
+                backup::print_backups(&path)
+            } else {
+                backup::restore_backup(&path, name.as_deref())
+            }
+        }
+        Subcommand::Doctor => {
+            let healthy = doctor::run_doctor();
+            std::process::exit(if healthy { 0 } else { 1 });
+        }
+        Subcommand::CaptureKey { combo } => capture::capture_keys(combo),
+        Subcommand::CaptureWindow { delay } => capture::capture_window(delay),
+        Subcommand::CredSet { target } => {
+            let password = secret::Secret::prompt(&format!("Password for {}: ", target))?;
+            password.store_credential(&target)?;
+            println!("Stored credential {}", target);
+            Ok(())
+        }
+        Subcommand::ListGamepads => gamepad::list_gamepads(),
+        Subcommand::Version => {
+            println!("{}", update::version_string());
+            Ok(())
+        }
+        Subcommand::CheckUpdate { url } => {
+            let url = match url {
+                Some(url) => url,
+                None => config::load_config(cli.config.as_deref(), cli.force, &cli.overrides)?
+                    .update_url
+                    .unwrap_or_else(|| update::DEFAULT_UPDATE_URL.to_string()),
+            };
+            let checked = update::check_update(&url);
+            std::process::exit(if checked { 0 } else { 1 });
+        }
+        Subcommand::Click {
+            interval,
+            button,
+            hold_hotkey,
+            exit_hotkey,
+            jitter_ms,
+            jitter_px,
+        } => {
+            let mut macro_config = click_config(
+                interval,
+                button,
+                hold_hotkey,
+                exit_hotkey,
+                jitter_ms,
+                jitter_px,
+            )?;
+            macro_config.seed = cli.seed;
+            log::info!(
+                "Clicking every {} while the hold hotkey is held, press the exit hotkey to stop",
+                interval
+            );
+
+            run(macro_config, None, cli.events_stdout, cli.trace_triggers)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backend::{
+        InputBackend, MemoryScreen, ScriptedInput, ScriptedScreen, SimulatedInput, SimulatedScreen,
+    };
+    use builder::{BuildCommands, CommandsBuilder};
+    use clock::VirtualClock;
+
+    pub fn commands(yaml: &str) -> Vec<Command> {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    /// A context for running commands on their own, reading keys from `backend` and time from
+    /// `clock`.
+    pub fn test_context(
+        backend: Arc<dyn InputBackend>,
+        clock: Arc<dyn clock::Clock>,
+    ) -> context::ExecutionContext {
+        let mut context = context::ExecutionContext::new(
+            "test".to_string(),
+            1,
+            Arc::new(events::EventBus::default()),
+            context::CancellationToken::default(),
+            context::PauseToken::default(),
+            backend,
+            Arc::new(Vec::new()),
+        );
+        context.set_clock(clock);
+        context.set_screen(Arc::new(SimulatedScreen));
+        context
+    }
+
+    #[test]
+    fn if_key_held_follows_the_key_state() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60)));
+        let backend = Arc::new(ScriptedInput::new(clock.clone()));
+        backend.hold(Key::Shift, Duration::ZERO, Duration::from_millis(1000));
+        let mut context = test_context(backend, clock.clone());
+
+        // Which branch ran shows in how long it took
+        let branch = "!IfKeyHeld {key: Shift, then: [!Wait 100], else: [!Wait 300]}";
+        run_block(
+            &commands(&format!("[{}, !Wait 1000, {}]", branch, branch)),
+            &mut context,
+        )
+        .unwrap();
+        assert_eq!(
+            clock.elapsed_total(),
+            Duration::from_millis(100 + 1000 + 300)
+        );
+    }
+
+    #[test]
+    fn if_key_held_sees_a_key_go_down_mid_macro() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60)));
+        let backend = Arc::new(ScriptedInput::new(clock.clone()));
+        backend.press_at(Key::LeftControl, Duration::from_millis(500));
+        let mut context = test_context(backend, clock.clone());
+
+        run_block(
+            &commands(
+                "[!Loop [3, [!IfKeyHeld {key: LeftControl, then: [!Break], else: [!Wait 400]}]]]",
+            ),
+            &mut context,
+        )
+        .unwrap();
+        // Up at 0 and 400 ms, down by 800 ms
+        assert_eq!(clock.elapsed_total(), Duration::from_millis(800));
+    }
+
+    /// Everything `commands` send when run on the simulated backend.
+    fn recorded(commands: Vec<Command>) -> Vec<String> {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60)));
+        let mut context = test_context(Arc::new(SimulatedInput), clock.clone());
+
+        let (result, recorded) =
+            diff_run::record_for_test(clock, || run_block(&commands, &mut context));
+        result.unwrap();
+        recorded
+    }
+
+    /// What `commands` send, keys going down as the key and typed text as the characters.
+    fn sent(commands: Vec<Command>) -> Vec<String> {
+        recorded(commands)
+            .iter()
+            .filter_map(|event| {
+                event
+                    .strip_prefix("key_down ")
+                    .or_else(|| event.strip_prefix("type "))
+            })
+            .map(|event| event.trim_matches('\'').to_string())
+            .collect()
+    }
+
+    #[test]
+    fn labelled_breaks_leave_every_loop_up_to_the_label() {
+        let commands = CommandsBuilder::default()
+            .named_loop("outer", 3, |outer| {
+                outer
+                    .press(Key::O)
+                    .repeat(3, |inner| inner.press(Key::I).break_loop(Some("outer")))
+                    .press(Key::P)
+            })
+            .press(Key::E)
+            .build();
+
+        assert_eq!(sent(commands), ["O", "I", "E"]);
+    }
+
+    #[test]
+    fn unlabelled_breaks_leave_only_the_innermost_loop() {
+        let commands = CommandsBuilder::default()
+            .named_loop("outer", 2, |outer| {
+                outer
+                    .press(Key::O)
+                    .repeat(3, |inner| {
+                        inner.press(Key::I).break_loop(None).press(Key::X)
+                    })
+                    .press(Key::P)
+            })
+            .build();
+
+        assert_eq!(sent(commands), ["O", "I", "P", "O", "I", "P"]);
+    }
+
+    #[test]
+    fn continues_skip_the_rest_of_the_iteration() {
+        let commands = CommandsBuilder::default()
+            .repeat(3, |body| {
+                body.press(Key::A).continue_loop(None).press(Key::B)
+            })
+            .press(Key::E)
+            .build();
+        assert_eq!(sent(commands), ["A", "A", "A", "E"]);
+
+        // Continuing the outer loop from the inner one also ends the inner loop
+        let commands = CommandsBuilder::default()
+            .named_loop("outer", 2, |outer| {
+                outer
+                    .press(Key::O)
+                    .repeat(3, |inner| {
+                        inner
+                            .press(Key::I)
+                            .continue_loop(Some("outer"))
+                            .press(Key::X)
+                    })
+                    .press(Key::P)
+            })
+            .build();
+        assert_eq!(sent(commands), ["O", "I", "O", "I"]);
+    }
+
+    #[test]
+    fn loop_indexes_count_each_loop_separately() {
+        let commands = CommandsBuilder::default()
+            .repeat(3, |body| body.secret_text("${loop_index}${loop_index1},"))
+            .build();
+        assert_eq!(sent(commands).concat(), "01,12,23,");
+
+        // The inner loop's index hides the outer one's, which stays reachable by name
+        let commands = CommandsBuilder::default()
+            .named_loop("row", 2, |row| {
+                row.repeat(3, |column| {
+                    column.secret_text("${loop:row}${loop_index}${loop_index1},")
+                })
+                .secret_text("${loop_index};")
+            })
+            .build();
+        assert_eq!(sent(commands).concat(), "001,012,023,0;101,112,123,1;");
+    }
+
+    #[test]
+    fn coordinates_compute_with_loop_indexes() {
+        let commands = CommandsBuilder::default()
+            .named_loop("row", 2, |row| {
+                row.repeat(2, |column| {
+                    column.set_mouse_pos("100 + 50 * ${loop_index}", "200 + 30 * ${loop:row}")
+                })
+            })
+            .build();
+
+        assert_eq!(
+            recorded(commands),
+            [
+                "cursor_to 100 200",
+                "cursor_to 150 200",
+                "cursor_to 100 230",
+                "cursor_to 150 230",
+            ]
+        );
+    }
+
+    #[test]
+    fn fixed_key_states_are_put_right_before_the_macro_goes_on() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60)));
+        let backend = Arc::new(ScriptedInput::new(clock.clone()));
+        // Shift is stuck until the fix has had time to take effect
+        backend.hold(Key::LeftShift, Duration::ZERO, Duration::from_millis(100));
+        let mut context = test_context(backend, clock.clone());
+
+        let commands = CommandsBuilder::default()
+            .assert_key_state(Key::LeftShift, KeyState::Up, true)
+            .assert_key_state(Key::LeftControl, KeyState::Down, true)
+            .wait_ms(100)
+            .assert_key_state(Key::LeftShift, KeyState::Up, false)
+            .press(Key::A)
+            .build();
+        let (result, recorded) =
+            diff_run::record_for_test(clock, || run_block(&commands, &mut context));
+        result.unwrap();
+
+        assert_eq!(
+            recorded,
+            [
+                "key_up LeftShift",
+                "key_down LeftControl",
+                "key_down A",
+                "key_up A",
+            ]
+        );
+    }
+
+    #[test]
+    fn mismatched_key_states_fail_without_fix() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60)));
+        let backend = Arc::new(ScriptedInput::new(clock.clone()));
+        backend.press_at(Key::LeftShift, Duration::ZERO);
+        let mut context = test_context(backend, clock.clone());
+
+        let commands = CommandsBuilder::default()
+            .assert_key_state(Key::LeftShift, KeyState::Up, false)
+            .press(Key::A)
+            .build();
+        let (result, recorded) =
+            diff_run::record_for_test(clock, || run_block(&commands, &mut context));
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Expected LeftShift to be Up but it is not"
+        );
+        assert!(recorded.is_empty());
+    }
+
+    #[test]
+    fn altgr_goes_down_as_control_then_right_alt_and_up_in_reverse() {
+        let commands = CommandsBuilder::default()
+            .key_down(Key::AltGr, None)
+            .press(Key::Key2)
+            .key_up(Key::AltGr)
+            .key_combo([Key::E, Key::AltGr])
+            .build();
+
+        assert_eq!(
+            recorded(commands),
+            [
+                "key_down LeftControl",
+                "key_down RightMenu",
+                "key_down Key2",
+                "key_up Key2",
+                "key_up RightMenu",
+                "key_up LeftControl",
+                "key_down LeftControl",
+                "key_down RightMenu",
+                "key_down E",
+                "key_up E",
+                "key_up RightMenu",
+                "key_up LeftControl",
+            ]
+        );
+    }
+
+    fn window(title: &str, process: &str) -> window::WindowInfo {
+        window::WindowInfo {
+            title: title.to_string(),
+            class: "Window".to_string(),
+            process: process.to_string(),
+        }
+    }
+
+    #[test]
+    fn input_stops_once_focus_leaves_the_allowed_targets() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60)));
+        let screen = Arc::new(ScriptedScreen::new(clock.clone()));
+        screen
+            .focus_at(Duration::ZERO, Some(window("notes.txt", "notepad.exe")))
+            .focus_at(ms(200), Some(window("Calculator", "calc.exe")));
+        let mut context = test_context(Arc::new(SimulatedInput), clock.clone());
+        context.set_screen(screen.clone());
+        context.set_allowed_targets(Arc::new(vec![window::AllowedTarget {
+            process: Some("notepad.exe".to_string()),
+            class: None,
+            title_contains: None,
+        }]));
+
+        let (result, recorded) = diff_run::record_for_test(clock, || {
+            run_block(
+                &commands(
+                    "[!PressKey A, !Wait 50, !PressKey B, !Wait 100, !PressKey C, !Wait 200, \
+                     !PressKey D]",
+                ),
+                &mut context,
+            )
+        });
+
+        let kind = error::kind_of(&result.unwrap_err());
+        assert_eq!(kind, error::ErrorKind::SafetyViolation);
+        assert!(kind.aborts());
+        assert_eq!(
+            recorded,
+            [
+                "key_down A",
+                "key_up A",
+                "key_down B",
+                "key_up B",
+                "key_down C",
+                "key_up C",
+            ]
+        );
+        // B at 50 ms rode on the check made for A, C at 150 ms needed a check of its own
+        assert_eq!(screen.foreground_reads(), 3);
+    }
+
+    #[test]
+    fn jitter_radius_must_be_reasonable() {
+        let jittered = |jitter_px| {
+            Macro::builder("wobbly")
+                .hotkey([Key::LeftControl, Key::F3])
+                .jitter_px(jitter_px)
+                .left_click()
+                .build()
+        };
+
+        assert!(jittered(0).is_ok());
+        assert!(jittered(jitter::MAX_RADIUS_PX).is_ok());
+        for jitter_px in [-1, jitter::MAX_RADIUS_PX + 1, i32::MAX] {
+            assert_eq!(
+                jittered(jitter_px).unwrap_err().to_string(),
+                "wobbly: jitter_px must be between 0 and 500"
+            );
+        }
+    }
+
+    #[test]
+    fn jitter_delay_must_be_reasonable() {
+        let jittered = |jitter_ms| {
+            Macro::builder("sluggish")
+                .hotkey([Key::LeftControl, Key::F3])
+                .jitter_ms(jitter_ms)
+                .left_click()
+                .build()
+        };
+
+        assert!(jittered(jitter::MAX_DELAY_MS).is_ok());
+        for jitter_ms in [jitter::MAX_DELAY_MS + 1, u64::MAX] {
+            assert_eq!(
+                jittered(jitter_ms).unwrap_err().to_string(),
+                "sluggish: jitter_ms must be at most 5000"
+            );
+        }
+    }
+
+    #[test]
+    fn jitter_waits_on_the_macro_clock() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60)));
+        let mut context = test_context(Arc::new(SimulatedInput), clock.clone());
+        context.set_jitter(jitter::Jitter::new(100, i32::MAX));
+
+        for _ in 0..20 {
+            context.jitter_delay();
+        }
+        let waited = clock.elapsed_total();
+        assert!(waited > Duration::ZERO && waited <= Duration::from_secs(2));
+
+        // Offsets past the edge of the i32 range stop at the edge instead of overflowing
+        for _ in 0..20 {
+            context.jitter_point(i32::MAX, i32::MIN);
+        }
+    }
+
+    /// Validates a config with a macro `a` running `commands` and a macro `b` with `b_commands`.
+    fn validate(commands: &str, b_commands: &str) -> Result<(), anyhow::Error> {
+        let yaml = format!(
+            "program_hotkey: [LeftShift, F6]\n\
+             macros:\n\
+             - macro_name: a\n\
+             \x20 macro_hotkey: [LeftControl, A]\n\
+             \x20 params: [name]\n\
+             \x20 commands: {}\n\
+             - macro_name: b\n\
+             \x20 macro_hotkey: [LeftControl, B]\n\
+             \x20 commands: {}\n",
+            commands, b_commands
+        );
+        let macro_config: MacroConfig = serde_yaml::from_str(&yaml).unwrap();
+        macro_config.validate()
+    }
+
+    fn variable_error(commands: &str) -> String {
+        validate(commands, "[]").unwrap_err().to_string()
+    }
+
+    #[test]
+    fn variables_that_can_be_set_are_accepted() {
+        validate(
+            r#"[!TextInput "${name} ${screen_width} ${monitor2_left} ${env:USERNAME} ${trigger}"]"#,
+            "[]",
+        )
+        .unwrap();
+        validate(
+            "[!GetWindowTitle {into: title}, !IfVarMatches {var: title, regex: '${name}', \
+             then: [], else: []}]",
+            "[]",
+        )
+        .unwrap();
+        validate(
+            r#"[!NamedLoop {name: outer, iterations: 2, commands: [!Loop [2, [!TextInput "${loop:outer} ${loop_index1}"]]]}]"#,
+            "[]",
+        )
+        .unwrap();
+        validate(
+            "[!Run {program: tool.exe, capture_output: true}, !TextInputSecret {text: \
+             '${output}${cred:site}'}]",
+            "[]",
+        )
+        .unwrap();
+        // Set by a command in another macro, which could have called this one
+        validate(
+            "[!TextInput '${window_x}']",
+            "[!StoreWindowOrigin {title: App}]",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn escaped_variables_are_not_checked() {
+        validate("[!TextInput '$${nope}']", "[]").unwrap();
+    }
+
+    #[test]
+    fn unknown_variables_fail_validation() {
+        assert_eq!(
+            variable_error("[!TextInput '${nmae}']"),
+            "a: unknown variable \"nmae\""
+        );
+        assert_eq!(
+            variable_error("[!IfVarMatches {var: title, regex: x, then: [], else: []}]"),
+            "a: unknown variable \"title\""
+        );
+        assert_eq!(
+            variable_error("[!Pause {message: '${monitor0_left}'}]"),
+            "a: unknown variable \"monitor0_left\""
+        );
+        assert_eq!(
+            variable_error("[!SetMousePos ['${cursor_z}', 0]]"),
+            "a: unknown variable \"cursor_z\""
+        );
+        assert_eq!(
+            variable_error(r#"[!Run {program: tool.exe, args: ["${output}"]}]"#),
+            "a: unknown variable \"output\""
+        );
+    }
+
+    #[test]
+    fn loop_variables_need_a_loop() {
+        assert_eq!(
+            variable_error("[!TextInput '${loop_index}']"),
+            "a: unknown variable \"loop_index\""
+        );
+        assert_eq!(
+            variable_error("[!Loop [2, [!TextInput '${loop:outer}']]]"),
+            "a: unknown variable \"loop:outer\""
+        );
+        // A called macro may use the loops around the call
+        validate(
+            "[!TextInput '${loop_index} ${loop:outer}']",
+            "[!NamedLoop {name: outer, iterations: 2, commands: [!CallMacro {name: a, args: \
+             {name: x}}]}]",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn credentials_only_where_they_are_filled_in() {
+        assert_eq!(
+            variable_error("[!TextInput '${cred:site}']"),
+            "a: credential site can only be used in TextInputSecret, ReplaceText and Run env"
+        );
+        validate(
+            "[!Run {program: tool.exe, env: {PASSWORD: '${cred:site}'}}, !ReplaceText {text: \
+             '${cred:site}'}]",
+            "[]",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn unterminated_variables_fail_validation() {
+        assert!(
+            variable_error("[!TextInput 'hello ${name']").starts_with("a: Unterminated variable")
+        );
+    }
+
+    /// The estimate of running `yaml`'s commands in a row.
+    fn estimate(yaml: &str) -> DurationEstimate {
+        commands(yaml)
+            .iter()
+            .fold(DurationEstimate::zero(), |total, command| {
+                total + command.estimated_duration()
+            })
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn nested_loops_multiply_out() {
+        assert_eq!(
+            estimate("[!Loop [3, [!Wait 100, !Loop [2, [!Wait 50, !LeftClick]]]]]"),
+            DurationEstimate::Exact(ms(3 * (100 + 2 * (50 + 1))))
+        );
+        assert_eq!(
+            estimate(
+                "[!NamedLoop {name: outer, iterations: 2, commands: [!Loop [5, [!Wait 1s]], \
+                 !TextInput abc]}]"
+            ),
+            DurationEstimate::Exact(ms(2 * (5 * 1000 + 3)))
+        );
+        assert_eq!(
+            estimate("[!Loop [4, [!Loop [0, [!Wait 10]]]]]"),
+            DurationEstimate::Unbounded
+        );
+    }
+
+    #[test]
+    fn mixed_sequences_add_up_to_ranges_or_unbounded() {
+        assert_eq!(
+            estimate("[!Wait 100, !WaitForFile {path: out.csv, timeout_ms: 1s}, !PressKey A]"),
+            DurationEstimate::Range {
+                min: ms(101),
+                max: ms(1101)
+            }
+        );
+        assert_eq!(
+            estimate(
+                "[!Loop [2, [!WaitForFile {path: out.csv, timeout_ms: 1s}, !Wait 10]], \
+                 !IfKeyHeld {key: Shift, then: [!Wait 500], else: []}]"
+            ),
+            DurationEstimate::Range {
+                min: ms(20),
+                max: ms(2 * 1010 + 500)
+            }
+        );
+        assert_eq!(
+            estimate("[!Wait 100, !Loop [3, [!Pause {}]], !Wait 100]"),
+            DurationEstimate::Unbounded
+        );
+        assert_eq!(
+            estimate("[!Loop [0, [!Wait 100]], !WaitForFile {path: out.csv, timeout_ms: 1s}]"),
+            DurationEstimate::Unbounded
+        );
+        assert_eq!(estimate("[]"), DurationEstimate::zero());
+    }
+
+    #[test]
+    fn long_waits_cancelled_midway_end_at_once() {
+        // The clock cancels whatever sleeps past its limit
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(3 * 60)));
+        let backend = Arc::new(ScriptedInput::new(clock.clone()));
+        let mut context = test_context(backend, clock.clone());
+        let started = Instant::now();
+
+        let (result, recorded) = diff_run::record_for_test(clock.clone(), || {
+            run_block(&commands("[!Wait 10m, !PressKey F12]"), &mut context)
+        });
+
+        assert_eq!(
+            error::kind_of(&result.unwrap_err()),
+            error::ErrorKind::Cancelled
+        );
+        assert_eq!(clock.elapsed_total(), Duration::from_secs(3 * 60));
+        assert!(recorded.is_empty());
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn waits_time_out_on_the_clock() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60 * 60)));
+        let backend = Arc::new(ScriptedInput::new(clock.clone()));
+        let mut context = test_context(backend, clock.clone());
+        let path = std::env::temp_dir().join("input_macro_runner_never_written");
+        let started = Instant::now();
+
+        let result = run_block(
+            &commands(&format!(
+                "[!WaitForFile {{path: {:?}, timeout_ms: 10m}}]",
+                path.display().to_string()
+            )),
+            &mut context,
+        );
+
+        assert_eq!(
+            error::kind_of(&result.unwrap_err()),
+            error::ErrorKind::Timeout
+        );
+        assert_eq!(clock.elapsed_total(), Duration::from_secs(10 * 60));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    /// Runs `command` capturing its output, with time read off `clock`.
+    fn run_captured(
+        command: &str,
+        clock: Arc<dyn clock::Clock>,
+    ) -> (Result<Flow, anyhow::Error>, context::ExecutionContext) {
+        let backend = Arc::new(SimulatedInput);
+        let mut context = test_context(backend, clock);
+        let result = run_block(&commands(&format!("[{}]", command)), &mut context);
+        (result, context)
+    }
+
+    #[test]
+    fn run_captures_the_output() {
+        let (result, context) = run_captured(
+            "!Run {program: cmd, args: [/C, echo captured], capture_output: true}",
+            Arc::new(clock::SystemClock),
+        );
+
+        result.unwrap();
+        assert_eq!(context.variable("output").as_deref(), Some("captured"));
+    }
+
+    #[test]
+    fn run_kills_the_program_when_cancelled_or_timed_out() {
+        // Pinging for half a minute, unless stopped
+        let ping = "!Run {program: ping, args: [-n, '30', 127.0.0.1], capture_output: true";
+        let started = Instant::now();
+
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(1)));
+        let (result, _) = run_captured(&format!("{}}}", ping), clock.clone());
+        assert_eq!(
+            error::kind_of(&result.unwrap_err()),
+            error::ErrorKind::Cancelled
+        );
+        assert_eq!(clock.elapsed_total(), Duration::from_secs(1));
+
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60)));
+        let (result, _) = run_captured(&format!("{}, timeout_ms: 500ms}}", ping), clock.clone());
+        assert_eq!(
+            error::kind_of(&result.unwrap_err()),
+            error::ErrorKind::Timeout
+        );
+        assert_eq!(clock.elapsed_total(), Duration::from_millis(500));
+
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    /// A context reading the CPU usage of processes off `processes` and time off `clock`.
+    fn process_context(
+        clock: Arc<VirtualClock>,
+        processes: Arc<process::ScriptedProcesses>,
+    ) -> context::ExecutionContext {
+        let backend = Arc::new(ScriptedInput::new(clock.clone()));
+        let mut context = test_context(backend, clock);
+        context.set_process_metrics(processes);
+        context
+    }
+
+    const WAIT_FOR_IDLE: &str =
+        "[!WaitForProcessIdle {process: export.exe, cpu_below_percent: 10, \
+                                 sustained_ms: 2s, timeout_ms: 1m}]";
+
+    #[test]
+    fn process_idle_waits_for_the_processes_together_to_stay_quiet() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60 * 60)));
+        let processes = Arc::new(process::ScriptedProcesses::new(clock.clone()));
+        processes
+            .uses(1, Duration::ZERO, 30.0)
+            .uses(2, Duration::ZERO, 30.0)
+            .uses(1, Duration::from_secs(3), 4.0)
+            .uses(2, Duration::from_secs(3), 7.0)
+            // Below the threshold on its own, but not together with the other
+            .uses(2, Duration::from_secs(4), 4.0);
+        let mut context = process_context(clock.clone(), processes.clone());
+
+        run_block(&commands(WAIT_FOR_IDLE), &mut context).unwrap();
+
+        // Quiet together from the reading at 4.5 s, which covers 4 s to 4.5 s
+        assert_eq!(clock.elapsed_total(), Duration::from_millis(4500 + 2000));
+        // Read every interval from the start
+        let reads = processes.reads();
+        assert_eq!(reads.len(), 6500 / 500 + 1);
+        assert!(reads
+            .iter()
+            .enumerate()
+            .all(|(index, read)| *read == CPU_SAMPLE_INTERVAL * index as u32));
+    }
+
+    #[test]
+    fn busy_processes_time_out() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60 * 60)));
+        let processes = Arc::new(process::ScriptedProcesses::new(clock.clone()));
+        processes
+            .uses(1, Duration::ZERO, 50.0)
+            // Quiet, but not for long enough before the timeout
+            .uses(1, Duration::from_secs(59), 1.0);
+        let mut context = process_context(clock.clone(), processes);
+
+        let e = run_block(&commands(WAIT_FOR_IDLE), &mut context).unwrap_err();
+
+        assert_eq!(error::kind_of(&e), error::ErrorKind::Timeout);
+        assert_eq!(clock.elapsed_total(), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn process_idle_waits_end_when_cancelled() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(3)));
+        let processes = Arc::new(process::ScriptedProcesses::new(clock.clone()));
+        processes.uses(1, Duration::ZERO, 50.0);
+        let mut context = process_context(clock.clone(), processes.clone());
+
+        let e = run_block(&commands(WAIT_FOR_IDLE), &mut context).unwrap_err();
+
+        assert_eq!(error::kind_of(&e), error::ErrorKind::Cancelled);
+        assert_eq!(clock.elapsed_total(), Duration::from_secs(3));
+        // Read last as the clock reached the limit, not again once cancelled
+        assert_eq!(processes.reads().last(), Some(&Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn process_idle_waits_fail_without_the_process() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60 * 60)));
+        let processes = Arc::new(process::ScriptedProcesses::new(clock.clone()));
+        processes.uses(1, Duration::from_secs(5), 0.0);
+        let mut context = process_context(clock.clone(), processes);
+
+        let e = run_block(&commands(WAIT_FOR_IDLE), &mut context).unwrap_err();
+
+        assert_eq!(e.to_string(), "No process named export.exe is running");
+        assert_eq!(clock.elapsed_total(), Duration::ZERO);
+    }
+
+    /// A context on a 1920x1080 screen with the pixel at (100, 200) painted `paints`, each at a
+    /// time since the start.
+    fn pixel_context(
+        clock: Arc<VirtualClock>,
+        paints: &[(u64, &str)],
+    ) -> context::ExecutionContext {
+        let screen = MemoryScreen::sized(clock.clone(), 1920, 1080);
+        for (at, color) in paints {
+            screen.paint_at(ms(*at), 100, 200, color.parse().unwrap());
+        }
+        let mut context = test_context(Arc::new(SimulatedInput), clock);
+        context.set_screen(Arc::new(screen));
+        context
+    }
+
+    #[test]
+    fn pixel_waits_end_once_the_color_is_close_enough() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60)));
+        let mut context = pixel_context(
+            clock.clone(),
+            &[(100, "#000080"), (300, "#3A8FBA"), (400, "#3C8DBC")],
+        );
+
+        run_block(
+            &commands(
+                "[!WaitForPixel {x: 100, y: 200, color: '#3C8DBC', tolerance: 2, timeout_ms: 1s}]",
+            ),
+            &mut context,
+        )
+        .unwrap();
+        assert_eq!(clock.elapsed_total(), ms(300));
+    }
+
+    #[test]
+    fn pixel_waits_time_out_on_colors_just_out_of_tolerance() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60)));
+        let mut context = pixel_context(clock.clone(), &[(100, "#3A8FBA")]);
+
+        let e = run_block(
+            &commands(
+                "[!WaitForPixel {x: 100, y: 200, color: '#3C8DBC', tolerance: 1, timeout_ms: 1s}]",
+            ),
+            &mut context,
+        )
+        .unwrap_err();
+
+        assert_eq!(error::kind_of(&e), error::ErrorKind::Timeout);
+        assert!(e.to_string().contains("(100, 200) to be #3C8DBC"), "{}", e);
+        assert_eq!(clock.elapsed_total(), ms(1000));
+    }
+
+    #[test]
+    fn pixel_waits_off_screen_fail_right_away() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60)));
+        let mut context = pixel_context(clock.clone(), &[]);
+
+        let e = run_block(
+            &commands("[!WaitForPixel {x: 1920, y: 200, color: '#000000', timeout_ms: 1s}]"),
+            &mut context,
+        )
+        .unwrap_err();
+
+        assert_eq!(error::kind_of(&e), error::ErrorKind::Validation);
+        assert_eq!(clock.elapsed_total(), Duration::ZERO);
+    }
+
+    /// A `RetryBlock` of `attempts` around `inner`, or around a wait for a file that never turns
+    /// up, which fails after 1 s.
+    fn retry_block(attempts: u32, inner: Option<&str>) -> String {
+        let path = std::env::temp_dir().join("input_macro_runner_never_written");
+        let never = format!(
+            "!WaitForFile {{path: {:?}, timeout_ms: 1s}}",
+            path.display().to_string()
+        );
+        format!(
+            "!RetryBlock {{attempts: {}, commands: [{}]}}",
+            attempts,
+            inner.unwrap_or(&never)
+        )
+    }
+
+    #[test]
+    fn nested_retries_without_a_budget_multiply_out() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60 * 60)));
+        let backend = Arc::new(ScriptedInput::new(clock.clone()));
+        let mut context = test_context(backend, clock.clone());
+
+        let inner = retry_block(3, None);
+        let result = run_block(
+            &commands(&format!("[{}]", retry_block(3, Some(&inner)))),
+            &mut context,
+        );
+
+        assert_eq!(
+            error::kind_of(&result.unwrap_err()),
+            error::ErrorKind::Timeout
+        );
+        // 3 runs of the inner block, each of 3 attempts
+        assert_eq!(clock.elapsed_total(), Duration::from_secs(9));
+        assert_eq!(context.retries(), 2 + 1 + 2 + 1 + 2);
+        assert!(!context.retry_budget_exhausted());
+    }
+
+    #[test]
+    fn nested_retries_share_the_budget() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60 * 60)));
+        let backend = Arc::new(ScriptedInput::new(clock.clone()));
+        let mut context = test_context(backend, clock.clone());
+        context.set_retry_budget(Some(3));
+
+        let inner = retry_block(3, None);
+        let result = run_block(
+            &commands(&format!("[{}]", retry_block(3, Some(&inner)))),
+            &mut context,
+        );
+
+        assert_eq!(
+            error::kind_of(&result.unwrap_err()),
+            error::ErrorKind::Timeout
+        );
+        // Two inner retries and one outer, then the second inner run fails without retrying
+        assert_eq!(clock.elapsed_total(), Duration::from_secs(3 + 1));
+        assert_eq!(context.retries(), 3);
+        assert!(context.retry_budget_exhausted());
+    }
+
+    #[test]
+    fn a_used_up_budget_stops_later_blocks_retrying() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60 * 60)));
+        let backend = Arc::new(ScriptedInput::new(clock.clone()));
+        let mut context = test_context(backend, clock.clone());
+        context.set_retry_budget(Some(2));
+
+        assert!(run_block(
+            &commands(&format!("[{}]", retry_block(3, None))),
+            &mut context
+        )
+        .is_err());
+        assert_eq!(clock.elapsed_total(), Duration::from_secs(3));
+        assert!(context.retry_budget_exhausted());
+
+        assert!(run_block(
+            &commands(&format!("[{}]", retry_block(5, None))),
+            &mut context
+        )
+        .is_err());
+        assert_eq!(clock.elapsed_total(), Duration::from_secs(3 + 1));
+        assert_eq!(context.retries(), 2);
+    }
+
+    #[test]
+    fn retries_that_end_in_success_still_count_against_the_budget() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60 * 60)));
+        let backend = Arc::new(ScriptedInput::new(clock.clone()));
+        backend.press_at(Key::LeftControl, Duration::from_millis(2500));
+        let mut context = test_context(backend, clock.clone());
+        context.set_retry_budget(Some(5));
+
+        // The key is down by the third attempt
+        run_block(
+            &commands(
+                "[!RetryBlock {attempts: 5, commands: [!Wait 1s, \
+                 !AssertKeyState {key: LeftControl, state: Down}]}]",
+            ),
+            &mut context,
+        )
+        .unwrap();
+
+        assert_eq!(clock.elapsed_total(), Duration::from_secs(3));
+        assert_eq!(context.retries(), 2);
+        assert!(!context.retry_budget_exhausted());
+    }
+
+    /// One command in each shape the serializer abbreviates, nests or names.
+    const COMMAND_SHAPES: &str = r#"
+- !PressKeyCombo Ctrl+Shift+K
+- !PressKeyCombo [S, LeftWindows, LeftMenu]
+- !SetMousePos [100, "50% + 8"]
+- !Wait 90000
+- !Wait 1.5s
+- !Loop
+  - 2
+  - - !PressKey Tab
+    - !Break
+- !NamedLoop {name: rows, iterations: 3, commands: [!TextInput "row ${loop:rows}", !Wait 250ms]}
+- !HoldKey {key: Space, duration_ms: 1h2m3.04s}
+- !IfKeyHeld
+  key: LeftShift
+  then:
+    - !LeftClick
+- !Media play_pause
+"#;
+
+    #[test]
+    fn commands_round_trip_through_the_serializer() {
+        let parsed = commands(COMMAND_SHAPES);
+        let written = serde_yaml::to_string(&parsed).unwrap();
+        let reparsed = commands(&written);
+
+        assert_eq!(reparsed, parsed);
+        assert_eq!(serde_yaml::to_string(&reparsed).unwrap(), written);
+    }
+
+    #[test]
+    fn commands_serialize_in_canonical_form() {
+        assert_eq!(
+            serde_yaml::to_string(&commands(COMMAND_SHAPES)).unwrap(),
+            "\
+- !PressKeyCombo Control+Shift+K
+- !PressKeyCombo LeftMenu+LeftWindows+S
+- !SetMousePos
+  - 100
+  - 50% + 8
+- !Wait 1m30s
+- !Wait 1.5s
+- !Loop
+  - 2
+  - - !PressKey Tab
+    - !Break null
+- !NamedLoop
+  name: rows
+  iterations: 3
+  commands:
+  - !TextInput row ${loop:rows}
+  - !Wait 250ms
+- !HoldKey
+  key: Space
+  duration_ms: 1h2m3.04s
+  repeat: null
+- !IfKeyHeld
+  key: LeftShift
+  then:
+  - LeftClick
+  else: []
+- !Media play_pause
+"
+        );
+    }
+
+    #[test]
+    fn configs_round_trip_through_the_serializer() {
+        let parsed: MacroConfig = serde_yaml::from_str(
+            "program_hotkey: [LeftShift, F6]\n\
+             macros:\n\
+             - {macro_name: zeta, macro_hotkey: Ctrl+Alt+Z, cooldown_ms: 1.5s, commands: \
+             [!PressKeyCombo [LeftControl, C], !Wait 2m]}\n\
+             - {macro_name: alpha, macro_hotkey: [F8, LeftControl], modifier_grace_ms: 250, \
+             commands: [!HoldKey {key: A, duration_ms: 90s}]}\n",
+        )
+        .unwrap();
+        let written = serde_yaml::to_string(&parsed).unwrap();
+        let reparsed: MacroConfig = serde_yaml::from_str(&written).unwrap();
+
+        assert_eq!(serde_yaml::to_string(&reparsed).unwrap(), written);
+        assert_eq!(reparsed.program_hotkey, parsed.program_hotkey);
+        for (current_macro, original) in reparsed.macros.iter().zip(parsed.macros.iter()) {
+            assert_eq!(current_macro.macro_name, original.macro_name);
+            assert_eq!(current_macro.macro_hotkey, original.macro_hotkey);
+            assert_eq!(current_macro.cooldown_ms, original.cooldown_ms);
+            assert_eq!(current_macro.modifier_grace_ms, original.modifier_grace_ms);
+            assert_eq!(current_macro.commands, original.commands);
+        }
+
+        // Written the compact way, in the order the file had them
+        assert!(written.contains("program_hotkey: LeftShift+F6\n"));
+        let zeta = written.find("macro_name: zeta").unwrap();
+        let alpha = written.find("macro_name: alpha").unwrap();
+        assert!(zeta < alpha);
+        assert!(written.contains("macro_hotkey: Control+Menu+Z\n"));
+        assert!(written.contains("cooldown_ms: 1.5s\n"));
+        assert!(written.contains("modifier_grace_ms: 250ms\n"));
+        assert!(written.contains("duration_ms: 1m30s\n"));
+    }
+}
@@ -0,0 +1,397 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::PathBuf,
+    time::Duration,
+};
+
+use serde_yaml::Value;
+
+use super::{backup, duration::DurationMs, estimate::DurationEstimate, Command, Key, MacroConfig};
+
+/// Waits longer than this are more likely a typo, e.g. seconds written as milliseconds, than
+/// meant.
+const LONG_WAIT: Duration = Duration::from_secs(5 * 60);
+
+/// An endless loop whose body takes less than this spins the CPU and floods the target with
+/// input.
+const MIN_ENDLESS_ITERATION: Duration = Duration::from_millis(10);
+
+/// The `Wait` the fix for `busy-loop` appends to the loop body.
+const BUSY_LOOP_WAIT: DurationMs = DurationMs(50);
+
+/// `TextInput` text at least this long may be taken for a password.
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// Shannon entropy, in bits per character, above which text is taken for a password rather than
+/// a word.
+const MIN_PASSWORD_ENTROPY: f64 = 3.0;
+
+/// A rewrite of the command a finding is about.
+#[derive(Debug, Clone)]
+enum Fix {
+    Remove,
+    Replace(Command),
+    /// Add a command to the end of the command's body, e.g. a loop's.
+    AppendToBody(Command),
+}
+
+impl Fix {
+    fn describe(&self) -> String {
+        match self {
+            Fix::Remove => "remove it".to_string(),
+            Fix::Replace(command) => format!("replace it with {}", to_inline_yaml(command)),
+            Fix::AppendToBody(command) => format!("append {} to its body", to_inline_yaml(command)),
+        }
+    }
+}
+
+/// One smell found in a macro.
+#[derive(Debug, Clone)]
+struct Finding {
+    rule: &'static str,
+    macro_name: String,
+    /// Zero-based position of the command, through the nested command lists, numbered as in
+    /// `validate_key_combos`. Empty for findings about the macro as a whole.
+    path: Vec<usize>,
+    message: String,
+    fix: Option<Fix>,
+}
+
+impl Finding {
+    fn position(&self) -> String {
+        self.path
+            .iter()
+            .map(|index| (index + 1).to_string())
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+}
+
+/// A command as it would appear in the config, on one line.
+pub fn to_inline_yaml(command: &Command) -> String {
+    serde_yaml::to_string(command)
+        .map(|yaml| yaml.trim().replace('\n', " "))
+        .unwrap_or_else(|_| format!("{:?}", command))
+}
+
+/// Whether `text` looks like a password: long, without spaces or variables, mixing at least three
+/// kinds of characters, and with high entropy per character.
+fn looks_like_password(text: &str) -> bool {
+    let length = text.chars().count();
+    if length < MIN_PASSWORD_LENGTH || text.contains(char::is_whitespace) || text.contains("${") {
+        return false;
+    }
+
+    let kinds = [
+        text.chars().any(|c| c.is_lowercase()),
+        text.chars().any(|c| c.is_uppercase()),
+        text.chars().any(|c| c.is_numeric()),
+        text.chars().any(|c| !c.is_alphanumeric()),
+    ];
+    if kinds.iter().filter(|kind| **kind).count() < 3 {
+        return false;
+    }
+
+    let mut counts = BTreeMap::new();
+    for c in text.chars() {
+        *counts.entry(c).or_insert(0usize) += 1;
+    }
+    let entropy: f64 = counts
+        .values()
+        .map(|count| {
+            let p = *count as f64 / length as f64;
+            -p * p.log2()
+        })
+        .sum();
+
+    entropy >= MIN_PASSWORD_ENTROPY
+}
+
+/// Whether one run of `commands` takes long enough for an endless loop around them not to spin.
+/// Bodies that may wait indefinitely, e.g. on a `Pause`, count as taking long enough.
+fn takes_time(commands: &[Command]) -> bool {
+    commands
+        .iter()
+        .fold(DurationEstimate::zero(), |total, command| {
+            total + command.estimated_duration()
+        })
+        .min()
+        .is_none_or(|min| min >= MIN_ENDLESS_ITERATION)
+}
+
+/// Checks `commands`, whose first command is at `first_index` of the list its parent exposes, see
+/// `Command::nested_commands`. `held` holds the keys the enclosing `WithKeysHeld` blocks hold.
+fn lint_commands(
+    macro_name: &str,
+    commands: &[Command],
+    first_index: usize,
+    path: &mut Vec<usize>,
+    held: &mut Vec<Key>,
+    findings: &mut Vec<Finding>,
+) {
+    for (offset, command) in commands.iter().enumerate() {
+        path.push(first_index + offset);
+        let mut finding = |rule, message: String, fix| {
+            findings.push(Finding {
+                rule,
+                macro_name: macro_name.to_string(),
+                path: path.clone(),
+                message,
+                fix,
+            })
+        };
+
+        match command {
+            Command::Wait(wait) if wait.as_duration() > LONG_WAIT => finding(
+                "long-wait",
+                format!("Wait of {} is longer than 5 minutes", wait),
+                None,
+            ),
+            Command::Loop(0, body)
+            | Command::NamedLoop {
+                iterations: 0,
+                commands: body,
+                ..
+            } if !takes_time(body) => finding(
+                "busy-loop",
+                "endless loop without anything that waits, it spins as fast as it can".to_string(),
+                Some(Fix::AppendToBody(Command::Wait(BUSY_LOOP_WAIT))),
+            ),
+            Command::TextInput(text) if looks_like_password(text) => finding(
+                "plaintext-secret",
+                "TextInput text looks like a password, keep it out of the config with \
+                 TextInputSecret or TextInputCredential"
+                    .to_string(),
+                None,
+            ),
+            Command::PressKeyCombo(keys) if held.iter().any(|key| keys.contains(key)) => {
+                let remaining: HashSet<Key> = keys
+                    .iter()
+                    .filter(|key| !held.contains(key))
+                    .copied()
+                    .collect();
+                let fix = match remaining.len() {
+                    0 => None,
+                    1 => remaining.into_iter().next().map(Command::PressKey),
+                    _ => Some(Command::PressKeyCombo(remaining)),
+                };
+                finding(
+                    "combo-holds-held-key",
+                    "PressKeyCombo presses a key an enclosing WithKeysHeld already holds, and \
+                     releases it early"
+                        .to_string(),
+                    fix.map(Fix::Replace),
+                )
+            }
+            _ => {}
+        }
+
+        if let (Command::SetMousePos(_, _), Some(Command::SetMousePos(_, _))) =
+            (command, commands.get(offset + 1))
+        {
+            finding(
+                "redundant-mouse-move",
+                "SetMousePos is immediately followed by another SetMousePos".to_string(),
+                Some(Fix::Remove),
+            );
+        }
+
+        let held_before = held.len();
+        if let Command::WithKeysHeld { keys, .. } = command {
+            held.extend(keys.iter().copied());
+        }
+
+        let mut nested_first_index = 0;
+        for nested in command.nested_commands() {
+            lint_commands(macro_name, nested, nested_first_index, path, held, findings);
+            nested_first_index += nested.len();
+        }
+
+        held.truncate(held_before);
+        path.pop();
+    }
+}
+
+/// Applies `fix` to the command at `index`, then `rest`, of `commands`. Returns whether the
+/// command was found.
+fn apply_fix(commands: &mut Vec<Command>, index: usize, rest: &[usize], fix: &Fix) -> bool {
+    if let Some((next, rest)) = rest.split_first() {
+        let mut next = *next;
+        let nested_lists = match commands.get_mut(index) {
+            Some(command) => command.nested_commands_mut(),
+            None => return false,
+        };
+        for nested in nested_lists {
+            if next < nested.len() {
+                return apply_fix(nested, next, rest, fix);
+            }
+            next -= nested.len();
+        }
+        return false;
+    }
+
+    if index >= commands.len() {
+        return false;
+    }
+
+    match fix {
+        Fix::Remove => {
+            commands.remove(index);
+        }
+        Fix::Replace(command) => commands[index] = command.clone(),
+        Fix::AppendToBody(command) => {
+            match commands[index].nested_commands_mut().into_iter().next() {
+                Some(body) => body.push(command.clone()),
+                None => return false,
+            }
+        }
+    }
+
+    true
+}
+
+/// Applies the fixes of `findings`, all about macros of the config file at `path`, to that file.
+/// Returns how many were applied.
+fn fix_file(
+    path: &PathBuf,
+    findings: &[&Finding],
+    config: &backup::BackupConfig,
+) -> Result<usize, anyhow::Error> {
+    let config_string = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read config {}: {}", path.display(), e))?;
+    let mut config_value: Value = serde_yaml::from_str(&config_string)
+        .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+
+    let mut fixed = 0;
+    let macros = config_value
+        .get_mut("macros")
+        .and_then(Value::as_sequence_mut);
+
+    for macro_value in macros.into_iter().flatten() {
+        let macro_name = match macro_value.get("macro_name").and_then(Value::as_str) {
+            Some(macro_name) => macro_name.to_string(),
+            None => continue,
+        };
+        let commands_value = match macro_value.get_mut("commands") {
+            Some(commands_value) => commands_value,
+            None => continue,
+        };
+
+        let mut macro_findings: Vec<&&Finding> = findings
+            .iter()
+            .filter(|finding| finding.macro_name == macro_name)
+            .collect();
+        if macro_findings.is_empty() {
+            continue;
+        }
+        // Last first, so that removing a command does not move the ones still to be fixed
+        macro_findings.sort_by(|a, b| b.path.cmp(&a.path));
+
+        let mut commands: Vec<Command> = serde_yaml::from_value(commands_value.clone())
+            .map_err(|e| anyhow::anyhow!("{}: {}: {}", path.display(), macro_name, e))?;
+        for finding in macro_findings {
+            let applied = match (finding.path.split_first(), &finding.fix) {
+                (Some((index, rest)), Some(fix)) => apply_fix(&mut commands, *index, rest, fix),
+                _ => false,
+            };
+            if applied {
+                fixed += 1;
+            }
+        }
+        *commands_value = serde_yaml::to_value(&commands)?;
+    }
+
+    if fixed > 0 {
+        backup::write_config(path, &serde_yaml::to_string(&config_value)?, config)?;
+    }
+
+    Ok(fixed)
+}
+
+/// Prints the smells found in the macros of `macro_config`: legal but likely unintended configs
+/// that `validate` lets through. With `fix`, rewrites the config files to apply every suggested
+/// fix. The files are rewritten from their parsed form, so comments and formatting are not kept,
+/// though the previous version is backed up.
+pub fn lint(macro_config: &MacroConfig, fix: bool) -> Result<(), anyhow::Error> {
+    let mut findings = Vec::new();
+
+    for current_macro in macro_config.macros.iter() {
+        if current_macro.commands.is_empty() {
+            findings.push(Finding {
+                rule: "empty-macro",
+                macro_name: current_macro.macro_name.clone(),
+                path: Vec::new(),
+                message: "macro has no commands".to_string(),
+                fix: None,
+            });
+        }
+
+        lint_commands(
+            &current_macro.macro_name,
+            &current_macro.commands,
+            0,
+            &mut Vec::new(),
+            &mut Vec::new(),
+            &mut findings,
+        );
+    }
+
+    for finding in findings.iter() {
+        let site = if finding.path.is_empty() {
+            finding.macro_name.clone()
+        } else {
+            format!("{}: command {}", finding.macro_name, finding.position())
+        };
+        println!("{}: [{}] {}", site, finding.rule, finding.message);
+        if let Some(fix) = &finding.fix {
+            println!("\tfix: {}", fix.describe());
+        }
+    }
+
+    if findings.is_empty() {
+        println!("No findings");
+        return Ok(());
+    }
+
+    let fixable: Vec<&Finding> = findings
+        .iter()
+        .filter(|finding| finding.fix.is_some())
+        .collect();
+    println!(
+        "{} finding(s), {} with a fix{}",
+        findings.len(),
+        fixable.len(),
+        if fix || fixable.is_empty() {
+            ""
+        } else {
+            ", apply them with lint --fix"
+        }
+    );
+
+    if !fix {
+        return Ok(());
+    }
+
+    let mut by_file: BTreeMap<PathBuf, Vec<&Finding>> = BTreeMap::new();
+    for finding in fixable {
+        let source = macro_config
+            .macros
+            .iter()
+            .find(|current_macro| current_macro.macro_name == finding.macro_name)
+            .and_then(|current_macro| current_macro.source.clone());
+        match source {
+            Some(source) if source.exists() => by_file.entry(source).or_default().push(finding),
+            _ => println!(
+                "{}: not fixing the built-in config, copy it to a file first",
+                finding.macro_name
+            ),
+        }
+    }
+
+    for (path, findings) in by_file.iter() {
+        let fixed = fix_file(path, findings, &macro_config.backup)?;
+        println!("Applied {} fix(es) to {}", fixed, path.display());
+    }
+
+    Ok(())
+}
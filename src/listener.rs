@@ -0,0 +1,1191 @@
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::mpsc::Receiver,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use super::{
+    backend::{InputBackend, ScreenBackend},
+    clock::Clock,
+    events::TriggerSource,
+    executor::Executor,
+    http::{MacroStatus, PressMatch, TriggerOutcome},
+    idle::IdleTracker,
+    injected,
+    keys::format_keys,
+    mouse_hook::MouseHook,
+    profile::{self, ProfileSwitcher},
+    session, window, CooldownFrom, Key, Macro, MacroMode, Message, OnLock, TriggerOn,
+    CONFIRMATION_WINDOW,
+};
+
+/// Snapshot of which hotkey keys are held, taken once per poll. Presses and releases are derived
+/// from the difference between consecutive snapshots, so every macro sees the same edges no matter
+/// how many of them share a key.
+pub struct KeyStateTracker {
+    /// The scan set: every key that appears in the hotkey of an enabled macro, and nothing else.
+    keys: HashSet<Key>,
+    held: HashSet<Key>,
+    previously_held: HashSet<Key>,
+    /// The keys of `held` that a macro put down, see `injected::is_injected`.
+    injected: HashSet<Key>,
+    previously_injected: HashSet<Key>,
+    /// Names of the macros whose `mouse_trigger` fired since the previous poll.
+    mouse_fired: HashSet<String>,
+}
+
+impl KeyStateTracker {
+    /// A tracker for the hotkeys of the enabled macros, and with `with_disabled` of the disabled
+    /// ones too, so that tracing can tell when one of those is pressed.
+    pub fn new(macros: &[Macro], with_disabled: bool) -> Self {
+        let keys: HashSet<Key> = macros
+            .iter()
+            .filter(|current_macro| with_disabled || current_macro.enabled)
+            .flat_map(|current_macro| current_macro.macro_hotkey.iter().copied())
+            .collect();
+        log::debug!("Polling {} trigger keys", keys.len());
+
+        KeyStateTracker {
+            keys,
+            held: HashSet::new(),
+            previously_held: HashSet::new(),
+            injected: HashSet::new(),
+            previously_injected: HashSet::new(),
+            mouse_fired: HashSet::new(),
+        }
+    }
+
+    /// A tracker that has seen `keys` go from up to down with `pressed`, or from down to up
+    /// without, and nothing else.
+    fn simulated(keys: &HashSet<Key>, pressed: bool) -> Self {
+        let (held, previously_held) = if pressed {
+            (keys.clone(), HashSet::new())
+        } else {
+            (HashSet::new(), keys.clone())
+        };
+
+        KeyStateTracker {
+            keys: keys.clone(),
+            held,
+            previously_held,
+            injected: HashSet::new(),
+            previously_injected: HashSet::new(),
+            mouse_fired: HashSet::new(),
+        }
+    }
+
+    pub fn poll(&mut self, backend: &dyn InputBackend) {
+        let held: HashSet<Key> = self
+            .keys
+            .iter()
+            .copied()
+            .filter(|key| backend.is_key_held(*key))
+            .collect();
+        let injected = held
+            .iter()
+            .copied()
+            .filter(|key| injected::is_injected(*key))
+            .collect();
+        self.previously_held = std::mem::replace(&mut self.held, held);
+        self.previously_injected = std::mem::replace(&mut self.injected, injected);
+    }
+
+    /// Takes the `mouse_trigger`s that fired since the previous poll from `mouse_hook`.
+    fn poll_mouse(&mut self, macros: &[Macro], mouse_hook: Option<&MouseHook>) {
+        self.mouse_fired = mouse_hook
+            .map(MouseHook::take_fired)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|index| macros.get(index))
+            .map(|current_macro| current_macro.macro_name.clone())
+            .collect();
+    }
+
+    /// Whether every key of `current_macro`'s hotkey is down in the latest snapshot.
+    fn held(&self, current_macro: &Macro) -> bool {
+        current_macro.macro_hotkey.is_subset(&self.held)
+    }
+
+    /// Whether `current_macro`'s hotkey fired with this poll: every key went down having not all
+    /// been down before, or the reverse for `trigger_on: release`. Depends on nothing but the
+    /// last two snapshots. A macro without a hotkey never fires. Unless the macro has
+    /// `allow_injected_trigger`, keys a macro put down count as up.
+    pub fn triggered(&self, current_macro: &Macro) -> bool {
+        let hotkey = &current_macro.macro_hotkey;
+        if hotkey.is_empty() {
+            return false;
+        }
+
+        let pressed = |held: &HashSet<Key>, injected: &HashSet<Key>| {
+            hotkey.iter().all(|key| {
+                held.contains(key)
+                    && (current_macro.allow_injected_trigger || !injected.contains(key))
+            })
+        };
+        let active = pressed(&self.held, &self.injected);
+        let was_active = pressed(&self.previously_held, &self.previously_injected);
+
+        match current_macro.trigger_on {
+            TriggerOn::Press => active && !was_active,
+            TriggerOn::Release => !active && was_active,
+        }
+    }
+}
+
+/// Tracks how long a hotkey has been held without a break, for hotkeys that only act once held
+/// for a while. Fed one sample per poll, so it measures the hold to within the poll interval.
+pub struct HoldTracker {
+    /// What the hotkey does, for the log.
+    name: &'static str,
+    hold: Duration,
+    held_since: Option<Instant>,
+}
+
+impl HoldTracker {
+    pub fn new(name: &'static str, hold: Duration) -> Self {
+        HoldTracker {
+            name,
+            hold,
+            held_since: None,
+        }
+    }
+
+    /// Records whether the hotkey is held at `now`, returning whether it has been held for the
+    /// whole hold. With no hold, that is as soon as it is down.
+    pub fn update(&mut self, held: bool, now: Instant) -> bool {
+        if !held {
+            if let Some(held_since) = self.held_since.take() {
+                let held_for = now.duration_since(held_since);
+                if held_for < self.hold {
+                    log::info!(
+                        "{} hotkey released after {}ms, before the {}ms hold",
+                        self.name,
+                        held_for.as_millis(),
+                        self.hold.as_millis()
+                    );
+                }
+            }
+            return false;
+        }
+
+        let held_since = *self.held_since.get_or_insert_with(|| {
+            if !self.hold.is_zero() {
+                log::info!(
+                    "Hold the {} hotkey for {}ms",
+                    self.name,
+                    self.hold.as_millis()
+                );
+            }
+            now
+        });
+
+        now.duration_since(held_since) >= self.hold
+    }
+}
+
+/// How much longer `current_macro` must wait before it may be triggered again, if at all.
+fn cooldown_remaining(
+    current_macro: &Macro,
+    last_triggered: Option<Instant>,
+    last_completed: Option<Instant>,
+    clock: &dyn Clock,
+) -> Option<Duration> {
+    let since = match current_macro.cooldown_from {
+        CooldownFrom::Trigger => last_triggered,
+        CooldownFrom::Completion => last_completed,
+    }?;
+
+    current_macro
+        .cooldown_ms
+        .as_duration()
+        .checked_sub(clock.elapsed(since))
+        .filter(|remaining| !remaining.is_zero())
+}
+
+/// A check that keeps a macro from starting right now.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Guard {
+    /// Outside the macro's `active_hours` or `active_days`.
+    Inactive,
+    /// A fullscreen app has the focus and the macro has `skip_when_fullscreen` or
+    /// `defer_when_fullscreen`.
+    Fullscreen,
+    CoolingDown(Duration),
+    /// The macro's `profile` is not the active one.
+    InactiveProfile(String),
+}
+
+impl fmt::Display for Guard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Guard::Inactive => write!(f, "outside its active hours or days"),
+            Guard::Fullscreen => write!(f, "a fullscreen app has the focus"),
+            Guard::CoolingDown(remaining) => {
+                write!(f, "cooling down for another {:?}", remaining)
+            }
+            Guard::InactiveProfile(profile) => write!(f, "its profile {} is not active", profile),
+        }
+    }
+}
+
+/// The guard keeping `current_macro` from being started by `source` right now, if any. No guard
+/// applies to sources the macro lists in `ignore_guards_for`.
+pub fn blocking_guard(
+    current_macro: &Macro,
+    source: TriggerSource,
+    last_triggered: Option<Instant>,
+    last_completed: Option<Instant>,
+    clock: &dyn Clock,
+    screen: &dyn ScreenBackend,
+) -> Option<Guard> {
+    if current_macro.ignore_guards_for.contains(&source) {
+        return None;
+    }
+
+    if !current_macro.is_active_now() {
+        return Some(Guard::Inactive);
+    }
+
+    if (current_macro.skip_when_fullscreen || current_macro.defer_when_fullscreen)
+        && screen.foreground_fullscreen()
+    {
+        return Some(Guard::Fullscreen);
+    }
+
+    cooldown_remaining(current_macro, last_triggered, last_completed, clock).map(Guard::CoolingDown)
+}
+
+/// Like `blocking_guard`, but first keeps macros out of the active profile from being started by
+/// their hotkey, mouse or idle triggers.
+fn listener_guard(
+    current_macro: &Macro,
+    source: TriggerSource,
+    profiles: &ProfileSwitcher,
+    last_triggered: Option<Instant>,
+    executor: &Executor,
+    index: usize,
+) -> Option<Guard> {
+    if !profiles.allows(current_macro) {
+        return profile::macro_profile(current_macro)
+            .map(|profile| Guard::InactiveProfile(profile.to_string()));
+    }
+
+    blocking_guard(
+        current_macro,
+        source,
+        last_triggered,
+        executor.last_completed(index),
+        executor.clock().as_ref(),
+        executor.screen().as_ref(),
+    )
+}
+
+/// What the hotkey matching made of one macro in one poll.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TriggerDecision {
+    /// No key of the hotkey is or just was held, there is nothing to explain.
+    Untouched,
+    Disabled,
+    /// Some keys of the hotkey are held, but not these.
+    MissingKeys(Vec<Key>),
+    /// Every key is held, but already was at the previous poll. A hotkey fires once per press.
+    StillHeld,
+    /// Every key is held and the macro, `trigger_on: release`, waits for them to be let go.
+    AwaitingRelease,
+    Blocked(Guard),
+    AlreadyRunning,
+    Fire,
+}
+
+impl fmt::Display for TriggerDecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TriggerDecision::Untouched => write!(f, "hotkey not pressed"),
+            TriggerDecision::Disabled => write!(f, "disabled"),
+            TriggerDecision::MissingKeys(keys) => write!(
+                f,
+                "missing {}",
+                format_keys(&keys.iter().copied().collect())
+            ),
+            TriggerDecision::StillHeld => {
+                write!(f, "hotkey still held from an earlier press")
+            }
+            TriggerDecision::AwaitingRelease => write!(f, "waiting for the hotkey to be released"),
+            TriggerDecision::Blocked(guard) => write!(f, "{}", guard),
+            TriggerDecision::AlreadyRunning => write!(f, "already running"),
+            TriggerDecision::Fire => write!(f, "fires"),
+        }
+    }
+}
+
+/// Decides whether `current_macro` fires on the latest poll of `key_states` and, if not, why.
+/// `guard` is only asked for once the hotkey has fired, `running` tells whether the macro is
+/// running or queued already.
+fn trigger_decision(
+    current_macro: &Macro,
+    key_states: &KeyStateTracker,
+    guard: impl FnOnce() -> Option<Guard>,
+    running: bool,
+) -> TriggerDecision {
+    // The hook already checked the held keys of a `mouse_trigger`
+    if current_macro.mouse_trigger.is_some() {
+        if !key_states.mouse_fired.contains(&current_macro.macro_name) {
+            return TriggerDecision::Untouched;
+        }
+        if !current_macro.enabled {
+            return TriggerDecision::Disabled;
+        }
+        return match guard() {
+            Some(guard) => TriggerDecision::Blocked(guard),
+            None if running => TriggerDecision::AlreadyRunning,
+            None => TriggerDecision::Fire,
+        };
+    }
+
+    let hotkey = &current_macro.macro_hotkey;
+    if hotkey.is_disjoint(&key_states.held) && hotkey.is_disjoint(&key_states.previously_held) {
+        return TriggerDecision::Untouched;
+    }
+
+    if !current_macro.enabled {
+        return TriggerDecision::Disabled;
+    }
+
+    if !key_states.triggered(current_macro) {
+        let mut missing: Vec<Key> = hotkey.difference(&key_states.held).copied().collect();
+        if !missing.is_empty() {
+            missing.sort();
+            return TriggerDecision::MissingKeys(missing);
+        }
+
+        return match current_macro.trigger_on {
+            TriggerOn::Press => TriggerDecision::StillHeld,
+            TriggerOn::Release => TriggerDecision::AwaitingRelease,
+        };
+    }
+
+    if let Some(guard) = guard() {
+        return TriggerDecision::Blocked(guard);
+    }
+
+    if running {
+        return TriggerDecision::AlreadyRunning;
+    }
+
+    TriggerDecision::Fire
+}
+
+/// How often `--trace-triggers` explains the decision on any one macro.
+const TRACE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Logs why macros whose hotkey is involved in a poll did or did not fire, for `--trace-triggers`.
+#[derive(Default)]
+struct TriggerTracer {
+    /// When the decision on each macro was last logged.
+    last_logged: HashMap<usize, Instant>,
+}
+
+impl TriggerTracer {
+    fn trace(&mut self, index: usize, macro_name: &str, decision: &TriggerDecision, now: Instant) {
+        if *decision == TriggerDecision::Untouched {
+            return;
+        }
+
+        if self
+            .last_logged
+            .get(&index)
+            .is_some_and(|logged_at| now.saturating_duration_since(*logged_at) < TRACE_INTERVAL)
+        {
+            return;
+        }
+
+        self.last_logged.insert(index, now);
+        log::info!("Trigger trace: {}: {}", macro_name, decision);
+    }
+}
+
+/// Starts a macro on behalf of `source`, such as an HTTP request or the palette, subject to the
+/// same schedule, cooldown and concurrency limits as a hotkey, but without confirmation.
+fn remote_trigger(
+    executor: &mut Executor,
+    macro_name: &str,
+    source: TriggerSource,
+    last_triggered: &mut HashMap<usize, Instant>,
+) -> TriggerOutcome {
+    let index = match executor
+        .macros()
+        .iter()
+        .position(|current_macro| current_macro.macro_name == macro_name)
+    {
+        Some(index) => index,
+        None => return TriggerOutcome::NotFound,
+    };
+    let current_macro = &executor.macros()[index];
+
+    if !current_macro.enabled {
+        return TriggerOutcome::Rejected("macro is disabled".to_string());
+    }
+
+    if let Some(guard) = blocking_guard(
+        current_macro,
+        source,
+        last_triggered.get(&index).copied(),
+        executor.last_completed(index),
+        executor.clock().as_ref(),
+        executor.screen().as_ref(),
+    ) {
+        if guard == Guard::Fullscreen && current_macro.defer_when_fullscreen {
+            executor.defer(index, source);
+            return TriggerOutcome::Deferred;
+        }
+        return TriggerOutcome::Rejected(guard.to_string());
+    }
+
+    match executor.start(index, 0, source) {
+        Some(_) => {
+            log::info!("{} triggered from {}", macro_name, source.as_str());
+            last_triggered.insert(index, executor.clock().now());
+            TriggerOutcome::Started
+        }
+        None => TriggerOutcome::Rejected("already running or too many macros running".to_string()),
+    }
+}
+
+/// Runs the hotkey matching on `keys` being pressed and released, as if they had been, and
+/// starts every macro that fires, subject to the same guards as a real press. Macros that need
+/// confirmation are reported rather than started, since nothing can confirm them.
+fn simulate_press(
+    executor: &mut Executor,
+    keys: &HashSet<Key>,
+    profiles: &ProfileSwitcher,
+    last_triggered: &mut HashMap<usize, Instant>,
+) -> Vec<PressMatch> {
+    let press = KeyStateTracker::simulated(keys, true);
+    let release = KeyStateTracker::simulated(keys, false);
+    let mut matches = Vec::new();
+
+    for index in 0..executor.macros().len() {
+        let current_macro = &executor.macros()[index];
+        if !current_macro.enabled
+            || !(press.triggered(current_macro) || release.triggered(current_macro))
+        {
+            continue;
+        }
+
+        let macro_name = current_macro.macro_name.clone();
+        let rejected = if current_macro.confirm {
+            Some("needs confirmation".to_string())
+        } else {
+            listener_guard(
+                current_macro,
+                TriggerSource::Hotkey,
+                profiles,
+                last_triggered.get(&index).copied(),
+                executor,
+                index,
+            )
+            .map(|guard| guard.to_string())
+        };
+
+        let execution_id = match rejected {
+            Some(_) => None,
+            None => executor.start(index, 0, TriggerSource::Hotkey),
+        };
+        if execution_id.is_some() {
+            log::info!("{} triggered by a simulated press", macro_name);
+            last_triggered.insert(index, executor.clock().now());
+        }
+
+        matches.push(PressMatch {
+            macro_name,
+            execution_id,
+            rejected: rejected.or_else(|| {
+                execution_id
+                    .is_none()
+                    .then(|| "already running or too many macros running".to_string())
+            }),
+        });
+    }
+
+    matches
+}
+
+/// Starts every `on_idle` macro that is due: the user has been idle for its `after` and it has
+/// not run since they were last active, or last ran `repeat_every` ago.
+fn start_idle_macros(
+    executor: &mut Executor,
+    profiles: &ProfileSwitcher,
+    idle_tracker: &IdleTracker,
+    idle_started: &mut HashMap<usize, Instant>,
+    last_triggered: &mut HashMap<usize, Instant>,
+) {
+    let idle_for = idle_tracker.idle_for();
+
+    for index in 0..executor.macros().len() {
+        let current_macro = &executor.macros()[index];
+        let on_idle = match current_macro.on_idle {
+            Some(on_idle) if current_macro.enabled && idle_for >= on_idle.after.as_duration() => {
+                on_idle
+            }
+            _ => continue,
+        };
+
+        let due = match (idle_started.get(&index), on_idle.repeat_every) {
+            (None, _) => true,
+            (Some(started), Some(repeat_every)) => {
+                executor.clock().elapsed(*started) >= repeat_every.as_duration()
+            }
+            (Some(_), None) => false,
+        };
+        if !due || executor.is_running(index) {
+            continue;
+        }
+
+        if let Some(guard) = listener_guard(
+            current_macro,
+            TriggerSource::Idle,
+            profiles,
+            last_triggered.get(&index).copied(),
+            executor,
+            index,
+        ) {
+            log::debug!(
+                "Ignoring idle trigger of {}, {}",
+                current_macro.macro_name,
+                guard
+            );
+            continue;
+        }
+
+        let macro_name = current_macro.macro_name.clone();
+        if executor.start(index, 0, TriggerSource::Idle).is_some() {
+            log::info!("{} started after {:?} idle", macro_name, idle_for);
+            let now = executor.clock().now();
+            idle_started.insert(index, now);
+            last_triggered.insert(index, now);
+        }
+    }
+}
+
+/// Polls the hotkeys and starts the macros they trigger until told to exit. With
+/// `trace_triggers`, also logs why each macro whose hotkey is touched does or does not fire.
+pub fn input_listener(
+    mut executor: Executor,
+    on_lock: OnLock,
+    mut profiles: ProfileSwitcher,
+    trace_triggers: bool,
+    rx: Receiver<Message>,
+) -> Result<(), anyhow::Error> {
+    let mut live_threads = 0;
+    let mut locked = false;
+    // Paused by an external request, see `Message::Pause`
+    let mut paused = false;
+    let mut key_states = KeyStateTracker::new(executor.macros(), trace_triggers);
+    // Removed when the listener returns
+    let mouse_hook = MouseHook::install(executor.macros())?;
+    let mut tracer = trace_triggers.then(TriggerTracer::default);
+    // Macros with `confirm: true` that have been triggered once and are awaiting a second press
+    let mut pending_confirmations: HashMap<usize, Instant> = HashMap::new();
+    // When each macro was last started by its hotkey, for cooldowns
+    let mut last_triggered: HashMap<usize, Instant> = HashMap::new();
+    let mut idle_tracker = IdleTracker::new();
+    // When each `on_idle` macro last started since the user was last active
+    let mut idle_started: HashMap<usize, Instant> = HashMap::new();
+
+    'poll: loop {
+        let was_suspended = locked || paused;
+
+        executor.reap();
+
+        while let Ok(message) = rx.try_recv() {
+            match message {
+                Message::Exit => {
+                    executor.cancel_all();
+                    break 'poll;
+                }
+                Message::Trigger {
+                    macro_name,
+                    source,
+                    reply,
+                } => {
+                    let outcome = if locked || paused {
+                        TriggerOutcome::Rejected("triggers are paused".to_string())
+                    } else {
+                        remote_trigger(&mut executor, &macro_name, source, &mut last_triggered)
+                    };
+                    let _ = reply.send(outcome);
+                }
+                Message::SimulatePress { keys, reply } => {
+                    let matches = if locked || paused {
+                        Vec::new()
+                    } else {
+                        simulate_press(&mut executor, &keys, &profiles, &mut last_triggered)
+                    };
+                    let _ = reply.send(matches);
+                }
+                Message::ListMacros(reply) => {
+                    let statuses = executor
+                        .macros()
+                        .iter()
+                        .enumerate()
+                        .map(|(index, current_macro)| {
+                            let progress = executor.progress(index);
+                            MacroStatus {
+                                name: current_macro.macro_name.clone(),
+                                enabled: current_macro.enabled,
+                                running: executor.is_running(index),
+                                queued: executor.is_queued(index),
+                                deferred: executor.is_deferred(index),
+                                mutex: current_macro.mutex.clone(),
+                                command_index: progress
+                                    .as_ref()
+                                    .map(|(progress, _)| progress.command_index()),
+                                elapsed_ms: progress
+                                    .as_ref()
+                                    .map(|(_, elapsed)| elapsed.as_millis() as u64),
+                                paused: progress
+                                    .as_ref()
+                                    .is_some_and(|(progress, _)| progress.awaiting_resume()),
+                                typed_percent: progress
+                                    .as_ref()
+                                    .and_then(|(progress, _)| progress.text())
+                                    .map(|(typed, total)| (typed * 100 / total.max(1)) as u8),
+                            }
+                        })
+                        .collect();
+                    let _ = reply.send(statuses);
+                }
+                Message::ActiveProfile(reply) => {
+                    let _ = reply.send(profiles.active().clone());
+                }
+                Message::Pause => {
+                    log::info!("Paused, ignoring triggers until resumed");
+                    paused = true;
+                }
+                Message::Resume => {
+                    log::info!("Resumed");
+                    paused = false;
+                }
+            }
+        }
+
+        pending_confirmations.retain(|index, requested_at| {
+            let pending = executor.clock().elapsed(*requested_at) < CONFIRMATION_WINDOW;
+            if !pending {
+                let macro_name = &executor.macros()[*index].macro_name;
+                window::close_confirmation_prompt(macro_name);
+                log::info!("Confirmation for {} timed out", macro_name);
+            }
+            pending
+        });
+
+        key_states.poll(executor.backend().as_ref());
+        key_states.poll_mouse(executor.macros(), mouse_hook.as_ref());
+
+        if session::session_locked() != locked {
+            locked = !locked;
+
+            if locked {
+                log::info!("Workstation locked, ignoring triggers until it is unlocked");
+                if on_lock == OnLock::Cancel {
+                    executor.cancel_all();
+                }
+            } else {
+                log::info!("Workstation unlocked");
+            }
+        }
+
+        match (was_suspended, locked || paused) {
+            (false, true) => executor.pause_all(),
+            (true, false) => executor.resume_all(),
+            _ => {}
+        }
+
+        if locked || paused {
+            sleep(Duration::from_millis(50));
+            continue;
+        }
+
+        if profiles.poll(executor.screen().as_ref(), executor.clock().now()) {
+            let active = profiles.active();
+            log::info!(
+                "Profile {} active, {}",
+                active.profile.as_deref().unwrap_or(profile::GLOBAL_PROFILE),
+                active.reason
+            );
+        }
+
+        for (index, current_macro) in executor.macros().iter().enumerate() {
+            if current_macro.mode == MacroMode::WhileHeld
+                && executor.is_running(index)
+                && !key_states.held(current_macro)
+            {
+                executor.cancel_if_triggered_by(index, TriggerSource::Hotkey);
+            }
+        }
+
+        if idle_tracker.poll() {
+            idle_started.clear();
+            for index in 0..executor.macros().len() {
+                executor.cancel_if_triggered_by(index, TriggerSource::Idle);
+            }
+        }
+        start_idle_macros(
+            &mut executor,
+            &profiles,
+            &idle_tracker,
+            &mut idle_started,
+            &mut last_triggered,
+        );
+
+        let mut triggered_macros = Vec::new();
+        let mut deferred_macros = Vec::new();
+
+        for (index, current_macro) in executor.macros().iter().enumerate() {
+            let decision = trigger_decision(
+                current_macro,
+                &key_states,
+                || {
+                    listener_guard(
+                        current_macro,
+                        TriggerSource::Hotkey,
+                        &profiles,
+                        last_triggered.get(&index).copied(),
+                        &executor,
+                        index,
+                    )
+                },
+                executor.is_running(index) || executor.is_queued(index),
+            );
+
+            if let Some(tracer) = tracer.as_mut() {
+                tracer.trace(
+                    index,
+                    &current_macro.macro_name,
+                    &decision,
+                    executor.clock().now(),
+                );
+            }
+
+            match decision {
+                TriggerDecision::Fire => {}
+                // Cooldowns are hit all the time by held or mashed hotkeys, and hotkeys shared
+                // between profiles by every press, keep them quiet
+                TriggerDecision::Blocked(
+                    guard @ (Guard::CoolingDown(_) | Guard::InactiveProfile(_)),
+                ) => {
+                    log::debug!("Ignoring {}, {}", current_macro.macro_name, guard);
+                    continue;
+                }
+                TriggerDecision::Blocked(Guard::Fullscreen)
+                    if current_macro.defer_when_fullscreen =>
+                {
+                    deferred_macros.push(index);
+                    continue;
+                }
+                TriggerDecision::Blocked(guard) => {
+                    log::info!("Ignoring {}, {}", current_macro.macro_name, guard);
+                    continue;
+                }
+                TriggerDecision::AlreadyRunning => {
+                    log::info!("Ignoring {}, already running", current_macro.macro_name);
+                    continue;
+                }
+                _ => continue,
+            }
+
+            if current_macro.confirm {
+                match pending_confirmations.remove(&index) {
+                    Some(_) => {
+                        window::close_confirmation_prompt(&current_macro.macro_name);
+                        log::info!("{} confirmed", current_macro.macro_name);
+                    }
+                    None => {
+                        pending_confirmations.insert(index, executor.clock().now());
+                        window::show_confirmation_prompt(
+                            &current_macro.macro_name,
+                            CONFIRMATION_WINDOW,
+                        );
+                        log::info!(
+                            "{} requires confirmation, press the hotkey again to run it",
+                            current_macro.macro_name
+                        );
+                        continue;
+                    }
+                }
+            }
+
+            triggered_macros.push(index);
+        }
+
+        for index in deferred_macros {
+            executor.defer(index, TriggerSource::Hotkey);
+        }
+
+        // Macros matching at the same time start highest priority first
+        triggered_macros.sort_by_key(|index| Reverse(executor.macros()[*index].priority));
+        for index in triggered_macros {
+            if executor.start(index, 0, TriggerSource::Hotkey).is_some() {
+                last_triggered.insert(index, executor.clock().now());
+            }
+        }
+
+        if executor.running_count() != live_threads {
+            live_threads = executor.running_count();
+            log::debug!(
+                "Macro threads running: {}/{}",
+                live_threads,
+                executor.max_macro_threads()
+            );
+        }
+
+        sleep(Duration::from_millis(50));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        backend::{ScriptedInput, SimulatedScreen},
+        clock::VirtualClock,
+        context::CancellationToken,
+        diff_run,
+    };
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn test_macro(yaml: &str) -> Macro {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn hotkeys_fire_once_when_the_last_key_goes_down() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60)));
+        let cancellation = CancellationToken::default();
+        let backend = ScriptedInput::new(clock.clone());
+        backend
+            .hold(Key::LeftMenu, ms(0), ms(500))
+            .hold(Key::F7, ms(100), ms(200))
+            .hold(Key::F7, ms(300), ms(400));
+        let on_press =
+            test_macro("{macro_name: press, macro_hotkey: [LeftMenu, F7], commands: []}");
+        let on_release = test_macro(
+            "{macro_name: release, macro_hotkey: [LeftMenu, F7], trigger_on: release, \
+             commands: []}",
+        );
+        let other = test_macro("{macro_name: other, macro_hotkey: [RightMenu, F7], commands: []}");
+        let macros = [on_press, on_release, other];
+        let mut tracker = KeyStateTracker::new(&macros, false);
+
+        // Which macros fire at each poll, polling every 50 ms
+        let mut fired = Vec::new();
+        for at in (0..=500).step_by(50) {
+            tracker.poll(&backend);
+            for current_macro in macros.iter() {
+                if tracker.triggered(current_macro) {
+                    fired.push((at, current_macro.macro_name.as_str()));
+                }
+            }
+            clock.sleep(ms(50), &cancellation);
+        }
+
+        assert_eq!(
+            fired,
+            [
+                (100, "press"),
+                (200, "release"),
+                (300, "press"),
+                (400, "release")
+            ]
+        );
+    }
+
+    #[test]
+    fn hotkeys_a_macro_presses_do_not_fire() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60)));
+        let backend = Arc::new(ScriptedInput::new(clock.clone()));
+        let pressing = test_macro(
+            "{macro_name: a, macro_hotkey: [LeftControl, F9], commands: [!PressKeyCombo \
+             [LeftControl, LeftShift, F10]]}",
+        );
+        let pressed = test_macro(
+            "{macro_name: b, macro_hotkey: [LeftControl, LeftShift, F10], commands: []}",
+        );
+        let opted_in = test_macro(
+            "{macro_name: c, macro_hotkey: [LeftControl, LeftShift, F10], \
+             allow_injected_trigger: true, commands: []}",
+        );
+        let macros = [pressing, pressed, opted_in];
+        let mut tracker = KeyStateTracker::new(&macros, false);
+        tracker.poll(backend.as_ref());
+
+        // A runs, and the keyboard shows its combo down as the listener next polls
+        let mut context = crate::tests::test_context(backend.clone(), clock.clone());
+        let (result, recorded) = diff_run::record_for_test(clock.clone(), || {
+            crate::run_block(&macros[0].commands, &mut context)
+        });
+        result.unwrap();
+        assert_eq!(
+            recorded,
+            [
+                "key_down LeftControl",
+                "key_down LeftShift",
+                "key_down F10",
+                "key_up F10",
+                "key_up LeftShift",
+                "key_up LeftControl",
+            ]
+        );
+        for key in [Key::LeftControl, Key::LeftShift, Key::F10] {
+            backend.press_at(key, Duration::ZERO);
+        }
+        tracker.poll(backend.as_ref());
+
+        assert!(!tracker.triggered(&macros[1]));
+        assert!(tracker.triggered(&macros[2]));
+    }
+
+    #[test]
+    fn hotkeys_pressed_by_hand_fire_once_macros_let_go() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60)));
+        let backend = Arc::new(ScriptedInput::new(clock.clone()));
+        let current_macro =
+            test_macro("{macro_name: b, macro_hotkey: [RightControl, F11], commands: []}");
+        let macros = [current_macro];
+        let mut tracker = KeyStateTracker::new(&macros, false);
+        tracker.poll(backend.as_ref());
+
+        let mut context = crate::tests::test_context(backend.clone(), clock.clone());
+        let (result, _) = diff_run::record_for_test(clock.clone(), || {
+            crate::run_block(
+                &crate::tests::commands("[!PressKeyCombo [RightControl, F11]]"),
+                &mut context,
+            )
+        });
+        result.unwrap();
+
+        // Pressed for real once the macro's release is long past
+        std::thread::sleep(injected::RELEASE_GRACE + Duration::from_millis(50));
+        backend
+            .press_at(Key::RightControl, Duration::ZERO)
+            .press_at(Key::F11, Duration::ZERO);
+        tracker.poll(backend.as_ref());
+
+        assert!(tracker.triggered(&macros[0]));
+    }
+
+    #[test]
+    fn cooldowns_run_on_the_clock() {
+        let clock = VirtualClock::new(Duration::from_secs(60));
+        let cancellation = CancellationToken::default();
+        let from_trigger = test_macro(
+            "{macro_name: a, macro_hotkey: [LeftControl, F7], cooldown_ms: 2s, commands: []}",
+        );
+        let from_completion = test_macro(
+            "{macro_name: b, macro_hotkey: [LeftControl, F8], cooldown_ms: 2s, \
+             cooldown_from: completion, commands: []}",
+        );
+
+        let triggered = clock.now();
+        clock.sleep(ms(500), &cancellation);
+        let completed = clock.now();
+        assert_eq!(
+            cooldown_remaining(&from_trigger, Some(triggered), Some(completed), &clock),
+            Some(ms(1500))
+        );
+        assert_eq!(
+            cooldown_remaining(&from_completion, Some(triggered), Some(completed), &clock),
+            Some(ms(2000))
+        );
+
+        clock.sleep(ms(1500), &cancellation);
+        assert_eq!(
+            cooldown_remaining(&from_trigger, Some(triggered), Some(completed), &clock),
+            None
+        );
+        assert_eq!(
+            cooldown_remaining(&from_completion, Some(triggered), Some(completed), &clock),
+            Some(ms(500))
+        );
+
+        clock.sleep(ms(500), &cancellation);
+        assert_eq!(
+            cooldown_remaining(&from_completion, Some(triggered), Some(completed), &clock),
+            None
+        );
+        assert_eq!(cooldown_remaining(&from_trigger, None, None, &clock), None);
+    }
+
+    #[test]
+    fn held_hotkeys_act_once_held_without_a_break() {
+        let start = Instant::now();
+        let at = |millis| start + ms(millis);
+        let mut hold = HoldTracker::new("Exit", ms(1000));
+
+        // Brushed against, then let go before the hold is up
+        assert!(!hold.update(true, at(0)));
+        assert!(!hold.update(true, at(600)));
+        assert!(!hold.update(false, at(650)));
+
+        // The hold starts over from the next press
+        assert!(!hold.update(true, at(700)));
+        assert!(!hold.update(true, at(1650)));
+        assert!(hold.update(true, at(1700)));
+
+        let mut no_hold = HoldTracker::new("Exit", Duration::ZERO);
+        assert!(no_hold.update(true, at(0)));
+    }
+
+    #[test]
+    fn simulated_presses_fire_without_polling() {
+        let current_macro =
+            test_macro("{macro_name: press, macro_hotkey: [LeftControl, F8], commands: []}");
+        let keys = current_macro.macro_hotkey.clone();
+
+        assert!(KeyStateTracker::simulated(&keys, true).triggered(&current_macro));
+        assert!(!KeyStateTracker::simulated(&keys, false).triggered(&current_macro));
+    }
+
+    #[test]
+    fn guards_hold_back_every_source_but_the_ignored_ones() {
+        let clock = VirtualClock::new(Duration::from_secs(60));
+        let cooling = test_macro(
+            "{macro_name: cooling, macro_hotkey: [LeftMenu, F7], cooldown_ms: 1000, \
+             ignore_guards_for: [cli, chain], commands: []}",
+        );
+        let just_triggered = Some(clock.now());
+
+        // Stops building when a source is added without a row below
+        let _ = |source: TriggerSource| match source {
+            TriggerSource::Hotkey
+            | TriggerSource::Http
+            | TriggerSource::Chain
+            | TriggerSource::Cli
+            | TriggerSource::Idle
+            | TriggerSource::Palette => {}
+        };
+        for (source, held_back) in [
+            (TriggerSource::Hotkey, true),
+            (TriggerSource::Http, true),
+            (TriggerSource::Chain, false),
+            (TriggerSource::Cli, false),
+            (TriggerSource::Idle, true),
+            (TriggerSource::Palette, true),
+        ] {
+            let guard = blocking_guard(
+                &cooling,
+                source,
+                just_triggered,
+                None,
+                &clock,
+                &SimulatedScreen,
+            );
+            let expected = held_back.then_some(Guard::CoolingDown(ms(1000)));
+            assert_eq!(guard, expected, "{:?}", source);
+
+            // Nothing holds back a macro that has not run yet
+            let guard = blocking_guard(&cooling, source, None, None, &clock, &SimulatedScreen);
+            assert_eq!(guard, None, "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn trigger_decisions_explain_every_outcome() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60)));
+        let cancellation = CancellationToken::default();
+        let backend = ScriptedInput::new(clock.clone());
+        backend
+            .hold(Key::LeftMenu, ms(100), ms(500))
+            .hold(Key::F7, ms(200), ms(400));
+        let on_press =
+            test_macro("{macro_name: press, macro_hotkey: [LeftMenu, F7], commands: []}");
+        let on_release = test_macro(
+            "{macro_name: release, macro_hotkey: [LeftMenu, F7], trigger_on: release, \
+             commands: []}",
+        );
+        let disabled = test_macro(
+            "{macro_name: disabled, macro_hotkey: [LeftMenu, F7], enabled: false, commands: []}",
+        );
+        let macros = [on_press, on_release, disabled];
+        let mut tracker = KeyStateTracker::new(&macros, true);
+        let cooling = || Some(Guard::CoolingDown(ms(250)));
+        // Guards are only asked for once the hotkey has fired
+        let unasked = || -> Option<Guard> { unreachable!() };
+        let [on_press, on_release, disabled] = &macros;
+
+        tracker.poll(&backend);
+        assert_eq!(
+            trigger_decision(on_press, &tracker, unasked, false),
+            TriggerDecision::Untouched
+        );
+        assert_eq!(
+            trigger_decision(disabled, &tracker, unasked, false),
+            TriggerDecision::Untouched
+        );
+
+        clock.sleep(ms(100), &cancellation);
+        tracker.poll(&backend);
+        assert_eq!(
+            trigger_decision(on_press, &tracker, unasked, false),
+            TriggerDecision::MissingKeys(vec![Key::F7])
+        );
+        assert_eq!(
+            trigger_decision(disabled, &tracker, unasked, false),
+            TriggerDecision::Disabled
+        );
+
+        clock.sleep(ms(100), &cancellation);
+        tracker.poll(&backend);
+        for (guard, running, decision) in [
+            (None, false, TriggerDecision::Fire),
+            (None, true, TriggerDecision::AlreadyRunning),
+            (
+                cooling(),
+                false,
+                TriggerDecision::Blocked(Guard::CoolingDown(ms(250))),
+            ),
+            (
+                cooling(),
+                true,
+                TriggerDecision::Blocked(Guard::CoolingDown(ms(250))),
+            ),
+        ] {
+            assert_eq!(
+                trigger_decision(on_press, &tracker, || guard, running),
+                decision
+            );
+        }
+        assert_eq!(
+            trigger_decision(on_release, &tracker, unasked, false),
+            TriggerDecision::AwaitingRelease
+        );
+
+        clock.sleep(ms(100), &cancellation);
+        tracker.poll(&backend);
+        assert_eq!(
+            trigger_decision(on_press, &tracker, unasked, false),
+            TriggerDecision::StillHeld
+        );
+
+        clock.sleep(ms(100), &cancellation);
+        tracker.poll(&backend);
+        assert_eq!(
+            trigger_decision(on_release, &tracker, || None, false),
+            TriggerDecision::Fire
+        );
+        assert_eq!(
+            trigger_decision(on_press, &tracker, unasked, false),
+            TriggerDecision::MissingKeys(vec![Key::F7])
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    cell::RefCell,
+    io::Write,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::{Deserialize, Serialize};
+use windows::Win32::{
+    Foundation::SYSTEMTIME,
+    System::SystemInformation::{GetLocalTime, GetSystemTime},
+};
+
+use super::error::ErrorKind;
+
+/// How log records are written to stdout.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// One line of text per record, for reading.
+    #[default]
+    Text,
+    /// One JSON object per line, for log collectors, with the `LogFields` of execution events as
+    /// fields of their own.
+    Json,
+}
+
+/// What a record is about, as fields of the record rather than words of its message. Set for a
+/// thread with `scope`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LogFields {
+    #[serde(rename = "macro", skip_serializing_if = "Option::is_none")]
+    pub macro_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub execution_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command_index: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<ErrorKind>,
+}
+
+impl LogFields {
+    /// The fields of everything logged about the execution `execution_id` of `macro_name`.
+    pub fn execution(macro_name: &str, execution_id: u64) -> Self {
+        LogFields {
+            macro_name: Some(macro_name.to_string()),
+            execution_id: Some(execution_id),
+            ..Default::default()
+        }
+    }
+}
+
+thread_local! {
+    static FIELDS: RefCell<LogFields> = RefCell::new(LogFields::default());
+}
+
+/// Puts back the fields that were set before `scope` when dropped.
+pub struct LogScope(Option<LogFields>);
+
+impl Drop for LogScope {
+    fn drop(&mut self) {
+        if let Some(previous) = self.0.take() {
+            FIELDS.with(|current| *current.borrow_mut() = previous);
+        }
+    }
+}
+
+/// Attaches `fields` to every record logged on this thread until the returned scope is dropped.
+pub fn scope(fields: LogFields) -> LogScope {
+    LogScope(Some(FIELDS.with(|current| current.replace(fields))))
+}
+
+/// Changes the command index of the current scope, as an execution moves from command to command.
+pub fn set_command_index(command_index: Option<usize>) {
+    FIELDS.with(|current| current.borrow_mut().command_index = command_index);
+}
+
+/// Whether records are written as JSON, see `set_format`.
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Whether records go to stderr rather than stdout, see `log_to_stderr`.
+static STDERR: AtomicBool = AtomicBool::new(false);
+
+static LOGGER: Logger = Logger;
+
+/// Writes records of `MAX_LEVEL` and up to stdout, or stderr after `log_to_stderr`, in the format
+/// given to `set_format`, text until then.
+struct Logger;
+
+const MAX_LEVEL: LevelFilter = LevelFilter::Info;
+
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    timestamp: String,
+    level: &'static str,
+    target: &'a str,
+    message: String,
+    #[serde(flatten)]
+    fields: &'a LogFields,
+}
+
+/// `time` as `YYYY-MM-DDTHH:MM:SS.mmm`.
+fn format_time(time: &SYSTEMTIME) -> String {
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}",
+        time.wYear,
+        time.wMonth,
+        time.wDay,
+        time.wHour,
+        time.wMinute,
+        time.wSecond,
+        time.wMilliseconds
+    )
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= MAX_LEVEL
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let target = match record.target() {
+            "" => record.module_path().unwrap_or_default(),
+            target => target,
+        };
+
+        let line = FIELDS.with(|fields| {
+            let fields = fields.borrow();
+
+            if JSON.load(Ordering::Relaxed) {
+                let mut now = SYSTEMTIME::default();
+                unsafe { GetSystemTime(&mut now) };
+
+                serde_json::to_string(&JsonRecord {
+                    timestamp: format!("{}Z", format_time(&now)),
+                    level: record.level().as_str(),
+                    target,
+                    message: record.args().to_string(),
+                    fields: &fields,
+                })
+                .unwrap_or_else(|e| format!("{{\"error\":\"{}\"}}", e))
+            } else {
+                let mut now = SYSTEMTIME::default();
+                unsafe { GetLocalTime(&mut now) };
+
+                let execution = match fields.execution_id {
+                    Some(execution_id) => format!("[#{}] ", execution_id),
+                    None => String::new(),
+                };
+                format!(
+                    "{} {:<5} [{}] {}{}",
+                    format_time(&now),
+                    record.level(),
+                    target,
+                    execution,
+                    record.args()
+                )
+            }
+        });
+
+        if STDERR.load(Ordering::Relaxed) {
+            let _ = writeln!(std::io::stderr().lock(), "{}", line);
+        } else {
+            let _ = writeln!(std::io::stdout().lock(), "{}", line);
+        }
+    }
+
+    fn flush(&self) {
+        if STDERR.load(Ordering::Relaxed) {
+            let _ = std::io::stderr().flush();
+        } else {
+            let _ = std::io::stdout().flush();
+        }
+    }
+}
+
+/// Installs the logger, writing text until the config says otherwise.
+pub fn init() -> Result<(), anyhow::Error> {
+    log::set_logger(&LOGGER).map_err(|e| anyhow::anyhow!("Failed to set up logging: {}", e))?;
+    log::set_max_level(MAX_LEVEL);
+    Ok(())
+}
+
+/// Switches every record logged from now on to `format`.
+pub fn set_format(format: LogFormat) {
+    JSON.store(format == LogFormat::Json, Ordering::Relaxed);
+}
+
+/// Sends every record logged from now on to stderr, leaving stdout to output that must not be
+/// mixed with log lines, such as `--events-stdout`.
+pub fn log_to_stderr() {
+    STDERR.store(true, Ordering::Relaxed);
+}
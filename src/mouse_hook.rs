@@ -0,0 +1,298 @@
+use std::{
+    collections::HashSet,
+    sync::{mpsc::channel, Mutex, PoisonError},
+    thread::JoinHandle,
+};
+
+use serde::{Deserialize, Serialize};
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::System::Threading::GetCurrentThreadId;
+use windows::Win32::UI::Input::KeyboardAndMouse::GetDoubleClickTime;
+use windows::Win32::UI::WindowsAndMessaging::{
+    CallNextHookEx, DispatchMessageW, GetMessageW, PostThreadMessageW, SetWindowsHookExW,
+    TranslateMessage, UnhookWindowsHookEx, HHOOK, LLMHF_INJECTED, MSG, MSLLHOOKSTRUCT, WH_MOUSE_LL,
+    WM_LBUTTONDOWN, WM_LBUTTONUP, WM_MBUTTONDOWN, WM_MBUTTONUP, WM_MOUSEWHEEL, WM_QUIT,
+    WM_RBUTTONDOWN, WM_RBUTTONUP, WM_XBUTTONDOWN, WM_XBUTTONUP, XBUTTON1,
+};
+
+use super::{key_held, Key, Macro};
+
+/// Which way the wheel turns for a `MouseTrigger`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WheelDirection {
+    /// Away from the user.
+    Up,
+    Down,
+}
+
+/// Triggers a macro from mouse events seen by a low-level hook rather than from polling, so that
+/// quick clicks are not missed and double clicks can be told apart. The macro's `macro_hotkey`
+/// then only lists the keys that must be held at the time, and may be empty.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MouseTrigger {
+    /// Mouse button whose press triggers the macro.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub button: Option<Key>,
+    /// Wheel notch that triggers the macro, instead of a button.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wheel: Option<WheelDirection>,
+    /// How many presses or notches in a row, each within the system double-click time of the
+    /// last, trigger the macro, e.g. 2 for a double click.
+    #[serde(default = "default_clicks")]
+    pub clicks: u32,
+    /// Keep the triggering press, and its release, from reaching the focused app.
+    #[serde(default)]
+    pub suppress: bool,
+}
+
+fn default_clicks() -> u32 {
+    1
+}
+
+impl MouseTrigger {
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        match (self.button, self.wheel) {
+            (Some(button), None) if !button.is_mouse_button() => Err(anyhow::anyhow!(
+                "mouse_trigger button {:?} is not a mouse button",
+                button
+            )),
+            (Some(_), None) | (None, Some(_)) if self.clicks == 0 => {
+                Err(anyhow::anyhow!("mouse_trigger clicks must be at least 1"))
+            }
+            (Some(_), None) | (None, Some(_)) => Ok(()),
+            _ => Err(anyhow::anyhow!(
+                "mouse_trigger needs exactly one of button and wheel"
+            )),
+        }
+    }
+}
+
+/// A mouse event the hook matches triggers against.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum MouseInput {
+    Button(Key),
+    Wheel(WheelDirection),
+}
+
+/// A `MouseTrigger` of the macro at `index`, as the hook matches it.
+struct HookTrigger {
+    index: usize,
+    input: MouseInput,
+    /// Virtual-key codes of the keys that must be held.
+    held: Vec<i32>,
+    clicks: u32,
+    suppress: bool,
+    allow_injected: bool,
+}
+
+#[derive(Default)]
+struct HookState {
+    triggers: Vec<HookTrigger>,
+    /// The last input, the event time it came at and how many came in a row.
+    last: Option<(MouseInput, u32, u32)>,
+    /// Indexes of the macros triggered since `MouseHook::take_fired`.
+    fired: Vec<usize>,
+    /// Buttons whose press was suppressed, so their release is too.
+    suppressed: HashSet<Key>,
+}
+
+/// Shared by the hook, which runs on its own thread, and the listener.
+static STATE: Mutex<Option<HookState>> = Mutex::new(None);
+
+/// The input of a button press or wheel notch, and of a button release.
+fn event_input(message: u32, event: &MSLLHOOKSTRUCT) -> (Option<MouseInput>, Option<Key>) {
+    let high_word = (event.mouseData.0 >> 16) as u16;
+    let x_button = if high_word as u32 == XBUTTON1.0 {
+        Key::XButton1
+    } else {
+        Key::XButton2
+    };
+
+    match message {
+        WM_LBUTTONDOWN => (Some(MouseInput::Button(Key::LeftButton)), None),
+        WM_RBUTTONDOWN => (Some(MouseInput::Button(Key::RightButton)), None),
+        WM_MBUTTONDOWN => (Some(MouseInput::Button(Key::MiddleButton)), None),
+        WM_XBUTTONDOWN => (Some(MouseInput::Button(x_button)), None),
+        WM_MOUSEWHEEL if high_word as i16 > 0 => {
+            (Some(MouseInput::Wheel(WheelDirection::Up)), None)
+        }
+        WM_MOUSEWHEEL => (Some(MouseInput::Wheel(WheelDirection::Down)), None),
+        WM_LBUTTONUP => (None, Some(Key::LeftButton)),
+        WM_RBUTTONUP => (None, Some(Key::RightButton)),
+        WM_MBUTTONUP => (None, Some(Key::MiddleButton)),
+        WM_XBUTTONUP => (None, Some(x_button)),
+        _ => (None, None),
+    }
+}
+
+/// Records an event, returning whether it must be swallowed. Events macros sent only reach the
+/// triggers of macros with `allow_injected_trigger`.
+fn handle_event(state: &mut HookState, message: u32, event: &MSLLHOOKSTRUCT) -> bool {
+    let injected = event.flags & LLMHF_INJECTED != 0;
+    if injected && !state.triggers.iter().any(|trigger| trigger.allow_injected) {
+        return false;
+    }
+
+    let input = match event_input(message, event) {
+        (Some(input), _) => input,
+        (None, Some(released)) => return state.suppressed.remove(&released),
+        (None, None) => return false,
+    };
+
+    let count = match state.last {
+        Some((last, time, count))
+            if last == input
+                && event.time.wrapping_sub(time) <= unsafe { GetDoubleClickTime() } =>
+        {
+            count + 1
+        }
+        _ => 1,
+    };
+    state.last = Some((input, event.time, count));
+
+    let mut suppress = false;
+    for trigger in state.triggers.iter() {
+        if trigger.input == input
+            && (trigger.allow_injected || !injected)
+            && trigger.clicks == count
+            && trigger.held.iter().all(|key| key_held(*key))
+        {
+            state.fired.push(trigger.index);
+            suppress |= trigger.suppress;
+        }
+    }
+
+    if let (true, MouseInput::Button(button)) = (suppress, input) {
+        state.suppressed.insert(button);
+    }
+
+    suppress
+}
+
+unsafe extern "system" fn mouse_hook(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    if code >= 0 {
+        let event = &*(lparam.0 as *const MSLLHOOKSTRUCT);
+        let swallow = STATE
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_mut()
+            .is_some_and(|state| handle_event(state, wparam.0 as u32, event));
+        if swallow {
+            return LRESULT(1);
+        }
+    }
+
+    CallNextHookEx(HHOOK::default(), code, wparam, lparam)
+}
+
+/// A `WH_MOUSE_LL` hook matching the `mouse_trigger` of every enabled macro, on a thread of its
+/// own so that the mouse never waits on the listener's polling. The hook is removed when this is
+/// dropped.
+pub struct MouseHook {
+    thread_id: u32,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MouseHook {
+    /// Installs the hook if any enabled macro has a `mouse_trigger`.
+    pub fn install(macros: &[Macro]) -> Result<Option<Self>, anyhow::Error> {
+        let triggers: Vec<HookTrigger> = macros
+            .iter()
+            .enumerate()
+            .filter(|(_, current_macro)| current_macro.enabled)
+            .filter_map(|(index, current_macro)| {
+                let trigger = current_macro.mouse_trigger.as_ref()?;
+                let input = match (trigger.button, trigger.wheel) {
+                    (Some(button), _) => MouseInput::Button(button),
+                    (None, Some(wheel)) => MouseInput::Wheel(wheel),
+                    (None, None) => return None,
+                };
+                Some(HookTrigger {
+                    index,
+                    input,
+                    held: current_macro
+                        .macro_hotkey
+                        .iter()
+                        .map(|key| key.virtual_key())
+                        .collect(),
+                    clicks: trigger.clicks,
+                    suppress: trigger.suppress,
+                    allow_injected: current_macro.allow_injected_trigger,
+                })
+            })
+            .collect();
+
+        if triggers.is_empty() {
+            return Ok(None);
+        }
+
+        log::debug!("Hooking the mouse for {} trigger(s)", triggers.len());
+        *STATE.lock().unwrap_or_else(PoisonError::into_inner) = Some(HookState {
+            triggers,
+            ..Default::default()
+        });
+
+        let (tx, rx) = channel();
+        let thread = std::thread::spawn(move || {
+            let hook = unsafe {
+                GetModuleHandleW(PCWSTR::null())
+                    .and_then(|module| SetWindowsHookExW(WH_MOUSE_LL, Some(mouse_hook), module, 0))
+            };
+            let hook = match hook {
+                Ok(hook) => {
+                    let _ = tx.send(Ok(unsafe { GetCurrentThreadId() }));
+                    hook
+                }
+                Err(e) => {
+                    let _ = tx.send(Err(e));
+                    return;
+                }
+            };
+
+            // Low-level hooks are called from this thread's message loop, until `WM_QUIT`
+            let mut message = MSG::default();
+            while unsafe { GetMessageW(&mut message, HWND::default(), 0, 0) }.as_bool() {
+                unsafe {
+                    TranslateMessage(&message);
+                    DispatchMessageW(&message);
+                }
+            }
+
+            unsafe { UnhookWindowsHookEx(hook) };
+        });
+
+        let thread_id = rx
+            .recv()
+            .map_err(|_| anyhow::anyhow!("The mouse hook thread stopped"))?
+            .map_err(|e| anyhow::anyhow!("Failed to install the mouse hook: {}", e))?;
+
+        Ok(Some(MouseHook {
+            thread_id,
+            thread: Some(thread),
+        }))
+    }
+
+    /// Indexes of the macros whose `mouse_trigger` fired since the last call.
+    pub fn take_fired(&self) -> Vec<usize> {
+        STATE
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_mut()
+            .map(|state| std::mem::take(&mut state.fired))
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for MouseHook {
+    fn drop(&mut self) {
+        unsafe { PostThreadMessageW(self.thread_id, WM_QUIT, WPARAM(0), LPARAM(0)) };
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        *STATE.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        log::debug!("Removed the mouse hook");
+    }
+}
@@ -0,0 +1,253 @@
+use windows::core::{HSTRING, PCWSTR};
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::Graphics::Gdi::{GetStockObject, DEFAULT_GUI_FONT};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::System::Threading::{AttachThreadInput, GetCurrentThreadId};
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+    SetFocus, VIRTUAL_KEY, VK_DOWN, VK_ESCAPE, VK_RETURN, VK_UP,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW, GetForegroundWindow,
+    GetMessageW, GetSystemMetrics, GetWindowTextW, GetWindowThreadProcessId, IsWindow, LoadCursorW,
+    PostMessageW, PostQuitMessage, RegisterClassW, SendMessageW, SetForegroundWindow,
+    TranslateMessage, ES_AUTOHSCROLL, HMENU, IDC_ARROW, LBS_NOINTEGRALHEIGHT, LBS_NOTIFY,
+    LB_ADDSTRING, LB_GETCURSEL, LB_RESETCONTENT, LB_SETCURSEL, MSG, SM_CXSCREEN, SM_CYSCREEN,
+    WA_INACTIVE, WINDOW_STYLE, WM_ACTIVATE, WM_CLOSE, WM_DESTROY, WM_KEYDOWN, WM_SETFONT,
+    WNDCLASSW, WS_BORDER, WS_CHILD, WS_EX_TOOLWINDOW, WS_EX_TOPMOST, WS_POPUP, WS_VISIBLE,
+    WS_VSCROLL,
+};
+
+use super::get_last_windows_error;
+
+const CLASS_NAME: &str = "InputMacroRunnerPalette";
+const WIDTH: i32 = 400;
+const HEIGHT: i32 = 300;
+const FILTER_HEIGHT: i32 = 24;
+/// Longest filter read back from the text box, in UTF-16 units.
+const MAX_FILTER_LENGTH: usize = 256;
+
+/// Whether `macro_name` is listed for `filter`: every word of the filter appears in the name,
+/// ignoring case.
+fn matches_filter(macro_name: &str, filter: &str) -> bool {
+    let macro_name = macro_name.to_lowercase();
+    filter
+        .to_lowercase()
+        .split_whitespace()
+        .all(|word| macro_name.contains(word))
+}
+
+unsafe extern "system" fn palette_proc(
+    hwnd: HWND,
+    message: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match message {
+        // Switching to another window dismisses the palette, like Escape
+        WM_ACTIVATE if (wparam.0 & 0xFFFF) as u32 == WA_INACTIVE => {
+            PostMessageW(hwnd, WM_CLOSE, WPARAM(0), LPARAM(0));
+            LRESULT(0)
+        }
+        WM_DESTROY => {
+            PostQuitMessage(0);
+            LRESULT(0)
+        }
+        _ => DefWindowProcW(hwnd, message, wparam, lparam),
+    }
+}
+
+/// Brings `hwnd` to the foreground. Windows only lets the process owning the foreground window
+/// do that, so the input of its thread is borrowed for the call.
+fn take_foreground(hwnd: HWND, focus: HWND) {
+    unsafe {
+        let foreground_thread =
+            GetWindowThreadProcessId(GetForegroundWindow(), std::ptr::null_mut());
+        let current_thread = GetCurrentThreadId();
+        let attached = foreground_thread != current_thread
+            && AttachThreadInput(current_thread, foreground_thread, true).as_bool();
+
+        if !SetForegroundWindow(hwnd).as_bool() {
+            log::warn!("Failed to bring the palette to the foreground");
+        }
+        SetFocus(focus);
+
+        if attached {
+            AttachThreadInput(current_thread, foreground_thread, false);
+        }
+    }
+}
+
+/// Replaces the entries of `list` with the macros matching `filter`, selecting the first one.
+fn fill_list<'a>(list: HWND, macro_names: &'a [String], filter: &str) -> Vec<&'a String> {
+    let shown: Vec<&String> = macro_names
+        .iter()
+        .filter(|macro_name| matches_filter(macro_name, filter))
+        .collect();
+
+    unsafe {
+        SendMessageW(list, LB_RESETCONTENT, WPARAM(0), LPARAM(0));
+        for macro_name in shown.iter() {
+            let entry: Vec<u16> = macro_name
+                .encode_utf16()
+                .chain(std::iter::once(0))
+                .collect();
+            SendMessageW(
+                list,
+                LB_ADDSTRING,
+                WPARAM(0),
+                LPARAM(entry.as_ptr() as isize),
+            );
+        }
+        SendMessageW(list, LB_SETCURSEL, WPARAM(0), LPARAM(0));
+    }
+
+    shown
+}
+
+fn read_filter(filter_box: HWND) -> String {
+    let mut buffer = [0u16; MAX_FILTER_LENGTH];
+    let copied = unsafe { GetWindowTextW(filter_box, &mut buffer) };
+    String::from_utf16_lossy(&buffer[..copied.max(0) as usize])
+}
+
+/// Shows an always-on-top list of `macro_names`, filtered by what is typed, and blocks until one
+/// is picked with Enter or the list is dismissed with Escape or by switching away. Focus is
+/// handed back to the window that had it before this returns, so the picked macro sends its
+/// input there.
+pub fn pick_macro(macro_names: &[String]) -> Result<Option<String>, anyhow::Error> {
+    let previous = unsafe { GetForegroundWindow() };
+    let instance = unsafe { GetModuleHandleW(PCWSTR::null()) }?;
+    let class_name = HSTRING::from(CLASS_NAME);
+
+    let class = WNDCLASSW {
+        lpfnWndProc: Some(palette_proc),
+        hInstance: instance,
+        hCursor: unsafe { LoadCursorW(None, IDC_ARROW) }?,
+        lpszClassName: PCWSTR::from(&class_name),
+        ..Default::default()
+    };
+    // Fails harmlessly once the class exists from an earlier palette
+    unsafe { RegisterClassW(&class) };
+
+    let (x, y) = unsafe {
+        (
+            (GetSystemMetrics(SM_CXSCREEN) - WIDTH) / 2,
+            (GetSystemMetrics(SM_CYSCREEN) - HEIGHT) / 3,
+        )
+    };
+
+    let palette = unsafe {
+        CreateWindowExW(
+            WS_EX_TOPMOST | WS_EX_TOOLWINDOW,
+            &class_name,
+            &HSTRING::from("Run macro"),
+            WS_POPUP | WS_BORDER | WS_VISIBLE,
+            x,
+            y,
+            WIDTH,
+            HEIGHT,
+            HWND::default(),
+            HMENU::default(),
+            instance,
+            std::ptr::null(),
+        )
+    };
+    if palette.0 == 0 {
+        return Err(anyhow::anyhow!(
+            "Failed to create the palette window: {}",
+            get_last_windows_error()
+        ));
+    }
+
+    let child = |class: &str, style: i32, y: i32, height: i32| unsafe {
+        let hwnd = CreateWindowExW(
+            Default::default(),
+            &HSTRING::from(class),
+            PCWSTR::null(),
+            WS_CHILD | WS_VISIBLE | WS_BORDER | WINDOW_STYLE(style as u32),
+            0,
+            y,
+            WIDTH,
+            height,
+            palette,
+            HMENU::default(),
+            instance,
+            std::ptr::null(),
+        );
+        let font = GetStockObject(DEFAULT_GUI_FONT);
+        SendMessageW(hwnd, WM_SETFONT, WPARAM(font.0 as usize), LPARAM(1));
+        hwnd
+    };
+    let filter_box = child("EDIT", ES_AUTOHSCROLL, 0, FILTER_HEIGHT);
+    let list = child(
+        "LISTBOX",
+        LBS_NOTIFY | LBS_NOINTEGRALHEIGHT | WS_VSCROLL.0 as i32,
+        FILTER_HEIGHT,
+        HEIGHT - FILTER_HEIGHT,
+    );
+
+    let mut filter = String::new();
+    let mut shown = fill_list(list, macro_names, &filter);
+    take_foreground(palette, filter_box);
+
+    let mut picked = None;
+    let mut message = MSG::default();
+
+    while unsafe { GetMessageW(&mut message, HWND::default(), 0, 0) }.as_bool() {
+        // Navigation keys are handled here, whichever control has the focus
+        if message.message == WM_KEYDOWN {
+            let selected = unsafe { SendMessageW(list, LB_GETCURSEL, WPARAM(0), LPARAM(0)) }.0;
+
+            match VIRTUAL_KEY(message.wParam.0 as u16) {
+                VK_RETURN => {
+                    picked = usize::try_from(selected)
+                        .ok()
+                        .and_then(|selected| shown.get(selected))
+                        .map(|macro_name| macro_name.to_string());
+                    break;
+                }
+                VK_ESCAPE => break,
+                key @ (VK_UP | VK_DOWN) => {
+                    let last = shown.len() as isize - 1;
+                    let next = if key == VK_UP {
+                        selected - 1
+                    } else {
+                        selected + 1
+                    };
+                    unsafe {
+                        SendMessageW(
+                            list,
+                            LB_SETCURSEL,
+                            WPARAM(next.clamp(0, last.max(0)) as usize),
+                            LPARAM(0),
+                        )
+                    };
+                    continue;
+                }
+                _ => {}
+            }
+        }
+
+        unsafe {
+            TranslateMessage(&message);
+            DispatchMessageW(&message);
+        }
+
+        let typed = read_filter(filter_box);
+        if typed != filter {
+            filter = typed;
+            shown = fill_list(list, macro_names, &filter);
+        }
+    }
+
+    unsafe {
+        if IsWindow(palette).as_bool() {
+            DestroyWindow(palette);
+        }
+        if previous.0 != 0 {
+            SetForegroundWindow(previous);
+        }
+    }
+
+    Ok(picked)
+}
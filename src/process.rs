@@ -0,0 +1,240 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+#[cfg(test)]
+use std::sync::{Arc, Mutex, PoisonError};
+
+#[cfg(test)]
+use super::clock::Clock;
+
+/// Ids of every running process whose executable name matches `name`, ignoring case.
+#[cfg(windows)]
+pub fn process_ids_by_name(name: &str) -> Result<Vec<u32>, anyhow::Error> {
+    use windows::Win32::Foundation::CloseHandle;
+    use windows::Win32::System::Diagnostics::ToolHelp::{
+        CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+        TH32CS_SNAPPROCESS,
+    };
+
+    let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) }
+        .map_err(|e| anyhow::anyhow!("Failed to snapshot processes: {}", e))?;
+
+    let mut entry = PROCESSENTRY32W {
+        dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32,
+        ..Default::default()
+    };
+
+    let mut process_ids = Vec::new();
+    let mut has_entry = unsafe { Process32FirstW(snapshot, &mut entry) }.as_bool();
+
+    while has_entry {
+        let length = entry
+            .szExeFile
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(entry.szExeFile.len());
+        let exe_name = String::from_utf16_lossy(&entry.szExeFile[..length]);
+
+        if exe_name.eq_ignore_ascii_case(name) {
+            process_ids.push(entry.th32ProcessID);
+        }
+
+        has_entry = unsafe { Process32NextW(snapshot, &mut entry) }.as_bool();
+    }
+
+    unsafe { CloseHandle(snapshot) };
+
+    Ok(process_ids)
+}
+
+#[cfg(windows)]
+pub fn process_running(name: &str) -> Result<bool, anyhow::Error> {
+    Ok(!process_ids_by_name(name)?.is_empty())
+}
+
+/// Reads how much CPU time processes have used, so that `WaitForProcessIdle` can be run against
+/// made-up processes.
+pub trait ProcessMetrics: Send + Sync {
+    /// Kernel and user CPU time used so far by each running process whose executable name
+    /// matches `name`, ignoring case, by process id. Processes that cannot be queried are left
+    /// out.
+    fn cpu_times(&self, name: &str) -> Result<HashMap<u32, Duration>, anyhow::Error>;
+}
+
+/// Reads the CPU times of real processes with `GetProcessTimes`.
+#[derive(Debug, Default)]
+pub struct WindowsProcessMetrics;
+
+#[cfg(windows)]
+impl ProcessMetrics for WindowsProcessMetrics {
+    fn cpu_times(&self, name: &str) -> Result<HashMap<u32, Duration>, anyhow::Error> {
+        use windows::Win32::Foundation::{CloseHandle, FILETIME};
+        use windows::Win32::System::Threading::{
+            GetProcessTimes, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
+        };
+
+        // FILETIMEs count 100ns intervals
+        let duration = |time: FILETIME| {
+            Duration::from_nanos(
+                ((time.dwHighDateTime as u64) << 32 | time.dwLowDateTime as u64) * 100,
+            )
+        };
+
+        let mut cpu_times = HashMap::new();
+        for process_id in process_ids_by_name(name)? {
+            let process = match unsafe {
+                OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, process_id)
+            } {
+                Ok(process) => process,
+                Err(e) => {
+                    log::debug!("Failed to open process {}: {}", process_id, e);
+                    continue;
+                }
+            };
+
+            let (mut creation, mut exit, mut kernel, mut user) = Default::default();
+            let queried = unsafe {
+                GetProcessTimes(process, &mut creation, &mut exit, &mut kernel, &mut user)
+            }
+            .as_bool();
+            unsafe { CloseHandle(process) };
+
+            if queried {
+                cpu_times.insert(process_id, duration(kernel) + duration(user));
+            }
+        }
+
+        Ok(cpu_times)
+    }
+}
+
+/// Processes whose CPU usage changes on a script, for tests: each step sets how much of every
+/// core together a process uses from a time after the provider was made, as read off `clock`.
+/// Every process matches any name, and one no step has started yet is not running.
+#[cfg(test)]
+pub struct ScriptedProcesses {
+    clock: Arc<dyn Clock>,
+    start: Instant,
+    cores: u32,
+    /// From when each process uses what percentage of the CPU, in the order the steps were
+    /// added.
+    timeline: Mutex<Vec<(u32, Duration, f64)>>,
+    /// When the CPU times were read, after the provider was made.
+    reads: Mutex<Vec<Duration>>,
+}
+
+#[cfg(test)]
+impl ScriptedProcesses {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        ScriptedProcesses {
+            start: clock.now(),
+            clock,
+            cores: std::thread::available_parallelism().map_or(1, |cores| cores.get() as u32),
+            timeline: Mutex::new(Vec::new()),
+            reads: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Has `process_id` use `percent` of the CPU from `from` after the provider was made.
+    pub fn uses(&self, process_id: u32, from: Duration, percent: f64) -> &Self {
+        self.timeline
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((process_id, from, percent));
+        self
+    }
+
+    pub fn reads(&self) -> Vec<Duration> {
+        self.reads
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+#[cfg(test)]
+impl ProcessMetrics for ScriptedProcesses {
+    fn cpu_times(&self, _name: &str) -> Result<HashMap<u32, Duration>, anyhow::Error> {
+        let now = self.clock.elapsed(self.start);
+        self.reads
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(now);
+
+        let timeline = self.timeline.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut cpu_times = HashMap::new();
+        for (index, (process_id, from, percent)) in timeline.iter().enumerate() {
+            if *from > now {
+                continue;
+            }
+
+            // Each step lasts until the next one for the same process
+            let until = timeline[index + 1..]
+                .iter()
+                .find(|(next_id, _, _)| next_id == process_id)
+                .map_or(now, |(_, next_from, _)| (*next_from).min(now));
+            let used = (until - *from).mul_f64(percent / 100.0) * self.cores;
+            *cpu_times.entry(*process_id).or_default() += used;
+        }
+
+        Ok(cpu_times)
+    }
+}
+
+/// Turns successive CPU time readings of the processes with one name into CPU usage, as a
+/// percentage of every core together, the way Task Manager shows it.
+pub struct CpuSampler {
+    name: String,
+    cores: u32,
+    last: Option<(Instant, HashMap<u32, Duration>)>,
+}
+
+impl CpuSampler {
+    pub fn new(name: &str) -> Self {
+        CpuSampler {
+            name: name.to_string(),
+            cores: std::thread::available_parallelism().map_or(1, |cores| cores.get() as u32),
+            last: None,
+        }
+    }
+
+    /// Reads the CPU times now and returns the usage since the previous reading, `None` for the
+    /// first. Only processes seen by both readings count, so one starting or exiting in between
+    /// does not skew the result. Fails if no process has the name at the first reading.
+    pub fn sample(
+        &mut self,
+        metrics: &dyn ProcessMetrics,
+        now: Instant,
+    ) -> Result<Option<f32>, anyhow::Error> {
+        let cpu_times = metrics.cpu_times(&self.name)?;
+
+        let usage = match &self.last {
+            None if cpu_times.is_empty() => {
+                return Err(anyhow::anyhow!("No process named {} is running", self.name));
+            }
+            None => None,
+            Some((last_at, last_times)) => {
+                let used: Duration = cpu_times
+                    .iter()
+                    .filter_map(|(process_id, time)| {
+                        last_times
+                            .get(process_id)
+                            .map(|last_time| time.saturating_sub(*last_time))
+                    })
+                    .sum();
+                let available = now.saturating_duration_since(*last_at) * self.cores;
+
+                Some(if available.is_zero() {
+                    0.0
+                } else {
+                    (used.as_secs_f64() / available.as_secs_f64() * 100.0) as f32
+                })
+            }
+        };
+
+        self.last = Some((now, cpu_times));
+        Ok(usage)
+    }
+}
@@ -0,0 +1,162 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use super::{backend::ScreenBackend, window::AllowedTarget, Macro};
+
+/// Profile of the macros that stay available whichever profile is active, as do macros without
+/// a profile.
+pub const GLOBAL_PROFILE: &str = "global";
+
+/// How long the focus must stay on a window before the profile it calls for takes over, so that
+/// alt-tabbing past other apps does not switch profiles on the way.
+const SWITCH_DELAY: Duration = Duration::from_millis(750);
+
+/// How often the window with the focus is looked at.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// One entry of the config's `auto_profile`: while a window it matches has the focus, `profile`
+/// is active. Windows are matched as by `allowed_targets` entries.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfileRule {
+    #[serde(flatten)]
+    pub window: AllowedTarget,
+    pub profile: String,
+}
+
+/// The profile of `current_macro`, `None` for the global one.
+pub fn macro_profile(current_macro: &Macro) -> Option<&str> {
+    current_macro
+        .profile
+        .as_deref()
+        .filter(|profile| *profile != GLOBAL_PROFILE)
+}
+
+/// Whether `a` and `b` can ever be triggered at the same time: one of them is global, or both are
+/// in the same profile.
+pub fn profiles_overlap(a: &Macro, b: &Macro) -> bool {
+    match (macro_profile(a), macro_profile(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => true,
+    }
+}
+
+/// The profile that is active and why, as served by `GET /profile`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActiveProfile {
+    /// `None` while only the global profile is active.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    pub reason: String,
+}
+
+/// Picks the active profile from the window with the focus, following `auto_profile`. Macros
+/// already running are left alone when it switches.
+pub struct ProfileSwitcher {
+    rules: Vec<ProfileRule>,
+    active: ActiveProfile,
+    /// The profile the window with the focus calls for, when it is not the active one, and since
+    /// when it has.
+    pending: Option<(ActiveProfile, Instant)>,
+    last_poll: Option<Instant>,
+}
+
+impl ProfileSwitcher {
+    pub fn new(rules: Vec<ProfileRule>) -> Self {
+        let reason = if rules.is_empty() {
+            "the config has no auto_profile rules"
+        } else {
+            "no window has matched an auto_profile rule yet"
+        };
+
+        ProfileSwitcher {
+            rules,
+            active: ActiveProfile {
+                profile: None,
+                reason: reason.to_string(),
+            },
+            pending: None,
+            last_poll: None,
+        }
+    }
+
+    pub fn active(&self) -> &ActiveProfile {
+        &self.active
+    }
+
+    /// Whether `current_macro` may be triggered while the active profile is.
+    pub fn allows(&self, current_macro: &Macro) -> bool {
+        macro_profile(current_macro)
+            .is_none_or(|profile| self.active.profile.as_deref() == Some(profile))
+    }
+
+    /// Looks at the window with the focus, at most every `POLL_INTERVAL`, and switches to the
+    /// profile it calls for once it has called for it for `SWITCH_DELAY`. Returns whether the
+    /// profile changed.
+    pub fn poll(&mut self, screen: &dyn ScreenBackend, now: Instant) -> bool {
+        if self.rules.is_empty()
+            || self
+                .last_poll
+                .is_some_and(|last_poll| now.saturating_duration_since(last_poll) < POLL_INTERVAL)
+        {
+            return false;
+        }
+        self.last_poll = Some(now);
+
+        let wanted = self.select(screen);
+        if wanted.profile == self.active.profile {
+            self.pending = None;
+            return false;
+        }
+
+        let since = match &self.pending {
+            Some((pending, since)) if pending.profile == wanted.profile => *since,
+            _ => {
+                self.pending = Some((wanted, now));
+                return false;
+            }
+        };
+        if now.saturating_duration_since(since) < SWITCH_DELAY {
+            return false;
+        }
+
+        self.pending = None;
+        self.active = wanted;
+        true
+    }
+
+    /// The profile the window with the focus calls for, the first rule matching it winning.
+    fn select(&self, screen: &dyn ScreenBackend) -> ActiveProfile {
+        let window = match screen.foreground_window() {
+            Ok(Some(window)) => window,
+            Ok(None) => {
+                return ActiveProfile {
+                    profile: None,
+                    reason: "no window has the focus".to_string(),
+                }
+            }
+            Err(e) => {
+                return ActiveProfile {
+                    profile: None,
+                    reason: format!("the window with the focus could not be inspected ({})", e),
+                }
+            }
+        };
+
+        match self
+            .rules
+            .iter()
+            .enumerate()
+            .find(|(_, rule)| rule.window.matches(&window))
+        {
+            Some((index, rule)) => ActiveProfile {
+                profile: Some(rule.profile.clone()),
+                reason: format!("{} matches auto_profile rule {}", window, index + 1),
+            },
+            None => ActiveProfile {
+                profile: None,
+                reason: format!("{} matches no auto_profile rule", window),
+            },
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    sync::{Arc, Mutex, PoisonError, RwLock},
+    time::{Duration, Instant},
+};
+
+use super::{
+    clock::{Clock, SystemClock},
+    context::CancellationToken,
+    error::MacroError,
+};
+
+/// Input events per second all macros together may inject unless the config's
+/// `max_events_per_second` says otherwise.
+pub const DEFAULT_MAX_EVENTS_PER_SECOND: u32 = 500;
+
+/// Asking for this many times the limit, sustained for a second, fails with `RateLimited` instead
+/// of being slowed down.
+const HARD_CEILING_FACTOR: f64 = 5.0;
+
+/// How far back the rate injection is asked for is measured.
+const WINDOW: Duration = Duration::from_secs(1);
+
+struct Bucket {
+    /// Events that may be injected right away. Negative while events are waiting their turn.
+    tokens: f64,
+    updated: Instant,
+    /// Time that has passed with nothing held back, i.e. the time macros spent asking for input
+    /// rather than waiting for it.
+    demand_time: Duration,
+    /// Events asked for within the last `WINDOW` of `demand_time`, with when they were asked for.
+    requested: VecDeque<(Duration, f64)>,
+    requested_total: f64,
+}
+
+/// A token bucket shared by every macro: it holds up to a second's worth of events, refills at
+/// `max_events_per_second`, and makes injection wait whenever it runs dry.
+///
+/// Waiting hides how fast macros really ask for input, so the rate asked for is measured apart
+/// from it, over time spent anywhere but waiting here. A macro that keeps asking for
+/// `HARD_CEILING_FACTOR` times the limit fails instead of being slowed down for ever.
+pub struct RateLimiter {
+    max_events_per_second: f64,
+    clock: Arc<dyn Clock>,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(max_events_per_second: u32, clock: Arc<dyn Clock>) -> Self {
+        let max_events_per_second = max_events_per_second as f64;
+        RateLimiter {
+            max_events_per_second,
+            bucket: Mutex::new(Bucket {
+                tokens: max_events_per_second,
+                updated: clock.now(),
+                demand_time: Duration::ZERO,
+                requested: VecDeque::new(),
+                requested_total: 0.0,
+            }),
+            clock,
+        }
+    }
+
+    /// Takes `events` from the bucket, waiting until they fit under the limit or `cancellation`
+    /// is cancelled. Fails with `RateLimited`, taking nothing, when the events asked for within
+    /// the last second of time not spent waiting would come to more than `HARD_CEILING_FACTOR`
+    /// seconds' worth. A single batch counts as at most a second's worth, so that one long text
+    /// is slowed down rather than failed.
+    pub fn acquire(
+        &self,
+        events: usize,
+        cancellation: &CancellationToken,
+    ) -> Result<(), MacroError> {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap_or_else(PoisonError::into_inner);
+            let now = self.clock.now();
+            let passed = now.saturating_duration_since(bucket.updated);
+            // While the bucket was in debt, somebody was waiting for it
+            let waited = if bucket.tokens < 0.0 {
+                passed.min(Duration::from_secs_f64(
+                    -bucket.tokens / self.max_events_per_second,
+                ))
+            } else {
+                Duration::ZERO
+            };
+            bucket.demand_time += passed - waited;
+            bucket.tokens = (bucket.tokens + passed.as_secs_f64() * self.max_events_per_second)
+                .min(self.max_events_per_second);
+            bucket.updated = now;
+
+            while let Some(&(at, requested)) = bucket.requested.front() {
+                if bucket.demand_time.saturating_sub(at) < WINDOW {
+                    break;
+                }
+                bucket.requested.pop_front();
+                bucket.requested_total -= requested;
+            }
+
+            let requested = (events as f64).min(self.max_events_per_second);
+            if bucket.requested_total + requested > HARD_CEILING_FACTOR * self.max_events_per_second
+            {
+                return Err(MacroError::RateLimited {
+                    max_events_per_second: self.max_events_per_second as u32,
+                });
+            }
+            let demand_time = bucket.demand_time;
+            bucket.requested.push_back((demand_time, requested));
+            bucket.requested_total += requested;
+            bucket.tokens -= events as f64;
+
+            Duration::from_secs_f64((-bucket.tokens).max(0.0) / self.max_events_per_second)
+        };
+
+        if !wait.is_zero() {
+            log::debug!("Holding {} input event(s) back for {:?}", events, wait);
+            if !self.clock.sleep(wait, cancellation) {
+                // Nothing is injected after all, so the events go back into the bucket
+                let mut bucket = self.bucket.lock().unwrap_or_else(PoisonError::into_inner);
+                bucket.tokens = (bucket.tokens + events as f64).min(self.max_events_per_second);
+                return Err(MacroError::Cancelled);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The limiter every injection goes through, `None` when the config turns limiting off.
+static LIMITER: RwLock<Option<Arc<RateLimiter>>> = RwLock::new(None);
+
+/// Limits injection to `max_events_per_second` from now on, or not at all for 0.
+pub fn configure(max_events_per_second: u32) {
+    let limiter = (max_events_per_second > 0).then(|| {
+        Arc::new(RateLimiter::new(
+            max_events_per_second,
+            Arc::new(SystemClock),
+        ))
+    });
+    *LIMITER.write().unwrap_or_else(PoisonError::into_inner) = limiter;
+}
+
+thread_local! {
+    /// The cancellation of the execution running on this thread, which waits for the limit end on.
+    static CANCELLATION: RefCell<CancellationToken> = RefCell::new(CancellationToken::default());
+}
+
+/// Puts back the cancellation that was set before `cancel_with` when dropped.
+pub struct CancellationScope(Option<CancellationToken>);
+
+impl Drop for CancellationScope {
+    fn drop(&mut self) {
+        if let Some(previous) = self.0.take() {
+            CANCELLATION.with(|current| *current.borrow_mut() = previous);
+        }
+    }
+}
+
+/// Makes waits for the limit on this thread end when `cancellation` is cancelled, until the
+/// returned scope is dropped.
+pub fn cancel_with(cancellation: CancellationToken) -> CancellationScope {
+    CancellationScope(Some(
+        CANCELLATION.with(|current| current.replace(cancellation)),
+    ))
+}
+
+/// Waits until `events` more input events fit under the configured limit, see
+/// `RateLimiter::acquire`. Fails with `Cancelled` when the execution on this thread is cancelled
+/// while waiting.
+pub fn acquire(events: usize) -> Result<(), MacroError> {
+    let limiter = LIMITER
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+
+    match limiter {
+        Some(limiter) => {
+            let cancellation = CANCELLATION.with(|current| current.borrow().clone());
+            limiter.acquire(events, &cancellation)
+        }
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::VirtualClock;
+
+    fn limiter(max_events_per_second: u32) -> (RateLimiter, Arc<VirtualClock>) {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(3600)));
+        (
+            RateLimiter::new(max_events_per_second, clock.clone()),
+            clock,
+        )
+    }
+
+    #[test]
+    fn under_the_limit_nothing_waits() {
+        let (limiter, clock) = limiter(100);
+        let cancellation = CancellationToken::default();
+
+        for _ in 0..50 {
+            limiter.acquire(2, &cancellation).unwrap();
+        }
+        assert_eq!(clock.elapsed_total(), Duration::ZERO);
+    }
+
+    #[test]
+    fn over_the_limit_waits_for_the_debt() {
+        let (limiter, clock) = limiter(100);
+        let cancellation = CancellationToken::default();
+
+        limiter.acquire(100, &cancellation).unwrap();
+        limiter.acquire(50, &cancellation).unwrap();
+        assert_eq!(clock.elapsed_total(), Duration::from_millis(500));
+
+        // The wait paid the debt off, so the next event waits only for itself
+        limiter.acquire(1, &cancellation).unwrap();
+        assert_eq!(clock.elapsed_total(), Duration::from_millis(510));
+    }
+
+    #[test]
+    fn the_bucket_refills_while_macros_do_other_things() {
+        let (limiter, clock) = limiter(100);
+        let cancellation = CancellationToken::default();
+
+        limiter.acquire(100, &cancellation).unwrap();
+        clock.sleep(Duration::from_secs(2), &cancellation);
+        limiter.acquire(100, &cancellation).unwrap();
+        assert_eq!(clock.elapsed_total(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn a_runaway_macro_is_stopped() {
+        let (limiter, clock) = limiter(100);
+        let cancellation = CancellationToken::default();
+
+        // Pressing keys in a loop with nothing in between, each press waiting its turn
+        let mut presses = 0;
+        let error = loop {
+            match limiter.acquire(2, &cancellation) {
+                Ok(()) => presses += 1,
+                Err(e) => break e,
+            }
+            assert!(presses <= 1000, "never stopped");
+        };
+
+        assert!(matches!(
+            error,
+            MacroError::RateLimited {
+                max_events_per_second: 100
+            }
+        ));
+        assert_eq!(presses, 250);
+        // The first second's worth went through at once, the rest at the limit
+        assert_eq!(clock.elapsed_total(), Duration::from_secs(4));
+    }
+
+    #[test]
+    fn a_steady_rate_over_the_limit_is_only_slowed_down() {
+        let (limiter, clock) = limiter(100);
+        let cancellation = CancellationToken::default();
+
+        // Asking for twice the limit, for far longer than a second
+        for _ in 0..2000 {
+            limiter.acquire(1, &cancellation).unwrap();
+            clock.sleep(Duration::from_millis(5), &cancellation);
+        }
+        assert!(clock.elapsed_total() >= Duration::from_secs(19));
+    }
+
+    #[test]
+    fn a_single_long_batch_is_only_slowed_down() {
+        let (limiter, clock) = limiter(100);
+        let cancellation = CancellationToken::default();
+
+        limiter.acquire(2000, &cancellation).unwrap();
+        limiter.acquire(2, &cancellation).unwrap();
+        assert_eq!(clock.elapsed_total(), Duration::from_millis(19_020));
+    }
+
+    #[test]
+    fn cancelling_ends_the_wait() {
+        let (limiter, clock) = limiter(100);
+        let cancellation = CancellationToken::default();
+
+        limiter.acquire(100, &cancellation).unwrap();
+        cancellation.cancel();
+        assert!(matches!(
+            limiter.acquire(50, &cancellation),
+            Err(MacroError::Cancelled)
+        ));
+        assert_eq!(clock.elapsed_total(), Duration::ZERO);
+
+        // The cancelled events went back into the bucket
+        let cancellation = CancellationToken::default();
+        limiter.acquire(10, &cancellation).unwrap();
+        assert_eq!(clock.elapsed_total(), Duration::from_millis(100));
+    }
+}
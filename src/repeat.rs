@@ -0,0 +1,97 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{sleep, spawn, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{context::CancellationToken, key_down, Key};
+
+/// OS-style auto-repeat for a held key: after `initial_delay_ms` the key-down event is re-sent
+/// every `interval_ms` until the key is released.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyRepeat {
+    #[serde(default = "default_initial_delay_ms")]
+    pub initial_delay_ms: u64,
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+}
+
+// Windows' default keyboard delay and repeat rate
+fn default_initial_delay_ms() -> u64 {
+    500
+}
+
+fn default_interval_ms() -> u64 {
+    33
+}
+
+/// Re-sends key-down events for a held key on a background thread until dropped.
+pub struct KeyRepeater {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl KeyRepeater {
+    pub fn start(key: Key, repeat: KeyRepeat, cancellation: CancellationToken) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+
+        let handle = spawn(move || {
+            let stopped = || thread_stop.load(Ordering::SeqCst) || cancellation.is_cancelled();
+
+            if !wait_unless(Duration::from_millis(repeat.initial_delay_ms), &stopped) {
+                return;
+            }
+
+            loop {
+                if let Err(e) = key_down(key as i32) {
+                    log::error!("Stopping auto-repeat for {:?}: {}", key, e);
+                    return;
+                }
+
+                if !wait_unless(Duration::from_millis(repeat.interval_ms), &stopped) {
+                    return;
+                }
+            }
+        });
+
+        KeyRepeater {
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for KeyRepeater {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Sleeps for `duration` in short slices, returning false as soon as `stopped` reports true.
+fn wait_unless(duration: Duration, stopped: &impl Fn() -> bool) -> bool {
+    const SLICE: Duration = Duration::from_millis(5);
+
+    let deadline = Instant::now() + duration;
+
+    loop {
+        if stopped() {
+            return false;
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+
+        sleep(SLICE.min(deadline - now));
+    }
+}
@@ -0,0 +1,183 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc,
+    },
+    thread::{sleep, spawn, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use super::{
+    backend, clock, event_bus, executor, http, input_block, key_held, listener, open_palette,
+    profile, startup, watchdog, MacroConfig, Message,
+};
+
+/// Runs the macros of a config built in code, as the `run` subcommand runs those of a config file.
+pub struct MacroRunner;
+
+impl MacroRunner {
+    /// Starts listening for the triggers of `macro_config`'s macros and running them, until the
+    /// returned handle is stopped or the program hotkey is held.
+    pub fn run(macro_config: MacroConfig) -> Result<RunnerHandle, anyhow::Error> {
+        macro_config.validate()?;
+        start(macro_config, None, false, false)
+    }
+}
+
+/// A running `MacroRunner`. Dropping it stops it as `stop` does.
+pub struct RunnerHandle {
+    tx: Sender<Message>,
+    /// Tells the hotkey thread to finish.
+    stopping: Arc<AtomicBool>,
+    listener: Option<JoinHandle<Result<(), anyhow::Error>>>,
+    hotkeys: Option<JoinHandle<()>>,
+}
+
+impl RunnerHandle {
+    /// Cancels the running macros, stops listening and waits for the listener to finish.
+    pub fn stop(mut self) -> Result<(), anyhow::Error> {
+        // Already gone if the program hotkey stopped it
+        let _ = self.tx.send(Message::Exit);
+        self.join()
+    }
+
+    /// Waits until the program hotkey stops the runner.
+    pub fn wait(mut self) -> Result<(), anyhow::Error> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<(), anyhow::Error> {
+        let result = match self.listener.take().map(JoinHandle::join) {
+            Some(Ok(result)) => {
+                log::info!("Input listener thread exited");
+                result
+            }
+            Some(Err(e)) => Err(anyhow::anyhow!("Input listener thread panicked: {:?}", e)),
+            None => Ok(()),
+        };
+
+        self.stopping.store(true, Ordering::SeqCst);
+        if let Some(hotkeys) = self.hotkeys.take() {
+            let _ = hotkeys.join();
+        }
+
+        result
+    }
+}
+
+impl Drop for RunnerHandle {
+    fn drop(&mut self) {
+        if self.listener.is_some() {
+            let _ = self.tx.send(Message::Exit);
+            if let Err(e) = self.join() {
+                log::error!("Error: {}", e);
+            }
+        }
+    }
+}
+
+/// Starts the executor, the input listener and, where configured, the HTTP endpoint for
+/// `macro_config`, which must have been validated, along with a thread watching the program and
+/// palette hotkeys.
+pub fn start(
+    macro_config: MacroConfig,
+    config_path: Option<PathBuf>,
+    events_stdout: bool,
+    trace_triggers: bool,
+) -> Result<RunnerHandle, anyhow::Error> {
+    #[cfg(debug_assertions)]
+    log::info!("{:#?}", macro_config);
+
+    let startup_report = Arc::new(startup::StartupReport::build(&macro_config, config_path));
+    startup_report.log();
+
+    let (tx, rx) = std::sync::mpsc::channel();
+
+    let (events, _) = event_bus(&macro_config, events_stdout);
+
+    if let Some(idle_secs) = macro_config.modifier_watchdog_secs {
+        let watchdog_rx = events.subscribe();
+        spawn(move || watchdog::watch_modifiers(watchdog_rx, Duration::from_secs(idle_secs)));
+    }
+
+    let palette_macros: Arc<Vec<String>> = Arc::new(
+        macro_config
+            .macros
+            .iter()
+            .filter(|current_macro| current_macro.enabled)
+            .map(|current_macro| current_macro.macro_name.clone())
+            .collect(),
+    );
+
+    // Spawn a worker thread that acts as an input listener and executes the macros
+    let mut executor = executor::Executor::new(
+        macro_config.macros,
+        macro_config.max_macro_threads,
+        macro_config.worker_threads,
+        events,
+        backend::input_backend()?,
+        Arc::new(backend::GdiScreen),
+        Arc::new(clock::SystemClock),
+    );
+    executor.set_seed(macro_config.seed);
+    executor.set_allowed_targets(macro_config.allowed_targets.clone());
+    input_block::set_allowed_keys(&macro_config.program_hotkey);
+
+    if let Some(http_config) = macro_config.http.clone() {
+        let http_tx = tx.clone();
+        spawn(move || {
+            if let Err(e) = http::serve(http_config, http_tx, startup_report) {
+                log::error!("HTTP endpoint stopped: {}", e);
+            }
+        });
+    }
+
+    let on_lock = macro_config.on_lock;
+    let profiles = profile::ProfileSwitcher::new(macro_config.auto_profile.clone());
+    let listener =
+        spawn(move || listener::input_listener(executor, on_lock, profiles, trace_triggers, rx));
+
+    let stopping = Arc::new(AtomicBool::new(false));
+    let hotkeys = {
+        let tx = tx.clone();
+        let stopping = stopping.clone();
+        let program_hotkey = macro_config.program_hotkey;
+        let palette_hotkey = macro_config.palette_hotkey;
+        let mut exit_hold = listener::HoldTracker::new(
+            "Exit",
+            Duration::from_millis(macro_config.program_hotkey_hold_ms),
+        );
+
+        spawn(move || {
+            let palette_open = Arc::new(AtomicBool::new(false));
+            let mut palette_held = false;
+
+            while !stopping.load(Ordering::SeqCst) {
+                // If program_hotkey is held long enough, exit program
+                let exit_held = program_hotkey.iter().all(|key| key_held(key.virtual_key()));
+                if exit_hold.update(exit_held, Instant::now()) {
+                    let _ = tx.send(Message::Exit);
+                    break;
+                }
+
+                let held = !palette_hotkey.is_empty()
+                    && palette_hotkey.iter().all(|key| key_held(key.virtual_key()));
+                if held && !palette_held {
+                    open_palette(palette_macros.clone(), tx.clone(), palette_open.clone());
+                }
+                palette_held = held;
+
+                sleep(Duration::from_millis(50));
+            }
+        })
+    };
+
+    Ok(RunnerHandle {
+        tx,
+        stopping,
+        listener: Some(listener),
+        hotkeys: Some(hotkeys),
+    })
+}
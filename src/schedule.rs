@@ -0,0 +1,270 @@
+use std::{fmt, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Part of the day, as `HH:MM` in local time, during which a macro may be triggered. An `end`
+/// earlier than `start` is an overnight window, e.g. 22:00 to 06:00, and an `end` equal to `start`
+/// covers the whole day.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActiveHours {
+    pub start: TimeOfDay,
+    pub end: TimeOfDay,
+}
+
+/// A time of day, as minutes since midnight, written in the config as `HH:MM`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimeOfDay(u32);
+
+/// A day of the week (0 is Sunday), written in the config as a name such as `mon` or `Monday`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Weekday(u32);
+
+/// Local wall-clock time, as minutes since midnight and day of the week (0 is Sunday).
+#[derive(Debug, Copy, Clone)]
+pub struct LocalTime {
+    pub minutes: u32,
+    pub weekday: u32,
+}
+
+#[cfg(windows)]
+pub fn local_time() -> LocalTime {
+    use windows::Win32::System::SystemInformation::GetLocalTime;
+
+    let mut time = Default::default();
+    unsafe { GetLocalTime(&mut time) };
+
+    LocalTime {
+        minutes: u32::from(time.wHour) * 60 + u32::from(time.wMinute),
+        weekday: u32::from(time.wDayOfWeek),
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = anyhow::Error;
+
+    /// Parses `HH:MM`.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || anyhow::anyhow!("invalid time {:?}, expected HH:MM", text);
+
+        let (hours, minutes) = text.split_once(':').ok_or_else(invalid)?;
+        let hours: u32 = hours.trim().parse().map_err(|_| invalid())?;
+        let minutes: u32 = minutes.trim().parse().map_err(|_| invalid())?;
+
+        if hours >= 24 || minutes >= 60 {
+            return Err(invalid());
+        }
+
+        Ok(TimeOfDay(hours * 60 + minutes))
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.0 / 60, self.0 % 60)
+    }
+}
+
+impl FromStr for Weekday {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let day = match name.to_ascii_lowercase().as_str() {
+            "sun" | "sunday" => 0,
+            "mon" | "monday" => 1,
+            "tue" | "tuesday" => 2,
+            "wed" | "wednesday" => 3,
+            "thu" | "thursday" => 4,
+            "fri" | "friday" => 5,
+            "sat" | "saturday" => 6,
+            _ => return Err(anyhow::anyhow!("invalid day {:?}, expected mon..sun", name)),
+        };
+
+        Ok(Weekday(day))
+    }
+}
+
+impl fmt::Display for Weekday {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+        f.write_str(names[self.0 as usize])
+    }
+}
+
+/// Serializes `value` as the text it is parsed from.
+fn serialize_text<T: fmt::Display, S: Serializer>(
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+/// Deserializes a value from text, so that a bad time or day fails as the config loads.
+fn deserialize_text<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: FromStr<Err = anyhow::Error>,
+    D: Deserializer<'de>,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(de::Error::custom)
+}
+
+impl Serialize for TimeOfDay {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_text(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeOfDay {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_text(deserializer)
+    }
+}
+
+impl Serialize for Weekday {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_text(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Weekday {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_text(deserializer)
+    }
+}
+
+/// Whether `now` falls inside the given hours and days. When an overnight window has run past
+/// midnight, it is the day the window started on that has to be active.
+pub fn is_active(
+    active_hours: Option<&ActiveHours>,
+    active_days: &[Weekday],
+    now: LocalTime,
+) -> bool {
+    let mut day = now.weekday;
+
+    if let Some(ActiveHours {
+        start: TimeOfDay(start),
+        end: TimeOfDay(end),
+    }) = active_hours.copied()
+    {
+        let within = if start == end {
+            true
+        } else if start < end {
+            start <= now.minutes && now.minutes < end
+        } else {
+            now.minutes >= start || now.minutes < end
+        };
+
+        if !within {
+            return false;
+        }
+
+        if start > end && now.minutes < end {
+            day = (day + 6) % 7;
+        }
+    }
+
+    active_days.is_empty() || active_days.contains(&Weekday(day))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(weekday: u32, hours: u32, minutes: u32) -> LocalTime {
+        LocalTime {
+            minutes: hours * 60 + minutes,
+            weekday,
+        }
+    }
+
+    fn hours(start: &str, end: &str) -> ActiveHours {
+        ActiveHours {
+            start: start.parse().unwrap(),
+            end: end.parse().unwrap(),
+        }
+    }
+
+    fn days(names: &[&str]) -> Vec<Weekday> {
+        names.iter().map(|name| name.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn daytime_windows_include_the_start_and_not_the_end() {
+        let office = hours("09:00", "17:30");
+        let weekdays = days(&["mon", "tue", "wed", "thu", "fri"]);
+
+        assert!(!is_active(Some(&office), &weekdays, at(1, 8, 59)));
+        assert!(is_active(Some(&office), &weekdays, at(1, 9, 0)));
+        assert!(is_active(Some(&office), &weekdays, at(5, 17, 29)));
+        assert!(!is_active(Some(&office), &weekdays, at(5, 17, 30)));
+        assert!(!is_active(Some(&office), &weekdays, at(6, 12, 0)));
+        assert!(is_active(None, &weekdays, at(3, 0, 0)));
+        assert!(is_active(Some(&hours("12:00", "12:00")), &[], at(0, 3, 0)));
+    }
+
+    #[test]
+    fn overnight_windows_run_past_midnight() {
+        let night = hours("22:00", "06:00");
+
+        assert!(!is_active(Some(&night), &[], at(3, 21, 59)));
+        assert!(is_active(Some(&night), &[], at(3, 22, 0)));
+        assert!(is_active(Some(&night), &[], at(3, 23, 59)));
+        assert!(is_active(Some(&night), &[], at(4, 0, 0)));
+        assert!(is_active(Some(&night), &[], at(4, 5, 59)));
+        assert!(!is_active(Some(&night), &[], at(4, 6, 0)));
+        assert!(!is_active(Some(&night), &[], at(4, 12, 0)));
+    }
+
+    #[test]
+    fn overnight_windows_belong_to_the_day_they_start_on() {
+        let night = hours("22:00", "06:00");
+        let friday = days(&["friday"]);
+
+        assert!(!is_active(Some(&night), &friday, at(5, 3, 0)));
+        assert!(is_active(Some(&night), &friday, at(5, 23, 0)));
+        assert!(is_active(Some(&night), &friday, at(6, 3, 0)));
+        assert!(!is_active(Some(&night), &friday, at(6, 23, 0)));
+
+        // Saturday night runs into Sunday, which wraps around to the start of the week
+        let saturday = days(&["Sat"]);
+        assert!(is_active(Some(&night), &saturday, at(0, 5, 59)));
+        assert!(!is_active(Some(&night), &days(&["sun"]), at(0, 5, 59)));
+
+        // Outside an overnight window, the day is the current one
+        assert!(is_active(None, &saturday, at(6, 0, 0)));
+        assert!(!is_active(None, &saturday, at(0, 0, 0)));
+    }
+
+    #[test]
+    fn times_and_days_are_parsed_as_they_load() {
+        let active_hours: ActiveHours =
+            serde_yaml::from_str("{start: \"9:05\", end: \"23:59\"}").unwrap();
+        assert_eq!(active_hours, hours("09:05", "23:59"));
+        assert_eq!(
+            serde_yaml::to_string(&active_hours).unwrap(),
+            "start: 09:05\nend: 23:59\n"
+        );
+        assert_eq!(
+            serde_yaml::from_str::<Vec<Weekday>>("[mon, Tuesday, SUN]").unwrap(),
+            [Weekday(1), Weekday(2), Weekday(0)]
+        );
+
+        for (yaml, error) in [
+            (
+                "{start: \"24:00\", end: \"06:00\"}",
+                "invalid time \"24:00\"",
+            ),
+            ("{start: \"22:00\", end: \"6\"}", "invalid time \"6\""),
+            (
+                "{start: \"22:60\", end: \"06:00\"}",
+                "invalid time \"22:60\"",
+            ),
+        ] {
+            let e = serde_yaml::from_str::<ActiveHours>(yaml).unwrap_err();
+            assert!(e.to_string().contains(error), "{}", e);
+        }
+        let e = serde_yaml::from_str::<Vec<Weekday>>("[mon, someday]").unwrap_err();
+        assert!(e.to_string().contains("invalid day \"someday\""), "{}", e);
+    }
+}
@@ -0,0 +1,957 @@
+use std::collections::BTreeSet;
+
+use serde::{
+    de::{self, value::Error, DeserializeOwned, IntoDeserializer, Visitor},
+    forward_to_deserialize_any, Deserializer, Serialize,
+};
+
+use super::{Command, Macro, MacroConfig};
+
+/// One argument of a command: a field of a command written as a map, or a position of one
+/// written as a value or a list.
+#[derive(Debug, Serialize)]
+struct ArgSchema {
+    name: &'static str,
+    /// One of the names in `TYPES`, `list of` one of them, or the accepted values separated by
+    /// `|`.
+    r#type: &'static str,
+    /// The value used when the argument is left out, as written in the config. Required
+    /// arguments have none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default: Option<&'static str>,
+    doc: &'static str,
+}
+
+/// What a command does and what it takes.
+#[derive(Debug, Serialize)]
+struct CommandSchema {
+    name: &'static str,
+    doc: &'static str,
+    args: &'static [ArgSchema],
+}
+
+const fn arg(name: &'static str, r#type: &'static str, doc: &'static str) -> ArgSchema {
+    ArgSchema {
+        name,
+        r#type,
+        default: None,
+        doc,
+    }
+}
+
+const fn optional(
+    name: &'static str,
+    r#type: &'static str,
+    default: &'static str,
+    doc: &'static str,
+) -> ArgSchema {
+    ArgSchema {
+        name,
+        r#type,
+        default: Some(default),
+        doc,
+    }
+}
+
+/// The argument types that are more than a plain string, number or boolean.
+const TYPES: &[(&str, &str)] = &[
+    (
+        "coordinate",
+        "A number of pixels, or an expression over `${}` variables such as \"200 + 32 * \
+         ${loop_index}\" or \"50%\" of the primary screen",
+    ),
+    ("color", "A color written as \"#RRGGBB\", e.g. \"#FF8800\""),
+    (
+        "duration",
+        "A number of milliseconds, or a duration such as \"1m30s\"",
+    ),
+    (
+        "key",
+        "A key name such as `LeftControl` or `A`, or a raw virtual-key code",
+    ),
+    ("commands", "A list of commands"),
+    (
+        "key_repeat",
+        "A map of `initial_delay_ms` (default 500) and `interval_ms` (default 33)",
+    ),
+    (
+        "point",
+        "A list of `[x, y, ms]`, where `ms` is the time since the start of the path",
+    ),
+    ("map", "A map of strings to strings"),
+];
+
+/// Every command, described by hand. `check` keeps this in step with `Command`.
+const COMMANDS: &[CommandSchema] = &[
+    CommandSchema {
+        name: "GetMousePos",
+        doc: "Prints the mouse position.",
+        args: &[],
+    },
+    CommandSchema {
+        name: "SetMousePos",
+        doc: "Moves the mouse to a screen position.",
+        args: &[
+            arg("x", "coordinate", "Horizontal position."),
+            arg("y", "coordinate", "Vertical position."),
+        ],
+    },
+    CommandSchema {
+        name: "LeftClick",
+        doc: "Clicks the left mouse button.",
+        args: &[],
+    },
+    CommandSchema {
+        name: "MiddleClick",
+        doc: "Clicks the middle mouse button.",
+        args: &[],
+    },
+    CommandSchema {
+        name: "RightClick",
+        doc: "Clicks the right mouse button.",
+        args: &[],
+    },
+    CommandSchema {
+        name: "ScrollLines",
+        doc: "Scrolls the wheel by a number of lines, up for positive counts and down for \
+              negative ones.",
+        args: &[arg("lines", "integer", "Lines to scroll.")],
+    },
+    CommandSchema {
+        name: "PressKey",
+        doc: "Presses and releases a key.",
+        args: &[arg("key", "key", "Key to press.")],
+    },
+    CommandSchema {
+        name: "PressKeyCombo",
+        doc: "Presses keys together, e.g. Ctrl+C, and releases them in reverse.",
+        args: &[arg("keys", "list of key", "Keys to press.")],
+    },
+    CommandSchema {
+        name: "TextInput",
+        doc: "Types text.",
+        args: &[arg(
+            "text",
+            "string",
+            "Text to type, may use `${}` variables. `$${` types a literal `${`.",
+        )],
+    },
+    CommandSchema {
+        name: "Wait",
+        doc: "Waits before the next command.",
+        args: &[arg("duration", "duration", "How long to wait.")],
+    },
+    CommandSchema {
+        name: "Loop",
+        doc: "Runs commands a number of times. `${loop_index}` is the zero-based iteration.",
+        args: &[
+            arg("iterations", "integer", "How many times, 0 for forever."),
+            arg("commands", "commands", "Loop body."),
+        ],
+    },
+    CommandSchema {
+        name: "NamedLoop",
+        doc: "A `Loop` whose index nested loops can reach as `${loop:<name>}`.",
+        args: &[
+            arg(
+                "name",
+                "string",
+                "Name for `${loop:<name>}`, `Break` and `Continue`.",
+            ),
+            arg("iterations", "integer", "How many times, 0 for forever."),
+            arg("commands", "commands", "Loop body."),
+        ],
+    },
+    CommandSchema {
+        name: "Break",
+        doc: "Leaves the innermost loop, or the enclosing loop with this name.",
+        args: &[optional(
+            "loop",
+            "string",
+            "null",
+            "Name of the loop to leave.",
+        )],
+    },
+    CommandSchema {
+        name: "Continue",
+        doc: "Skips to the next iteration of the innermost loop, or of the enclosing loop with \
+              this name.",
+        args: &[optional(
+            "loop",
+            "string",
+            "null",
+            "Name of the loop to continue.",
+        )],
+    },
+    CommandSchema {
+        name: "SendKeyToWindow",
+        doc: "Posts a key press to a window without focusing it. Many apps ignore posted keys.",
+        args: &[
+            optional("title", "string", "null", "Exact window title."),
+            optional(
+                "class",
+                "string",
+                "null",
+                "Window class name, e.g. `Notepad`.",
+            ),
+            optional(
+                "process",
+                "string",
+                "null",
+                "Executable file name, e.g. `notepad.exe`.",
+            ),
+            optional(
+                "current_desktop_only",
+                "boolean",
+                "false",
+                "Skip windows on other virtual desktops.",
+            ),
+            arg("key", "key", "Key to post."),
+        ],
+    },
+    CommandSchema {
+        name: "SendTextToWindow",
+        doc: "Posts text to a window without focusing it. Many apps ignore posted keys.",
+        args: &[
+            optional("title", "string", "null", "Exact window title."),
+            optional(
+                "class",
+                "string",
+                "null",
+                "Window class name, e.g. `Notepad`.",
+            ),
+            optional(
+                "process",
+                "string",
+                "null",
+                "Executable file name, e.g. `notepad.exe`.",
+            ),
+            optional(
+                "current_desktop_only",
+                "boolean",
+                "false",
+                "Skip windows on other virtual desktops.",
+            ),
+            arg("text", "string", "Text to post."),
+        ],
+    },
+    CommandSchema {
+        name: "HoldKey",
+        doc: "Holds a key down for a while.",
+        args: &[
+            arg("key", "key", "Key to hold."),
+            arg("duration_ms", "duration", "How long to hold it."),
+            optional(
+                "repeat",
+                "key_repeat",
+                "null",
+                "Auto-repeat it like a physically held key.",
+            ),
+        ],
+    },
+    CommandSchema {
+        name: "KeyDown",
+        doc: "Puts a key down until a matching `KeyUp` or the end of the macro.",
+        args: &[
+            arg("key", "key", "Key to put down."),
+            optional(
+                "repeat",
+                "key_repeat",
+                "null",
+                "Auto-repeat it like a physically held key.",
+            ),
+        ],
+    },
+    CommandSchema {
+        name: "KeyUp",
+        doc: "Releases a key put down with `KeyDown`.",
+        args: &[arg("key", "key", "Key to release.")],
+    },
+    CommandSchema {
+        name: "WithKeysHeld",
+        doc: "Holds keys down while commands run, releasing them however the block ends.",
+        args: &[
+            arg(
+                "keys",
+                "list of key",
+                "Keys to hold, pressed in order and released in reverse.",
+            ),
+            arg(
+                "commands",
+                "commands",
+                "Commands to run while they are held.",
+            ),
+        ],
+    },
+    CommandSchema {
+        name: "RetryBlock",
+        doc: "Runs commands, starting the whole block over when one fails.",
+        args: &[
+            arg("attempts", "integer", "Most runs in total."),
+            optional(
+                "backoff_ms",
+                "integer",
+                "0",
+                "Delay before the first retry.",
+            ),
+            optional(
+                "multiplier",
+                "number",
+                "2.0",
+                "Factor the delay grows by on every retry.",
+            ),
+            optional(
+                "retry_on",
+                "list of win32 | input_dropped | timeout | window_not_found | validation | \
+                 safety_violation | rate_limited | cancelled | other",
+                "[]",
+                "Only retry failures of these kinds, all when empty.",
+            ),
+            arg("commands", "commands", "Commands to run."),
+        ],
+    },
+    CommandSchema {
+        name: "CallMacro",
+        doc: "Runs another macro's commands as part of this one.",
+        args: &[
+            arg("name", "string", "Name of the macro."),
+            optional(
+                "args",
+                "map",
+                "{}",
+                "Arguments for its `params`, may use `${}` variables.",
+            ),
+        ],
+    },
+    CommandSchema {
+        name: "IfKeyHeld",
+        doc: "Runs `then` if a key is held down, and `else` otherwise.",
+        args: &[
+            arg("key", "key", "Key to look at."),
+            arg("then", "commands", "Commands to run if it is held."),
+            optional("else", "commands", "[]", "Commands to run if it is not."),
+        ],
+    },
+    CommandSchema {
+        name: "ReplaceText",
+        doc: "Replaces the contents of the focused text field: selects all, deletes, then \
+              enters the text.",
+        args: &[
+            arg(
+                "text",
+                "string",
+                "New text, may use `${}` variables and `${cred:<target>}` credentials.",
+            ),
+            optional(
+                "method",
+                "TypeOver | Paste",
+                "TypeOver",
+                "Type the text, or paste it through the clipboard.",
+            ),
+            optional("settle_ms", "duration", "50", "Wait between the steps."),
+        ],
+    },
+    CommandSchema {
+        name: "GetWindowTitle",
+        doc: "Stores the title of the foreground window in a variable.",
+        args: &[arg("into", "string", "Variable to store it in.")],
+    },
+    CommandSchema {
+        name: "IfVarMatches",
+        doc: "Runs `then` if a variable matches a regex, and `else` otherwise.",
+        args: &[
+            arg("var", "string", "Variable to match."),
+            arg("regex", "string", "Regex, may use `${}` variables."),
+            arg("then", "commands", "Commands to run if it matches."),
+            optional("else", "commands", "[]", "Commands to run if it does not."),
+        ],
+    },
+    CommandSchema {
+        name: "MousePath",
+        doc: "Moves the mouse along a recorded path, holding a button from the first point to \
+              the last if given, as in a drag.",
+        args: &[
+            arg("points", "list of point", "Points of the path."),
+            optional(
+                "button",
+                "Left | Right | Middle",
+                "null",
+                "Button to hold along the path.",
+            ),
+        ],
+    },
+    CommandSchema {
+        name: "Media",
+        doc: "Presses a media or browser key.",
+        args: &[arg(
+            "action",
+            "play_pause | next_track | previous_track | stop | volume_up | volume_down | mute | \
+             browser_back | browser_forward | browser_refresh | browser_home",
+            "Key to press.",
+        )],
+    },
+    CommandSchema {
+        name: "WaitForProcess",
+        doc: "Waits until a process is running, or until none is.",
+        args: &[
+            arg(
+                "name",
+                "string",
+                "Executable name, e.g. `setup.exe`, case-insensitive.",
+            ),
+            arg("state", "Running | Exited", "State to wait for."),
+            arg("timeout_ms", "duration", "Fail after this long."),
+        ],
+    },
+    CommandSchema {
+        name: "WaitForProcessIdle",
+        doc: "Waits until the processes with a name stay below a CPU usage for a while.",
+        args: &[
+            arg(
+                "process",
+                "string",
+                "Executable name, e.g. `excel.exe`, case-insensitive. Every process with it \
+                 counts.",
+            ),
+            arg(
+                "cpu_below_percent",
+                "number",
+                "CPU usage, of every core together, to stay below.",
+            ),
+            arg(
+                "sustained_ms",
+                "duration",
+                "How long usage must stay below it.",
+            ),
+            arg("timeout_ms", "duration", "Fail after this long."),
+        ],
+    },
+    CommandSchema {
+        name: "IfFileExists",
+        doc: "Runs `then` if a file exists, and `else` otherwise.",
+        args: &[
+            arg(
+                "path",
+                "string",
+                "Path, may use `${}` variables, relative to the config file.",
+            ),
+            arg("then", "commands", "Commands to run if it exists."),
+            optional("else", "commands", "[]", "Commands to run if it does not."),
+        ],
+    },
+    CommandSchema {
+        name: "WaitForFile",
+        doc: "Waits until a file exists.",
+        args: &[
+            arg(
+                "path",
+                "string",
+                "Path, may use `${}` variables, relative to the config file.",
+            ),
+            arg("timeout_ms", "duration", "Fail after this long."),
+        ],
+    },
+    CommandSchema {
+        name: "WaitForPixel",
+        doc: "Waits until a pixel on the screen has a color.",
+        args: &[
+            arg("x", "coordinate", "Horizontal position."),
+            arg("y", "coordinate", "Vertical position."),
+            arg("color", "color", "Color to wait for."),
+            optional(
+                "tolerance",
+                "integer",
+                "0",
+                "How far each channel may be from the color's.",
+            ),
+            arg("timeout_ms", "duration", "Fail after this long."),
+        ],
+    },
+    CommandSchema {
+        name: "WaitForClipboardChange",
+        doc: "Waits until the clipboard contents change.",
+        args: &[
+            arg("timeout_ms", "duration", "Fail after this long."),
+            optional(
+                "into",
+                "string",
+                "null",
+                "Variable to store the new text in, unset if it is not text.",
+            ),
+        ],
+    },
+    CommandSchema {
+        name: "Pause",
+        doc: "Logs a message and waits for a key, or else the macro's hotkey, to be pressed.",
+        args: &[
+            optional(
+                "message",
+                "string",
+                "null",
+                "Message to log, may use `${}` variables.",
+            ),
+            optional("resume_key", "key", "null", "Key that ends the wait."),
+        ],
+    },
+    CommandSchema {
+        name: "Run",
+        doc: "Launches a program.",
+        args: &[
+            arg("program", "string", "Program to run."),
+            optional(
+                "args",
+                "list of string",
+                "[]",
+                "Arguments, may use `${}` variables.",
+            ),
+            optional(
+                "cwd",
+                "string",
+                "null",
+                "Working directory, may use `${}` variables.",
+            ),
+            optional(
+                "env",
+                "map",
+                "{}",
+                "Environment variables, may use `${}` variables and `${cred:<target>}` \
+                 credentials.",
+            ),
+            optional(
+                "capture_output",
+                "boolean",
+                "false",
+                "Wait for it to exit and store its stdout in `${output}`.",
+            ),
+            optional(
+                "timeout_ms",
+                "duration",
+                "null",
+                "With `capture_output`, stop it and fail if it has not exited after this long.",
+            ),
+        ],
+    },
+    CommandSchema {
+        name: "NormalizeWindow",
+        doc: "Moves and resizes a window so that coordinate clicks land where expected.",
+        args: &[
+            optional("title", "string", "null", "Exact window title."),
+            optional(
+                "class",
+                "string",
+                "null",
+                "Window class name, e.g. `Notepad`.",
+            ),
+            optional(
+                "process",
+                "string",
+                "null",
+                "Executable file name, e.g. `notepad.exe`.",
+            ),
+            optional(
+                "current_desktop_only",
+                "boolean",
+                "false",
+                "Skip windows on other virtual desktops.",
+            ),
+            arg("x", "integer", "Left edge."),
+            arg("y", "integer", "Top edge."),
+            arg("width", "integer", "Width."),
+            arg("height", "integer", "Height."),
+            optional(
+                "restore_after",
+                "boolean",
+                "false",
+                "Put the original placement back when the macro ends.",
+            ),
+        ],
+    },
+    CommandSchema {
+        name: "StoreWindowOrigin",
+        doc: "Stores the top-left corner of a window in `${window_x}` and `${window_y}`.",
+        args: &[
+            optional("title", "string", "null", "Exact window title."),
+            optional(
+                "class",
+                "string",
+                "null",
+                "Window class name, e.g. `Notepad`.",
+            ),
+            optional(
+                "process",
+                "string",
+                "null",
+                "Executable file name, e.g. `notepad.exe`.",
+            ),
+            optional(
+                "current_desktop_only",
+                "boolean",
+                "false",
+                "Skip windows on other virtual desktops.",
+            ),
+        ],
+    },
+    CommandSchema {
+        name: "SwitchToWindowDesktop",
+        doc: "Switches to the virtual desktop a window is on, leaving the window where it is.",
+        args: &[
+            optional("title", "string", "null", "Exact window title."),
+            optional(
+                "class",
+                "string",
+                "null",
+                "Window class name, e.g. `Notepad`.",
+            ),
+            optional(
+                "process",
+                "string",
+                "null",
+                "Executable file name, e.g. `notepad.exe`.",
+            ),
+        ],
+    },
+    CommandSchema {
+        name: "TextInputSecret",
+        doc: "Types a secret kept out of the config. Takes exactly one of `from_env` and `text`.",
+        args: &[
+            optional(
+                "from_env",
+                "string",
+                "null",
+                "Environment variable holding the secret.",
+            ),
+            optional(
+                "text",
+                "string",
+                "null",
+                "Text in which `${cred:<target>}` stands for a stored credential.",
+            ),
+        ],
+    },
+    CommandSchema {
+        name: "TextInputCredential",
+        doc: "Types the password of a generic Windows Credential Manager entry.",
+        args: &[arg("target", "string", "Target name of the entry.")],
+    },
+    CommandSchema {
+        name: "SetLockKey",
+        doc: "Turns CapsLock, NumLock or ScrollLock on or off.",
+        args: &[
+            arg("key", "CapsLock | NumLock | ScrollLock", "Lock key."),
+            arg("state", "on | off", "State to put it in."),
+        ],
+    },
+    CommandSchema {
+        name: "AssertKeyState",
+        doc: "Fails unless a key is up or down, or a lock key toggled on or off.",
+        args: &[
+            arg("key", "key", "Key to check."),
+            arg(
+                "state",
+                "Up | Down | ToggledOn | ToggledOff",
+                "State it must be in.",
+            ),
+            optional(
+                "fix",
+                "boolean",
+                "false",
+                "Correct a mismatch instead of failing.",
+            ),
+        ],
+    },
+    CommandSchema {
+        name: "AssertNotBlocked",
+        doc: "Fails when the foreground window is elevated above the runner, since input to it \
+              would be discarded.",
+        args: &[],
+    },
+    CommandSchema {
+        name: "Breakpoint",
+        doc: "Under run-macro --debug, stops before the next command until Enter, S or Q is \
+              pressed. Does nothing otherwise.",
+        args: &[],
+    },
+];
+
+/// How a command is written, as serde sees it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Form {
+    /// Just the name, e.g. `- LeftClick`.
+    Unit,
+    /// The name with one value, e.g. `- !PressKey A`.
+    Value,
+    /// The name with a list of values, e.g. `- !Loop [3, [...]]`.
+    Tuple(usize),
+    /// The name with a map of these fields.
+    Fields(&'static [&'static str]),
+}
+
+impl Form {
+    fn name(&self) -> &'static str {
+        match self {
+            Form::Unit => "unit",
+            Form::Value => "value",
+            Form::Tuple(_) => "list",
+            Form::Fields(_) => "map",
+        }
+    }
+}
+
+/// What `Introspector` saw of a type while it was being deserialized.
+#[derive(Debug, Default)]
+struct Seen {
+    variants: Option<&'static [&'static str]>,
+    fields: Option<&'static [&'static str]>,
+    form: Option<Form>,
+}
+
+/// A deserializer that reads no data, only the names serde asks it for, so the variants and
+/// fields of a type come from its derived `Deserialize` itself. With `variant`, it also picks
+/// that variant of an enum to see its form.
+struct Introspector<'a> {
+    seen: &'a mut Seen,
+    variant: Option<&'static str>,
+}
+
+impl<'de> Deserializer<'de> for Introspector<'_> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+        Err(de::Error::custom("introspection only"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.seen.fields = Some(fields);
+        Err(de::Error::custom("introspection only"))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.seen.variants = Some(variants);
+        match self.variant {
+            Some(_) => visitor.visit_enum(self),
+            None => Err(de::Error::custom("introspection only")),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map identifier
+        ignored_any
+    }
+}
+
+impl<'de> de::EnumAccess<'de> for Introspector<'_> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<S: de::DeserializeSeed<'de>>(self, seed: S) -> Result<(S::Value, Self), Error> {
+        let name = self.variant.unwrap_or_default();
+        let value = seed.deserialize(IntoDeserializer::<Error>::into_deserializer(name))?;
+        Ok((value, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Introspector<'_> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        self.seen.form = Some(Form::Unit);
+        Err(de::Error::custom("introspection only"))
+    }
+
+    fn newtype_variant_seed<S: de::DeserializeSeed<'de>>(
+        self,
+        _seed: S,
+    ) -> Result<S::Value, Error> {
+        self.seen.form = Some(Form::Value);
+        Err(de::Error::custom("introspection only"))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, _visitor: V) -> Result<V::Value, Error> {
+        self.seen.form = Some(Form::Tuple(len));
+        Err(de::Error::custom("introspection only"))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.seen.form = Some(Form::Fields(fields));
+        Err(de::Error::custom("introspection only"))
+    }
+}
+
+/// What deserializing a `T` reveals of it, picking `variant` if it is an enum.
+fn introspect<T: DeserializeOwned>(variant: Option<&'static str>) -> Seen {
+    let mut seen = Seen::default();
+    let _ = T::deserialize(Introspector {
+        seen: &mut seen,
+        variant,
+    });
+    seen
+}
+
+/// The form of every `Command` variant, in declaration order.
+fn command_forms() -> Vec<(&'static str, Form)> {
+    introspect::<Command>(None)
+        .variants
+        .unwrap_or_default()
+        .iter()
+        .map(|variant| {
+            let form = introspect::<Command>(Some(variant))
+                .form
+                .unwrap_or(Form::Unit);
+            (*variant, form)
+        })
+        .collect()
+}
+
+/// Checks `COMMANDS` against `Command`: every variant needs an entry with the same arguments,
+/// and every entry a variant.
+fn check(forms: &[(&'static str, Form)]) -> Result<(), anyhow::Error> {
+    let mut problems = Vec::new();
+
+    for (name, form) in forms.iter() {
+        let schema = match COMMANDS.iter().find(|schema| schema.name == *name) {
+            Some(schema) => schema,
+            None => {
+                problems.push(format!("{} is not described", name));
+                continue;
+            }
+        };
+
+        let mismatch = match form {
+            Form::Unit => !schema.args.is_empty(),
+            Form::Value => schema.args.len() != 1,
+            Form::Tuple(len) => schema.args.len() != *len,
+            Form::Fields(fields) => {
+                let described: BTreeSet<&str> = schema.args.iter().map(|arg| arg.name).collect();
+                described != fields.iter().copied().collect()
+            }
+        };
+        if mismatch {
+            problems.push(format!(
+                "{} is described with other arguments than it takes",
+                name
+            ));
+        }
+    }
+
+    for schema in COMMANDS.iter() {
+        if !forms.iter().any(|(name, _)| *name == schema.name) {
+            problems.push(format!("{} is described but is not a command", schema.name));
+        }
+    }
+
+    if !problems.is_empty() {
+        return Err(anyhow::anyhow!(
+            "The command schema is out of date: {}",
+            problems.join(", ")
+        ));
+    }
+
+    Ok(())
+}
+
+/// Prints a JSON description of the config for editor tooling: the fields of the config and of a
+/// macro, and every command with its arguments, their types, defaults and docs. Fails, printing
+/// nothing, when a command is missing from the description or described wrong, so that running
+/// it in CI catches a command added without one.
+pub fn print_schema() -> Result<(), anyhow::Error> {
+    let forms = command_forms();
+    check(&forms)?;
+
+    let commands: Vec<serde_json::Value> = forms
+        .iter()
+        .filter_map(|(name, form)| {
+            let schema = COMMANDS.iter().find(|schema| schema.name == *name)?;
+            Some(serde_json::json!({
+                "name": schema.name,
+                "form": form.name(),
+                "doc": schema.doc,
+                "args": schema.args,
+            }))
+        })
+        .collect();
+
+    let schema = serde_json::json!({
+        "config_fields": introspect::<MacroConfig>(None).fields.unwrap_or_default(),
+        "macro_fields": introspect::<Macro>(None).fields.unwrap_or_default(),
+        "types": TYPES
+            .iter()
+            .map(|(name, doc)| serde_json::json!({ "name": name, "doc": doc }))
+            .collect::<Vec<_>>(),
+        "commands": commands,
+    });
+    println!("{}", serde_json::to_string_pretty(&schema)?);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_command_is_described() {
+        let forms = command_forms();
+
+        assert_eq!(forms.len(), COMMANDS.len());
+        check(&forms).unwrap();
+    }
+
+    #[test]
+    fn forms_come_from_the_derived_deserialize() {
+        let forms = command_forms();
+        let form = |name: &str| {
+            forms
+                .iter()
+                .find(|(variant, _)| *variant == name)
+                .map(|(_, form)| form.clone())
+        };
+
+        assert_eq!(form("LeftClick"), Some(Form::Unit));
+        assert_eq!(form("PressKey"), Some(Form::Value));
+        assert_eq!(form("Loop"), Some(Form::Tuple(2)));
+        assert_eq!(
+            form("WaitForFile"),
+            Some(Form::Fields(&["path", "timeout_ms"]))
+        );
+    }
+
+    #[test]
+    fn undescribed_and_misdescribed_commands_fail_the_check() {
+        let mut forms = command_forms();
+        forms.push(("Teleport", Form::Unit));
+        for (name, form) in forms.iter_mut() {
+            if *name == "PressKey" {
+                *form = Form::Tuple(2);
+            }
+        }
+        forms.retain(|(name, _)| *name != "Breakpoint");
+
+        let e = check(&forms).unwrap_err().to_string();
+        assert!(e.contains("Teleport is not described"), "{}", e);
+        assert!(
+            e.contains("PressKey is described with other arguments than it takes"),
+            "{}",
+            e
+        );
+        assert!(
+            e.contains("Breakpoint is described but is not a command"),
+            "{}",
+            e
+        );
+    }
+}
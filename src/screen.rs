@@ -0,0 +1,403 @@
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{backend::ScreenBackend, error::MacroError, get_cursor_pos};
+
+/// A rectangle in virtual-desktop coordinates.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ScreenRect {
+    pub left: i32,
+    pub top: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl ScreenRect {
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.left && x < self.left + self.width && y >= self.top && y < self.top + self.height
+    }
+}
+
+impl fmt::Display for ScreenRect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}x{} at ({}, {})",
+            self.width, self.height, self.left, self.top
+        )
+    }
+}
+
+/// A color on the screen, written in the config as `"#RRGGBB"`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color {
+        red: 0,
+        green: 0,
+        blue: 0,
+    };
+
+    /// Whether every channel of `self` is within `tolerance` of the same channel of `other`.
+    pub fn matches(self, other: Color, tolerance: u8) -> bool {
+        [
+            (self.red, other.red),
+            (self.green, other.green),
+            (self.blue, other.blue),
+        ]
+        .into_iter()
+        .all(|(channel, other)| channel.abs_diff(other) <= tolerance)
+    }
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid color {:?}, expected e.g. \"#FF8800\"", text);
+        let hex = text
+            .trim()
+            .strip_prefix('#')
+            .filter(|hex| hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(invalid)?;
+        let channel = |at: usize| u8::from_str_radix(&hex[at..at + 2], 16).map_err(|_| invalid());
+
+        Ok(Color {
+            red: channel(0)?,
+            green: channel(2)?,
+            blue: channel(4)?,
+        })
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02X}{:02X}{:02X}", self.red, self.green, self.blue)
+    }
+}
+
+impl Serialize for Color {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Size of the primary monitor.
+#[cfg(windows)]
+pub fn primary_size() -> (i32, i32) {
+    use windows::Win32::UI::WindowsAndMessaging::{GetSystemMetrics, SM_CXSCREEN, SM_CYSCREEN};
+
+    unsafe { (GetSystemMetrics(SM_CXSCREEN), GetSystemMetrics(SM_CYSCREEN)) }
+}
+
+/// The bounding box of every monitor, which may start left of or above the primary monitor.
+#[cfg(windows)]
+pub fn virtual_desktop() -> ScreenRect {
+    use windows::Win32::UI::WindowsAndMessaging::{
+        GetSystemMetrics, SM_CXVIRTUALSCREEN, SM_CYVIRTUALSCREEN, SM_XVIRTUALSCREEN,
+        SM_YVIRTUALSCREEN,
+    };
+
+    unsafe {
+        ScreenRect {
+            left: GetSystemMetrics(SM_XVIRTUALSCREEN),
+            top: GetSystemMetrics(SM_YVIRTUALSCREEN),
+            width: GetSystemMetrics(SM_CXVIRTUALSCREEN),
+            height: GetSystemMetrics(SM_CYVIRTUALSCREEN),
+        }
+    }
+}
+
+/// Every monitor, the primary one first and the rest from left to right.
+#[cfg(windows)]
+pub fn monitors() -> Vec<ScreenRect> {
+    use windows::Win32::Foundation::{BOOL, LPARAM, RECT};
+    use windows::Win32::Graphics::Gdi::{EnumDisplayMonitors, HDC, HMONITOR};
+
+    unsafe extern "system" fn collect(
+        _monitor: HMONITOR,
+        _hdc: HDC,
+        rect: *mut RECT,
+        data: LPARAM,
+    ) -> BOOL {
+        let monitors = &mut *(data.0 as *mut Vec<ScreenRect>);
+        let rect = &*rect;
+
+        monitors.push(ScreenRect {
+            left: rect.left,
+            top: rect.top,
+            width: rect.right - rect.left,
+            height: rect.bottom - rect.top,
+        });
+
+        true.into()
+    }
+
+    let mut monitors: Vec<ScreenRect> = Vec::new();
+    unsafe {
+        EnumDisplayMonitors(
+            HDC::default(),
+            std::ptr::null(),
+            Some(collect),
+            LPARAM(&mut monitors as *mut Vec<ScreenRect> as isize),
+        )
+    };
+
+    // The primary monitor is the one at the origin
+    monitors.sort_by_key(|monitor| {
+        (
+            monitor.left != 0 || monitor.top != 0,
+            monitor.left,
+            monitor.top,
+        )
+    });
+
+    monitors
+}
+
+/// The color of the pixel at `(x, y)` on the virtual desktop.
+#[cfg(windows)]
+pub fn pixel(x: i32, y: i32) -> Result<Color, anyhow::Error> {
+    use windows::Win32::{
+        Foundation::HWND,
+        Graphics::Gdi::{GetDC, GetPixel, ReleaseDC, CLR_INVALID},
+    };
+
+    let color = unsafe {
+        let desktop = GetDC(HWND::default());
+        let color = GetPixel(desktop, x, y);
+        ReleaseDC(HWND::default(), desktop);
+        color
+    };
+
+    // Points off every monitor, and the whole screen while the desktop is locked, read as invalid
+    if color == CLR_INVALID {
+        return Err(anyhow::anyhow!(
+            "Failed to read the pixel at ({}, {})",
+            x,
+            y
+        ));
+    }
+
+    // A COLORREF is 0x00BBGGRR
+    Ok(Color {
+        red: color as u8,
+        green: (color >> 8) as u8,
+        blue: (color >> 16) as u8,
+    })
+}
+
+/// Fails unless `(x, y)` lies on a monitor, rather than letting Windows clamp it to the nearest
+/// edge. Points in the gaps of a virtual desktop whose monitors differ in size fail too. The error
+/// describes the virtual desktop and every monitor so a changed layout is easy to spot.
+pub fn ensure_on_screen(screen: &dyn ScreenBackend, x: i32, y: i32) -> Result<(), anyhow::Error> {
+    let monitors = screen.monitors();
+    if monitors.iter().any(|monitor| monitor.contains(x, y)) {
+        return Ok(());
+    }
+
+    let layout: Vec<String> = monitors
+        .iter()
+        .enumerate()
+        .map(|(index, monitor)| format!("monitor {}: {}", index + 1, monitor))
+        .collect();
+    Err(MacroError::Validation(format!(
+        "({}, {}) is off-screen: the virtual desktop is {}, {}",
+        x,
+        y,
+        screen.virtual_bounds(),
+        layout.join(", ")
+    ))
+    .into())
+}
+
+/// The built-in variables set at the start of every macro: `screen_width` and `screen_height` for
+/// the primary monitor, `virtual_left`, `virtual_top`, `virtual_width` and `virtual_height` for
+/// the whole desktop across monitors, `monitor_count`, `monitor<N>_left`, `_top`, `_width` and
+/// `_height` for each monitor numbered from 1 (the primary), `cursor_x` and `cursor_y`, and
+/// `fullscreen`, 1 while a fullscreen app has the focus and 0 otherwise.
+pub fn builtin_variables(screen: &dyn ScreenBackend) -> HashMap<String, String> {
+    let mut variables = HashMap::new();
+    let mut set = |name: String, value: i32| {
+        variables.insert(name, value.to_string());
+    };
+
+    let (screen_width, screen_height) = screen.primary_size();
+    set("screen_width".to_string(), screen_width);
+    set("screen_height".to_string(), screen_height);
+
+    let desktop = screen.virtual_bounds();
+    set("virtual_left".to_string(), desktop.left);
+    set("virtual_top".to_string(), desktop.top);
+    set("virtual_width".to_string(), desktop.width);
+    set("virtual_height".to_string(), desktop.height);
+
+    let monitors = screen.monitors();
+    set("monitor_count".to_string(), monitors.len() as i32);
+    for (index, monitor) in monitors.iter().enumerate() {
+        let prefix = format!("monitor{}", index + 1);
+        set(format!("{}_left", prefix), monitor.left);
+        set(format!("{}_top", prefix), monitor.top);
+        set(format!("{}_width", prefix), monitor.width);
+        set(format!("{}_height", prefix), monitor.height);
+    }
+
+    match get_cursor_pos() {
+        Ok(point) => {
+            set("cursor_x".to_string(), point.x);
+            set("cursor_y".to_string(), point.y);
+        }
+        Err(e) => log::warn!("{}", e),
+    }
+
+    set(
+        "fullscreen".to_string(),
+        screen.foreground_fullscreen() as i32,
+    );
+
+    variables
+}
+
+/// Whether `name` is one of the variables `builtin_variables` sets, with any number of monitors.
+pub fn is_builtin_variable(name: &str) -> bool {
+    const NAMES: [&str; 10] = [
+        "screen_width",
+        "screen_height",
+        "virtual_left",
+        "virtual_top",
+        "virtual_width",
+        "virtual_height",
+        "monitor_count",
+        "cursor_x",
+        "cursor_y",
+        "fullscreen",
+    ];
+
+    NAMES.contains(&name)
+        || name
+            .strip_prefix("monitor")
+            .and_then(|rest| rest.split_once('_'))
+            .is_some_and(|(number, edge)| {
+                number.parse::<u32>().is_ok_and(|number| number >= 1)
+                    && matches!(edge, "left" | "top" | "width" | "height")
+            })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::*;
+    use crate::{backend::MemoryScreen, clock::VirtualClock};
+
+    #[test]
+    fn colors_read_as_hex() {
+        assert_eq!(
+            "#3c8DBC".parse(),
+            Ok(Color {
+                red: 0x3C,
+                green: 0x8D,
+                blue: 0xBC
+            })
+        );
+        assert_eq!(Color::BLACK.to_string(), "#000000");
+        for text in ["3C8DBC", "#3C8DB", "#3C8DBCA", "#3C8DBG", "#+C8DBC", ""] {
+            assert!(text.parse::<Color>().is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn colors_match_within_the_tolerance_of_every_channel() {
+        let color: Color = "#3C8DBC".parse().unwrap();
+
+        assert!(color.matches(color, 0));
+        assert!(color.matches("#3A8FBA".parse().unwrap(), 2));
+        assert!(!color.matches("#3A8FBA".parse().unwrap(), 1));
+        // One channel out is enough to miss
+        assert!(!color.matches("#3C8DFF".parse().unwrap(), 2));
+        assert!(Color::BLACK.matches("#FFFFFF".parse().unwrap(), 255));
+    }
+
+    #[test]
+    fn builtin_variables_describe_the_screen() {
+        let clock = Arc::new(VirtualClock::new(Duration::from_secs(60)));
+        let screen = MemoryScreen::new(
+            clock,
+            vec![
+                ScreenRect {
+                    left: 0,
+                    top: 0,
+                    width: 2560,
+                    height: 1440,
+                },
+                ScreenRect {
+                    left: -1280,
+                    top: 200,
+                    width: 1280,
+                    height: 1024,
+                },
+            ],
+        )
+        .fullscreen();
+
+        let variables = builtin_variables(&screen);
+        let variable = |name: &str| variables.get(name).map(String::as_str);
+
+        for (name, value) in [
+            ("screen_width", "2560"),
+            ("screen_height", "1440"),
+            ("virtual_left", "-1280"),
+            ("virtual_top", "0"),
+            ("virtual_width", "3840"),
+            ("virtual_height", "1440"),
+            ("monitor_count", "2"),
+            ("monitor1_left", "0"),
+            ("monitor1_width", "2560"),
+            ("monitor2_left", "-1280"),
+            ("monitor2_top", "200"),
+            ("monitor2_width", "1280"),
+            ("monitor2_height", "1024"),
+            ("fullscreen", "1"),
+        ] {
+            assert_eq!(variable(name), Some(value), "{}", name);
+        }
+        assert_eq!(variable("monitor3_left"), None);
+        assert!(variables.keys().all(|name| is_builtin_variable(name)));
+    }
+
+    #[test]
+    fn builtin_variables_are_known() {
+        for name in [
+            "screen_width",
+            "cursor_y",
+            "fullscreen",
+            "monitor1_left",
+            "monitor12_height",
+        ] {
+            assert!(is_builtin_variable(name), "{}", name);
+        }
+        for name in [
+            "monitor0_left",
+            "monitor_left",
+            "monitorx_top",
+            "monitor1_right",
+            "screen",
+        ] {
+            assert!(!is_builtin_variable(name), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use std::fmt;
+
+/// `GetLastError` when `CredReadW` finds no credential with the target name.
+const ERROR_NOT_FOUND: u32 = 1168;
+
+/// A value that must never end up in logs, events or error messages. Both `Debug` and `Display`
+/// print `<redacted>`, and the contents are wiped when it is dropped.
+#[derive(Default)]
+pub struct Secret(String);
+
+impl Secret {
+    /// The secret itself, only to be handed straight to the input layer.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// The secret itself, for building one up in place.
+    pub fn expose_mut(&mut self) -> &mut String {
+        &mut self.0
+    }
+
+    /// Reads the secret from the environment variable `name`.
+    pub fn from_env(name: &str) -> Result<Self, anyhow::Error> {
+        std::env::var(name)
+            .map(Secret)
+            .map_err(|e| anyhow::anyhow!("Failed to read secret from ${}: {}", name, e))
+    }
+
+    /// Reads the password of the generic Windows Credential Manager entry `target`, as stored by
+    /// `cmdkey /generic:<target>` or the Credential Manager control panel.
+    #[cfg(windows)]
+    pub fn from_credential(target: &str) -> Result<Self, anyhow::Error> {
+        use windows::core::HSTRING;
+        use windows::Win32::Security::Credentials::{
+            CredFree, CredReadW, CREDENTIALW, CRED_TYPE_GENERIC,
+        };
+
+        let mut credential: *mut CREDENTIALW = std::ptr::null_mut();
+
+        if !unsafe {
+            CredReadW(
+                &HSTRING::from(target),
+                CRED_TYPE_GENERIC.0,
+                0,
+                &mut credential,
+            )
+        }
+        .as_bool()
+        {
+            return Err(match super::get_last_windows_error() {
+                ERROR_NOT_FOUND => {
+                    anyhow::anyhow!("No credential {} in the Windows Credential Manager", target)
+                }
+                error => anyhow::anyhow!("Failed to read credential {}: {}", target, error),
+            });
+        }
+
+        // A credential stored without a password has no blob at all
+        let (data, size) = unsafe {
+            (
+                (*credential).CredentialBlob,
+                (*credential).CredentialBlobSize,
+            )
+        };
+        let blob: &[u8] = if data.is_null() || size == 0 {
+            &[]
+        } else {
+            unsafe { std::slice::from_raw_parts(data, size as usize) }
+        };
+        let secret = Secret(decode_blob(blob));
+
+        unsafe { CredFree(credential as *const _) };
+
+        Ok(secret)
+    }
+
+    /// Stores the secret as the password of the generic Windows Credential Manager entry
+    /// `target`, replacing any there is, as UTF-16 like `cmdkey` does.
+    #[cfg(windows)]
+    pub fn store_credential(&self, target: &str) -> Result<(), anyhow::Error> {
+        use windows::core::PWSTR;
+        use windows::Win32::Security::Credentials::{
+            CredWriteW, CREDENTIALW, CRED_PERSIST_LOCAL_MACHINE, CRED_TYPE_GENERIC,
+        };
+
+        let mut target_name: Vec<u16> = target.encode_utf16().chain(Some(0)).collect();
+        let mut blob: Vec<u8> = self
+            .0
+            .encode_utf16()
+            .flat_map(|unit| unit.to_le_bytes())
+            .collect();
+
+        let credential = CREDENTIALW {
+            Type: CRED_TYPE_GENERIC,
+            TargetName: PWSTR(target_name.as_mut_ptr()),
+            CredentialBlobSize: blob.len() as u32,
+            CredentialBlob: blob.as_mut_ptr(),
+            Persist: CRED_PERSIST_LOCAL_MACHINE,
+            ..Default::default()
+        };
+        let written = unsafe { CredWriteW(&credential, 0) }.as_bool();
+        blob.fill(0);
+
+        if !written {
+            return Err(anyhow::anyhow!(
+                "Failed to write credential {}: {}",
+                target,
+                super::get_last_windows_error()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Prints `prompt` and reads a line from the console without echoing it.
+    #[cfg(windows)]
+    pub fn prompt(prompt: &str) -> Result<Self, anyhow::Error> {
+        use std::io::{BufRead, Write};
+        use windows::Win32::System::Console::{
+            GetConsoleMode, GetStdHandle, SetConsoleMode, CONSOLE_MODE, ENABLE_ECHO_INPUT,
+            STD_INPUT_HANDLE,
+        };
+
+        print!("{}", prompt);
+        std::io::stdout().flush()?;
+
+        let input = unsafe { GetStdHandle(STD_INPUT_HANDLE) }?;
+        let mut mode = CONSOLE_MODE::default();
+        // Input that is not a console, e.g. a pipe, has nothing to echo
+        let console = unsafe { GetConsoleMode(input, &mut mode) }.as_bool();
+        if console {
+            unsafe { SetConsoleMode(input, mode & !ENABLE_ECHO_INPUT) };
+        }
+
+        let mut secret = Secret::default();
+        let read = std::io::stdin().lock().read_line(&mut secret.0);
+
+        if console {
+            unsafe { SetConsoleMode(input, mode) };
+            println!();
+        }
+        read?;
+
+        let len = secret.0.trim_end_matches(['\r', '\n']).len();
+        secret.0.truncate(len);
+
+        Ok(secret)
+    }
+}
+
+/// Decodes a credential blob. `cmdkey` and the control panel store UTF-16, so a blob of even
+/// length is taken as UTF-16. Only one of odd length, which cannot be UTF-16, or one that is not
+/// valid UTF-16 is taken as UTF-8, as other tools store it. The UTF-16 units are wiped once
+/// decoded.
+fn decode_blob(blob: &[u8]) -> String {
+    if blob.len().is_multiple_of(2) {
+        let mut units: Vec<u16> = blob
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        let text = String::from_utf16(&units);
+        units.fill(0);
+        if let Ok(text) = text {
+            return text;
+        }
+    }
+
+    String::from_utf8_lossy(blob).into_owned()
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        // Safety: zero bytes are valid UTF-8
+        unsafe { self.0.as_bytes_mut() }.fill(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(text: &str) -> Vec<u8> {
+        text.encode_utf16()
+            .flat_map(|unit| unit.to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn decode_blob_reads_utf16() {
+        assert_eq!(decode_blob(&utf16("hunter2")), "hunter2");
+        assert_eq!(decode_blob(&utf16("pässwörd")), "pässwörd");
+        // No zero bytes and valid UTF-8 as well, but stored as UTF-16
+        assert_eq!(decode_blob(&utf16("密码")), "密码");
+    }
+
+    #[test]
+    fn decode_blob_falls_back_to_utf8() {
+        assert_eq!(decode_blob(b"abc"), "abc");
+        assert_eq!(decode_blob("pässwörd!".as_bytes()), "pässwörd!");
+        // An unpaired surrogate is not UTF-16
+        assert_eq!(decode_blob(&[0x00, 0xd8, b'a', b'b']), "\0\u{fffd}ab");
+    }
+
+    #[test]
+    fn decode_blob_of_nothing_is_empty() {
+        assert_eq!(decode_blob(&[]), "");
+    }
+
+    #[test]
+    fn secrets_are_redacted() {
+        let secret = Secret("hunter2".to_string());
+        assert_eq!(format!("{}", secret), "<redacted>");
+        assert_eq!(format!("{:?}", secret), "<redacted>");
+        assert_eq!(secret.expose(), "hunter2");
+    }
+}
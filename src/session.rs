@@ -0,0 +1,40 @@
+/// Whether the workstation is locked, as Windows reports it for this session. The input desktop
+/// cannot tell: it is the secure Winlogon desktop both while locked and while a UAC prompt is
+/// up.
+#[cfg(windows)]
+pub fn session_locked() -> bool {
+    use windows::core::PWSTR;
+    use windows::Win32::Foundation::HANDLE;
+    use windows::Win32::System::RemoteDesktop::{
+        WTSFreeMemory, WTSQuerySessionInformationW, WTSSessionInfoEx, WTSINFOEXW,
+        WTS_CURRENT_SESSION, WTS_SESSIONSTATE_LOCK,
+    };
+
+    let mut buffer = PWSTR::null();
+    let mut bytes = 0;
+    // A null server handle is WTS_CURRENT_SERVER_HANDLE
+    let queried = unsafe {
+        WTSQuerySessionInformationW(
+            HANDLE(0),
+            WTS_CURRENT_SESSION,
+            WTSSessionInfoEx,
+            &mut buffer,
+            &mut bytes,
+        )
+    }
+    .as_bool();
+
+    if !queried || buffer.is_null() {
+        log::debug!(
+            "Failed to query the session state: {}",
+            windows::core::Error::from_win32()
+        );
+        return false;
+    }
+
+    let info = unsafe { *(buffer.0 as *const WTSINFOEXW) };
+    unsafe { WTSFreeMemory(buffer.0 as *mut _) };
+
+    info.Level == 1
+        && unsafe { info.Data.WTSInfoExLevel1 }.SessionFlags == WTS_SESSIONSTATE_LOCK as i32
+}
@@ -0,0 +1,809 @@
+use std::{collections::HashSet, fmt};
+
+use serde::{
+    de::{self, value::SeqAccessDeserializer, IntoDeserializer},
+    Deserialize, Deserializer,
+};
+
+use super::{duration::DurationMs, expr::Coordinate, Command, Key};
+
+/// The commands the shorthand knows, for the error on any other.
+const COMMAND_NAMES: &str = "click, right_click, move, wait, key, combo, text, loop";
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(i64),
+    Str(String),
+    OpenParen,
+    CloseParen,
+    OpenBrace,
+    CloseBrace,
+    Comma,
+    Semicolon,
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "`{}`", name),
+            Token::Number(number) => write!(f, "number {}", number),
+            Token::Str(text) => write!(f, "string {:?}", text),
+            Token::OpenParen => f.write_str("`(`"),
+            Token::CloseParen => f.write_str("`)`"),
+            Token::OpenBrace => f.write_str("`{`"),
+            Token::CloseBrace => f.write_str("`}`"),
+            Token::Comma => f.write_str("`,`"),
+            Token::Semicolon => f.write_str("`;`"),
+            Token::End => f.write_str("the end"),
+        }
+    }
+}
+
+/// An error at `column`, counted in characters from 1.
+fn error_at(column: usize, message: impl fmt::Display) -> anyhow::Error {
+    anyhow::anyhow!("column {}: {}", column, message)
+}
+
+/// Splits `text` into tokens, each with the column it starts at.
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, anyhow::Error> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().enumerate().peekable();
+
+    while let Some((index, c)) = chars.next() {
+        let column = index + 1;
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::OpenParen,
+            ')' => Token::CloseParen,
+            '{' => Token::OpenBrace,
+            '}' => Token::CloseBrace,
+            ',' => Token::Comma,
+            ';' => Token::Semicolon,
+            '\'' | '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, end)) if end == c => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, 'n')) => value.push('\n'),
+                            Some((_, 't')) => value.push('\t'),
+                            Some((_, escaped @ ('\\' | '\'' | '"'))) => value.push(escaped),
+                            Some((escape_index, escaped)) => {
+                                return Err(error_at(
+                                    escape_index + 1,
+                                    format!("unknown escape `\\{}` in string", escaped),
+                                ))
+                            }
+                            None => return Err(error_at(column, "string is never closed")),
+                        },
+                        Some((_, other)) => value.push(other),
+                        None => return Err(error_at(column, "string is never closed")),
+                    }
+                }
+                Token::Str(value)
+            }
+            c if c == '-' || c.is_ascii_digit() => {
+                let mut digits = c.to_string();
+                while let Some((_, digit)) = chars.next_if(|(_, next)| next.is_ascii_digit()) {
+                    digits.push(digit);
+                }
+                let number = digits.parse().map_err(|_| {
+                    error_at(column, format!("expected a number, found `{}`", digits))
+                })?;
+                Token::Number(number)
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut name = c.to_string();
+                while let Some((_, next)) =
+                    chars.next_if(|(_, next)| next.is_alphanumeric() || matches!(next, '_' | ':'))
+                {
+                    name.push(next);
+                }
+                Token::Ident(name)
+            }
+            other => return Err(error_at(column, format!("unexpected `{}`", other))),
+        };
+        tokens.push((token, column));
+    }
+
+    tokens.push((Token::End, text.chars().count() + 1));
+    Ok(tokens)
+}
+
+/// Reads a key as a config would, or else by a common lowercase name such as `enter` or `f5`.
+fn parse_key(name: &str, column: usize) -> Result<Key, anyhow::Error> {
+    if let Ok(key) = Key::parse(name) {
+        return Ok(key);
+    }
+
+    let lowercase = name.to_lowercase();
+    let alias = match lowercase.as_str() {
+        "enter" => "Return".to_string(),
+        "esc" => "Escape".to_string(),
+        "backspace" => "Back".to_string(),
+        "del" => "Delete".to_string(),
+        "win" => "LeftWindows".to_string(),
+        "pageup" | "pgup" => "Prior".to_string(),
+        "pagedown" | "pgdn" => "Next".to_string(),
+        "capslock" => "Capital".to_string(),
+        _ => {
+            let mut chars = lowercase.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect())
+                .unwrap_or_default()
+        }
+    };
+
+    Key::parse(&alias).map_err(|_| error_at(column, format!("unknown key `{}`", name)))
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &(Token, usize) {
+        &self.tokens[self.position.min(self.tokens.len() - 1)]
+    }
+
+    fn next(&mut self) -> (Token, usize) {
+        let token = self.peek().clone();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), anyhow::Error> {
+        match self.next() {
+            (token, _) if token == expected => Ok(()),
+            (token, column) => Err(error_at(
+                column,
+                format!("expected {}, found {}", expected, token),
+            )),
+        }
+    }
+
+    /// Commands separated by `;`, up to `end`, which is left for the caller.
+    fn commands(&mut self, end: &Token) -> Result<Vec<Command>, anyhow::Error> {
+        let mut commands = Vec::new();
+
+        loop {
+            while self.peek().0 == Token::Semicolon {
+                self.next();
+            }
+            if &self.peek().0 == end {
+                return Ok(commands);
+            }
+
+            commands.extend(self.command()?);
+
+            match self.peek() {
+                (Token::Semicolon, _) => {}
+                (token, _) if token == end => {}
+                (token, column) => {
+                    return Err(error_at(
+                        *column,
+                        format!("expected `;` or {}, found {}", end, token),
+                    ))
+                }
+            }
+        }
+    }
+
+    /// One shorthand command, which may stand for several commands, e.g. `click(x, y)` for a
+    /// move and a click.
+    fn command(&mut self) -> Result<Vec<Command>, anyhow::Error> {
+        let (name, column) = match self.next() {
+            (Token::Ident(name), column) => (name, column),
+            (token, column) => {
+                return Err(error_at(
+                    column,
+                    format!("expected a command, found {}", token),
+                ))
+            }
+        };
+
+        self.expect(Token::OpenParen)?;
+        let mut args = Vec::new();
+        if self.peek().0 != Token::CloseParen {
+            loop {
+                match self.next() {
+                    arg @ (Token::Ident(_) | Token::Number(_) | Token::Str(_), _) => args.push(arg),
+                    (token, column) => {
+                        return Err(error_at(
+                            column,
+                            format!("expected an argument, found {}", token),
+                        ))
+                    }
+                }
+                match self.next() {
+                    (Token::Comma, _) => {}
+                    (Token::CloseParen, _) => break,
+                    (token, column) => {
+                        return Err(error_at(
+                            column,
+                            format!("expected `,` or `)`, found {}", token),
+                        ))
+                    }
+                }
+            }
+        } else {
+            self.next();
+        }
+
+        let arity = |expected: &str| {
+            error_at(
+                column,
+                format!(
+                    "{} takes {}, got {} argument(s)",
+                    name,
+                    expected,
+                    args.len()
+                ),
+            )
+        };
+
+        let commands = match (name.as_str(), args.as_slice()) {
+            ("click" | "right_click", []) => vec![click(&name)],
+            ("click" | "right_click", [x, y]) => {
+                vec![mouse_move(x, y)?, click(&name)]
+            }
+            ("click" | "right_click", _) => return Err(arity("no arguments, or x and y")),
+            ("move", [x, y]) => vec![mouse_move(x, y)?],
+            ("move", _) => return Err(arity("x and y")),
+            ("wait", [duration]) => vec![Command::Wait(parse_duration(duration)?)],
+            ("wait", _) => return Err(arity("a duration")),
+            ("key", [key]) => vec![Command::PressKey(key_arg(key)?)],
+            ("key", _) => return Err(arity("one key")),
+            ("combo", keys) if keys.len() >= 2 => {
+                let mut combo = HashSet::new();
+                for key in keys {
+                    if !combo.insert(key_arg(key)?) {
+                        return Err(error_at(
+                            key.1,
+                            format!("{} is already in the combo", key.0),
+                        ));
+                    }
+                }
+                vec![Command::PressKeyCombo(combo)]
+            }
+            ("combo", _) => return Err(arity("two keys or more")),
+            ("text", [(Token::Str(text), _)]) => vec![Command::TextInput(text.clone())],
+            ("text", [(token, column)]) => {
+                return Err(error_at(
+                    *column,
+                    format!("expected a string, found {}", token),
+                ))
+            }
+            ("text", _) => return Err(arity("one string")),
+            ("loop", [(Token::Number(iterations), iterations_column)]) => {
+                let iterations = u32::try_from(*iterations).map_err(|_| {
+                    error_at(
+                        *iterations_column,
+                        format!("expected a loop count, found {}", iterations),
+                    )
+                })?;
+                self.expect(Token::OpenBrace)?;
+                let body = self.commands(&Token::CloseBrace)?;
+                self.expect(Token::CloseBrace)?;
+                vec![Command::Loop(iterations, body)]
+            }
+            ("loop", [(token, column)]) => {
+                return Err(error_at(
+                    *column,
+                    format!("expected a loop count, found {}", token),
+                ))
+            }
+            ("loop", _) => return Err(arity("a count")),
+            _ => {
+                return Err(error_at(
+                    column,
+                    format!(
+                        "unknown command `{}`, expected one of {}",
+                        name, COMMAND_NAMES
+                    ),
+                ))
+            }
+        };
+
+        Ok(commands)
+    }
+}
+
+fn click(name: &str) -> Command {
+    match name {
+        "right_click" => Command::RightClick,
+        _ => Command::LeftClick,
+    }
+}
+
+fn mouse_move(x: &(Token, usize), y: &(Token, usize)) -> Result<Command, anyhow::Error> {
+    Ok(Command::SetMousePos(coordinate(x)?, coordinate(y)?))
+}
+
+fn coordinate((token, column): &(Token, usize)) -> Result<Coordinate, anyhow::Error> {
+    match token {
+        Token::Number(value) => i32::try_from(*value)
+            .map(Coordinate::Value)
+            .map_err(|_| error_at(*column, format!("{} is off any screen", value))),
+        token => Err(error_at(
+            *column,
+            format!("expected a coordinate, found {}", token),
+        )),
+    }
+}
+
+/// A number of milliseconds, or a duration string such as `'1.5s'`.
+fn parse_duration((token, column): &(Token, usize)) -> Result<DurationMs, anyhow::Error> {
+    match token {
+        Token::Number(ms) => u64::try_from(*ms)
+            .map(DurationMs)
+            .map_err(|_| error_at(*column, "a duration cannot be negative")),
+        Token::Str(text) => DurationMs::deserialize(text.as_str().into_deserializer())
+            .map_err(|e: de::value::Error| error_at(*column, e)),
+        token => Err(error_at(
+            *column,
+            format!("expected a duration, found {}", token),
+        )),
+    }
+}
+
+/// A key written as a name, e.g. `enter` or `LeftShift`, or as a digit.
+fn key_arg((token, column): &(Token, usize)) -> Result<Key, anyhow::Error> {
+    match token {
+        Token::Ident(name) => parse_key(name, *column),
+        Token::Number(digit @ 0..=9) => parse_key(&format!("Key{}", digit), *column),
+        token => Err(error_at(
+            *column,
+            format!("expected a key, found {}", token),
+        )),
+    }
+}
+
+/// Parses the shorthand for a macro's commands, e.g.
+/// `move(500, 500); click(); wait(200); text('hello'); key(enter); loop(3) { combo(ctrl, v) }`.
+pub fn parse(text: &str) -> Result<Vec<Command>, anyhow::Error> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        position: 0,
+    };
+
+    let commands = parser.commands(&Token::End)?;
+    if commands.is_empty() {
+        return Err(error_at(parser.peek().1, "no commands given"));
+    }
+    Ok(commands)
+}
+
+/// Reads a macro's `commands` as the usual list, or as a shorthand string, see `parse`.
+pub fn deserialize_commands<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Command>, D::Error> {
+    struct CommandsVisitor;
+
+    impl<'de> de::Visitor<'de> for CommandsVisitor {
+        type Value = Vec<Command>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a list of commands or a shorthand string")
+        }
+
+        fn visit_str<E: de::Error>(self, text: &str) -> Result<Vec<Command>, E> {
+            parse(text).map_err(|e| E::custom(format!("in the commands shorthand, {}", e)))
+        }
+
+        fn visit_seq<A: de::SeqAccess<'de>>(self, seq: A) -> Result<Vec<Command>, A::Error> {
+            Vec::deserialize(SeqAccessDeserializer::new(seq))
+        }
+    }
+
+    deserializer.deserialize_any(CommandsVisitor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> String {
+        match parse(text) {
+            Ok(commands) => panic!("{:?} parsed as {:?}", text, commands),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    fn single(text: &str) -> Command {
+        let mut commands = parse(text).unwrap();
+        assert_eq!(commands.len(), 1, "{:?}", commands);
+        commands.remove(0)
+    }
+
+    #[test]
+    fn tokenize_records_columns() {
+        assert_eq!(
+            tokenize("click(1, -2); text('a b')").unwrap(),
+            vec![
+                (Token::Ident("click".to_string()), 1),
+                (Token::OpenParen, 6),
+                (Token::Number(1), 7),
+                (Token::Comma, 8),
+                (Token::Number(-2), 10),
+                (Token::CloseParen, 12),
+                (Token::Semicolon, 13),
+                (Token::Ident("text".to_string()), 15),
+                (Token::OpenParen, 19),
+                (Token::Str("a b".to_string()), 20),
+                (Token::CloseParen, 25),
+                (Token::End, 26),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_reads_escapes_and_both_quotes() {
+        assert_eq!(
+            tokenize(r#"'it\'s' "a\"b" 'x\ny\tz\\'"#).unwrap(),
+            vec![
+                (Token::Str("it's".to_string()), 1),
+                (Token::Str("a\"b".to_string()), 9),
+                (Token::Str("x\ny\tz\\".to_string()), 16),
+                (Token::End, 27),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_counts_columns_in_characters() {
+        assert_eq!(
+            tokenize("'ü' {").unwrap(),
+            vec![
+                (Token::Str("ü".to_string()), 1),
+                (Token::OpenBrace, 5),
+                (Token::End, 6),
+            ]
+        );
+    }
+
+    #[test]
+    fn click_and_right_click() {
+        assert!(matches!(single("click()"), Command::LeftClick));
+        assert!(matches!(single("right_click()"), Command::RightClick));
+
+        let commands = parse("click(100, 200)").unwrap();
+        assert!(
+            matches!(
+                commands.as_slice(),
+                [
+                    Command::SetMousePos(Coordinate::Value(100), Coordinate::Value(200)),
+                    Command::LeftClick
+                ]
+            ),
+            "{:?}",
+            commands
+        );
+
+        let commands = parse("right_click(-10, 5)").unwrap();
+        assert!(
+            matches!(
+                commands.as_slice(),
+                [
+                    Command::SetMousePos(Coordinate::Value(-10), Coordinate::Value(5)),
+                    Command::RightClick
+                ]
+            ),
+            "{:?}",
+            commands
+        );
+    }
+
+    #[test]
+    fn move_wait_key_combo_and_text() {
+        assert!(matches!(
+            single("move(500,500)"),
+            Command::SetMousePos(Coordinate::Value(500), Coordinate::Value(500))
+        ));
+        assert!(matches!(
+            single("wait(500)"),
+            Command::Wait(DurationMs(500))
+        ));
+        assert!(matches!(single("key(Tab)"), Command::PressKey(Key::Tab)));
+        assert!(matches!(single("key(7)"), Command::PressKey(Key::Key7)));
+        assert!(matches!(
+            single("text('hello, world')"),
+            Command::TextInput(text) if text == "hello, world"
+        ));
+
+        match single("combo(ctrl, shift, r)") {
+            Command::PressKeyCombo(keys) => {
+                assert_eq!(keys, HashSet::from([Key::Control, Key::Shift, Key::R]))
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn wait_takes_duration_strings() {
+        assert!(matches!(
+            single("wait('1.5s')"),
+            Command::Wait(DurationMs(1500))
+        ));
+        assert!(matches!(
+            single("wait(\"1m30s\")"),
+            Command::Wait(DurationMs(90_000))
+        ));
+        assert!(matches!(
+            single("wait('250ms')"),
+            Command::Wait(DurationMs(250))
+        ));
+        assert!(matches!(single("wait('2')"), Command::Wait(DurationMs(2))));
+    }
+
+    #[test]
+    fn lowercase_key_aliases() {
+        let aliases = [
+            ("enter", Key::Return),
+            ("esc", Key::Escape),
+            ("backspace", Key::Back),
+            ("del", Key::Delete),
+            ("win", Key::LeftWindows),
+            ("pageup", Key::Prior),
+            ("pgup", Key::Prior),
+            ("pagedown", Key::Next),
+            ("pgdn", Key::Next),
+            ("capslock", Key::Capital),
+            ("tab", Key::Tab),
+            ("f5", Key::F5),
+            ("ENTER", Key::Return),
+            ("v", Key::V),
+        ];
+
+        for (name, expected) in aliases {
+            match single(&format!("key({})", name)) {
+                Command::PressKey(key) => assert_eq!(key, expected, "{}", name),
+                other => panic!("{}: {:?}", name, other),
+            }
+        }
+    }
+
+    #[test]
+    fn separators_and_whitespace() {
+        let commands = parse(" ; click() ;; wait(5);\n key(enter) ; ").unwrap();
+        assert!(
+            matches!(
+                commands.as_slice(),
+                [
+                    Command::LeftClick,
+                    Command::Wait(DurationMs(5)),
+                    Command::PressKey(Key::Return)
+                ]
+            ),
+            "{:?}",
+            commands
+        );
+    }
+
+    #[test]
+    fn loops_nest() {
+        let commands = parse("loop(2) { loop(3) { click() }; wait(10) }; key(esc)").unwrap();
+        match commands.as_slice() {
+            [Command::Loop(2, outer), Command::PressKey(Key::Escape)] => match outer.as_slice() {
+                [Command::Loop(3, inner), Command::Wait(DurationMs(10))] => {
+                    assert!(
+                        matches!(inner.as_slice(), [Command::LeftClick]),
+                        "{:?}",
+                        inner
+                    )
+                }
+                other => panic!("{:?}", other),
+            },
+            other => panic!("{:?}", other),
+        }
+
+        assert!(
+            matches!(single("loop(0) { click(1, 2) }"), Command::Loop(0, body) if body.len() == 2)
+        );
+        assert!(matches!(single("loop(4) {}"), Command::Loop(4, body) if body.is_empty()));
+    }
+
+    #[test]
+    fn unclosed_string() {
+        assert_eq!(
+            error("click(); text('abc"),
+            "column 15: string is never closed"
+        );
+        assert_eq!(error("text(\"abc\\"), "column 6: string is never closed");
+    }
+
+    #[test]
+    fn unknown_escape() {
+        assert_eq!(
+            error(r"text('a\qb')"),
+            r"column 9: unknown escape `\q` in string"
+        );
+    }
+
+    #[test]
+    fn lone_minus() {
+        assert_eq!(
+            error("move(-, 5)"),
+            "column 6: expected a number, found `-`"
+        );
+    }
+
+    #[test]
+    fn missing_close_paren() {
+        assert_eq!(
+            error("click(1, 2"),
+            "column 11: expected `,` or `)`, found the end"
+        );
+        assert_eq!(
+            error("click(1 2)"),
+            "column 9: expected `,` or `)`, found number 2"
+        );
+        assert_eq!(error("click"), "column 6: expected `(`, found the end");
+    }
+
+    #[test]
+    fn missing_close_brace() {
+        assert_eq!(
+            error("loop(2) { click()"),
+            "column 18: expected `;` or `}`, found the end"
+        );
+        assert_eq!(
+            error("loop(2) click()"),
+            "column 9: expected `{`, found `click`"
+        );
+    }
+
+    #[test]
+    fn wrong_arity() {
+        assert_eq!(
+            error("move(1)"),
+            "column 1: move takes x and y, got 1 argument(s)"
+        );
+        assert_eq!(
+            error("click(); key()"),
+            "column 10: key takes one key, got 0 argument(s)"
+        );
+        assert_eq!(
+            error("click(1)"),
+            "column 1: click takes no arguments, or x and y, got 1 argument(s)"
+        );
+        assert_eq!(
+            error("wait(1, 2)"),
+            "column 1: wait takes a duration, got 2 argument(s)"
+        );
+        assert_eq!(
+            error("combo(ctrl)"),
+            "column 1: combo takes two keys or more, got 1 argument(s)"
+        );
+        assert_eq!(
+            error("text('a', 'b')"),
+            "column 1: text takes one string, got 2 argument(s)"
+        );
+        assert_eq!(
+            error("loop() { click() }"),
+            "column 1: loop takes a count, got 0 argument(s)"
+        );
+    }
+
+    #[test]
+    fn duplicate_combo_keys() {
+        assert_eq!(
+            error("combo(ctrl, ctrl)"),
+            "column 13: `ctrl` is already in the combo"
+        );
+        // Both names stand for the same key
+        assert_eq!(
+            error("combo(ctrl, v, Control)"),
+            "column 16: `Control` is already in the combo"
+        );
+    }
+
+    #[test]
+    fn empty_input() {
+        assert_eq!(error(""), "column 1: no commands given");
+        assert_eq!(error("  ;  "), "column 6: no commands given");
+    }
+
+    #[test]
+    fn wrong_argument_types() {
+        assert_eq!(
+            error("move(x, 5)"),
+            "column 6: expected a coordinate, found `x`"
+        );
+        assert_eq!(
+            error("text(hello)"),
+            "column 6: expected a string, found `hello`"
+        );
+        assert_eq!(
+            error("key('a')"),
+            "column 5: expected a key, found string \"a\""
+        );
+        assert_eq!(
+            error("loop(-1) { click() }"),
+            "column 6: expected a loop count, found -1"
+        );
+        assert_eq!(error("wait(-5)"), "column 6: a duration cannot be negative");
+        assert!(error("wait('soon')").starts_with("column 6: invalid duration \"soon\""));
+        assert_eq!(
+            error("move(1, 99999999999)"),
+            "column 9: 99999999999 is off any screen"
+        );
+    }
+
+    #[test]
+    fn unknown_names() {
+        assert_eq!(
+            error("click(); jump()"),
+            format!(
+                "column 10: unknown command `jump`, expected one of {}",
+                COMMAND_NAMES
+            )
+        );
+        assert_eq!(error("key(nokey)"), "column 5: unknown key `nokey`");
+    }
+
+    #[test]
+    fn stray_tokens() {
+        assert_eq!(error("click() @"), "column 9: unexpected `@`");
+        assert_eq!(
+            error("click() click()"),
+            "column 9: expected `;` or the end, found `click`"
+        );
+        assert_eq!(
+            error("click(); }"),
+            "column 10: expected a command, found `}`"
+        );
+        assert_eq!(
+            error("click(,)"),
+            "column 7: expected an argument, found `,`"
+        );
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Commands {
+        #[serde(deserialize_with = "deserialize_commands")]
+        commands: Vec<Command>,
+    }
+
+    #[test]
+    fn deserialize_commands_takes_a_list_or_a_shorthand() {
+        let shorthand: Commands =
+            serde_yaml::from_str("commands: \"click(1, 2); wait('1s')\"").unwrap();
+        assert!(
+            matches!(
+                shorthand.commands.as_slice(),
+                [
+                    Command::SetMousePos(Coordinate::Value(1), Coordinate::Value(2)),
+                    Command::LeftClick,
+                    Command::Wait(DurationMs(1000))
+                ]
+            ),
+            "{:?}",
+            shorthand.commands
+        );
+
+        let list: Commands =
+            serde_yaml::from_str("commands:\n  - LeftClick\n  - !Wait 1s\n").unwrap();
+        assert!(
+            matches!(
+                list.commands.as_slice(),
+                [Command::LeftClick, Command::Wait(DurationMs(1000))]
+            ),
+            "{:?}",
+            list.commands
+        );
+
+        let e = serde_yaml::from_str::<Commands>("commands: \"click(\"")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            e.contains("in the commands shorthand, column 7: expected an argument, found the end"),
+            "{}",
+            e
+        );
+    }
+}
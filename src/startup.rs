@@ -0,0 +1,205 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::PathBuf,
+};
+
+use serde::Serialize;
+use windows::Win32::{
+    Foundation::HWND,
+    UI::Input::KeyboardAndMouse::{
+        RegisterHotKey, UnregisterHotKey, HOT_KEY_MODIFIERS, MOD_ALT, MOD_CONTROL, MOD_NOREPEAT,
+        MOD_SHIFT, MOD_WIN,
+    },
+};
+
+use super::{doctor, elevation, format_keys, Key, MacroConfig};
+
+/// How hotkeys are detected. Every trigger goes through the polling loop of the input listener.
+const TRIGGER_BACKEND: &str = "GetAsyncKeyState polling every 50 ms";
+/// Added to `TRIGGER_BACKEND` when a hotkey uses gamepad buttons.
+const GAMEPAD_BACKEND: &str = "XInput for gamepad buttons";
+
+/// Id of the throwaway registration used to probe whether a hotkey is free.
+const PROBE_HOTKEY_ID: i32 = 0x4D52;
+
+/// Hotkeys Windows and its bundled tools are known to claim, as `RegisterHotKey` modifiers and key.
+const KNOWN_OWNERS: &[(u32, Key, &str)] = &[
+    (MOD_WIN.0, Key::L, "Windows (lock workstation)"),
+    (MOD_WIN.0, Key::D, "Windows (show desktop)"),
+    (MOD_WIN.0, Key::E, "Windows (File Explorer)"),
+    (MOD_WIN.0, Key::R, "Windows (Run dialog)"),
+    (MOD_WIN.0, Key::Tab, "Windows (Task View)"),
+    (
+        MOD_CONTROL.0 | MOD_SHIFT.0,
+        Key::Escape,
+        "Windows (Task Manager)",
+    ),
+    (MOD_WIN.0 | MOD_SHIFT.0, Key::S, "Snipping Tool"),
+    (0, Key::Snapshot, "Snipping Tool or another screenshot tool"),
+];
+
+/// A hotkey that another application has already registered, so pressing it may never reach
+/// the runner.
+#[derive(Debug, Clone, Serialize)]
+pub struct HotkeyConflict {
+    /// The macro the hotkey belongs to, `None` for `program_hotkey`.
+    pub macro_name: Option<String>,
+    pub hotkey: String,
+    pub likely_owner: String,
+}
+
+/// Summary of the environment the runner started in, logged once at startup and served over
+/// HTTP so that a hook or hotkey problem shows up before a macro silently fails to fire.
+#[derive(Debug, Clone, Serialize)]
+pub struct StartupReport {
+    /// `None` when the built-in config is used.
+    pub config_path: Option<PathBuf>,
+    /// Enabled macros per config file, the main one and any it includes.
+    pub macros_per_file: BTreeMap<String, usize>,
+    pub disabled_macros: usize,
+    pub trigger_backend: String,
+    /// Why a low-level keyboard hook could not be installed, `None` when it could.
+    pub keyboard_hook_error: Option<String>,
+    pub hotkey_conflicts: Vec<HotkeyConflict>,
+    /// `None` when the elevation could not be determined.
+    pub elevated: Option<bool>,
+}
+
+impl StartupReport {
+    pub fn build(macro_config: &MacroConfig, config_path: Option<PathBuf>) -> Self {
+        let mut macros_per_file = BTreeMap::new();
+        for current_macro in macro_config.macros.iter().filter(|m| m.enabled) {
+            let file = match &current_macro.source {
+                Some(source) if config_path.is_some() => source.display().to_string(),
+                _ => "built-in".to_string(),
+            };
+            *macros_per_file.entry(file).or_insert(0) += 1;
+        }
+
+        let uses_gamepad = macro_config
+            .macros
+            .iter()
+            .filter(|m| m.enabled)
+            .any(|m| m.macro_hotkey.iter().any(Key::is_gamepad));
+
+        let mut hotkey_conflicts = Vec::new();
+        let hotkeys = std::iter::once((None, &macro_config.program_hotkey)).chain(
+            macro_config
+                .macros
+                .iter()
+                .filter(|m| m.enabled && !m.macro_hotkey.is_empty())
+                .map(|m| (Some(m.macro_name.clone()), &m.macro_hotkey)),
+        );
+        for (macro_name, hotkey) in hotkeys {
+            if let Some(likely_owner) = probe_hotkey(hotkey) {
+                hotkey_conflicts.push(HotkeyConflict {
+                    macro_name,
+                    hotkey: format_keys(hotkey),
+                    likely_owner,
+                });
+            }
+        }
+
+        StartupReport {
+            config_path,
+            macros_per_file,
+            disabled_macros: macro_config.macros.iter().filter(|m| !m.enabled).count(),
+            trigger_backend: if uses_gamepad {
+                format!("{}, {}", TRIGGER_BACKEND, GAMEPAD_BACKEND)
+            } else {
+                TRIGGER_BACKEND.to_string()
+            },
+            keyboard_hook_error: doctor::check_keyboard_hook().err().map(|e| e.to_string()),
+            hotkey_conflicts,
+            elevated: elevation::is_current_process_elevated().ok(),
+        }
+    }
+
+    pub fn log(&self) {
+        log::info!(
+            "Config: {}",
+            self.config_path
+                .as_ref()
+                .map_or("built-in".to_string(), |path| path.display().to_string())
+        );
+        for (file, count) in self.macros_per_file.iter() {
+            log::info!("  {} enabled macro(s) from {}", count, file);
+        }
+        if self.disabled_macros > 0 {
+            log::info!("  {} disabled macro(s)", self.disabled_macros);
+        }
+        log::info!("Trigger backend: {}", self.trigger_backend);
+
+        match &self.keyboard_hook_error {
+            None => log::info!("Low-level keyboard hook: available"),
+            Some(e) => log::warn!("Low-level keyboard hook failed: {}", e),
+        }
+
+        for conflict in self.hotkey_conflicts.iter() {
+            log::warn!(
+                "Hotkey {} of {} is already registered, likely by {}",
+                conflict.hotkey,
+                conflict.macro_name.as_deref().map_or(
+                    "program_hotkey".to_string(),
+                    |name| format!("macro {}", name)
+                ),
+                conflict.likely_owner
+            );
+        }
+
+        match self.elevated {
+            Some(true) => log::info!("Elevated: yes"),
+            Some(false) => log::info!("Elevated: no"),
+            None => log::warn!("Elevated: unknown"),
+        }
+    }
+}
+
+/// Tries to register `hotkey` and returns the likely owner when another application already
+/// has. Only hotkeys `RegisterHotKey` can express, modifiers and one other keyboard key, are
+/// probed.
+fn probe_hotkey(hotkey: &HashSet<Key>) -> Option<String> {
+    let mut modifiers = 0;
+    let mut others = Vec::new();
+
+    for key in hotkey.iter() {
+        match key.modifier_order() {
+            0 => modifiers |= MOD_CONTROL.0,
+            1 => modifiers |= MOD_SHIFT.0,
+            2 if *key == Key::AltGr => modifiers |= MOD_CONTROL.0 | MOD_ALT.0,
+            2 => modifiers |= MOD_ALT.0,
+            3 => modifiers |= MOD_WIN.0,
+            _ => others.push(*key),
+        }
+    }
+
+    let key = match others.as_slice() {
+        [key] if !key.is_mouse_button() && !key.is_gamepad() => *key,
+        _ => return None,
+    };
+
+    let registered = unsafe {
+        RegisterHotKey(
+            HWND::default(),
+            PROBE_HOTKEY_ID,
+            HOT_KEY_MODIFIERS(modifiers | MOD_NOREPEAT.0),
+            key.virtual_key() as u32,
+        )
+    }
+    .as_bool();
+
+    if registered {
+        unsafe { UnregisterHotKey(HWND::default(), PROBE_HOTKEY_ID) };
+        return None;
+    }
+
+    Some(
+        KNOWN_OWNERS
+            .iter()
+            .find(|(owner_modifiers, owner_key, _)| {
+                *owner_modifiers == modifiers && *owner_key == key
+            })
+            .map_or("another application", |(_, _, owner)| owner)
+            .to_string(),
+    )
+}
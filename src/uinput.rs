@@ -0,0 +1,767 @@
+use std::{
+    ffi::{c_int, c_ulong},
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    mem::size_of,
+    os::unix::io::AsRawFd,
+    path::Path,
+};
+
+use super::{
+    backend::{InputBackend, InputEvent},
+    Key, MouseButton,
+};
+
+const UINPUT_PATH: &str = "/dev/uinput";
+/// Where the real keyboards and mice are read from, for which keys are held.
+const EVENT_DEVICES: &str = "/dev/input";
+/// Screen size the absolute pointer's axes cover when no connected monitor reports one.
+const DEFAULT_SCREEN_SIZE: (i32, i32) = (1920, 1080);
+/// The udev rule that lets members of the `input` group create devices and read the real ones.
+const UDEV_HINT: &str = "add the rule KERNEL==\"uinput\", GROUP=\"input\", MODE=\"0660\", \
+    OPTIONS+=\"static_node=uinput\" to /etc/udev/rules.d/99-uinput.rules, run \
+    `sudo udevadm control --reload && sudo udevadm trigger`, add yourself to the input group \
+    with `sudo usermod -aG input $USER` and log in again";
+
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const EV_REL: u16 = 0x02;
+const EV_ABS: u16 = 0x03;
+const SYN_REPORT: u16 = 0x00;
+const REL_X: u16 = 0x00;
+const REL_Y: u16 = 0x01;
+const REL_WHEEL: u16 = 0x08;
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+const BTN_LEFT: u16 = 0x110;
+const BTN_RIGHT: u16 = 0x111;
+const BTN_MIDDLE: u16 = 0x112;
+const BTN_SIDE: u16 = 0x113;
+const BTN_EXTRA: u16 = 0x114;
+const KEY_MAX: usize = 0x2ff;
+const BUS_VIRTUAL: u16 = 0x06;
+
+const IOC_NONE: c_ulong = 0;
+const IOC_WRITE: c_ulong = 1;
+const IOC_READ: c_ulong = 2;
+
+/// The `_IOC` macro of the kernel headers.
+const fn ioc(direction: c_ulong, kind: u8, number: u8, size: usize) -> c_ulong {
+    direction << 30 | (size as c_ulong) << 16 | (kind as c_ulong) << 8 | number as c_ulong
+}
+
+const UI_DEV_CREATE: c_ulong = ioc(IOC_NONE, b'U', 1, 0);
+const UI_DEV_DESTROY: c_ulong = ioc(IOC_NONE, b'U', 2, 0);
+const UI_DEV_SETUP: c_ulong = ioc(IOC_WRITE, b'U', 3, size_of::<UinputSetup>());
+const UI_ABS_SETUP: c_ulong = ioc(IOC_WRITE, b'U', 4, size_of::<UinputAbsSetup>());
+const UI_SET_EVBIT: c_ulong = ioc(IOC_WRITE, b'U', 100, size_of::<c_int>());
+const UI_SET_KEYBIT: c_ulong = ioc(IOC_WRITE, b'U', 101, size_of::<c_int>());
+const UI_SET_RELBIT: c_ulong = ioc(IOC_WRITE, b'U', 102, size_of::<c_int>());
+const UI_SET_ABSBIT: c_ulong = ioc(IOC_WRITE, b'U', 103, size_of::<c_int>());
+const EVIOCGKEY: c_ulong = ioc(IOC_READ, b'E', 0x18, KEY_MAX / 8 + 1);
+
+extern "C" {
+    fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+}
+
+#[repr(C)]
+struct InputId {
+    bustype: u16,
+    vendor: u16,
+    product: u16,
+    version: u16,
+}
+
+#[repr(C)]
+struct UinputSetup {
+    id: InputId,
+    name: [u8; 80],
+    ff_effects_max: u32,
+}
+
+#[repr(C)]
+struct InputAbsinfo {
+    value: i32,
+    minimum: i32,
+    maximum: i32,
+    fuzz: i32,
+    flat: i32,
+    resolution: i32,
+}
+
+#[repr(C)]
+struct UinputAbsSetup {
+    code: u16,
+    absinfo: InputAbsinfo,
+}
+
+/// `struct input_event`, with the time left for the kernel to fill in.
+#[repr(C)]
+struct RawEvent {
+    time: [std::ffi::c_long; 2],
+    kind: u16,
+    code: u16,
+    value: i32,
+}
+
+/// Injects input on Linux without a display server, through two virtual devices: a keyboard with
+/// a relative mouse, and a pointer with absolute axes spanning the screen, which is how the cursor
+/// is put at a point. Which keys are held is read from the keyboards and mice in `/dev/input`.
+pub struct UinputBackend {
+    keyboard: Device,
+    pointer: Device,
+    screen_size: (i32, i32),
+    /// The input devices that could be opened, the virtual ones included.
+    event_devices: Vec<File>,
+}
+
+impl UinputBackend {
+    pub fn open() -> Result<Self, anyhow::Error> {
+        Self::open_at(Path::new(UINPUT_PATH))
+    }
+
+    fn open_at(path: &Path) -> Result<Self, anyhow::Error> {
+        let screen_size = connected_screen_size().unwrap_or_else(|| {
+            log::warn!(
+                "No connected monitor reports its size, taking the screen to be {}x{}",
+                DEFAULT_SCREEN_SIZE.0,
+                DEFAULT_SCREEN_SIZE.1
+            );
+            DEFAULT_SCREEN_SIZE
+        });
+
+        let keyboard = Device::create(path, "input macro runner keyboard", |file| {
+            set_bits(file, UI_SET_EVBIT, &[EV_KEY, EV_REL])?;
+            let keys: Vec<u16> = ALL_KEYS.iter().filter_map(|key| key_code(*key)).collect();
+            set_bits(file, UI_SET_KEYBIT, &keys)?;
+            set_bits(file, UI_SET_RELBIT, &[REL_X, REL_Y, REL_WHEEL])
+        })?;
+        let pointer = Device::create(path, "input macro runner pointer", |file| {
+            set_bits(file, UI_SET_EVBIT, &[EV_KEY, EV_ABS])?;
+            // Without a button the device is taken for a joystick rather than a pointer
+            set_bits(file, UI_SET_KEYBIT, &[BTN_LEFT])?;
+            set_bits(file, UI_SET_ABSBIT, &[ABS_X, ABS_Y])?;
+            set_axis(file, ABS_X, screen_size.0)?;
+            set_axis(file, ABS_Y, screen_size.1)
+        })?;
+
+        Ok(UinputBackend {
+            keyboard,
+            pointer,
+            screen_size,
+            event_devices: open_event_devices(),
+        })
+    }
+
+    fn send_event(&self, event: InputEvent) -> Result<(), anyhow::Error> {
+        match event {
+            InputEvent::KeyDown(key) | InputEvent::KeyUp(key) => {
+                let code = key_code(key).ok_or_else(|| {
+                    anyhow::anyhow!("{:?} has no Linux key code to send through uinput", key)
+                })?;
+                let value = matches!(event, InputEvent::KeyDown(_)) as i32;
+                self.keyboard.emit(&[(EV_KEY, code, value)])
+            }
+            InputEvent::ButtonDown(button) => {
+                self.keyboard.emit(&[(EV_KEY, button_code(button), 1)])
+            }
+            InputEvent::ButtonUp(button) => self.keyboard.emit(&[(EV_KEY, button_code(button), 0)]),
+            InputEvent::MoveTo(x, y) => {
+                let x = x.clamp(0, self.screen_size.0 - 1);
+                let y = y.clamp(0, self.screen_size.1 - 1);
+                self.pointer.emit(&[(EV_ABS, ABS_X, x), (EV_ABS, ABS_Y, y)])
+            }
+            InputEvent::Wheel(lines) => self.keyboard.emit(&[(EV_REL, REL_WHEEL, lines)]),
+        }
+        .map_err(|e| anyhow::anyhow!("Failed to send {:?} through uinput: {}", event, e))
+    }
+}
+
+impl InputBackend for UinputBackend {
+    fn is_key_held(&self, key: Key) -> bool {
+        let code = match key_code(key) {
+            Some(code) => code as usize,
+            None => return false,
+        };
+
+        self.event_devices.iter().any(|device| {
+            let mut state = [0u8; KEY_MAX / 8 + 1];
+            let read = unsafe { ioctl(device.as_raw_fd(), EVIOCGKEY, state.as_mut_ptr()) };
+            read >= 0 && state[code / 8] & (1 << (code % 8)) != 0
+        })
+    }
+
+    fn send(&self, events: &[InputEvent]) -> Result<(), anyhow::Error> {
+        events.iter().try_for_each(|event| self.send_event(*event))
+    }
+}
+
+/// A virtual input device, destroyed when dropped.
+struct Device {
+    file: File,
+}
+
+impl Device {
+    /// Makes a device named `name`, after `setup` has declared the events it sends.
+    fn create(
+        path: &Path,
+        name: &str,
+        setup: impl FnOnce(&File) -> io::Result<()>,
+    ) -> Result<Self, anyhow::Error> {
+        let file = OpenOptions::new()
+            .write(true)
+            .open(path)
+            .map_err(|e| open_error(path, e))?;
+
+        let mut device = UinputSetup {
+            id: InputId {
+                bustype: BUS_VIRTUAL,
+                vendor: 0,
+                product: 0,
+                version: 1,
+            },
+            name: [0; 80],
+            ff_effects_max: 0,
+        };
+        device.name[..name.len()].copy_from_slice(name.as_bytes());
+
+        setup(&file)
+            .and_then(|()| {
+                check(unsafe {
+                    ioctl(
+                        file.as_raw_fd(),
+                        UI_DEV_SETUP,
+                        &device as *const UinputSetup,
+                    )
+                })
+            })
+            .and_then(|()| check(unsafe { ioctl(file.as_raw_fd(), UI_DEV_CREATE) }))
+            .map_err(|e| anyhow::anyhow!("Failed to create the uinput device {}: {}", name, e))?;
+
+        Ok(Device { file })
+    }
+
+    /// Writes `events`, each `(type, code, value)`, followed by the report that delivers them.
+    fn emit(&self, events: &[(u16, u16, i32)]) -> io::Result<()> {
+        let bytes: Vec<u8> = events
+            .iter()
+            .copied()
+            .chain([(EV_SYN, SYN_REPORT, 0)])
+            .flat_map(|(kind, code, value)| {
+                let event = RawEvent {
+                    time: [0, 0],
+                    kind,
+                    code,
+                    value,
+                };
+                let bytes = unsafe {
+                    std::slice::from_raw_parts(
+                        &event as *const RawEvent as *const u8,
+                        size_of::<RawEvent>(),
+                    )
+                };
+                bytes.to_vec()
+            })
+            .collect();
+
+        (&self.file).write_all(&bytes)
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        unsafe { ioctl(self.file.as_raw_fd(), UI_DEV_DESTROY) };
+    }
+}
+
+/// Turns a failure to open `/dev/uinput` into what to do about it.
+fn open_error(path: &Path, error: io::Error) -> anyhow::Error {
+    match error.kind() {
+        io::ErrorKind::PermissionDenied => anyhow::anyhow!(
+            "Not allowed to open {}, so no input can be sent. To allow it, {}",
+            path.display(),
+            UDEV_HINT
+        ),
+        io::ErrorKind::NotFound => anyhow::anyhow!(
+            "{} does not exist, load the uinput module with `sudo modprobe uinput`",
+            path.display()
+        ),
+        _ => anyhow::anyhow!("Failed to open {}: {}", path.display(), error),
+    }
+}
+
+fn check(result: c_int) -> io::Result<()> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+fn set_bits(file: &File, request: c_ulong, codes: &[u16]) -> io::Result<()> {
+    codes
+        .iter()
+        .try_for_each(|code| check(unsafe { ioctl(file.as_raw_fd(), request, *code as c_int) }))
+}
+
+/// Makes `axis` run over the `pixels` of the screen along it.
+fn set_axis(file: &File, axis: u16, pixels: i32) -> io::Result<()> {
+    let setup = UinputAbsSetup {
+        code: axis,
+        absinfo: InputAbsinfo {
+            value: 0,
+            minimum: 0,
+            maximum: pixels - 1,
+            fuzz: 0,
+            flat: 0,
+            resolution: 0,
+        },
+    };
+
+    check(unsafe {
+        ioctl(
+            file.as_raw_fd(),
+            UI_ABS_SETUP,
+            &setup as *const UinputAbsSetup,
+        )
+    })
+}
+
+/// Every event device that can be read. Without read access to them no key reads as held, which
+/// is logged along with how to get it.
+fn open_event_devices() -> Vec<File> {
+    let entries = match fs::read_dir(EVENT_DEVICES) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::warn!("Failed to list {}: {}", EVENT_DEVICES, e);
+            return Vec::new();
+        }
+    };
+
+    let mut denied = false;
+    let devices: Vec<File> = entries
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("event"))
+        .filter_map(|entry| match File::open(entry.path()) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                denied |= e.kind() == io::ErrorKind::PermissionDenied;
+                None
+            }
+        })
+        .collect();
+
+    if denied {
+        log::warn!(
+            "Some of {} cannot be read, so hotkeys and held keys on them go unseen. To allow \
+             it, {}",
+            EVENT_DEVICES,
+            UDEV_HINT
+        );
+    }
+
+    devices
+}
+
+/// The size of the first connected monitor, from the first mode its DRM connector lists.
+fn connected_screen_size() -> Option<(i32, i32)> {
+    fs::read_dir("/sys/class/drm")
+        .ok()?
+        .flatten()
+        .filter(|connector| {
+            fs::read_to_string(connector.path().join("status"))
+                .is_ok_and(|status| status.trim() == "connected")
+        })
+        .find_map(|connector| {
+            let modes = fs::read_to_string(connector.path().join("modes")).ok()?;
+            parse_mode(modes.lines().next()?)
+        })
+}
+
+/// Reads a mode written `1920x1080`, the way DRM lists them.
+fn parse_mode(mode: &str) -> Option<(i32, i32)> {
+    let (width, height) = mode.trim().split_once('x')?;
+    // Interlaced modes end in `i`
+    let height = height.trim_end_matches(|c: char| !c.is_ascii_digit());
+    match (width.parse().ok()?, height.parse().ok()?) {
+        (width, height) if width > 0 && height > 0 => Some((width, height)),
+        _ => None,
+    }
+}
+
+fn button_code(button: MouseButton) -> u16 {
+    match button {
+        MouseButton::Left => BTN_LEFT,
+        MouseButton::Right => BTN_RIGHT,
+        MouseButton::Middle => BTN_MIDDLE,
+    }
+}
+
+/// The keys given to the virtual keyboard, every one with a Linux key code.
+const ALL_KEYS: &[Key] = &[
+    Key::LeftButton,
+    Key::RightButton,
+    Key::MiddleButton,
+    Key::XButton1,
+    Key::XButton2,
+    Key::Back,
+    Key::Tab,
+    Key::Return,
+    Key::Pause,
+    Key::Capital,
+    Key::Escape,
+    Key::Convert,
+    Key::NonConvert,
+    Key::Space,
+    Key::Prior,
+    Key::Next,
+    Key::End,
+    Key::Home,
+    Key::Left,
+    Key::Up,
+    Key::Right,
+    Key::Down,
+    Key::Select,
+    Key::Print,
+    Key::Snapshot,
+    Key::Insert,
+    Key::Delete,
+    Key::Help,
+    Key::Key0,
+    Key::Key1,
+    Key::Key2,
+    Key::Key3,
+    Key::Key4,
+    Key::Key5,
+    Key::Key6,
+    Key::Key7,
+    Key::Key8,
+    Key::Key9,
+    Key::A,
+    Key::B,
+    Key::C,
+    Key::D,
+    Key::E,
+    Key::F,
+    Key::G,
+    Key::H,
+    Key::I,
+    Key::J,
+    Key::K,
+    Key::L,
+    Key::M,
+    Key::N,
+    Key::O,
+    Key::P,
+    Key::Q,
+    Key::R,
+    Key::S,
+    Key::T,
+    Key::U,
+    Key::V,
+    Key::W,
+    Key::X,
+    Key::Y,
+    Key::Z,
+    Key::LeftWindows,
+    Key::RightWindows,
+    Key::Applications,
+    Key::Sleep,
+    Key::Numpad0,
+    Key::Numpad1,
+    Key::Numpad2,
+    Key::Numpad3,
+    Key::Numpad4,
+    Key::Numpad5,
+    Key::Numpad6,
+    Key::Numpad7,
+    Key::Numpad8,
+    Key::Numpad9,
+    Key::NumpadEnter,
+    Key::Multiply,
+    Key::Add,
+    Key::Separator,
+    Key::Subtract,
+    Key::Decimal,
+    Key::Divide,
+    Key::F1,
+    Key::F2,
+    Key::F3,
+    Key::F4,
+    Key::F5,
+    Key::F6,
+    Key::F7,
+    Key::F8,
+    Key::F9,
+    Key::F10,
+    Key::F11,
+    Key::F12,
+    Key::F13,
+    Key::F14,
+    Key::F15,
+    Key::F16,
+    Key::F17,
+    Key::F18,
+    Key::F19,
+    Key::F20,
+    Key::F21,
+    Key::F22,
+    Key::F23,
+    Key::F24,
+    Key::Numlock,
+    Key::Scroll,
+    Key::LeftShift,
+    Key::RightShift,
+    Key::LeftControl,
+    Key::RightControl,
+    Key::LeftMenu,
+    Key::RightMenu,
+    Key::BrowserBack,
+    Key::BrowserForward,
+    Key::BrowserRefresh,
+    Key::BrowserStop,
+    Key::BrowserSearch,
+    Key::BrowserFavorites,
+    Key::BrowserHome,
+    Key::VolumeMute,
+    Key::VolumeDown,
+    Key::VolumeUp,
+    Key::MediaNextTrack,
+    Key::MediaPrevTrack,
+    Key::MediaStop,
+    Key::MediaPlayPause,
+    Key::LaunchMail,
+    Key::LaunchMediaSelect,
+    Key::LaunchApp1,
+    Key::LaunchApp2,
+    Key::Oem1,
+    Key::OemPlus,
+    Key::OemComma,
+    Key::OemMinus,
+    Key::OemPeriod,
+    Key::Oem2,
+    Key::Oem3,
+    Key::Oem4,
+    Key::Oem5,
+    Key::Oem6,
+    Key::Oem7,
+    Key::Oem102,
+    Key::Play,
+    Key::Zoom,
+];
+
+/// The Linux key code of `key`, from `linux/input-event-codes.h`. The generic modifiers are their
+/// left-hand keys, and AltGr is the right Alt key, which is what it is on Linux.
+fn key_code(key: Key) -> Option<u16> {
+    let code = match key {
+        Key::LeftButton => BTN_LEFT,
+        Key::RightButton => BTN_RIGHT,
+        Key::MiddleButton => BTN_MIDDLE,
+        Key::XButton1 => BTN_SIDE,
+        Key::XButton2 => BTN_EXTRA,
+        Key::Escape => 1,
+        Key::Key1 => 2,
+        Key::Key2 => 3,
+        Key::Key3 => 4,
+        Key::Key4 => 5,
+        Key::Key5 => 6,
+        Key::Key6 => 7,
+        Key::Key7 => 8,
+        Key::Key8 => 9,
+        Key::Key9 => 10,
+        Key::Key0 => 11,
+        Key::OemMinus => 12,
+        Key::OemPlus => 13,
+        Key::Back => 14,
+        Key::Tab => 15,
+        Key::Q => 16,
+        Key::W => 17,
+        Key::E => 18,
+        Key::R => 19,
+        Key::T => 20,
+        Key::Y => 21,
+        Key::U => 22,
+        Key::I => 23,
+        Key::O => 24,
+        Key::P => 25,
+        Key::Oem4 => 26,
+        Key::Oem6 => 27,
+        Key::Return => 28,
+        Key::LeftControl | Key::Control => 29,
+        Key::A => 30,
+        Key::S => 31,
+        Key::D => 32,
+        Key::F => 33,
+        Key::G => 34,
+        Key::H => 35,
+        Key::J => 36,
+        Key::K => 37,
+        Key::L => 38,
+        Key::Oem1 => 39,
+        Key::Oem7 => 40,
+        Key::Oem3 => 41,
+        Key::LeftShift | Key::Shift => 42,
+        Key::Oem5 => 43,
+        Key::Z => 44,
+        Key::X => 45,
+        Key::C => 46,
+        Key::V => 47,
+        Key::B => 48,
+        Key::N => 49,
+        Key::M => 50,
+        Key::OemComma => 51,
+        Key::OemPeriod => 52,
+        Key::Oem2 => 53,
+        Key::RightShift => 54,
+        Key::Multiply => 55,
+        Key::LeftMenu | Key::Menu => 56,
+        Key::Space => 57,
+        Key::Capital => 58,
+        Key::F1 => 59,
+        Key::F2 => 60,
+        Key::F3 => 61,
+        Key::F4 => 62,
+        Key::F5 => 63,
+        Key::F6 => 64,
+        Key::F7 => 65,
+        Key::F8 => 66,
+        Key::F9 => 67,
+        Key::F10 => 68,
+        Key::Numlock => 69,
+        Key::Scroll => 70,
+        Key::Numpad7 => 71,
+        Key::Numpad8 => 72,
+        Key::Numpad9 => 73,
+        Key::Subtract => 74,
+        Key::Numpad4 => 75,
+        Key::Numpad5 => 76,
+        Key::Numpad6 => 77,
+        Key::Add => 78,
+        Key::Numpad1 => 79,
+        Key::Numpad2 => 80,
+        Key::Numpad3 => 81,
+        Key::Numpad0 => 82,
+        Key::Decimal => 83,
+        Key::Oem102 => 86,
+        Key::F11 => 87,
+        Key::F12 => 88,
+        Key::Convert => 92,
+        Key::NonConvert => 94,
+        Key::NumpadEnter => 96,
+        Key::RightControl => 97,
+        Key::Divide => 98,
+        Key::Snapshot => 99,
+        Key::RightMenu | Key::AltGr => 100,
+        Key::Home => 102,
+        Key::Up => 103,
+        Key::Prior => 104,
+        Key::Left => 105,
+        Key::Right => 106,
+        Key::End => 107,
+        Key::Down => 108,
+        Key::Next => 109,
+        Key::Insert => 110,
+        Key::Delete => 111,
+        Key::VolumeMute => 113,
+        Key::VolumeDown => 114,
+        Key::VolumeUp => 115,
+        Key::Pause => 119,
+        Key::Separator => 121,
+        Key::LeftWindows => 125,
+        Key::RightWindows => 126,
+        Key::Applications => 127,
+        Key::BrowserStop => 128,
+        Key::Help => 138,
+        Key::LaunchApp2 => 140,
+        Key::Sleep => 142,
+        Key::LaunchMail => 155,
+        Key::BrowserFavorites => 156,
+        Key::LaunchApp1 => 157,
+        Key::BrowserBack => 158,
+        Key::BrowserForward => 159,
+        Key::MediaNextTrack => 163,
+        Key::MediaPlayPause => 164,
+        Key::MediaPrevTrack => 165,
+        Key::MediaStop => 166,
+        Key::BrowserHome => 172,
+        Key::BrowserRefresh => 173,
+        Key::F13 => 183,
+        Key::F14 => 184,
+        Key::F15 => 185,
+        Key::F16 => 186,
+        Key::F17 => 187,
+        Key::F18 => 188,
+        Key::F19 => 189,
+        Key::F20 => 190,
+        Key::F21 => 191,
+        Key::F22 => 192,
+        Key::F23 => 193,
+        Key::F24 => 194,
+        Key::Play => 207,
+        Key::Print => 210,
+        Key::BrowserSearch => 217,
+        Key::LaunchMediaSelect => 226,
+        Key::Select => 0x161,
+        Key::Zoom => 0x174,
+        _ => return None,
+    };
+
+    Some(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn structs_match_the_kernel_layout() {
+        assert_eq!(size_of::<UinputSetup>(), 92);
+        assert_eq!(size_of::<UinputAbsSetup>(), 28);
+        assert_eq!(size_of::<RawEvent>(), 24);
+        assert_eq!(UI_DEV_SETUP, 0x405c_5503);
+        assert_eq!(UI_SET_KEYBIT, 0x4004_5565);
+        assert_eq!(EVIOCGKEY, 0x8060_4518);
+    }
+
+    #[test]
+    fn every_key_given_to_the_keyboard_has_its_own_code() {
+        let mut codes: Vec<u16> = ALL_KEYS.iter().filter_map(|key| key_code(*key)).collect();
+        assert_eq!(codes.len(), ALL_KEYS.len());
+
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), ALL_KEYS.len());
+        assert!(codes.iter().all(|code| (*code as usize) <= KEY_MAX));
+    }
+
+    #[test]
+    fn modifiers_map_onto_their_left_hand_keys() {
+        assert_eq!(key_code(Key::Shift), key_code(Key::LeftShift));
+        assert_eq!(key_code(Key::Control), key_code(Key::LeftControl));
+        assert_eq!(key_code(Key::AltGr), key_code(Key::RightMenu));
+        assert_eq!(key_code(Key::GamepadA), None);
+    }
+
+    #[test]
+    fn modes_are_read_as_width_and_height() {
+        assert_eq!(parse_mode("1920x1080\n"), Some((1920, 1080)));
+        assert_eq!(parse_mode("1920x1080i"), Some((1920, 1080)));
+        assert_eq!(parse_mode("0x0"), None);
+        assert_eq!(parse_mode("garbage"), None);
+    }
+
+    #[test]
+    fn unopenable_devices_say_what_to_do() {
+        let denied = open_error(
+            Path::new(UINPUT_PATH),
+            io::Error::from(io::ErrorKind::PermissionDenied),
+        );
+        assert!(denied.to_string().contains("99-uinput.rules"));
+
+        let missing = UinputBackend::open_at(Path::new("/nonexistent/uinput"))
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(missing.contains("modprobe uinput"), "{}", missing);
+    }
+}
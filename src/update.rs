@@ -0,0 +1,229 @@
+/// Where `check-update` looks for the latest release unless told otherwise.
+pub const DEFAULT_UPDATE_URL: &str =
+    "https://api.github.com/repos/ATabor89/rusty_command_macro_runner/releases/latest";
+
+pub fn version_string() -> String {
+    format!(
+        "{} {} ({})",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        env!("GIT_HASH")
+    )
+}
+
+/// The numeric parts of a version such as `v1.2.3` or `1.2.3-beta`, ignoring the leading `v` and
+/// any pre-release suffix. Trailing zeros are dropped so that `1.2` and `1.2.0` compare equal.
+fn version_parts(version: &str) -> Result<Vec<u64>, anyhow::Error> {
+    let version = version.trim().trim_start_matches('v');
+    let release = version.split(['-', '+']).next().unwrap_or_default();
+
+    let mut parts = release
+        .split('.')
+        .map(|part| {
+            part.parse()
+                .map_err(|_| anyhow::anyhow!("{:?} is not a version number", version))
+        })
+        .collect::<Result<Vec<u64>, _>>()?;
+    while parts.last() == Some(&0) {
+        parts.pop();
+    }
+
+    Ok(parts)
+}
+
+/// The latest version named by a response: the `tag_name` of a GitHub release, or the whole body
+/// for URLs that answer with a bare version.
+fn latest_version(body: &str) -> Result<String, anyhow::Error> {
+    match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(release) => release
+            .get("tag_name")
+            .and_then(serde_json::Value::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| anyhow::anyhow!("The response has no tag_name")),
+        Err(_) => Ok(body.trim().to_string()),
+    }
+}
+
+/// Reports whether `url` names a newer version than this one. Returns whether the check itself
+/// succeeded; failures are explained rather than returned, since they are usually a network
+/// problem rather than a bug.
+pub fn check_update(url: &str) -> bool {
+    let result = fetch(url).and_then(|body| {
+        let latest = latest_version(&body)?;
+        let newer = version_parts(&latest)? > version_parts(env!("CARGO_PKG_VERSION"))?;
+        Ok((latest, newer))
+    });
+
+    match result {
+        Ok((latest, true)) => {
+            println!(
+                "A newer version is available: {} (running {})",
+                latest,
+                env!("CARGO_PKG_VERSION")
+            );
+            true
+        }
+        Ok((_, false)) => {
+            println!("Up to date ({})", env!("CARGO_PKG_VERSION"));
+            true
+        }
+        Err(e) => {
+            println!("Could not check for updates at {}: {}", url, e);
+            false
+        }
+    }
+}
+
+#[cfg(not(feature = "update-check"))]
+fn fetch(_url: &str) -> Result<String, anyhow::Error> {
+    Err(anyhow::anyhow!(
+        "this build does not include the update-check feature"
+    ))
+}
+
+/// Fetches `url` with WinHTTP, which brings TLS and the system proxy settings without adding a
+/// dependency.
+#[cfg(feature = "update-check")]
+fn fetch(url: &str) -> Result<String, anyhow::Error> {
+    use windows::core::{HSTRING, PCWSTR};
+    use windows::Win32::Networking::WinHttp::{
+        WinHttpCloseHandle, WinHttpConnect, WinHttpOpen, WinHttpOpenRequest, WinHttpQueryHeaders,
+        WinHttpReadData, WinHttpReceiveResponse, WinHttpSendRequest, INTERNET_PORT,
+        WINHTTP_ACCESS_TYPE_AUTOMATIC_PROXY, WINHTTP_FLAG_SECURE, WINHTTP_OPEN_REQUEST_FLAGS,
+        WINHTTP_QUERY_FLAG_NUMBER, WINHTTP_QUERY_STATUS_CODE,
+    };
+
+    use super::get_last_windows_error;
+
+    /// Closes a WinHTTP handle when dropped.
+    struct Handle(*mut std::ffi::c_void);
+
+    impl Handle {
+        fn new(raw: *mut std::ffi::c_void, what: &str) -> Result<Self, anyhow::Error> {
+            if raw.is_null() {
+                return Err(anyhow::anyhow!(
+                    "{} failed: {}",
+                    what,
+                    get_last_windows_error()
+                ));
+            }
+            Ok(Handle(raw))
+        }
+    }
+
+    impl Drop for Handle {
+        fn drop(&mut self) {
+            unsafe { WinHttpCloseHandle(self.0) };
+        }
+    }
+
+    let (secure, rest) = if let Some(rest) = url.strip_prefix("https://") {
+        (true, rest)
+    } else if let Some(rest) = url.strip_prefix("http://") {
+        (false, rest)
+    } else {
+        return Err(anyhow::anyhow!(
+            "the URL must start with http:// or https://"
+        ));
+    };
+    let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    let (host, port) = match authority.split_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .map_err(|_| anyhow::anyhow!("invalid port {:?}", port))?,
+        ),
+        None => (authority, if secure { 443 } else { 80 }),
+    };
+    let path = if path.is_empty() { "/" } else { path };
+
+    let session = Handle::new(
+        unsafe {
+            WinHttpOpen(
+                &HSTRING::from(version_string()),
+                WINHTTP_ACCESS_TYPE_AUTOMATIC_PROXY,
+                PCWSTR::null(),
+                PCWSTR::null(),
+                0,
+            )
+        },
+        "Opening a WinHTTP session",
+    )?;
+    let connection = Handle::new(
+        unsafe { WinHttpConnect(session.0, &HSTRING::from(host), INTERNET_PORT(port), 0) },
+        "Connecting",
+    )?;
+    let request = Handle::new(
+        unsafe {
+            WinHttpOpenRequest(
+                connection.0,
+                &HSTRING::from("GET"),
+                &HSTRING::from(path),
+                PCWSTR::null(),
+                PCWSTR::null(),
+                std::ptr::null_mut(),
+                if secure {
+                    WINHTTP_FLAG_SECURE
+                } else {
+                    WINHTTP_OPEN_REQUEST_FLAGS(0)
+                },
+            )
+        },
+        "Opening the request",
+    )?;
+
+    let sent = unsafe { WinHttpSendRequest(request.0, &[], std::ptr::null(), 0, 0, 0) };
+    if !sent.as_bool()
+        || !unsafe { WinHttpReceiveResponse(request.0, std::ptr::null_mut()) }.as_bool()
+    {
+        return Err(anyhow::anyhow!(
+            "the request failed, check the network connection: {}",
+            get_last_windows_error()
+        ));
+    }
+
+    let mut status = 0u32;
+    let mut status_size = std::mem::size_of::<u32>() as u32;
+    unsafe {
+        WinHttpQueryHeaders(
+            request.0,
+            WINHTTP_QUERY_STATUS_CODE | WINHTTP_QUERY_FLAG_NUMBER,
+            PCWSTR::null(),
+            &mut status as *mut u32 as *mut std::ffi::c_void,
+            &mut status_size,
+            std::ptr::null_mut(),
+        )
+    };
+    if status != 200 {
+        return Err(anyhow::anyhow!(
+            "the server answered with status {}",
+            status
+        ));
+    }
+
+    let mut body = Vec::new();
+    let mut buffer = [0u8; 8192];
+    loop {
+        let mut read = 0u32;
+        let succeeded = unsafe {
+            WinHttpReadData(
+                request.0,
+                buffer.as_mut_ptr() as *mut std::ffi::c_void,
+                buffer.len() as u32,
+                &mut read,
+            )
+        };
+        if !succeeded.as_bool() {
+            return Err(anyhow::anyhow!(
+                "reading the response failed: {}",
+                get_last_windows_error()
+            ));
+        }
+        if read == 0 {
+            break;
+        }
+        body.extend_from_slice(&buffer[..read as usize]);
+    }
+
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
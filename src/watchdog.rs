@@ -0,0 +1,91 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crossbeam::channel::{Receiver, RecvTimeoutError};
+
+use super::{
+    events::{ExecutionEvent, ExecutionEventKind},
+    idle::time_since_last_input,
+    key_held, key_up, Key,
+};
+
+/// Every key this process has injected a key down for this session, and whether it has since
+/// injected the matching key up.
+static INJECTED_KEYS: Mutex<BTreeMap<Key, bool>> = Mutex::new(BTreeMap::new());
+
+const WATCHDOG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub fn record_key_down(key: i32) {
+    if let Ok(mut injected_keys) = INJECTED_KEYS.lock() {
+        injected_keys.insert(Key::from(key), true);
+    }
+}
+
+pub fn record_key_up(key: i32) {
+    if let Ok(mut injected_keys) = INJECTED_KEYS.lock() {
+        if let Some(down) = injected_keys.get_mut(&Key::from(key)) {
+            *down = false;
+        }
+    }
+}
+
+/// Modifiers that were injected down and then up again at some point, i.e. any that still read
+/// as held should not be.
+fn released_modifiers() -> Vec<Key> {
+    INJECTED_KEYS
+        .lock()
+        .map(|injected_keys| {
+            injected_keys
+                .iter()
+                .filter(|(key, down)| key.is_modifier() && !**down)
+                .map(|(key, _)| *key)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Releases modifiers that read as held although the last thing this process did with them was
+/// let them up, e.g. because an application stole focus in the middle of a combo. Only acts once
+/// no macro has run, and no input at all has arrived, for `idle`, so a modifier the user is
+/// really holding is left alone.
+pub fn watch_modifiers(rx: Receiver<ExecutionEvent>, idle: Duration) {
+    let mut running = HashSet::new();
+    let mut last_activity = Instant::now();
+
+    loop {
+        match rx.recv_timeout(WATCHDOG_POLL_INTERVAL) {
+            Ok(event) => {
+                match event.kind {
+                    ExecutionEventKind::MacroStarted { .. } => {
+                        running.insert(event.execution_id);
+                    }
+                    ExecutionEventKind::MacroCompleted { .. }
+                    | ExecutionEventKind::MacroCancelled { .. } => {
+                        running.remove(&event.execution_id);
+                    }
+                    _ => {}
+                }
+                last_activity = Instant::now();
+                continue;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        if !running.is_empty() || last_activity.elapsed() < idle || time_since_last_input() < idle {
+            continue;
+        }
+
+        for key in released_modifiers() {
+            if key_held(key.virtual_key()) {
+                match key_up(key as i32) {
+                    Ok(()) => log::warn!("Released stuck modifier {:?}", key),
+                    Err(e) => log::error!("Failed to release stuck modifier {:?}: {}", key, e),
+                }
+            }
+        }
+    }
+}
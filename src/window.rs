@@ -0,0 +1,509 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use windows::Win32::Foundation::HWND;
+
+use super::{desktop, error::MacroError, get_last_windows_error};
+
+/// A top-level window and the program it belongs to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowInfo {
+    pub title: String,
+    /// Window class name, e.g. `Notepad` or `CabinetWClass`, which unlike the title does not
+    /// change with the language or the open document.
+    pub class: String,
+    /// File name of the executable, e.g. `excel.exe`.
+    pub process: String,
+}
+
+impl fmt::Display for WindowInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} ({}, {})", self.title, self.process, self.class)
+    }
+}
+
+/// One entry of the config's `allowed_targets`. A window is allowed when everything the entry
+/// sets matches it, ignoring case.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AllowedTarget {
+    /// File name of the executable, e.g. `excel.exe`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub process: Option<String>,
+    /// Window class name, e.g. `Notepad`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub class: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title_contains: Option<String>,
+}
+
+impl AllowedTarget {
+    pub fn matches(&self, window: &WindowInfo) -> bool {
+        self.process
+            .as_deref()
+            .is_none_or(|process| process.eq_ignore_ascii_case(&window.process))
+            && self
+                .class
+                .as_deref()
+                .is_none_or(|class| class.eq_ignore_ascii_case(&window.class))
+            && self
+                .title_contains
+                .as_deref()
+                .is_none_or(|title| window.title.to_lowercase().contains(&title.to_lowercase()))
+    }
+}
+
+/// Which top-level window a command acts on. A window matches when everything the selector sets
+/// matches it: the title exactly, the class and the executable's file name ignoring case.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct WindowSelector<'a> {
+    pub title: Option<&'a str>,
+    pub class: Option<&'a str>,
+    pub process: Option<&'a str>,
+    /// Skip windows on other virtual desktops.
+    pub current_desktop_only: bool,
+}
+
+impl<'a> WindowSelector<'a> {
+    /// Selects the window with this exact title.
+    pub fn title(title: &'a str) -> Self {
+        WindowSelector {
+            title: Some(title),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none() && self.class.is_none() && self.process.is_none()
+    }
+}
+
+impl fmt::Display for WindowSelector<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(title) = self.title {
+            parts.push(format!("title {:?}", title));
+        }
+        if let Some(class) = self.class {
+            parts.push(format!("class {:?}", class));
+        }
+        if let Some(process) = self.process {
+            parts.push(format!("process {}", process));
+        }
+        if self.current_desktop_only {
+            parts.push("on the current desktop".to_string());
+        }
+        f.write_str(&parts.join(", "))
+    }
+}
+
+/// Finds the top-level window `selector` matches. A title alone may also find a hidden window,
+/// while a class or process only find visible ones, the first in z-order when there are several.
+/// With `current_desktop_only`, only visible windows are found, and those on other virtual
+/// desktops are passed over.
+#[cfg(windows)]
+pub fn find_window(selector: &WindowSelector) -> Result<HWND, anyhow::Error> {
+    use windows::core::{HSTRING, PCWSTR};
+    use windows::Win32::Foundation::{BOOL, LPARAM};
+    use windows::Win32::UI::WindowsAndMessaging::{EnumWindows, FindWindowW, IsWindowVisible};
+
+    struct Search<'a, 'b> {
+        selector: &'a WindowSelector<'b>,
+        found: Option<HWND>,
+    }
+
+    unsafe extern "system" fn visit(hwnd: HWND, lparam: LPARAM) -> BOOL {
+        let search = &mut *(lparam.0 as *mut Search);
+        if IsWindowVisible(hwnd).as_bool() && window_matches(hwnd, search.selector) {
+            search.found = Some(hwnd);
+            return BOOL(0);
+        }
+        BOOL(1)
+    }
+
+    let found = match selector {
+        WindowSelector {
+            title: Some(title),
+            class: None,
+            process: None,
+            current_desktop_only: false,
+        } => Some(unsafe { FindWindowW(PCWSTR::null(), &HSTRING::from(*title)) })
+            .filter(|hwnd| hwnd.0 != 0),
+        _ => {
+            let mut search = Search {
+                selector,
+                found: None,
+            };
+            // Stopping early makes EnumWindows report a failure, so only `found` tells
+            unsafe { EnumWindows(Some(visit), LPARAM(&mut search as *mut Search as isize)) };
+            search.found
+        }
+    };
+
+    found.ok_or_else(|| {
+        MacroError::WindowNotFound {
+            pattern: selector.to_string(),
+        }
+        .into()
+    })
+}
+
+/// Whether `hwnd` matches `selector`, looking up only what the selector asks about.
+#[cfg(windows)]
+fn window_matches(hwnd: HWND, selector: &WindowSelector) -> bool {
+    selector
+        .title
+        .is_none_or(|title| window_title(hwnd).is_ok_and(|actual| actual == title))
+        && selector.class.is_none_or(|class| {
+            window_class(hwnd).is_ok_and(|actual| actual.eq_ignore_ascii_case(class))
+        })
+        && selector.process.is_none_or(|process| {
+            window_process(hwnd).is_ok_and(|actual| actual.eq_ignore_ascii_case(process))
+        })
+        && (!selector.current_desktop_only || desktop::is_on_current_desktop(hwnd))
+}
+
+/// Title of the window that currently has the focus, empty if there is none.
+#[cfg(windows)]
+pub fn foreground_window_title() -> Result<String, anyhow::Error> {
+    use windows::Win32::UI::WindowsAndMessaging::GetForegroundWindow;
+
+    let hwnd = unsafe { GetForegroundWindow() };
+    if hwnd.0 == 0 {
+        return Ok(String::new());
+    }
+
+    window_title(hwnd)
+}
+
+/// The window that currently has the focus, `None` if there is none.
+#[cfg(windows)]
+pub fn foreground_window() -> Result<Option<WindowInfo>, anyhow::Error> {
+    use windows::Win32::UI::WindowsAndMessaging::GetForegroundWindow;
+
+    let hwnd = unsafe { GetForegroundWindow() };
+    if hwnd.0 == 0 {
+        return Ok(None);
+    }
+
+    window_info(hwnd).map(Some)
+}
+
+/// Title, class and program of `hwnd`.
+#[cfg(windows)]
+pub fn window_info(hwnd: HWND) -> Result<WindowInfo, anyhow::Error> {
+    Ok(WindowInfo {
+        title: window_title(hwnd)?,
+        class: window_class(hwnd)?,
+        process: window_process(hwnd)?,
+    })
+}
+
+/// File name of the executable `hwnd` belongs to, e.g. `excel.exe`.
+#[cfg(windows)]
+fn window_process(hwnd: HWND) -> Result<String, anyhow::Error> {
+    use windows::core::PWSTR;
+    use windows::Win32::Foundation::{CloseHandle, MAX_PATH};
+    use windows::Win32::System::Threading::{
+        OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32,
+        PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+    use windows::Win32::UI::WindowsAndMessaging::GetWindowThreadProcessId;
+
+    let mut process_id = 0;
+    unsafe { GetWindowThreadProcessId(hwnd, &mut process_id) };
+
+    let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, process_id) }
+        .map_err(|_| MacroError::win32("OpenProcess"))?;
+    let mut path = [0u16; MAX_PATH as usize];
+    let mut length = path.len() as u32;
+    let succeeded = unsafe {
+        QueryFullProcessImageNameW(
+            process,
+            PROCESS_NAME_WIN32,
+            PWSTR(path.as_mut_ptr()),
+            &mut length,
+        )
+    };
+    // Read before CloseHandle can overwrite it
+    let error = (!succeeded.as_bool()).then(|| MacroError::win32("QueryFullProcessImageNameW"));
+    unsafe { CloseHandle(process) };
+    if let Some(error) = error {
+        return Err(error.into());
+    }
+
+    let path = String::from_utf16_lossy(&path[..length as usize]);
+    Ok(path.rsplit('\\').next().unwrap_or_default().to_string())
+}
+
+/// Class name of `hwnd`, e.g. `Notepad`.
+#[cfg(windows)]
+fn window_class(hwnd: HWND) -> Result<String, anyhow::Error> {
+    use windows::Win32::UI::WindowsAndMessaging::GetClassNameW;
+
+    // Class names are at most 256 characters
+    let mut buffer = [0u16; 257];
+    let copied = unsafe { GetClassNameW(hwnd, &mut buffer) };
+    if copied == 0 {
+        return Err(MacroError::win32("GetClassNameW").into());
+    }
+
+    Ok(String::from_utf16_lossy(&buffer[..copied as usize]))
+}
+
+/// Whether the window with the focus is a fullscreen app such as a game: Windows reports a
+/// fullscreen or Direct3D exclusive app, or the window has no title bar and covers its whole
+/// monitor, as borderless fullscreen windows do. The desktop itself does not count.
+#[cfg(windows)]
+pub fn foreground_fullscreen() -> bool {
+    use windows::Win32::Foundation::RECT;
+    use windows::Win32::Graphics::Gdi::{
+        GetMonitorInfoW, MonitorFromWindow, MONITORINFO, MONITOR_DEFAULTTONEAREST,
+    };
+    use windows::Win32::UI::Shell::{
+        SHQueryUserNotificationState, QUNS_BUSY, QUNS_RUNNING_D3D_FULL_SCREEN,
+    };
+    use windows::Win32::UI::WindowsAndMessaging::{
+        GetDesktopWindow, GetForegroundWindow, GetShellWindow, GetWindowLongW, GetWindowRect,
+        GWL_STYLE, WS_CAPTION,
+    };
+
+    if let Ok(state) = unsafe { SHQueryUserNotificationState() } {
+        if state == QUNS_BUSY || state == QUNS_RUNNING_D3D_FULL_SCREEN {
+            return true;
+        }
+    }
+
+    let hwnd = unsafe { GetForegroundWindow() };
+    if hwnd.0 == 0 || hwnd == unsafe { GetShellWindow() } || hwnd == unsafe { GetDesktopWindow() } {
+        return false;
+    }
+
+    let style = unsafe { GetWindowLongW(hwnd, GWL_STYLE) } as u32;
+    if style & WS_CAPTION.0 == WS_CAPTION.0 {
+        return false;
+    }
+
+    let mut rect = RECT::default();
+    let mut monitor = MONITORINFO {
+        cbSize: std::mem::size_of::<MONITORINFO>() as u32,
+        ..Default::default()
+    };
+    let found = unsafe {
+        GetWindowRect(hwnd, &mut rect).as_bool()
+            && GetMonitorInfoW(
+                MonitorFromWindow(hwnd, MONITOR_DEFAULTTONEAREST),
+                &mut monitor,
+            )
+            .as_bool()
+    };
+
+    found
+        && rect.left <= monitor.rcMonitor.left
+        && rect.top <= monitor.rcMonitor.top
+        && rect.right >= monitor.rcMonitor.right
+        && rect.bottom >= monitor.rcMonitor.bottom
+}
+
+/// Title of `hwnd`, empty if it has none.
+#[cfg(windows)]
+fn window_title(hwnd: HWND) -> Result<String, anyhow::Error> {
+    use windows::Win32::UI::WindowsAndMessaging::{GetWindowTextLengthW, GetWindowTextW};
+
+    let length = unsafe { GetWindowTextLengthW(hwnd) };
+    if length == 0 {
+        return Ok(String::new());
+    }
+
+    let mut buffer = vec![0u16; length as usize + 1];
+    let copied = unsafe { GetWindowTextW(hwnd, &mut buffer) };
+    if copied == 0 {
+        return Err(MacroError::win32("GetWindowTextW").into());
+    }
+
+    Ok(String::from_utf16_lossy(&buffer[..copied as usize]))
+}
+
+/// Screen coordinates of the top-left corner of `hwnd`.
+#[cfg(windows)]
+pub fn window_origin(hwnd: HWND) -> Result<(i32, i32), anyhow::Error> {
+    let rect = window_rect(hwnd)?;
+    Ok((rect.left, rect.top))
+}
+
+/// Screen rectangle of `hwnd`, including its frame.
+#[cfg(windows)]
+pub fn window_rect(hwnd: HWND) -> Result<windows::Win32::Foundation::RECT, anyhow::Error> {
+    use windows::Win32::Foundation::RECT;
+    use windows::Win32::UI::WindowsAndMessaging::GetWindowRect;
+
+    let mut rect = RECT::default();
+
+    if !unsafe { GetWindowRect(hwnd, &mut rect) }.as_bool() {
+        return Err(MacroError::win32("GetWindowRect").into());
+    }
+
+    Ok(rect)
+}
+
+/// Posts a key down/up pair to `hwnd` without touching the global input queue.
+///
+/// Posted keystrokes bypass the keyboard state, so applications that poll the keyboard (most
+/// games, some editors) will never see them.
+#[cfg(windows)]
+pub fn post_key(hwnd: HWND, key: i32) -> Result<(), anyhow::Error> {
+    use windows::Win32::Foundation::{LPARAM, WPARAM};
+    use windows::Win32::UI::Input::KeyboardAndMouse::MapVirtualKeyW;
+    use windows::Win32::UI::WindowsAndMessaging::{
+        PostMessageW, MAPVK_VK_TO_VSC, WM_KEYDOWN, WM_KEYUP,
+    };
+
+    let scan_code = unsafe { MapVirtualKeyW(key as u32, MAPVK_VK_TO_VSC) } as isize;
+
+    // Repeat count of 1 and the scan code, plus the previous-state and transition bits for key up
+    let key_down_lparam = 1 | (scan_code << 16);
+    let key_up_lparam = key_down_lparam | (1 << 30) | (1 << 31);
+
+    for (message, lparam, direction) in [
+        (WM_KEYDOWN, key_down_lparam, "down"),
+        (WM_KEYUP, key_up_lparam, "up"),
+    ] {
+        if !unsafe { PostMessageW(hwnd, message, WPARAM(key as usize), LPARAM(lparam)) }.as_bool() {
+            return Err(anyhow::anyhow!(
+                "Failed to post key {} for {} to window: {}",
+                direction,
+                key,
+                get_last_windows_error()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Posts `text` to `hwnd` as a series of `WM_CHAR` messages, one per UTF-16 code unit.
+#[cfg(windows)]
+pub fn post_text(hwnd: HWND, text: &str) -> Result<(), anyhow::Error> {
+    use windows::Win32::Foundation::{LPARAM, WPARAM};
+    use windows::Win32::UI::WindowsAndMessaging::{PostMessageW, WM_CHAR};
+
+    for unit in text.encode_utf16() {
+        if !unsafe { PostMessageW(hwnd, WM_CHAR, WPARAM(unit as usize), LPARAM(1)) }.as_bool() {
+            return Err(anyhow::anyhow!(
+                "Failed to post character {:#06x} to window: {}",
+                unit,
+                get_last_windows_error()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn confirmation_caption(macro_name: &str) -> String {
+    format!("Confirm macro: {}", macro_name)
+}
+
+/// Shows a topmost message box asking the user to confirm `macro_name` by pressing its hotkey
+/// again. The box is shown from its own thread so the caller is never blocked on it.
+#[cfg(windows)]
+pub fn show_confirmation_prompt(macro_name: &str, window: std::time::Duration) {
+    use windows::core::HSTRING;
+    use windows::Win32::UI::WindowsAndMessaging::{MessageBoxW, MB_ICONWARNING, MB_OK, MB_TOPMOST};
+
+    let caption = HSTRING::from(confirmation_caption(macro_name));
+    let text = HSTRING::from(format!(
+        "Run macro {}? Press the hotkey again within {} seconds to confirm.",
+        macro_name,
+        window.as_secs()
+    ));
+
+    std::thread::spawn(move || unsafe {
+        MessageBoxW(
+            HWND::default(),
+            &text,
+            &caption,
+            MB_OK | MB_ICONWARNING | MB_TOPMOST,
+        );
+    });
+}
+
+/// Dismisses the prompt opened by `show_confirmation_prompt`, if it is still showing.
+#[cfg(windows)]
+pub fn close_confirmation_prompt(macro_name: &str) {
+    use windows::Win32::Foundation::{LPARAM, WPARAM};
+    use windows::Win32::UI::WindowsAndMessaging::{PostMessageW, WM_CLOSE};
+
+    if let Ok(hwnd) = find_window(&WindowSelector::title(&confirmation_caption(macro_name))) {
+        unsafe { PostMessageW(hwnd, WM_CLOSE, WPARAM(0), LPARAM(0)) };
+    }
+}
+
+/// Moves and resizes `hwnd`, restoring it first if maximized since maximized windows silently
+/// ignore `SetWindowPos`. Returns the placement the window had before, for `restore_placement`.
+#[cfg(windows)]
+pub fn move_window(
+    hwnd: HWND,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+) -> Result<windows::Win32::UI::WindowsAndMessaging::WINDOWPLACEMENT, anyhow::Error> {
+    use windows::Win32::UI::WindowsAndMessaging::{
+        GetWindowPlacement, IsZoomed, SetWindowPos, ShowWindow, SWP_NOACTIVATE, SWP_NOZORDER,
+        SW_RESTORE, WINDOWPLACEMENT,
+    };
+
+    let mut placement = WINDOWPLACEMENT {
+        length: std::mem::size_of::<WINDOWPLACEMENT>() as u32,
+        ..Default::default()
+    };
+
+    if !unsafe { GetWindowPlacement(hwnd, &mut placement) }.as_bool() {
+        return Err(anyhow::anyhow!(
+            "Failed to get window placement: {}",
+            get_last_windows_error()
+        ));
+    }
+
+    if unsafe { IsZoomed(hwnd) }.as_bool() {
+        unsafe { ShowWindow(hwnd, SW_RESTORE) };
+    }
+
+    if !unsafe {
+        SetWindowPos(
+            hwnd,
+            HWND::default(),
+            x,
+            y,
+            width,
+            height,
+            SWP_NOZORDER | SWP_NOACTIVATE,
+        )
+    }
+    .as_bool()
+    {
+        return Err(anyhow::anyhow!(
+            "Failed to move window: {}",
+            get_last_windows_error()
+        ));
+    }
+
+    Ok(placement)
+}
+
+#[cfg(windows)]
+pub fn restore_placement(
+    hwnd: HWND,
+    placement: &windows::Win32::UI::WindowsAndMessaging::WINDOWPLACEMENT,
+) -> Result<(), anyhow::Error> {
+    use windows::Win32::UI::WindowsAndMessaging::SetWindowPlacement;
+
+    if !unsafe { SetWindowPlacement(hwnd, placement) }.as_bool() {
+        return Err(anyhow::anyhow!(
+            "Failed to restore window placement: {}",
+            get_last_windows_error()
+        ));
+    }
+
+    Ok(())
+}
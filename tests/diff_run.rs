@@ -0,0 +1,97 @@
+//! Runs `diff-run` through the command line on a fixture config, from the YAML to the input the
+//! macro records.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use input_macro_runner::run_cli_with;
+
+/// A copy of the fixture config `name` in a directory of its own, next to which `diff-run` keeps
+/// its baselines.
+fn fixture(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "input_macro_runner_{}_{}",
+        name,
+        std::process::id()
+    ));
+    fs::create_dir_all(&dir).unwrap();
+
+    let path = dir.join(name);
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    fs::copy(fixtures.join(name), &path).unwrap();
+    path
+}
+
+/// `diff-run login` on `config`, with `options` after the macro name.
+fn diff_run(config: &Path, options: &[&str]) -> Result<(), anyhow::Error> {
+    let config = config.to_str().unwrap();
+    run_cli_with(
+        ["--config", config, "diff-run", "login"]
+            .iter()
+            .chain(options)
+            .map(|arg| arg.to_string()),
+    )
+}
+
+#[test]
+fn diff_runs_record_the_config_and_catch_changes_to_it() {
+    let config = fixture("login.yaml");
+
+    diff_run(&config, &["--update-baseline"]).unwrap();
+    let baseline: serde_json::Value = serde_json::from_str(
+        &fs::read_to_string(config.with_file_name("login.baseline.json")).unwrap(),
+    )
+    .unwrap();
+    // Execution events are recorded alongside the input, as JSON
+    let inputs: Vec<(u64, &str)> = baseline["events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|event| {
+            (
+                event["at_ms"].as_u64().unwrap(),
+                event["event"].as_str().unwrap(),
+            )
+        })
+        .filter(|(_, event)| !event.starts_with('{'))
+        .collect();
+    assert_eq!(
+        inputs.iter().map(|(_, event)| *event).collect::<Vec<_>>(),
+        [
+            "key_down Tab",
+            "key_up Tab",
+            "type 'm'",
+            "type 'e'",
+            "cursor_to 800 400",
+            "button_down left",
+            "button_up left",
+            "key_down LeftControl",
+            "key_down Return",
+            "key_up Return",
+            "key_up LeftControl",
+        ]
+    );
+    let moved_at = inputs
+        .iter()
+        .find(|(_, event)| event.starts_with("cursor_to"))
+        .map(|(at_ms, _)| *at_ms);
+    assert!(moved_at >= Some(250), "{:?}", moved_at);
+
+    // Unchanged, the macro does the same again
+    diff_run(&config, &[]).unwrap();
+
+    let changed = fs::read_to_string(&config)
+        .unwrap()
+        .replace("!PressKey Tab", "!PressKey Escape");
+    fs::write(&config, changed).unwrap();
+    let e = diff_run(&config, &[]).unwrap_err();
+    assert!(
+        e.to_string().starts_with("login differs from its baseline"),
+        "{}",
+        e
+    );
+
+    fs::remove_dir_all(config.parent().unwrap()).unwrap();
+}